@@ -43,6 +43,8 @@ enum Method {
 	#[cfg(feature = "wasmedge")]
 	CompiledWasmedge {
 		fast_instance_reuse: bool,
+		decommit_only_dirty: bool,
+		decommit_after_call: bool,
 		precompile: bool,
 	},
 }
@@ -110,15 +112,22 @@ fn initialize(
 			.map(|runtime| -> Arc<dyn WasmModule> { Arc::new(runtime) })
 		},
 		#[cfg(feature = "wasmedge")]
-		Method::CompiledWasmedge { fast_instance_reuse, precompile } => {
+		Method::CompiledWasmedge {
+			fast_instance_reuse,
+			decommit_only_dirty,
+			decommit_after_call,
+			precompile,
+		} => {
 			let config = sc_executor_wasmedge::Config {
 				allow_missing_func_imports,
 				semantics: sc_executor_wasmedge::Semantics {
 					extra_heap_pages: heap_pages,
-					deterministic_stack_limit: None,
 					fast_instance_reuse,
-					max_memory_size: None,
+					decommit_only_dirty,
+					decommit_after_call,
+					..Default::default()
 				},
+				..Default::default()
 			};
 
 			if precompile {
@@ -258,16 +267,36 @@ fn bench_call_instance(c: &mut Criterion) {
 		#[cfg(feature = "wasmedge")]
 		("wasmedge_reuse", Method::CompiledWasmedge {
 			fast_instance_reuse: true,
+			decommit_only_dirty: false,
+			decommit_after_call: true,
+			precompile: false,
+		}),
+		#[cfg(feature = "wasmedge")]
+		("wasmedge_reuse_decommit_only_dirty", Method::CompiledWasmedge {
+			fast_instance_reuse: true,
+			decommit_only_dirty: true,
+			decommit_after_call: true,
+			precompile: false,
+		}),
+		#[cfg(feature = "wasmedge")]
+		("wasmedge_reuse_no_decommit", Method::CompiledWasmedge {
+			fast_instance_reuse: true,
+			decommit_only_dirty: false,
+			decommit_after_call: false,
 			precompile: false,
 		}),
 		#[cfg(feature = "wasmedge")]
 		("wasmedge_recreate", Method::CompiledWasmedge {
 			fast_instance_reuse: false,
+			decommit_only_dirty: false,
+			decommit_after_call: true,
 			precompile: false,
 		}),
 		#[cfg(feature = "wasmedge")]
 		("wasmedge_recreate_precompiled", Method::CompiledWasmedge {
 			fast_instance_reuse: false,
+			decommit_only_dirty: false,
+			decommit_after_call: true,
 			precompile: true,
 		}),
 		("interpreted", Method::Interpreted),
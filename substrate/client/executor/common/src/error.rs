@@ -107,6 +107,15 @@ pub enum Error {
 
 	#[error("Execution aborted due to trap: {0}")]
 	AbortedDueToTrap(MessageWithBacktrace),
+
+	#[error("Execution aborted due to integer division by zero: {0}")]
+	AbortedDueToDivisionByZero(MessageWithBacktrace),
+
+	#[error("Execution aborted due to integer overflow: {0}")]
+	AbortedDueToIntegerOverflow(MessageWithBacktrace),
+
+	#[error("Execution aborted because the call didn't finish in time")]
+	Timeout,
 }
 
 impl wasmi::HostError for Error {}
@@ -60,6 +60,11 @@ impl ExposedMutableGlobalsSet {
 			runtime_blob.exported_internal_global_names().map(ToOwned::to_owned).collect();
 		Self(global_names)
 	}
+
+	/// Returns the export names of the globals in the set.
+	pub fn names(&self) -> impl Iterator<Item = &str> {
+		self.0.iter().map(String::as_str)
+	}
 }
 
 /// A snapshot of a global variables values. This snapshot can be later used for restoring the
@@ -428,9 +428,15 @@ pub struct Store<DT> {
 impl<DT: Clone> Store<DT> {
 	/// Create a new empty sandbox store.
 	pub fn new(backend: SandboxBackend) -> Self {
+		Self::with_capacity(backend, 0)
+	}
+
+	/// Create a new empty sandbox store with room for `capacity` instances and as many memories
+	/// before it has to reallocate.
+	pub fn with_capacity(backend: SandboxBackend, capacity: usize) -> Self {
 		Store {
-			instances: Vec::new(),
-			memories: Vec::new(),
+			instances: Vec::with_capacity(capacity),
+			memories: Vec::with_capacity(capacity),
 			backend_context: BackendContext::new(backend),
 		}
 	}
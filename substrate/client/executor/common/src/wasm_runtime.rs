@@ -20,6 +20,7 @@
 
 use crate::error::Error;
 use sp_wasm_interface::Value;
+use std::time::Duration;
 
 pub use sc_allocator::AllocationStats;
 
@@ -88,12 +89,13 @@ pub trait WasmInstance: Send {
 	///
 	/// Before execution, instance is reset.
 	///
-	/// Returns the encoded result on success.
+	/// Returns the encoded result on success, along with the allocation stats and the time spent
+	/// in host functions during the call, if the engine measures it.
 	fn call_with_allocation_stats(
 		&mut self,
 		method: InvokeMethod,
 		data: &[u8],
-	) -> (Result<Vec<u8>, Error>, Option<AllocationStats>);
+	) -> (Result<Vec<u8>, Error>, Option<AllocationStats>, Option<Duration>);
 
 	/// Call an exported method on this WASM instance.
 	///
@@ -282,7 +282,7 @@ where
 
 		with_externalities_safe(&mut **ext, move || {
 			preregister_builtin_ext(module.clone());
-			let (result, allocation_stats) =
+			let (result, allocation_stats, _) =
 				instance.call_with_allocation_stats(export_name.into(), call_data);
 			**allocation_stats_out = allocation_stats;
 			result
@@ -347,10 +347,10 @@ where
 					allow_missing_func_imports,
 					semantics: sc_executor_wasmedge::Semantics {
 						extra_heap_pages: heap_pages,
-						deterministic_stack_limit: None,
 						fast_instance_reuse: true,
-						max_memory_size: None,
+						..Default::default()
 					},
+					..Default::default()
 				},
 			)
 			.map(|runtime| -> Arc<dyn WasmModule> { Arc::new(runtime) })
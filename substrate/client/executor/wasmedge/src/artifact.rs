@@ -0,0 +1,299 @@
+use crate::{runtime::Semantics, LOG_TARGET};
+use codec::{Decode, Encode};
+use sc_executor_common::{error::WasmError, runtime_blob::RuntimeBlob};
+use std::{
+	fs::{File, OpenOptions},
+	io::{Read, Seek, SeekFrom, Write},
+	path::Path,
+	time::{Duration, SystemTime},
+};
+
+lazy_static::lazy_static! {
+	static ref WASMEDGE_VERSION: String = wasmedge_sys::utils::version_string();
+}
+
+/// Returns the version of the WasmEdge library the runtime is using.
+pub fn wasmedge_version() -> &'static str {
+	&WASMEDGE_VERSION
+}
+
+/// The hash identifying the code an artifact was compiled from, see [`blob_hash`].
+pub type BlobHash = [u8; 32];
+
+/// Returns the hash of the given code, which is recorded in the header of the artifacts compiled
+/// from it.
+///
+/// Pass the hashes of the runtimes which are still in use to [`prune_artifact_cache`].
+pub fn blob_hash(blob: &RuntimeBlob) -> BlobHash {
+	sp_core_hashing::blake2_256(&blob.clone().serialize())
+}
+
+/// The bytes every artifact ends with, right after the length of its header.
+const HEADER_MAGIC: &[u8; 8] = b"wasmedge";
+
+/// The name of the custom section holding the header of an artifact, see [`append_header`].
+const HEADER_SECTION_NAME: &[u8] = b"substrate_wasmedge_header";
+
+/// The size of the end of an artifact locating its header: the length of the encoded header
+/// followed by [`HEADER_MAGIC`].
+const HEADER_FOOTER_LEN: u64 = 4 + HEADER_MAGIC.len() as u64;
+
+/// Metadata describing how a precompiled artifact was produced.
+///
+/// The header is stored at the end of the artifact itself, see [`append_header`], so that the
+/// two are always written and replaced together.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub(crate) struct ArtifactHeader {
+	/// The version of WasmEdge which compiled the artifact.
+	pub wasmedge_version: String,
+
+	/// The [`Semantics::artifact_key`] of the semantics the artifact was compiled with.
+	pub artifact_key: [u8; 32],
+
+	/// The [`blob_hash`] of the code the artifact was compiled from.
+	pub blob_hash: BlobHash,
+
+	/// Whether the artifact may be distributed to other machines, see
+	/// [`ArtifactFormat::is_shareable`](crate::ArtifactFormat::is_shareable).
+	pub shareable: bool,
+}
+
+impl ArtifactHeader {
+	/// Returns the header describing an artifact compiled by this process from the code with the
+	/// given hash and with the given semantics.
+	pub fn current(semantics: &Semantics, blob_hash: BlobHash) -> Self {
+		ArtifactHeader {
+			wasmedge_version: wasmedge_version().to_owned(),
+			artifact_key: semantics.artifact_key(),
+			blob_hash,
+			shareable: semantics.artifact_format.is_shareable(),
+		}
+	}
+
+	/// Checks that an artifact with this header can be loaded by this process with the given
+	/// semantics.
+	pub fn check_compatible(&self, semantics: &Semantics) -> Result<(), WasmError> {
+		if self.wasmedge_version != wasmedge_version() {
+			return Err(WasmError::Other(format!(
+				"the artifact was compiled by WasmEdge {}, but WasmEdge {} is in use",
+				self.wasmedge_version,
+				wasmedge_version(),
+			)))
+		}
+
+		if self.artifact_key != semantics.artifact_key() {
+			return Err(WasmError::Other(String::from(
+				"the artifact was compiled with semantics incompatible with the ones in use",
+			)))
+		}
+
+		Ok(())
+	}
+}
+
+/// Appends the header to the artifact at `artifact_path`.
+///
+/// The artifact must stay loadable by WasmEdge as is, so the header is appended as a custom
+/// section: a universal artifact is a wasm module, which may end with any custom section, while
+/// the loader of a native shared library ignores the trailing data. The section ends with the
+/// length of the encoded header and [`HEADER_MAGIC`], so that the header can be found from the end
+/// of the file. The last header appended is the one read by [`read_header`].
+pub(crate) fn append_header(
+	artifact_path: &Path,
+	header: &ArtifactHeader,
+) -> Result<(), WasmError> {
+	let mut payload = header.encode();
+	payload.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+	payload.extend_from_slice(HEADER_MAGIC);
+
+	// The custom section id, the size of the section and the length prefixed name.
+	let mut section = vec![0];
+	section
+		.extend_from_slice(&padded_leb128((1 + HEADER_SECTION_NAME.len() + payload.len()) as u32));
+	section.push(HEADER_SECTION_NAME.len() as u8);
+	section.extend_from_slice(HEADER_SECTION_NAME);
+	section.extend_from_slice(&payload);
+
+	OpenOptions::new()
+		.append(true)
+		.open(artifact_path)
+		.and_then(|mut file| file.write_all(&section))
+		.map_err(|e| WasmError::Other(format!("cannot write the artifact header: {}", e)))
+}
+
+/// Encodes the value as LEB128 in the maximum of 5 bytes, so that its size doesn't depend on the
+/// value.
+fn padded_leb128(value: u32) -> [u8; 5] {
+	let mut bytes = [0; 5];
+	for (index, byte) in bytes.iter_mut().enumerate() {
+		*byte = (value >> (7 * index)) as u8 & 0x7f;
+		if index < 4 {
+			*byte |= 0x80;
+		}
+	}
+	bytes
+}
+
+/// Reads the header of the artifact at `artifact_path`.
+///
+/// Fails if the artifact has no header, e.g. because it was compiled before the header was
+/// stored in the artifact. Such an artifact must be compiled again, which
+/// [`create_runtime_artifact_or_compile`](crate::create_runtime_artifact_or_compile) does.
+pub(crate) fn read_header(artifact_path: &Path) -> Result<ArtifactHeader, WasmError> {
+	let read_error =
+		|e: std::io::Error| WasmError::Other(format!("cannot read the artifact header: {}", e));
+	let missing_error = || WasmError::Other(String::from("the artifact has no header"));
+
+	let mut file = File::open(artifact_path).map_err(read_error)?;
+	let len = file.metadata().map_err(read_error)?.len();
+	if len < HEADER_FOOTER_LEN {
+		return Err(missing_error())
+	}
+
+	let mut footer = [0; HEADER_FOOTER_LEN as usize];
+	file.seek(SeekFrom::End(-(HEADER_FOOTER_LEN as i64))).map_err(read_error)?;
+	file.read_exact(&mut footer).map_err(read_error)?;
+	let (header_len, magic) = footer.split_at(4);
+	if magic != HEADER_MAGIC {
+		return Err(missing_error())
+	}
+
+	let header_len = u32::from_le_bytes(header_len.try_into().expect("split at 4 bytes; qed"));
+	if u64::from(header_len) + HEADER_FOOTER_LEN > len {
+		return Err(WasmError::Other(String::from("the artifact header is malformed")))
+	}
+
+	let mut encoded_header = vec![0; header_len as usize];
+	file.seek(SeekFrom::End(-(i64::from(header_len) + HEADER_FOOTER_LEN as i64)))
+		.map_err(read_error)?;
+	file.read_exact(&mut encoded_header).map_err(read_error)?;
+
+	ArtifactHeader::decode(&mut encoded_header.as_slice())
+		.map_err(|e| WasmError::Other(format!("the artifact header is malformed: {}", e)))
+}
+
+/// Returns whether the artifact at `artifact_path` may be distributed to other machines, as
+/// recorded in its header.
+///
+/// A cache shared between machines must only hand out the artifacts for which this returns
+/// `true`, the others are only valid on the machine which compiled them. See
+/// [`Semantics::artifact_format`](crate::Semantics::artifact_format).
+pub fn is_artifact_shareable(artifact_path: &Path) -> Result<bool, WasmError> {
+	Ok(read_header(artifact_path)?.shareable)
+}
+
+/// Removes the artifacts in `dir` which can't or needn't be loaded anymore, returning how many
+/// were removed.
+///
+/// An artifact is removed if its header is missing or malformed, if it was compiled by another
+/// version of WasmEdge, or if it was compiled from code whose [`blob_hash`] isn't in `keep`. The
+/// `.header` files older versions stored next to the artifacts are removed as well. This lets a
+/// node clean up the artifacts of the runtimes replaced by an upgrade.
+///
+/// Every other file in `dir` is considered to be an artifact, so the directory should be
+/// dedicated to the artifacts. The contents of the artifacts besides their headers aren't checked.
+///
+/// The files modified within the last [`ARTIFACT_WRITE_GRACE_PERIOD`] are left alone, as they
+/// might still be being written, e.g. by
+/// [`prepare_runtime_artifact`](crate::prepare_runtime_artifact) in another process or by a copy
+/// from a shared cache. Older temporary files left behind by an interrupted
+/// [`prepare_runtime_artifact`](crate::prepare_runtime_artifact) are removed.
+pub fn prune_artifact_cache(dir: &Path, keep: &[BlobHash]) -> Result<usize, WasmError> {
+	prune_artifact_cache_with_grace_period(dir, keep, ARTIFACT_WRITE_GRACE_PERIOD)
+}
+
+/// How long after its last modification a file is assumed to possibly still be being written,
+/// see [`prune_artifact_cache`].
+pub const ARTIFACT_WRITE_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// [`prune_artifact_cache`] with a custom grace period.
+pub(crate) fn prune_artifact_cache_with_grace_period(
+	dir: &Path,
+	keep: &[BlobHash],
+	grace_period: Duration,
+) -> Result<usize, WasmError> {
+	let entries = std::fs::read_dir(dir).map_err(|e| {
+		WasmError::Other(format!("cannot read the artifact cache directory: {}", e))
+	})?;
+
+	let mut removed = 0;
+	for entry in entries {
+		let path = entry
+			.map_err(|e| {
+				WasmError::Other(format!("cannot read the artifact cache directory: {}", e))
+			})?
+			.path();
+		if !path.is_file() || modified_within(&path, grace_period) {
+			continue
+		}
+
+		if is_temporary_artifact(&path) ||
+			path.extension().map_or(false, |extension| extension == "header")
+		{
+			remove_file(&path)?;
+			continue
+		}
+
+		let reason = match read_header(&path) {
+			Ok(header) if header.wasmedge_version != wasmedge_version() =>
+				format!("compiled by WasmEdge {}", header.wasmedge_version),
+			Ok(header) if !keep.contains(&header.blob_hash) => String::from("not kept"),
+			Ok(_) => continue,
+			Err(e) => e.to_string(),
+		};
+
+		log::debug!(
+			target: LOG_TARGET,
+			"Removing the artifact {} from the cache: {}",
+			path.display(),
+			reason,
+		);
+		remove_file(&path)?;
+		removed += 1;
+	}
+
+	Ok(removed)
+}
+
+/// The suffix of the temporary files the artifacts are written to before they're moved into
+/// place, see [`temporary_artifact`].
+const TEMPORARY_ARTIFACT_SUFFIX: &str = ".tmp";
+
+/// Creates a temporary file next to the artifact at `artifact_path`, for writing the artifact
+/// before moving it into place. The file is removed when the returned path is dropped.
+pub(crate) fn temporary_artifact(artifact_path: &Path) -> Result<tempfile::TempPath, WasmError> {
+	let dir = match artifact_path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+
+	tempfile::Builder::new()
+		.prefix(".")
+		.suffix(TEMPORARY_ARTIFACT_SUFFIX)
+		.tempfile_in(dir)
+		.map(|file| file.into_temp_path())
+		.map_err(|e| WasmError::Other(format!("cannot create a temporary artifact: {}", e)))
+}
+
+/// Whether the file at `path` is an artifact still being written, see [`temporary_artifact`].
+fn is_temporary_artifact(path: &Path) -> bool {
+	path.file_name()
+		.and_then(|name| name.to_str())
+		.map_or(false, |name| name.starts_with('.') && name.ends_with(TEMPORARY_ARTIFACT_SUFFIX))
+}
+
+/// Whether the file at `path` was modified less than `period` ago. Errs on the side of `true` if
+/// that can't be told.
+fn modified_within(path: &Path, period: Duration) -> bool {
+	match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+		Ok(modified) => SystemTime::now().duration_since(modified).map_or(true, |age| age < period),
+		// The file might have been moved into place or removed in the meantime.
+		Err(_) => true,
+	}
+}
+
+fn remove_file(path: &Path) -> Result<(), WasmError> {
+	std::fs::remove_file(path).map_err(|e| {
+		WasmError::Other(format!("cannot remove {} from the artifact cache: {}", path.display(), e))
+	})
+}
@@ -0,0 +1,122 @@
+use crate::{
+	runtime::{compile_module, CompiledModule, Semantics},
+	LOG_TARGET,
+};
+use codec::Encode;
+use sc_executor_common::{error::WasmError, runtime_blob::RuntimeBlob};
+use std::{
+	collections::VecDeque,
+	sync::{Mutex, MutexGuard},
+};
+
+/// Statistics of the lookups performed on an [`ArtifactCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArtifactCacheStats {
+	/// The number of lookups which found the module in the cache.
+	pub hits: u64,
+
+	/// The number of lookups which had to compile the module.
+	pub misses: u64,
+
+	/// The number of modules removed from the cache to make room for new ones.
+	pub evictions: u64,
+}
+
+/// An in-memory cache of compiled modules, keyed by the code and the semantics they were
+/// compiled with.
+///
+/// This avoids compiling the same runtime over and over, e.g. when a node switches back and forth
+/// between several runtime versions. Once the cache is full, the least recently used module is
+/// evicted. An evicted module is only freed once all the runtimes created from it are gone.
+pub struct ArtifactCache {
+	capacity: usize,
+	inner: Mutex<Inner>,
+}
+
+struct Inner {
+	/// The cached modules, the most recently used one at the front.
+	entries: VecDeque<([u8; 32], CompiledModule)>,
+	stats: ArtifactCacheStats,
+}
+
+impl ArtifactCache {
+	/// Creates an empty cache holding at most `capacity` modules.
+	///
+	/// A capacity of zero disables the caching, every lookup compiles the module.
+	pub fn new(capacity: usize) -> Self {
+		ArtifactCache {
+			capacity,
+			inner: Mutex::new(Inner {
+				entries: VecDeque::with_capacity(capacity),
+				stats: Default::default(),
+			}),
+		}
+	}
+
+	/// Returns the module compiled from `blob` with the given semantics, compiling it with
+	/// [`compile_module`] if it's not in the cache yet.
+	///
+	/// The returned module can be passed to
+	/// [`WasmEdgeRuntime::from_compiled_module`](crate::WasmEdgeRuntime::from_compiled_module).
+	pub fn get_or_compile(
+		&self,
+		blob: RuntimeBlob,
+		semantics: &Semantics,
+	) -> Result<CompiledModule, WasmError> {
+		let key = sp_core_hashing::blake2_256(
+			&(blob.clone().serialize(), semantics.artifact_key()).encode(),
+		);
+
+		{
+			let Inner { entries, stats } = &mut *self.lock()?;
+			if let Some(index) = entries.iter().position(|(entry_key, _)| *entry_key == key) {
+				stats.hits += 1;
+				let entry = entries.remove(index).expect("the index was just found; qed");
+				let module = entry.1.clone();
+				entries.push_front(entry);
+				return Ok(module)
+			}
+			stats.misses += 1;
+		}
+
+		// Don't hold the lock while compiling, so that lookups of other modules aren't blocked.
+		let module = compile_module(blob, semantics)?;
+
+		let Inner { entries, stats } = &mut *self.lock()?;
+		if self.capacity == 0 || entries.iter().any(|(entry_key, _)| *entry_key == key) {
+			// Either nothing is cached, or the module was compiled concurrently and is already
+			// there.
+			return Ok(module)
+		}
+
+		if entries.len() == self.capacity {
+			entries.pop_back();
+			stats.evictions += 1;
+			log::debug!(target: LOG_TARGET, "Evicted a module from the artifact cache");
+		}
+		entries.push_front((key, module.clone()));
+
+		Ok(module)
+	}
+
+	/// Returns the number of modules in the cache.
+	pub fn len(&self) -> usize {
+		self.lock().map_or(0, |inner| inner.entries.len())
+	}
+
+	/// Returns whether the cache holds no modules.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns the statistics of the lookups performed so far.
+	pub fn stats(&self) -> ArtifactCacheStats {
+		self.lock().map_or_else(|_| Default::default(), |inner| inner.stats)
+	}
+
+	fn lock(&self) -> Result<MutexGuard<Inner>, WasmError> {
+		self.inner
+			.lock()
+			.map_err(|_| WasmError::Other(String::from("failed to lock the artifact cache")))
+	}
+}
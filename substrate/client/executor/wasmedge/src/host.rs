@@ -1,15 +1,25 @@
-use crate::util;
-use codec::{Decode, Encode};
+use crate::{
+	imports::{AllocationPanic, DoubleFreePanic},
+	util, AllocatorConfig, PanicCallback, TraceSink,
+};
+use codec::{Compact, Decode, Encode};
 use log::trace;
 use sc_allocator::{AllocationStats, FreeingBumpHeapAllocator};
 use sc_executor_common::{
-	error::{Result, WasmError},
+	error::{Error, Result, WasmError},
 	sandbox::{self, SupervisorFuncIndex},
-	util::MemoryTransfer,
+	util::{checked_range, MemoryTransfer},
+};
+use sp_sandbox::{env as sandbox_env, HostError};
+use sp_wasm_interface::{
+	FunctionContext, MemoryId, Pointer, ReturnValue, Sandbox, Value, WordSize,
+};
+use std::{
+	cell::{Cell, RefCell},
+	collections::HashMap,
+	sync::Arc,
+	time::{Duration, Instant},
 };
-use sp_sandbox::env as sandbox_env;
-use sp_wasm_interface::{FunctionContext, MemoryId, Pointer, Sandbox, WordSize};
-use std::sync::Arc;
 use wasmedge_sdk::{types::Val, Executor, FuncRef, Memory, Table, WasmValue};
 
 // The sandbox store is inside of a Option<Box<..>>> so that we can temporarily borrow it.
@@ -19,6 +29,123 @@ struct SandboxStore(Option<Box<sandbox::Store<Arc<FuncRef>>>>);
 // those within one thread so this should be safe.
 unsafe impl Send for SandboxStore {}
 
+thread_local! {
+	/// The number of executors created on this thread for calling supervisor functions.
+	pub(crate) static SANDBOX_EXECUTORS_CREATED: Cell<usize> = Cell::new(0);
+
+	/// The allocator statistics of the call whose host function is running on this thread, see
+	/// [`current_allocator_stats`].
+	static CURRENT_ALLOCATOR_STATS: RefCell<Option<AllocationStats>> = RefCell::new(None);
+}
+
+/// Returns the statistics of the allocator accumulated so far by the call whose host function is
+/// running on this thread, or `None` if no host function is running.
+///
+/// Host functions only get a [`FunctionContext`], so this is how those doing large temporary
+/// allocations check how much memory the call already uses before requesting more.
+pub fn current_allocator_stats() -> Option<AllocationStats> {
+	CURRENT_ALLOCATOR_STATS.with(|stats| stats.borrow().clone())
+}
+
+/// Makes [`current_allocator_stats`] return the statistics of a call while one of its host
+/// functions runs. The statistics of the outer call, if any, are restored when dropped.
+pub(crate) struct AllocatorStatsScope(Option<AllocationStats>);
+
+impl AllocatorStatsScope {
+	pub fn enter(stats: AllocationStats) -> Self {
+		AllocatorStatsScope(CURRENT_ALLOCATOR_STATS.with(|current| current.replace(Some(stats))))
+	}
+}
+
+impl Drop for AllocatorStatsScope {
+	fn drop(&mut self) {
+		CURRENT_ALLOCATOR_STATS.with(|current| *current.borrow_mut() = self.0.take());
+	}
+}
+
+/// Describes why a call into a sandboxed instance failed.
+///
+/// The sandbox ABI only reports such failures as [`sandbox_env::ERR_EXECUTION`], the reason is
+/// recorded in the [`HostState`] instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxError {
+	/// The sandboxed code trapped, e.g. by executing `unreachable`.
+	Trap(String),
+	/// A supervisor function called by the sandboxed code returned an error.
+	HostFunction(String),
+	/// The sandboxed code accessed its linear memory out of bounds.
+	MemoryFault(String),
+	/// A WasmEdge executor for calling a supervisor function couldn't be created, e.g. due to
+	/// resource exhaustion.
+	ExecutorUnavailable(String),
+	/// The invocation would have been nested deeper than the given
+	/// [`Config::max_sandbox_depth`](crate::Config::max_sandbox_depth) allows.
+	DepthExceeded(u32),
+	/// The call failed for another reason, e.g. the requested export doesn't exist.
+	Other(String),
+}
+
+/// The beginnings of the messages of the sandbox invocations which failed before or after
+/// executing the sandboxed code, e.g. due to a missing export. `Function: ` is reported by the
+/// wasmi backend, the others by the wasmer one.
+const SANDBOX_NOT_EXECUTED: &[&str] = &[
+	"Function: ",
+	"Missing export",
+	"Incompatible Export Type",
+	"Unsupported return value",
+	"multiple return types",
+];
+
+impl SandboxError {
+	/// Classifies the error of a sandbox invocation in which no supervisor function failed.
+	///
+	/// The sandbox backends only report their errors as messages. The wasmi backend describes
+	/// the traps by their kind, e.g. `Trap: Code(MemoryAccessOutOfBounds)`, whereas the wasmer
+	/// one uses their descriptions, e.g. `out of bounds memory access`.
+	pub(crate) fn from_invoke_error(error: Error) -> Self {
+		let message = match error {
+			Error::Sandbox(message) => message,
+			error => return SandboxError::Other(error.to_string()),
+		};
+
+		if message.contains("MemoryAccessOutOfBounds") ||
+			message.contains("out of bounds memory access")
+		{
+			SandboxError::MemoryFault(message)
+		} else if SANDBOX_NOT_EXECUTED.iter().any(|prefix| message.starts_with(prefix)) {
+			SandboxError::Other(message)
+		} else {
+			SandboxError::Trap(message)
+		}
+	}
+}
+
+/// A host function call made by the runtime, as recorded when
+/// [`Config::record_host_calls`](crate::Config::record_host_calls) is enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostCall {
+	/// The name of the host function.
+	pub name: String,
+	/// The arguments the host function was called with.
+	pub args: Vec<Value>,
+	/// The value returned by the host function, or the error it failed with.
+	pub result: std::result::Result<Option<Value>, String>,
+	/// The writes the host function made into the linear memory, as the address and the
+	/// written bytes, in order.
+	pub memory_writes: Vec<(u32, Vec<u8>)>,
+}
+
+/// What is done with the host function calls made during a runtime call.
+pub(crate) enum HostCallMode {
+	/// The host functions are executed.
+	Execute,
+	/// The host functions are executed and their calls are recorded.
+	Record,
+	/// The host functions are not executed, the calls are answered with the results of the given
+	/// recorded calls instead, in order.
+	Replay(std::vec::IntoIter<HostCall>),
+}
+
 /// The state required to construct a InstanceWrapper context. The context only lasts for one host
 /// call, whereas the state is maintained for the duration of a Wasm runtime call, which may make
 /// many different host calls that must share state.
@@ -26,6 +153,55 @@ pub struct HostState {
 	sandbox_store: SandboxStore,
 	allocator: Box<FreeingBumpHeapAllocator>,
 	panic_message: Option<String>,
+	sandbox_error: Option<SandboxError>,
+	/// Executors for calling supervisor functions, kept for reuse by the following calls.
+	///
+	/// Nested sandbox invocations each need an executor of their own, hence there might be more
+	/// than one.
+	sandbox_executors: Vec<Executor>,
+	/// Why creating an executor for calling a supervisor function failed during the current
+	/// sandbox invocation.
+	sandbox_executor_error: Option<String>,
+	/// Whether a supervisor function failed during the current sandbox invocation.
+	sandbox_supervisor_failed: bool,
+	host_call_mode: HostCallMode,
+	/// The host function calls recorded so far, if [`HostCallMode::Record`] is used.
+	host_calls: Vec<HostCall>,
+	/// The memory writes made by the host function being recorded so far.
+	memory_writes: Vec<(u32, Vec<u8>)>,
+	/// Whether deallocating memory which isn't allocated is logged and ignored rather than
+	/// failing.
+	ignore_double_free: bool,
+	/// The time spent in host functions so far, if measured.
+	host_time: Option<Duration>,
+	/// The time the host functions may take in total before the call is aborted.
+	max_host_time: Option<Duration>,
+	/// The time by which the call is aborted, if the runtime checks for it.
+	deadline: Option<Instant>,
+	/// The address and the size of the most recent allocation made through the host functions.
+	last_allocation: Option<(u32, WordSize)>,
+	/// The number of sandbox invocations currently in progress, each nested in the previous one.
+	sandbox_depth: u32,
+	/// The number of sandbox invocations which may be nested.
+	max_sandbox_depth: Option<u32>,
+	/// The number of arguments a sandbox invocation may pass.
+	max_sandbox_args: Option<u32>,
+	/// The number of sandbox memories which currently exist.
+	sandbox_memories: u32,
+	/// The number of sandbox memories which may exist at the same time.
+	max_sandbox_memories: Option<u32>,
+	/// The callback invoked with the message of a runtime panic.
+	on_runtime_panic: Option<PanicCallback>,
+	/// The consumer of the call trace.
+	trace_sink: Option<TraceSink>,
+	/// How the allocator is tuned.
+	allocator_config: AllocatorConfig,
+	/// The sizes of the allocations made through the host functions, keyed by their address,
+	/// if [`AllocatorConfig::poison_freed_memory`] is enabled.
+	allocation_sizes: HashMap<u32, WordSize>,
+	/// The typed panic payload of the last failure of the allocator during the host function
+	/// being executed, see [`HostFuncPanic`](crate::HostFuncPanic).
+	allocator_failure: Option<Box<dyn std::any::Any + Send>>,
 }
 
 impl HostState {
@@ -37,6 +213,28 @@ impl HostState {
 			)))),
 			allocator: Box::new(allocator),
 			panic_message: None,
+			sandbox_error: None,
+			sandbox_executors: Vec::new(),
+			sandbox_executor_error: None,
+			sandbox_supervisor_failed: false,
+			host_call_mode: HostCallMode::Execute,
+			host_calls: Vec::new(),
+			memory_writes: Vec::new(),
+			ignore_double_free: false,
+			host_time: None,
+			max_host_time: None,
+			deadline: None,
+			last_allocation: None,
+			sandbox_depth: 0,
+			max_sandbox_depth: None,
+			max_sandbox_args: None,
+			sandbox_memories: 0,
+			max_sandbox_memories: None,
+			on_runtime_panic: None,
+			trace_sink: None,
+			allocator_config: AllocatorConfig::default(),
+			allocation_sizes: HashMap::new(),
+			allocator_failure: None,
 		}
 	}
 
@@ -45,6 +243,205 @@ impl HostState {
 		self.panic_message.take()
 	}
 
+	/// Takes the last failure of the allocator out of the host state, leaving a `None` in its
+	/// place.
+	pub(crate) fn take_allocator_failure(&mut self) -> Option<Box<dyn std::any::Any + Send>> {
+		self.allocator_failure.take()
+	}
+
+	/// Takes the reason of the last failed call into a sandboxed instance out of the host state,
+	/// leaving a `None` in its place.
+	pub fn take_sandbox_error(&mut self) -> Option<SandboxError> {
+		self.sandbox_error.take()
+	}
+
+	/// Sets what is done with the host function calls made from now on.
+	pub(crate) fn set_host_call_mode(&mut self, host_call_mode: HostCallMode) {
+		self.host_call_mode = host_call_mode;
+	}
+
+	/// Sets whether deallocating memory which isn't allocated is logged and ignored rather than
+	/// failing, see [`Config::ignore_double_free`](crate::Config::ignore_double_free).
+	pub(crate) fn set_ignore_double_free(&mut self, ignore_double_free: bool) {
+		self.ignore_double_free = ignore_double_free;
+	}
+
+	/// Sets how deep the sandbox invocations may be nested, see
+	/// [`Config::max_sandbox_depth`](crate::Config::max_sandbox_depth).
+	pub(crate) fn set_max_sandbox_depth(&mut self, max_sandbox_depth: Option<u32>) {
+		self.max_sandbox_depth = max_sandbox_depth;
+	}
+
+	/// Sets how many arguments a sandbox invocation may pass, see
+	/// [`Config::max_sandbox_args`](crate::Config::max_sandbox_args).
+	pub(crate) fn set_max_sandbox_args(&mut self, max_sandbox_args: Option<u32>) {
+		self.max_sandbox_args = max_sandbox_args;
+	}
+
+	/// Sets how many sandbox memories may exist at the same time, see
+	/// [`Config::max_sandbox_memories`](crate::Config::max_sandbox_memories).
+	pub(crate) fn set_max_sandbox_memories(&mut self, max_sandbox_memories: Option<u32>) {
+		self.max_sandbox_memories = max_sandbox_memories;
+	}
+
+	/// Sets the callback invoked when the runtime panics, see
+	/// [`Config::on_runtime_panic`](crate::Config::on_runtime_panic).
+	pub(crate) fn set_on_runtime_panic(&mut self, on_runtime_panic: Option<PanicCallback>) {
+		self.on_runtime_panic = on_runtime_panic;
+	}
+
+	/// Sets how the allocator is tuned, see
+	/// [`Config::allocator_config`](crate::Config::allocator_config).
+	pub(crate) fn set_allocator_config(&mut self, allocator_config: AllocatorConfig) {
+		self.allocator_config = allocator_config;
+	}
+
+	/// Makes room in the sandbox store for the given number of instances and memories, see
+	/// [`Config::sandbox_store_capacity`](crate::Config::sandbox_store_capacity).
+	///
+	/// Replaces the store, so this must be called before any sandbox is created.
+	pub(crate) fn set_sandbox_store_capacity(&mut self, capacity: usize) {
+		self.sandbox_store = SandboxStore(Some(Box::new(sandbox::Store::with_capacity(
+			sandbox::SandboxBackend::TryWasmer,
+			capacity,
+		))));
+	}
+
+	/// Sets the consumer of the call trace, see
+	/// [`Config::trace_sink`](crate::Config::trace_sink).
+	pub(crate) fn set_trace_sink(&mut self, trace_sink: Option<TraceSink>) {
+		self.trace_sink = trace_sink;
+	}
+
+	/// Returns the consumer of the call trace, if any.
+	pub(crate) fn trace_sink(&self) -> Option<&TraceSink> {
+		self.trace_sink.as_ref()
+	}
+
+	/// Starts measuring the time spent in host functions, letting the host function call which
+	/// makes it exceed `max_host_time` fail, see
+	/// [`Config::max_host_time`](crate::Config::max_host_time).
+	pub(crate) fn measure_host_time(&mut self, max_host_time: Option<Duration>) {
+		self.host_time = Some(Duration::ZERO);
+		self.max_host_time = max_host_time;
+	}
+
+	/// Whether the time spent in host functions is being measured.
+	pub(crate) fn is_measuring_host_time(&self) -> bool {
+		self.host_time.is_some()
+	}
+
+	/// Adds the time spent in a host function, returning whether the time spent so far is still
+	/// within [`Config::max_host_time`](crate::Config::max_host_time).
+	pub(crate) fn add_host_time(&mut self, elapsed: Duration) -> bool {
+		let host_time = self.host_time.get_or_insert(Duration::ZERO);
+		*host_time = host_time.saturating_add(elapsed);
+		self.max_host_time.map_or(true, |max_host_time| *host_time <= max_host_time)
+	}
+
+	/// Returns the time spent in host functions so far, if measured.
+	pub(crate) fn host_time(&self) -> Option<Duration> {
+		self.host_time
+	}
+
+	/// Lets the deadline checks of the runtime abort the call once `deadline` passed, see
+	/// [`Semantics::interruptible`](crate::Semantics::interruptible).
+	pub(crate) fn set_deadline(&mut self, deadline: Instant) {
+		self.deadline = Some(deadline);
+	}
+
+	/// Whether the call has a deadline and it passed.
+	pub(crate) fn deadline_passed(&self) -> bool {
+		self.deadline.map_or(false, |deadline| Instant::now() >= deadline)
+	}
+
+	/// Returns the error the call is aborted with, if its deadline passed.
+	pub(crate) fn deadline_error(&self) -> Option<Error> {
+		self.deadline_passed().then(|| Error::Timeout)
+	}
+
+	/// Returns the address and the size of the most recent allocation made through the host
+	/// functions during the call.
+	pub(crate) fn last_allocation(&self) -> Option<(u32, WordSize)> {
+		self.last_allocation
+	}
+
+	/// Returns the error the call is aborted with, if the host functions took longer than
+	/// [`Config::max_host_time`](crate::Config::max_host_time).
+	pub(crate) fn host_time_error(&self) -> Option<Error> {
+		match (self.host_time, self.max_host_time) {
+			(Some(host_time), Some(max_host_time)) if host_time > max_host_time =>
+				Some(Error::Other(format!(
+					"the host functions took {:?}, which exceeds the limit of {:?}",
+					host_time, max_host_time,
+				))),
+			_ => None,
+		}
+	}
+
+	/// Takes the host function calls recorded so far out of the host state.
+	pub(crate) fn take_host_calls(&mut self) -> Vec<HostCall> {
+		std::mem::take(&mut self.host_calls)
+	}
+
+	/// Whether the host function calls are being recorded.
+	pub(crate) fn is_recording_host_calls(&self) -> bool {
+		matches!(self.host_call_mode, HostCallMode::Record)
+	}
+
+	/// Records a host function call, if the host function calls are being recorded.
+	pub(crate) fn record_host_call(
+		&mut self,
+		name: &str,
+		args: Vec<Value>,
+		result: std::result::Result<Option<Value>, String>,
+	) {
+		if self.is_recording_host_calls() {
+			let memory_writes = std::mem::take(&mut self.memory_writes);
+			self.host_calls
+				.push(HostCall { name: name.to_owned(), args, result, memory_writes });
+		}
+	}
+
+	/// Forgets the memory writes recorded so far, which is done before recording a host function,
+	/// so that only its own writes are attributed to it.
+	pub(crate) fn discard_memory_writes(&mut self) {
+		self.memory_writes.clear();
+	}
+
+	/// Records a write into the linear memory made by the host function being executed, if the
+	/// host function calls are being recorded.
+	fn record_memory_write(&mut self, address: Pointer<u8>, data: &[u8]) {
+		if self.is_recording_host_calls() {
+			self.memory_writes.push((address.into(), data.to_vec()));
+		}
+	}
+
+	/// Returns the recorded call matching the given host function call if the calls are being
+	/// replayed, or `None` if the host function should be executed.
+	///
+	/// A call which doesn't match the next recorded one, i.e. the execution diverged from the
+	/// recording, results in an error.
+	pub(crate) fn replay_host_call(
+		&mut self,
+		name: &str,
+		args: &[Value],
+	) -> Option<std::result::Result<HostCall, String>> {
+		let recorded_calls = match &mut self.host_call_mode {
+			HostCallMode::Replay(recorded_calls) => recorded_calls,
+			HostCallMode::Execute | HostCallMode::Record => return None,
+		};
+
+		Some(match recorded_calls.next() {
+			Some(recorded) if recorded.name == name && recorded.args == args => Ok(recorded),
+			Some(recorded) => Err(format!(
+				"replay diverged: expected a call to {} with {:?}, got {} with {:?}",
+				recorded.name, recorded.args, name, args,
+			)),
+			None => Err(format!("replay diverged: unexpected call to {} past the recording", name)),
+		})
+	}
+
 	pub(crate) fn allocation_stats(&self) -> AllocationStats {
 		self.allocator.stats()
 	}
@@ -57,6 +454,10 @@ impl HostState {
 /// A `HostContext` implements `FunctionContext` for making host calls from a WasmEdge
 /// runtime. The `HostContext` exists only for the lifetime of the call and borrows state from
 /// a longer-living `HostState`.
+///
+/// The memory might be grown while the context is alive, which can move its backing store. For
+/// this reason every operation obtains a fresh view of the memory, and no view outlives the
+/// operation it was obtained for.
 pub(crate) struct HostContext<'a> {
 	memory: Memory,
 	table: Option<Table>,
@@ -68,6 +469,28 @@ impl<'a> HostContext<'a> {
 		HostContext { memory, table, host_state }
 	}
 
+	/// Updates the statistics returned by [`current_allocator_stats`] after an allocation or a
+	/// deallocation, if a host function is running.
+	fn update_allocator_stats(&self) {
+		CURRENT_ALLOCATOR_STATS.with(|current| {
+			if let Some(stats) = current.borrow_mut().as_mut() {
+				*stats = self.host_state.allocation_stats();
+			}
+		});
+	}
+
+	/// Reads a little endian `u32` at the given address, which doesn't have to be aligned.
+	#[allow(dead_code)]
+	pub fn read_u32(&self, address: Pointer<u8>) -> Result<u32> {
+		util::read_u32(util::memory_slice(&self.memory), address)
+	}
+
+	/// Reads a little endian `u64` at the given address, which doesn't have to be aligned.
+	#[allow(dead_code)]
+	pub fn read_u64(&self, address: Pointer<u8>) -> Result<u64> {
+		util::read_u64(util::memory_slice(&self.memory), address)
+	}
+
 	fn sandbox_store(&self) -> &sandbox::Store<Arc<FuncRef>> {
 		self.host_state
 			.sandbox_store
@@ -83,6 +506,42 @@ impl<'a> HostContext<'a> {
 			.as_mut()
 			.expect("sandbox store is only empty when temporarily borrowed")
 	}
+
+	/// Fills the freed allocation at `ptr` with [`AllocatorConfig::POISON`], if it was made
+	/// through the host functions.
+	fn poison_freed_memory(&mut self, ptr: Pointer<u8>) {
+		let size = match self.host_state.allocation_sizes.remove(&u32::from(ptr)) {
+			Some(size) => size,
+			None => return,
+		};
+		let memory = util::memory_slice_mut(&mut self.memory);
+		if let Some(range) = checked_range(u32::from(ptr) as usize, size as usize, memory.len()) {
+			memory[range].fill(AllocatorConfig::POISON);
+		}
+	}
+}
+
+/// Whether the allocation at `ptr` was already deallocated, i.e. whether the header the allocator
+/// keeps in front of it is marked as free.
+///
+/// Mirrors the header layout of [`FreeingBumpHeapAllocator`]: 8 bytes in front of the allocation,
+/// of which the occupied ones have the 33rd bit set. Pointers without a header in the memory are
+/// left to the allocator to reject.
+fn is_double_free(memory: &[u8], ptr: Pointer<u8>) -> bool {
+	const HEADER_SIZE: usize = 8;
+	const OCCUPIED: u64 = 0x00000001_00000000;
+
+	let header = usize::from(ptr)
+		.checked_sub(HEADER_SIZE)
+		.and_then(|header_ptr| memory.get(header_ptr..header_ptr + HEADER_SIZE));
+	match header {
+		Some(header) => {
+			let header =
+				u64::from_le_bytes(header.try_into().expect("the header has 8 bytes; qed"));
+			header & OCCUPIED == 0
+		},
+		None => false,
+	}
 }
 
 impl<'a> sp_wasm_interface::FunctionContext for HostContext<'a> {
@@ -97,39 +556,53 @@ impl<'a> sp_wasm_interface::FunctionContext for HostContext<'a> {
 
 	fn write_memory(&mut self, address: Pointer<u8>, data: &[u8]) -> sp_wasm_interface::Result<()> {
 		util::write_memory_from(util::memory_slice_mut(&mut self.memory), address, data)
-			.map_err(|e| e.to_string())
+			.map_err(|e| e.to_string())?;
+		self.host_state.record_memory_write(address, data);
+		Ok(())
 	}
 
 	fn allocate_memory(&mut self, size: WordSize) -> sp_wasm_interface::Result<Pointer<u8>> {
-		let memory_slice = unsafe {
-			std::slice::from_raw_parts_mut(
-				self.memory
-					.data_pointer_mut(0, 1)
-					.expect("failed to returns the mut data pointer to the Memory."),
-				(self.memory.size() * 64 * 1024) as usize,
-			)
-		};
-
-		self.host_state
+		let ptr = match self
+			.host_state
 			.allocator()
-			.allocate(memory_slice, size)
-			.map_err(|e| e.to_string())
+			.allocate(util::memory_slice_mut(&mut self.memory), size)
+		{
+			Ok(ptr) => ptr,
+			Err(e) => {
+				let e = e.to_string();
+				self.host_state.allocator_failure = Some(Box::new(AllocationPanic(e.clone())));
+				return Err(e)
+			},
+		};
+		if self.host_state.allocator_config.poison_freed_memory {
+			self.host_state.allocation_sizes.insert(ptr.into(), size);
+		}
+		self.host_state.last_allocation = Some((ptr.into(), size));
+		self.update_allocator_stats();
+		Ok(ptr)
 	}
 
 	fn deallocate_memory(&mut self, ptr: Pointer<u8>) -> sp_wasm_interface::Result<()> {
-		let memory_slice = unsafe {
-			std::slice::from_raw_parts_mut(
-				self.memory
-					.data_pointer_mut(0, 1)
-					.expect("failed to returns the mut data pointer to the Memory."),
-				(self.memory.size() * 64 * 1024) as usize,
-			)
-		};
+		// The allocator poisons itself on a double free, so it's detected beforehand.
+		if is_double_free(util::memory_slice(&self.memory), ptr) {
+			if self.host_state.ignore_double_free {
+				log::warn!(target: crate::LOG_TARGET, "Ignoring double free of {:?}", ptr);
+				return Ok(())
+			}
+			let e = String::from("Double free: the pointer doesn't refer to an allocated area");
+			self.host_state.allocator_failure = Some(Box::new(DoubleFreePanic(e.clone())));
+			return Err(e)
+		}
 
 		self.host_state
 			.allocator()
-			.deallocate(memory_slice, ptr)
-			.map_err(|e| e.to_string())
+			.deallocate(util::memory_slice_mut(&mut self.memory), ptr)
+			.map_err(|e| e.to_string())?;
+		if self.host_state.allocator_config.poison_freed_memory {
+			self.poison_freed_memory(ptr);
+		}
+		self.update_allocator_stats();
+		Ok(())
 	}
 
 	fn sandbox(&mut self) -> &mut dyn Sandbox {
@@ -137,6 +610,9 @@ impl<'a> sp_wasm_interface::FunctionContext for HostContext<'a> {
 	}
 
 	fn register_panic_error_message(&mut self, message: &str) {
+		if let Some(on_runtime_panic) = &self.host_state.on_runtime_panic {
+			on_runtime_panic(message);
+		}
 		self.host_state.panic_message = Some(message.to_owned());
 	}
 }
@@ -151,19 +627,21 @@ impl<'a> Sandbox for HostContext<'a> {
 	) -> sp_wasm_interface::Result<u32> {
 		let sandboxed_memory = self.sandbox_store().memory(memory_id).map_err(|e| e.to_string())?;
 
-		let len = buf_len as usize;
-
-		let buffer = match sandboxed_memory.read(Pointer::new(offset as u32), len) {
-			Err(_) => return Ok(sandbox_env::ERR_OUT_OF_BOUNDS),
-			Ok(buffer) => buffer,
+		// Copy straight into our memory, without an intermediate buffer.
+		let memory_slice = util::memory_slice_mut(&mut self.memory);
+		let range = match checked_range(buf_ptr.into(), buf_len as usize, memory_slice.len()) {
+			Some(range) => range,
+			None => return Ok(sandbox_env::ERR_OUT_OF_BOUNDS),
 		};
 
-		if util::write_memory_from(util::memory_slice_mut(&mut self.memory), buf_ptr, &buffer)
+		if sandboxed_memory
+			.read_into(Pointer::new(offset as u32), &mut memory_slice[range.clone()])
 			.is_err()
 		{
 			return Ok(sandbox_env::ERR_OUT_OF_BOUNDS)
 		}
 
+		self.host_state.record_memory_write(buf_ptr, &memory_slice[range]);
 		Ok(sandbox_env::ERR_OK)
 	}
 
@@ -176,14 +654,17 @@ impl<'a> Sandbox for HostContext<'a> {
 	) -> sp_wasm_interface::Result<u32> {
 		let sandboxed_memory = self.sandbox_store().memory(memory_id).map_err(|e| e.to_string())?;
 
-		let len = val_len as usize;
-
-		let buffer = match util::read_memory(util::memory_slice(&self.memory), val_ptr, len) {
-			Err(_) => return Ok(sandbox_env::ERR_OUT_OF_BOUNDS),
-			Ok(buffer) => buffer,
+		// Copy straight from our memory, without an intermediate buffer.
+		let memory_slice = util::memory_slice(&self.memory);
+		let range = match checked_range(val_ptr.into(), val_len as usize, memory_slice.len()) {
+			Some(range) => range,
+			None => return Ok(sandbox_env::ERR_OUT_OF_BOUNDS),
 		};
 
-		if sandboxed_memory.write_from(Pointer::new(offset as u32), &buffer).is_err() {
+		if sandboxed_memory
+			.write_from(Pointer::new(offset as u32), &memory_slice[range])
+			.is_err()
+		{
 			return Ok(sandbox_env::ERR_OUT_OF_BOUNDS)
 		}
 
@@ -191,11 +672,29 @@ impl<'a> Sandbox for HostContext<'a> {
 	}
 
 	fn memory_teardown(&mut self, memory_id: MemoryId) -> sp_wasm_interface::Result<()> {
-		self.sandbox_store_mut().memory_teardown(memory_id).map_err(|e| e.to_string())
+		self.sandbox_store_mut().memory_teardown(memory_id).map_err(|e| e.to_string())?;
+		self.host_state.sandbox_memories -= 1;
+		Ok(())
 	}
 
 	fn memory_new(&mut self, initial: u32, maximum: u32) -> sp_wasm_interface::Result<u32> {
-		self.sandbox_store_mut().new_memory(initial, maximum).map_err(|e| e.to_string())
+		if let Some(max_sandbox_memories) = self.host_state.max_sandbox_memories {
+			if self.host_state.sandbox_memories >= max_sandbox_memories {
+				trace!(
+					target: "sp-sandbox",
+					"memory_new refused: at most {} sandbox memories may exist at the same time",
+					max_sandbox_memories,
+				);
+				return Ok(sandbox_env::ERR_MODULE)
+			}
+		}
+
+		let memory_id = self
+			.sandbox_store_mut()
+			.new_memory(initial, maximum)
+			.map_err(|e| e.to_string())?;
+		self.host_state.sandbox_memories += 1;
+		Ok(memory_id)
 	}
 
 	fn invoke(
@@ -208,6 +707,25 @@ impl<'a> Sandbox for HostContext<'a> {
 		state: u32,
 	) -> sp_wasm_interface::Result<u32> {
 		trace!(target: "sp-sandbox", "invoke, instance_idx={}", instance_id);
+		self.host_state.sandbox_error = None;
+		self.host_state.sandbox_executor_error = None;
+		self.host_state.sandbox_supervisor_failed = false;
+
+		// Check the number of arguments before decoding them, so that a huge claimed count isn't
+		// even attempted.
+		if let Some(max_sandbox_args) = self.host_state.max_sandbox_args {
+			let Compact(args_count) = Compact::<u32>::decode(&mut &args[..])
+				.map_err(|_| "Can't decode serialized arguments for the invocation")?;
+			if args_count > max_sandbox_args {
+				let error = SandboxError::Other(format!(
+					"the invocation passes {} arguments, but at most {} are allowed",
+					args_count, max_sandbox_args,
+				));
+				trace!(target: "sp-sandbox", "invoke refused: {:?}", error);
+				self.host_state.sandbox_error = Some(error);
+				return Ok(sandbox_env::ERR_EXECUTION)
+			}
+		}
 
 		// Deserialize arguments and convert them into wasmi types.
 		let args = Vec::<sp_wasm_interface::Value>::decode(&mut args)
@@ -220,12 +738,23 @@ impl<'a> Sandbox for HostContext<'a> {
 		let dispatch_thunk =
 			self.sandbox_store().dispatch_thunk(instance_id).map_err(|e| e.to_string())?;
 
+		if let Some(max_sandbox_depth) = self.host_state.max_sandbox_depth {
+			if self.host_state.sandbox_depth >= max_sandbox_depth {
+				trace!(target: "sp-sandbox", "invoke refused, the depth limit is reached");
+				self.host_state.sandbox_error =
+					Some(SandboxError::DepthExceeded(max_sandbox_depth));
+				return Ok(sandbox_env::ERR_EXECUTION)
+			}
+		}
+
+		self.host_state.sandbox_depth += 1;
 		let result = instance.invoke(
 			export_name,
 			&args,
 			state,
 			&mut SandboxContext { host_context: self, dispatch_thunk },
 		);
+		self.host_state.sandbox_depth -= 1;
 
 		match result {
 			Ok(None) => Ok(sandbox_env::ERR_OK),
@@ -240,7 +769,17 @@ impl<'a> Sandbox for HostContext<'a> {
 					Ok(sandbox_env::ERR_OK)
 				})
 			},
-			Err(_) => Ok(sandbox_env::ERR_EXECUTION),
+			Err(error) => {
+				let error = match self.host_state.sandbox_executor_error.take() {
+					Some(message) => SandboxError::ExecutorUnavailable(message),
+					None if self.host_state.sandbox_supervisor_failed =>
+						SandboxError::HostFunction(error.to_string()),
+					None => SandboxError::from_invoke_error(error),
+				};
+				trace!(target: "sp-sandbox", "invoke failed: {:?}", error);
+				self.host_state.sandbox_error = Some(error);
+				Ok(sandbox_env::ERR_EXECUTION)
+			},
 		}
 	}
 
@@ -327,6 +866,27 @@ struct SandboxContext<'a, 'b> {
 	dispatch_thunk: Arc<FuncRef>,
 }
 
+impl<'a, 'b> SandboxContext<'a, 'b> {
+	/// Whether the supervisor function returned a value, given the packed pointer and length of
+	/// its serialized result returned by the dispatch thunk.
+	///
+	/// The sandbox backends turn the result into a trap themselves, this only tells the
+	/// [`SandboxError::HostFunction`] apart from the other failures.
+	fn supervisor_succeeded(&self, result: i64) -> bool {
+		// The pointer is in the upper half, unlike with `pack_ptr_and_len`.
+		let (ptr, len) = ((result as u64 >> 32) as usize, (result as u64 & 0xFFFFFFFF) as usize);
+		let memory = util::memory_slice(&self.host_context.memory);
+		let serialized = match checked_range(ptr, len, memory.len()) {
+			Some(range) => &memory[range],
+			None => return false,
+		};
+		matches!(
+			std::result::Result::<ReturnValue, HostError>::decode(&mut &serialized[..]),
+			Ok(Ok(_))
+		)
+	}
+}
+
 impl<'a, 'b> sandbox::SandboxContext for SandboxContext<'a, 'b> {
 	fn invoke(
 		&mut self,
@@ -335,9 +895,30 @@ impl<'a, 'b> sandbox::SandboxContext for SandboxContext<'a, 'b> {
 		state: u32,
 		func_idx: SupervisorFuncIndex,
 	) -> Result<i64> {
-		let mut executor = Executor::new(None, None).map_err(|e| {
-			WasmError::Other(format!("fail to create a WasmEdge Executor context: {}", e))
-		})?;
+		let host_state = &mut self.host_context.host_state;
+		let mut executor = match host_state.sandbox_executors.pop() {
+			Some(executor) => executor,
+			None => match Executor::new(None, None) {
+				Ok(executor) => {
+					let created = SANDBOX_EXECUTORS_CREATED.with(|created| {
+						created.set(created.get() + 1);
+						created.get()
+					});
+					trace!(
+						target: "sp-sandbox",
+						"Created a WasmEdge Executor for the sandbox, {} on this thread so far",
+						created,
+					);
+					executor
+				},
+				Err(e) => {
+					let message =
+						format!("cannot create a WasmEdge Executor for the sandbox: {}", e);
+					host_state.sandbox_executor_error = Some(message.clone());
+					return Err(WasmError::Other(message).into())
+				},
+			},
+		};
 
 		let result = self.dispatch_thunk.call(
 			&mut executor,
@@ -349,9 +930,20 @@ impl<'a, 'b> sandbox::SandboxContext for SandboxContext<'a, 'b> {
 			],
 		);
 
+		self.host_context.host_state.sandbox_executors.push(executor);
+
 		match result {
-			Ok(result) => Ok(result[0].to_i64()),
-			Err(err) => Err(err.to_string().into()),
+			Ok(result) => {
+				let result = result[0].to_i64();
+				if !self.supervisor_succeeded(result) {
+					self.host_context.host_state.sandbox_supervisor_failed = true;
+				}
+				Ok(result)
+			},
+			Err(err) => {
+				self.host_context.host_state.sandbox_supervisor_failed = true;
+				Err(err.to_string().into())
+			},
 		}
 	}
 
@@ -1,18 +1,21 @@
 use crate::{
-	host::{HostContext, HostState},
+	host::{AllocatorStatsScope, HostContext, HostState},
 	instance_wrapper::InstanceWrapper,
-	util,
+	runtime::{HostCallTracer, TraceEvent},
+	util, LOG_TARGET,
 };
 use sc_executor_common::error::WasmError;
-use sp_wasm_interface::Function;
+use sp_runtime_interface::{pack_ptr_and_len, unpack_ptr_and_len};
+use sp_wasm_interface::{Function, FunctionContext, Pointer};
 use std::{
-	collections::HashMap,
+	collections::{BTreeMap, HashMap},
 	fmt,
 	sync::{Arc, Mutex},
+	time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use wasmedge_sdk::{
 	error::HostFuncError, host_function, Caller, ExternalInstanceType, FuncType,
-	ImportObjectBuilder, Module, WasmValue,
+	ImportObjectBuilder, Module, ValType, WasmValue,
 };
 
 lazy_static::lazy_static! {
@@ -30,24 +33,256 @@ struct HostWrapper {
 	host_state: *mut Option<HostState>,
 	returns_len: usize,
 	host_func: &'static dyn Function,
+	host_call_tracer: Option<HostCallTracer>,
 }
 unsafe impl Send for HostWrapper {}
 
+/// The data set to the context of the environment host function.
+struct EnvironmentWrapper {
+	host_state: *mut Option<HostState>,
+	environment: Arc<HashMap<String, String>>,
+}
+unsafe impl Send for EnvironmentWrapper {}
+
+/// The data set to the context of the deadline check.
+struct DeadlineWrapper {
+	host_state: *mut Option<HostState>,
+}
+unsafe impl Send for DeadlineWrapper {}
+
+/// The name of the host function giving the runtime access to the
+/// [`Config::environment`](crate::Config::environment).
+const ENVIRONMENT_FUNC_NAME: &str = "ext_wasmedge_environment_get_version_1";
+
+/// The name of the host function returning the current time, see
+/// [`Config::provide_host_time`](crate::Config::provide_host_time).
+const HOST_TIME_FUNC_NAME: &str = "ext_wasmedge_host_time_version_1";
+
+/// The name of the module the host functions are imported from.
+pub(crate) const IMPORT_MODULE_NAME: &str = "env";
+
+/// The name of the module the deadline checks injected for the
+/// [`Semantics::interruptible`](crate::Semantics::interruptible) are imported from.
+pub(crate) const DEADLINE_MODULE_NAME: &str = "wasmedge_deadline";
+
+/// The name of the deadline check, which is the one the gas metering of `wasm-instrument` uses.
+const DEADLINE_FUNC_NAME: &str = "gas";
+
+/// The host functions available to a runtime, keyed by their names.
+pub(crate) type HostFunctionRegistry = HashMap<&'static str, &'static dyn Function>;
+
+/// Builds the registry of the given host functions. If several host functions have the same
+/// name, the first one is used.
+pub(crate) fn host_function_registry(
+	host_functions: &[&'static dyn Function],
+) -> HostFunctionRegistry {
+	let mut registry = HashMap::with_capacity(host_functions.len());
+	for host_func in host_functions {
+		registry.entry(host_func.name()).or_insert(*host_func);
+	}
+	registry
+}
+
+/// Describes how the imports of a module are resolved against the host functions.
+///
+/// All the names are sorted. Imports from other modules than `env` are named `module:name`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+	/// The imports provided by the host.
+	pub satisfied: Vec<String>,
+	/// The function imports the host doesn't provide. These are replaced with stubs trapping
+	/// when called if
+	/// [`Config::allow_missing_func_imports`](crate::Config::allow_missing_func_imports) is set,
+	/// otherwise the runtime can't be instantiated.
+	pub stubbed: Vec<String>,
+	/// The imports the host can't provide, because of a different signature or kind. A runtime
+	/// with any of these can't be instantiated.
+	pub mismatched: Vec<String>,
+}
+
+impl ImportReport {
+	fn sort(&mut self) {
+		self.satisfied.sort();
+		self.stubbed.sort();
+		self.mismatched.sort();
+	}
+}
+
+/// How a function import from the `env` module is resolved.
+enum FuncImport {
+	Host(&'static dyn Function),
+	Environment,
+	HostTime,
+	DeadlineCheck,
+	Mismatched,
+	Missing,
+}
+
+/// Resolves the function import with the given module, name and type against the host
+/// functions.
+fn resolve_func_import(
+	module_name: &str,
+	name: &str,
+	func_ty: &FuncType,
+	host_functions: &HostFunctionRegistry,
+	provide_host_time: bool,
+	interruptible: bool,
+) -> FuncImport {
+	if is_deadline_module(module_name, interruptible) {
+		if name != DEADLINE_FUNC_NAME {
+			FuncImport::Missing
+		} else if *func_ty == FuncType::new(Some(vec![ValType::I64]), Some(vec![])) {
+			FuncImport::DeadlineCheck
+		} else {
+			FuncImport::Mismatched
+		}
+	} else if let Some(host_func) = host_functions.get(name) {
+		let signature = host_func.signature();
+		let params = signature.args.iter().cloned().map(util::into_wasmedge_val_type);
+		let results = signature.return_value.iter().cloned().map(util::into_wasmedge_val_type);
+
+		// Check that the signature of the host function is the same as the wasm import
+		if *func_ty == FuncType::new(Some(params.collect()), Some(results.collect())) {
+			FuncImport::Host(*host_func)
+		} else {
+			FuncImport::Mismatched
+		}
+	} else if name == ENVIRONMENT_FUNC_NAME {
+		if *func_ty == FuncType::new(Some(vec![ValType::I64]), Some(vec![ValType::I64])) {
+			FuncImport::Environment
+		} else {
+			FuncImport::Mismatched
+		}
+	} else if name == HOST_TIME_FUNC_NAME && provide_host_time {
+		if *func_ty == FuncType::new(Some(vec![]), Some(vec![ValType::I64])) {
+			FuncImport::HostTime
+		} else {
+			FuncImport::Mismatched
+		}
+	} else {
+		FuncImport::Missing
+	}
+}
+
+/// Whether the imports of the given module are the deadline checks, which are only provided to
+/// the [`Semantics::interruptible`](crate::Semantics::interruptible) runtimes.
+fn is_deadline_module(module_name: &str, interruptible: bool) -> bool {
+	interruptible && module_name == DEADLINE_MODULE_NAME
+}
+
+/// The name of an import in the [`ImportReport`].
+fn report_name(module_name: &str, name: &str) -> String {
+	if module_name == IMPORT_MODULE_NAME {
+		name.to_string()
+	} else {
+		format!("{}:{}", module_name, name)
+	}
+}
+
+/// The import objects the host functions are added to, one for every module they're imported
+/// from, along with the number of functions each of them holds.
+#[derive(Default)]
+struct ImportObjects(BTreeMap<String, (ImportObjectBuilder, usize)>);
+
+impl ImportObjects {
+	/// Adds a function to the import object of the given module with `add`.
+	fn add_func(
+		&mut self,
+		module_name: &str,
+		add: impl FnOnce(ImportObjectBuilder) -> Result<ImportObjectBuilder, WasmError>,
+	) -> Result<(), WasmError> {
+		let (import, func_count) = self
+			.0
+			.entry(module_name.to_string())
+			.or_insert_with(|| (ImportObjectBuilder::new(), 0));
+		*import = add(std::mem::replace(import, ImportObjectBuilder::new()))?;
+		*func_count += 1;
+		Ok(())
+	}
+}
+
+/// Describes how the imports of a module would be resolved by [`prepare_imports`], without
+/// registering anything.
+pub(crate) fn import_report(
+	module: &Module,
+	host_functions: &HostFunctionRegistry,
+	accept_any_import_module: bool,
+	provide_host_time: bool,
+	interruptible: bool,
+) -> ImportReport {
+	let mut report = ImportReport::default();
+
+	for import_ty in module.imports() {
+		let name = import_ty.name();
+		let module_name = import_ty.module_name();
+		let report_name = report_name(&module_name, &name);
+		if module_name != IMPORT_MODULE_NAME &&
+			!is_deadline_module(&module_name, interruptible) &&
+			!accept_any_import_module
+		{
+			report.mismatched.push(report_name);
+			continue
+		}
+
+		match import_ty.ty() {
+			Ok(ExternalInstanceType::Func(func_ty)) => match resolve_func_import(
+				&module_name,
+				&name,
+				&func_ty,
+				host_functions,
+				provide_host_time,
+				interruptible,
+			) {
+				FuncImport::Host(_) |
+				FuncImport::Environment |
+				FuncImport::HostTime |
+				FuncImport::DeadlineCheck => report.satisfied.push(report_name),
+				FuncImport::Mismatched => report.mismatched.push(report_name),
+				FuncImport::Missing => report.stubbed.push(report_name),
+			},
+			_ => report.mismatched.push(report_name),
+		}
+	}
+
+	report.sort();
+	report
+}
+
 /// Goes over all imports of a module and register host functions.
-/// Returns an error if there are imports that cannot be satisfied.
+/// Returns an error if there are imports that cannot be satisfied, otherwise a report of which
+/// imports were satisfied and which were stubbed.
+///
+/// The imports from `extra_module` are left alone, they are provided by an import object the
+/// caller registers separately. Function imports from other modules than `env` are only resolved
+/// if `accept_any_import_module` is set, except for the deadline checks of the `interruptible`
+/// runtimes.
 pub(crate) fn prepare_imports(
 	instance_wrapper: &mut InstanceWrapper,
 	module: &Module,
-	host_functions: &Vec<&'static dyn Function>,
+	host_functions: &HostFunctionRegistry,
 	allow_missing_func_imports: bool,
-) -> Result<(), WasmError> {
+	accept_any_import_module: bool,
+	provide_host_time: bool,
+	interruptible: bool,
+	host_call_tracer: Option<&HostCallTracer>,
+	environment: &Arc<HashMap<String, String>>,
+	extra_module: Option<&str>,
+) -> Result<ImportReport, WasmError> {
+	let mut report = ImportReport::default();
 	let mut pending_func_imports = HashMap::new();
 	let mut missing_func_imports = HashMap::new();
 
 	for import_ty in module.imports() {
 		let name = import_ty.name();
 
-		if import_ty.module_name() != "env" {
+		if Some(import_ty.module_name().as_ref()) == extra_module {
+			continue
+		}
+
+		if import_ty.module_name() != IMPORT_MODULE_NAME &&
+			!is_deadline_module(&import_ty.module_name(), interruptible) &&
+			!accept_any_import_module
+		{
 			return Err(WasmError::Other(format!(
 				"host doesn't provide any imports from non-env module: {}:{}",
 				import_ty.module_name(),
@@ -57,7 +292,10 @@ pub(crate) fn prepare_imports(
 
 		match import_ty.ty() {
 			Ok(ExternalInstanceType::Func(func_ty)) => {
-				pending_func_imports.insert(name.into_owned(), (import_ty, func_ty));
+				pending_func_imports.insert(
+					(import_ty.module_name().into_owned(), name.into_owned()),
+					(import_ty, func_ty),
+				);
 			},
 			_ =>
 				return Err(WasmError::Other(format!(
@@ -68,27 +306,70 @@ pub(crate) fn prepare_imports(
 		};
 	}
 
-	let mut import = ImportObjectBuilder::new();
+	let mut imports = ImportObjects::default();
 
-	for (name, (import_ty, func_ty)) in pending_func_imports {
-		if let Some(host_func) = host_functions.iter().find(|host_func| host_func.name() == name) {
-			let host_func: &'static dyn Function = *host_func;
-
-			let signature = host_func.signature();
-			let params = signature.args.iter().cloned().map(util::into_wasmedge_val_type);
-			let results = signature.return_value.iter().cloned().map(util::into_wasmedge_val_type);
+	for ((module_name, name), (import_ty, func_ty)) in pending_func_imports {
+		let resolved = resolve_func_import(
+			&module_name,
+			&name,
+			&func_ty,
+			host_functions,
+			provide_host_time,
+			interruptible,
+		);
+		let host_func = match resolved {
+			FuncImport::Host(host_func) => Some(host_func),
+			FuncImport::Environment => None,
+			FuncImport::HostTime => {
+				imports.add_func(&module_name, |import| {
+					import.with_func_by_type::<!>(&name, func_ty, host_time, None).map_err(|e| {
+						WasmError::Other(format!(
+							"failed to register host function '{}' into WASM: {}",
+							name, e
+						))
+					})
+				})?;
+				report.satisfied.push(report_name(&module_name, &name));
+				continue
+			},
+			FuncImport::DeadlineCheck => {
+				let mut deadline_wrapper =
+					Box::new(DeadlineWrapper { host_state: instance_wrapper.host_state_ptr() });
 
-			let returns_len = results.len();
+				imports.add_func(&module_name, |import| {
+					import
+						.with_func_by_type(
+							&name,
+							func_ty,
+							deadline_check,
+							Some(deadline_wrapper.as_mut()),
+						)
+						.map_err(|e| {
+							WasmError::Other(format!(
+								"failed to register host function '{}' into WASM: {}",
+								name, e
+							))
+						})
+				})?;
 
-			// Check that the signature of the host function is the same as the wasm import
-			let func_ty_check = FuncType::new(Some(params.collect()), Some(results.collect()));
-			if func_ty != func_ty_check {
+				instance_wrapper.keep_host_func_data(deadline_wrapper);
+				report.satisfied.push(report_name(&module_name, &name));
+				continue
+			},
+			FuncImport::Mismatched =>
 				return Err(WasmError::Other(format!(
 					"signature mismatch for: {}:{}",
 					import_ty.module_name(),
 					name,
-				)))
-			}
+				))),
+			FuncImport::Missing => {
+				missing_func_imports.insert((module_name, name), (import_ty, func_ty));
+				continue
+			},
+		};
+
+		if let Some(host_func) = host_func {
+			let returns_len = host_func.signature().return_value.iter().count();
 
 			#[host_function]
 			fn function_static(
@@ -101,45 +382,145 @@ pub(crate) fn prepare_imports(
 				let host_state = unsafe { &mut *(host_wrapper.host_state) };
 				let host_state = host_state.as_mut().expect("host state is always set; qed");
 
+				// Only values of the types supported by Substrate can be passed in, since the
+				// signature of this function is created from a `dyn Function` signature. Still,
+				// report a mismatch as an error rather than bringing down the node.
+				let params = inputs
+					.iter()
+					.cloned()
+					.map(util::try_from_wasmedge_value)
+					.collect::<Result<Vec<_>, _>>()
+					.map_err(|e| {
+						log::error!(
+							target: LOG_TARGET,
+							"Host function {} called with invalid arguments: {}",
+							host_wrapper.host_func.name(),
+							e,
+						);
+						HostFuncError::User(HostFuncErrorWasmEdge::Others as u32)
+					})?;
+
+				if let Some(host_call_tracer) = &host_wrapper.host_call_tracer {
+					host_call_tracer(host_wrapper.host_func.name(), &params);
+				}
+
+				let replayed = host_state.replay_host_call(host_wrapper.host_func.name(), &params);
+				if let Some(replayed) = replayed {
+					// The side effects of the host function on the memory are replayed as well.
+					let replayed = replayed.and_then(|recorded| {
+						let mut memory =
+							instance.memory("memory").expect("memory is always set; qed");
+						for (address, data) in &recorded.memory_writes {
+							util::write_memory_from(
+								util::memory_slice_mut(&mut memory),
+								Pointer::new(*address),
+								data,
+							)
+							.map_err(|e| format!("cannot replay a memory write: {}", e))?;
+						}
+						recorded.result
+					});
+					return match replayed {
+						Ok(ret_val) =>
+							Ok(ret_val.into_iter().map(util::into_wasmedge_value).collect()),
+						Err(e) => {
+							log::error!(
+								target: LOG_TARGET,
+								"Replayed host function {} failed: {}",
+								host_wrapper.host_func.name(),
+								e,
+							);
+							Err(HostFuncError::User(HostFuncErrorWasmEdge::Others as u32))
+						},
+					}
+				}
+
+				let recorded_args = host_state.is_recording_host_calls().then(|| {
+					host_state.discard_memory_writes();
+					params.clone()
+				});
+				let started = host_state.is_measuring_host_time().then(Instant::now);
+				let trace_sink = host_state.trace_sink().cloned();
+				if let Some(trace_sink) = &trace_sink {
+					trace_sink(TraceEvent::HostEnter {
+						name: host_wrapper.host_func.name(),
+						time: Instant::now(),
+					});
+				}
+
+				host_state.take_allocator_failure();
+				let _allocator_stats = AllocatorStatsScope::enter(host_state.allocation_stats());
 				let mut host_context = HostContext::new(
 					instance.memory("memory").expect("memory is always set; qed"),
 					instance.table("__indirect_function_table"),
 					host_state,
 				);
+
 				let unwind_result = {
-					// `from_wasmedge_val` panics if it encounters a value that doesn't fit into the
-					// values available in substrate.
-					//
-					// This, however, cannot happen since the signature of this function is created
-					// from a `dyn Function` signature of which cannot have a non substrate value by
-					// definition.
-					let mut params = inputs.iter().cloned().map(util::from_wasmedge_value);
+					let mut params = params.into_iter();
 
 					std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
 						host_wrapper.host_func.execute(&mut host_context, &mut params)
 					}))
 				};
+				if let Some(trace_sink) = trace_sink {
+					trace_sink(TraceEvent::HostExit {
+						name: host_wrapper.host_func.name(),
+						time: Instant::now(),
+					});
+				}
+				// The host functions only see the allocator failing as an `Err`, which they either
+				// return or panic on with a message of their own, e.g. in sp-io's `malloc`. Either
+				// way the typed payload of the failure is raised in its place.
+				let allocator_failure = host_state.take_allocator_failure();
 				let execution_result = match unwind_result {
 					Ok(execution_result) => execution_result,
-					Err(e) => {
-						let message = e
-							.downcast_ref::<String>()
-							.ok_or(HostFuncError::User(HostFuncErrorWasmEdge::Others as u32))?
-							.as_str();
-						if message.contains("Spawned task") {
-							return Err(HostFuncError::User(
-								HostFuncErrorWasmEdge::SpawnedTaskErr as u32,
-							))
+					Err(payload) => {
+						let payload = allocator_failure.unwrap_or_else(|| {
+							typed_panic_payload(host_wrapper.host_func.name(), payload)
+						});
+						let (code, message) = classify_panic(&*payload);
+						if let Some(args) = recorded_args {
+							host_state.record_host_call(
+								host_wrapper.host_func.name(),
+								args,
+								Err(format!(
+									"panicked: {}",
+									message.as_deref().unwrap_or("unknown")
+								)),
+							);
 						}
-						if message.contains("Failed to allocate memory") {
-							return Err(HostFuncError::User(
-								HostFuncErrorWasmEdge::AllocateMemoryErr as u32,
-							))
+
+						if let (HostFuncErrorWasmEdge::DoubleFree, Some(message)) =
+							(&code, &message)
+						{
+							log::error!(target: LOG_TARGET, "{}", message);
 						}
-						return Err(HostFuncError::User(HostFuncErrorWasmEdge::Others as u32))
+						return Err(HostFuncError::User(code as u32))
 					},
 				};
 
+				if let Some(started) = started {
+					if !host_state.add_host_time(started.elapsed()) {
+						log::debug!(
+							target: LOG_TARGET,
+							"Host function {} exceeded the time limit of the host functions",
+							host_wrapper.host_func.name(),
+						);
+						return Err(HostFuncError::User(
+							HostFuncErrorWasmEdge::HostTimeExceeded as u32,
+						))
+					}
+				}
+
+				if let Some(args) = recorded_args {
+					host_state.record_host_call(
+						host_wrapper.host_func.name(),
+						args,
+						execution_result.clone(),
+					);
+				}
+
 				match execution_result {
 					Ok(Some(ret_val)) => {
 						debug_assert!(
@@ -157,35 +538,74 @@ pub(crate) fn prepare_imports(
 						);
 						Ok(vec![])
 					},
-					Err(_) => Err(HostFuncError::User(HostFuncErrorWasmEdge::Others as u32)),
+					Err(_) => match allocator_failure.map(|payload| classify_panic(&*payload)) {
+						Some((code, message)) => {
+							if let (HostFuncErrorWasmEdge::DoubleFree, Some(message)) =
+								(&code, &message)
+							{
+								log::error!(target: LOG_TARGET, "{}", message);
+							}
+							Err(HostFuncError::User(code as u32))
+						},
+						None => Err(HostFuncError::User(HostFuncErrorWasmEdge::Others as u32)),
+					},
 				}
 			}
 
 			let host_state = instance_wrapper.host_state_ptr();
 
-			let mut host_wrapper = Box::new(HostWrapper { host_state, returns_len, host_func });
+			let mut host_wrapper = Box::new(HostWrapper {
+				host_state,
+				returns_len,
+				host_func,
+				host_call_tracer: host_call_tracer.cloned(),
+			});
 
-			import = import
-				.with_func_by_type(&name, func_ty, function_static, Some(host_wrapper.as_mut()))
-				.map_err(|e| {
-					WasmError::Other(format!(
-						"failed to register host function '{}' into WASM: {}",
-						name, e
-					))
-				})?;
+			imports.add_func(&module_name, |import| {
+				import
+					.with_func_by_type(&name, func_ty, function_static, Some(host_wrapper.as_mut()))
+					.map_err(|e| {
+						WasmError::Other(format!(
+							"failed to register host function '{}' into WASM: {}",
+							name, e
+						))
+					})
+			})?;
 
 			HOST_FUNC_DATA
 				.lock()
 				.map_err(|_| WasmError::Other("failed to lock the HOST_FUNC_DATA".to_string()))?
 				.push(host_wrapper);
 		} else {
-			missing_func_imports.insert(name, (import_ty, func_ty));
+			let mut environment_wrapper = Box::new(EnvironmentWrapper {
+				host_state: instance_wrapper.host_state_ptr(),
+				environment: environment.clone(),
+			});
+
+			imports.add_func(&module_name, |import| {
+				import
+					.with_func_by_type(
+						&name,
+						func_ty,
+						environment_get,
+						Some(environment_wrapper.as_mut()),
+					)
+					.map_err(|e| {
+						WasmError::Other(format!(
+							"failed to register host function '{}' into WASM: {}",
+							name, e
+						))
+					})
+			})?;
+
+			instance_wrapper.keep_host_func_data(environment_wrapper);
 		}
+		report.satisfied.push(report_name(&module_name, &name));
 	}
 
 	if !missing_func_imports.is_empty() {
 		if allow_missing_func_imports {
-			for (name, (_, _)) in missing_func_imports {
+			for ((module_name, name), (_, func_ty)) in missing_func_imports {
 				#[host_function]
 				fn function_static(
 					_: Caller,
@@ -194,15 +614,24 @@ pub(crate) fn prepare_imports(
 					Err(HostFuncError::User(HostFuncErrorWasmEdge::MissingHostFunc as u32))
 				}
 
-				import =
-					import.with_func::<(), (), !>(&name, function_static, None).map_err(|e| {
-						WasmError::Other(format!("fail to create a blank Function instance: {}", e))
-					})?;
+				// The stub has the signature of the import, so that calling it always results in
+				// the `MissingHostFunc` trap rather than in a failed type check.
+				imports.add_func(&module_name, |import| {
+					import.with_func_by_type::<!>(&name, func_ty, function_static, None).map_err(
+						|e| {
+							WasmError::Other(format!(
+								"fail to create a blank Function instance: {}",
+								e
+							))
+						},
+					)
+				})?;
+				report.stubbed.push(report_name(&module_name, &name));
 			}
 		} else {
 			let mut names = Vec::new();
-			for (name, (import_ty, _)) in missing_func_imports {
-				names.push(format!("'{}:{}'", import_ty.module_name(), name));
+			for ((module_name, name), _) in missing_func_imports {
+				names.push(format!("'{}:{}'", module_name, name));
 			}
 			let names = names.join(", ");
 			return Err(WasmError::Other(format!(
@@ -212,15 +641,94 @@ pub(crate) fn prepare_imports(
 		}
 	}
 
-	let import_obj = import
-		.build("env")
-		.map_err(|e| WasmError::Other(format!("fail to create a WasmEdge import object: {}", e)))?;
+	// The `env` module is always registered, even if the runtime imports nothing from it.
+	imports
+		.0
+		.entry(IMPORT_MODULE_NAME.to_string())
+		.or_insert_with(|| (ImportObjectBuilder::new(), 0));
+	for (module_name, (import, func_count)) in imports.0 {
+		let import_obj = import.build(&module_name).map_err(|e| {
+			WasmError::Other(format!("fail to create a WasmEdge import object: {}", e))
+		})?;
+
+		instance_wrapper.register_import(import_obj, &module_name, func_count)?;
+	}
+
+	report.sort();
+	Ok(report)
+}
+
+/// Looks up the value of the key passed by the runtime in the environment, see
+/// [`Config::environment`](crate::Config::environment).
+#[host_function]
+fn environment_get(
+	caller: Caller,
+	inputs: Vec<WasmValue>,
+	environment_wrapper: &mut EnvironmentWrapper,
+) -> std::result::Result<Vec<WasmValue>, HostFuncError> {
+	let instance = caller.instance().expect("wasm instance is always set; qed");
 
-	instance_wrapper
-		.register_import(import_obj)
-		.map_err(|e| WasmError::Other(format!("failed to register import object: {}", e)))?;
+	let host_state = unsafe { &mut *(environment_wrapper.host_state) };
+	let host_state = host_state.as_mut().expect("host state is always set; qed");
 
-	Ok(())
+	let mut host_context = HostContext::new(
+		instance.memory("memory").expect("memory is always set; qed"),
+		instance.table("__indirect_function_table"),
+		host_state,
+	);
+
+	let (key_ptr, key_len) = unpack_ptr_and_len(inputs[0].to_i64() as u64);
+	let key = host_context
+		.read_memory(Pointer::new(key_ptr), key_len)
+		.map_err(|_| HostFuncError::User(HostFuncErrorWasmEdge::Others as u32))?;
+
+	let value = match std::str::from_utf8(&key)
+		.ok()
+		.and_then(|key| environment_wrapper.environment.get(key))
+	{
+		Some(value) => value,
+		None => return Ok(vec![WasmValue::from_i64(-1)]),
+	};
+
+	let value_len = value.len() as u32;
+	let value_ptr = host_context
+		.allocate_memory(value_len)
+		.map_err(|_| HostFuncError::User(HostFuncErrorWasmEdge::AllocateMemoryErr as u32))?;
+	host_context
+		.write_memory(value_ptr, value.as_bytes())
+		.map_err(|_| HostFuncError::User(HostFuncErrorWasmEdge::Others as u32))?;
+
+	Ok(vec![WasmValue::from_i64(pack_ptr_and_len(value_ptr.into(), value_len) as i64)])
+}
+
+/// Returns the current Unix time in milliseconds, see
+/// [`Config::provide_host_time`](crate::Config::provide_host_time).
+#[host_function]
+fn host_time(_: Caller, _: Vec<WasmValue>) -> std::result::Result<Vec<WasmValue>, HostFuncError> {
+	// A clock set before the Unix epoch is reported as the epoch itself.
+	let millis = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |since_epoch| since_epoch.as_millis() as i64);
+	Ok(vec![WasmValue::from_i64(millis)])
+}
+
+/// Aborts the call once its deadline passed, see
+/// [`Semantics::interruptible`](crate::Semantics::interruptible).
+///
+/// The instrumentation calls this at the start of every metered block, passing the gas the block
+/// costs, which is ignored.
+#[host_function]
+fn deadline_check(
+	_: Caller,
+	_: Vec<WasmValue>,
+	deadline_wrapper: &mut DeadlineWrapper,
+) -> std::result::Result<Vec<WasmValue>, HostFuncError> {
+	let host_state = unsafe { &*(deadline_wrapper.host_state) };
+	match host_state {
+		Some(host_state) if host_state.deadline_passed() =>
+			Err(HostFuncError::User(HostFuncErrorWasmEdge::DeadlineExceeded as u32)),
+		_ => Ok(vec![]),
+	}
 }
 
 pub enum HostFuncErrorWasmEdge {
@@ -228,6 +736,116 @@ pub enum HostFuncErrorWasmEdge {
 	AllocateMemoryErr = 2,
 	SpawnedTaskErr = 3,
 	Others = 4,
+	DoubleFree = 5,
+	HostTimeExceeded = 6,
+	DeadlineExceeded = 7,
+}
+
+/// The payload of a panic of a host function, telling which [`HostFuncErrorWasmEdge`] the call
+/// into the runtime fails with.
+///
+/// The host functions are executed under `catch_unwind`, and the payloads of their panics are
+/// downcast to the types implementing this trait, so that the error doesn't depend on the
+/// wording of the panic message. The failures which are only reported with a message, or not at
+/// all, are raised as these in place of the original payload: the failures of the allocator,
+/// which the host functions only see as an `Err`, and the panics of joining a spawned task.
+pub trait HostFuncPanic: std::any::Any + Send + fmt::Display {
+	/// The error the call into the runtime fails with.
+	fn code(&self) -> HostFuncErrorWasmEdge;
+}
+
+/// The panic payload of a host function which failed to allocate memory for the runtime.
+#[derive(Debug)]
+pub struct AllocationPanic(pub String);
+
+impl HostFuncPanic for AllocationPanic {
+	fn code(&self) -> HostFuncErrorWasmEdge {
+		HostFuncErrorWasmEdge::AllocateMemoryErr
+	}
+}
+
+impl fmt::Display for AllocationPanic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Failed to allocate memory: {}", self.0)
+	}
+}
+
+/// The panic payload of a host function which deallocated memory that isn't allocated, see
+/// [`Config::ignore_double_free`](crate::Config::ignore_double_free).
+#[derive(Debug)]
+pub struct DoubleFreePanic(pub String);
+
+impl HostFuncPanic for DoubleFreePanic {
+	fn code(&self) -> HostFuncErrorWasmEdge {
+		HostFuncErrorWasmEdge::DoubleFree
+	}
+}
+
+impl fmt::Display for DoubleFreePanic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Failed to deallocate memory: {}", self.0)
+	}
+}
+
+/// The panic payload of the host function joining a spawned task, which failed.
+#[derive(Debug)]
+pub struct SpawnedTaskPanic(pub String);
+
+impl HostFuncPanic for SpawnedTaskPanic {
+	fn code(&self) -> HostFuncErrorWasmEdge {
+		HostFuncErrorWasmEdge::SpawnedTaskErr
+	}
+}
+
+impl fmt::Display for SpawnedTaskPanic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Failed to join a spawned task: {}", self.0)
+	}
+}
+
+/// The name of the host function joining a spawned task.
+const JOIN_SPAWNED_TASK_FUNC_NAME: &str = "ext_runtime_tasks_join_version_1";
+
+/// Returns the typed payload the panic of the host function `name` with the given payload is
+/// raised as.
+///
+/// Joining a spawned task is implemented by `sc-executor` for all the executors alike, which only
+/// panics with a message, so every panic of its host function is a [`SpawnedTaskPanic`].
+pub(crate) fn typed_panic_payload(
+	name: &str,
+	payload: Box<dyn std::any::Any + Send>,
+) -> Box<dyn std::any::Any + Send> {
+	if name != JOIN_SPAWNED_TASK_FUNC_NAME {
+		return payload
+	}
+	Box::new(SpawnedTaskPanic(panic_message(&*payload).unwrap_or_default()))
+}
+
+/// Returns the message of a panic with the given payload, if it has one.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> Option<String> {
+	match payload.downcast_ref::<String>() {
+		Some(message) => Some(message.clone()),
+		None => payload.downcast_ref::<&str>().map(|message| message.to_string()),
+	}
+}
+
+/// Returns the error a call into the runtime fails with due to a panic of a host function with
+/// the given payload, together with the panic message, if any.
+pub(crate) fn classify_panic(
+	payload: &(dyn std::any::Any + Send),
+) -> (HostFuncErrorWasmEdge, Option<String>) {
+	fn typed<T: HostFuncPanic>(
+		payload: &(dyn std::any::Any + Send),
+	) -> Option<(HostFuncErrorWasmEdge, Option<String>)> {
+		payload
+			.downcast_ref::<T>()
+			.map(|payload| (payload.code(), Some(payload.to_string())))
+	}
+
+	typed::<AllocationPanic>(payload)
+		.or_else(|| typed::<DoubleFreePanic>(payload))
+		.or_else(|| typed::<SpawnedTaskPanic>(payload))
+		.unwrap_or_else(|| (HostFuncErrorWasmEdge::Others, panic_message(payload)))
 }
 
 impl fmt::Display for HostFuncErrorWasmEdge {
@@ -237,6 +855,9 @@ impl fmt::Display for HostFuncErrorWasmEdge {
 			HostFuncErrorWasmEdge::AllocateMemoryErr => write!(f, "2"),
 			HostFuncErrorWasmEdge::SpawnedTaskErr => write!(f, "3"),
 			HostFuncErrorWasmEdge::Others => write!(f, "4"),
+			HostFuncErrorWasmEdge::DoubleFree => write!(f, "5"),
+			HostFuncErrorWasmEdge::HostTimeExceeded => write!(f, "6"),
+			HostFuncErrorWasmEdge::DeadlineExceeded => write!(f, "7"),
 		}
 	}
 }
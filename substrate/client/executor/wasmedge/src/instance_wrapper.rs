@@ -1,29 +1,159 @@
-use crate::{host::HostState, util};
+use crate::{
+	host::HostState,
+	runtime::{InstanceGlobals, InstanceState, TrapCallback, TrapPolicy, MAX_STACK_DEPTH_GLOBAL},
+	util, LOG_TARGET,
+};
+use sc_allocator::AllocationStats;
 use sc_executor_common::{
 	error::{Backtrace, Error, MessageWithBacktrace, Result, WasmError},
+	runtime_blob::{DataSegmentsSnapshot, GlobalsSnapshot},
 	wasm_runtime::InvokeMethod,
 };
 use sp_wasm_interface::{Pointer, Value, WordSize};
+use std::{
+	collections::HashMap,
+	ops::Range,
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		Arc, Mutex,
+	},
+	time::{Duration, Instant},
+};
 use wasmedge_sdk::{
-	types::Val, Executor, Func, FuncRef, ImportObject, Instance, Memory, Module, Store, ValType,
-	WasmValue,
+	types::Val, Executor, ExternalInstanceType, Func, FuncRef, FuncType, Global, ImportObject,
+	Instance, Memory, Module, Statistics, Store, ValType, WasmValue,
 };
 
+/// The total size in bytes of the linear memories of all the live instances, see
+/// [`total_instance_memory`].
+static TOTAL_INSTANCE_MEMORY: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the total size in bytes of the linear memories of all the live instances in the
+/// process.
+///
+/// The memory of an instance is accounted when it is instantiated and whenever it is
+/// decommitted, i.e. memory grown during a call is accounted once the call finished, and it is
+/// released when the instance is dropped. A monitoring loop can watch this for unbounded growth,
+/// which indicates that instances are leaked.
+pub fn total_instance_memory() -> usize {
+	TOTAL_INSTANCE_MEMORY.load(Ordering::Relaxed)
+}
+
+/// The number of decommits done by the OS, see [`decommit_stats`].
+static FAST_DECOMMITS: AtomicU64 = AtomicU64::new(0);
+
+/// The number of decommits done by zeroing the memory, see [`decommit_stats`].
+static FALLBACK_DECOMMITS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(test)]
+thread_local! {
+	/// Makes the decommits on this thread zero the memory, as if the OS failed to decommit it.
+	pub(crate) static FORCE_DECOMMIT_FALLBACK: std::cell::Cell<bool> =
+		std::cell::Cell::new(false);
+
+	/// The number of instances reset rather than instantiated again on this thread.
+	pub(crate) static RESETS: std::cell::Cell<u64> = std::cell::Cell::new(0);
+}
+
+/// How the linear memories were decommitted in the process so far, see [`decommit_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecommitStats {
+	/// The number of decommits done by the OS, i.e. by `madvise` on Linux or by `mmap` on macOS.
+	pub fast: u64,
+	/// The number of decommits which zeroed the memory instead, because the OS failed to
+	/// decommit it or isn't supported.
+	pub fallback: u64,
+}
+
+/// Returns how the linear memories were decommitted in the process so far.
+///
+/// Zeroing the memory is much slower than letting the OS decommit it, so a rising share of
+/// [`DecommitStats::fallback`] indicates a misconfigured kernel or container.
+pub fn decommit_stats() -> DecommitStats {
+	DecommitStats {
+		fast: FAST_DECOMMITS.load(Ordering::Relaxed),
+		fallback: FALLBACK_DECOMMITS.load(Ordering::Relaxed),
+	}
+}
+
+/// The state of an instance right after it was instantiated, which [`InstanceWrapper::reset`]
+/// brings the instance back to.
+pub(crate) struct InitialState {
+	/// The size of the linear memory in pages.
+	memory_pages: u32,
+	data_segments_snapshot: Arc<DataSegmentsSnapshot>,
+	globals_snapshot: GlobalsSnapshot<Arc<Mutex<Global>>>,
+}
+
 pub struct InstanceWrapper {
 	store: Store,
 	executor: Executor,
+	/// The statistics gathered by the `executor`, if any are enabled, see
+	/// [`Semantics::statistics`](crate::Semantics::statistics). Declared after the `executor`,
+	/// which refers to them, so that they are dropped after it.
+	statistics: Option<Statistics>,
 	instance: Option<Instance>,
 	memory: Option<Memory>,
 	host_state: Option<HostState>,
-	import: Option<ImportObject>,
+	/// The import objects holding the host functions, one for every module they're imported from.
+	imports: Vec<ImportObject>,
+	/// The import object provided by the embedder besides the host functions, if any.
+	extra_import: Option<ImportObject>,
+	/// Exported functions which were already resolved (and had their signature checked) by
+	/// [`InstanceWrapper::call`], keyed by the export name.
+	///
+	/// Only valid for the current `instance`, therefore cleared on every instantiation.
+	export_funcs: HashMap<String, Func>,
+	/// Whether the linear memory might have been written to since it was last decommitted.
+	memory_dirty: bool,
+	/// Whether a call trapped since the last instantiation.
+	///
+	/// Reusing an instance only restores its linear memory and its mutable globals, anything
+	/// else a call might have left half way through when trapping isn't. Hence such an instance
+	/// is instantiated again rather than reused.
+	poisoned: bool,
+	/// Which traps are reported by a dedicated error variant.
+	trap_policy: TrapPolicy,
+	/// The callback invoked when a call fails.
+	on_trap: Option<TrapCallback>,
+	/// The size of the linear memory accounted in [`TOTAL_INSTANCE_MEMORY`] and the
+	/// `memory_counter`.
+	accounted_memory: usize,
+	/// The counter of the memory of the instances of a single runtime, if any.
+	memory_counter: Option<Arc<AtomicUsize>>,
+	/// Whether a decommit makes sure the pages are given back to the OS, see
+	/// [`Semantics::synchronous_decommit`](crate::Semantics::synchronous_decommit).
+	#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+	synchronous_decommit: bool,
+	/// Whether the linear memory must be followed by guard pages, see
+	/// [`Semantics::guard_pages`](crate::Semantics::guard_pages).
+	guard_pages: bool,
+	/// How long the last instantiation or reset took.
+	last_instantiate_duration: Option<Duration>,
+	/// The frames parsed from the backtrace of the last call, if it trapped.
+	backtrace_frames: Vec<Frame>,
+	/// The stats of the allocator at the moment the last call trapped, if it did.
+	trapped_allocation_stats: Option<AllocationStats>,
+	/// The data passed to the host functions which are specific to this instance, e.g. the
+	/// environment. Declared last, so that it outlives the `imports` referring to it.
+	host_func_data: Vec<Box<dyn std::any::Any + Send>>,
 }
 
 impl InstanceWrapper {
 	pub fn new(semantics: &crate::runtime::Semantics) -> Result<Self> {
-		let executor = Executor::new(Some(&crate::runtime::common_config(semantics)?), None)
-			.map_err(|e| {
-				WasmError::Other(format!("fail to create a WasmEdge Executor context: {}", e))
-			})?;
+		let mut statistics = if semantics.statistics == Default::default() {
+			None
+		} else {
+			Some(Statistics::new().map_err(|e| {
+				WasmError::Other(format!("fail to create a WasmEdge Statistics context: {}", e))
+			})?)
+		};
+
+		let executor =
+			Executor::new(Some(&crate::runtime::common_config(semantics)?), statistics.as_mut())
+				.map_err(|e| {
+					WasmError::Other(format!("fail to create a WasmEdge Executor context: {}", e))
+				})?;
 
 		let store = Store::new().map_err(|e| {
 			WasmError::Other(format!("fail to create a WasmEdge Store context: {}", e))
@@ -32,38 +162,227 @@ impl InstanceWrapper {
 		Ok(InstanceWrapper {
 			store,
 			executor,
+			statistics,
 			instance: None,
 			memory: None,
 			host_state: None,
-			import: None,
+			imports: Vec::new(),
+			extra_import: None,
+			export_funcs: HashMap::new(),
+			memory_dirty: false,
+			poisoned: false,
+			trap_policy: TrapPolicy::default(),
+			on_trap: None,
+			accounted_memory: 0,
+			memory_counter: None,
+			synchronous_decommit: semantics.synchronous_decommit,
+			guard_pages: semantics.guard_pages,
+			last_instantiate_duration: None,
+			backtrace_frames: Vec::new(),
+			trapped_allocation_stats: None,
+			host_func_data: Vec::new(),
 		})
 	}
 
-	pub fn register_import(&mut self, import_obj: ImportObject) -> Result<()> {
-		self.import = Some(import_obj);
+	/// Sets which traps are reported by a dedicated error variant, see
+	/// [`Config::trap_policy`](crate::Config::trap_policy).
+	pub(crate) fn set_trap_policy(&mut self, trap_policy: TrapPolicy) {
+		self.trap_policy = trap_policy;
+	}
+
+	/// Sets the callback invoked when a call fails, see
+	/// [`Config::on_trap`](crate::Config::on_trap).
+	pub(crate) fn set_on_trap(&mut self, on_trap: Option<TrapCallback>) {
+		self.on_trap = on_trap;
+	}
+
+	/// Returns the statistics gathered during the execution, if any are enabled.
+	pub(crate) fn statistics(&self) -> Option<&Statistics> {
+		self.statistics.as_ref()
+	}
+
+	/// Returns the callback invoked when a call fails, if any.
+	pub(crate) fn on_trap(&self) -> Option<&TrapCallback> {
+		self.on_trap.as_ref()
+	}
+
+	/// Sets the counter the size of the linear memory is accounted in, besides
+	/// [`total_instance_memory`].
+	pub(crate) fn set_memory_counter(&mut self, memory_counter: Arc<AtomicUsize>) {
+		self.memory_counter = Some(memory_counter);
+	}
+
+	/// Keeps the data passed to a host function of this instance alive until the instance is
+	/// dropped.
+	pub(crate) fn keep_host_func_data(&mut self, data: Box<dyn std::any::Any + Send>) {
+		self.host_func_data.push(data);
+	}
+
+	/// Registers the import object, which was built under the module name `module_name` and
+	/// holds `func_count` functions.
+	pub fn register_import(
+		&mut self,
+		import_obj: ImportObject,
+		module_name: &str,
+		func_count: usize,
+	) -> std::result::Result<(), WasmError> {
 		self.store
-			.register_import_module(&mut self.executor, &self.import.as_ref().unwrap())
+			.register_import_module(&mut self.executor, &import_obj)
 			.map_err(|error| {
-				WasmError::Other(format!("failed to register import object: {}", error,))
+				WasmError::Other(format!(
+					"failed to register import module '{}' with {} functions: {}",
+					module_name, func_count, error,
+				))
 			})?;
+
+		self.imports.push(import_obj);
+		Ok(())
+	}
+
+	/// Registers an import object provided by the embedder besides the host functions, see
+	/// `WasmEdgeRuntime::new_instance_with_imports`.
+	pub(crate) fn register_extra_import(
+		&mut self,
+		import_obj: ImportObject,
+	) -> std::result::Result<(), WasmError> {
+		self.store
+			.register_import_module(&mut self.executor, &import_obj)
+			.map_err(|error| {
+				WasmError::Other(format!(
+					"failed to register import module '{}': {}",
+					import_obj.name(),
+					error,
+				))
+			})?;
+
+		self.extra_import = Some(import_obj);
 		Ok(())
 	}
 
 	pub fn instantiate(&mut self, module: &Module) -> Result<()> {
+		let started = Instant::now();
 		let instance = self
 			.store
 			.register_active_module(&mut self.executor, &module)
 			.map_err(|e| WasmError::Other(format!("failed to register active module: {}", e,)))?;
 
-		let memory = instance
-			.memory("memory")
-			.ok_or(WasmError::Other(String::from("fail to get WASM memory named 'memory'")))?;
+		let memory = instance.memory("memory").ok_or_else(|| missing_memory_error(module))?;
+
+		log::debug!(target: LOG_TARGET, "Instantiated runtime module");
 
 		self.instance = Some(instance);
 		self.memory = Some(memory);
+		self.memory_dirty = true;
+		self.poisoned = false;
+		self.clear_export_cache();
+		self.account_memory();
+		if self.guard_pages {
+			self.check_guard_pages()?;
+		}
+		self.last_instantiate_duration = Some(started.elapsed());
 		Ok(())
 	}
 
+	/// Returns how long the last successful [`InstanceWrapper::instantiate`] or
+	/// [`InstanceWrapper::reset`] took, if any.
+	pub(crate) fn last_instantiate_duration(&self) -> Option<Duration> {
+		self.last_instantiate_duration
+	}
+
+	/// Checks that the linear memory is followed by a mapping without any access permissions,
+	/// see [`Semantics::guard_pages`](crate::Semantics::guard_pages).
+	fn check_guard_pages(&self) -> Result<()> {
+		let memory_len = util::memory_len(self.memory());
+		// There is no address to check after a memory without any pages.
+		if memory_len == 0 {
+			return Ok(())
+		}
+		let end = self.base_ptr() as usize + memory_len;
+
+		cfg_if::cfg_if! {
+			if #[cfg(target_os = "linux")] {
+				let maps = std::fs::read_to_string("/proc/self/maps").map_err(|error| {
+					WasmError::Other(format!("cannot check the guard pages: {}", error))
+				})?;
+				let permissions = maps.lines().find_map(|line| {
+					let mut fields = line.split_whitespace();
+					let (start, limit) = fields.next()?.split_once('-')?;
+					let start = usize::from_str_radix(start, 16).ok()?;
+					let limit = usize::from_str_radix(limit, 16).ok()?;
+					(start..limit).contains(&end).then(|| fields.next()).flatten()
+				});
+				match permissions {
+					Some(permissions) if permissions.starts_with("---") => Ok(()),
+					Some(permissions) => Err(WasmError::Other(format!(
+						"the linear memory is followed by memory with the permissions `{}` \
+						 rather than by guard pages",
+						permissions,
+					))
+					.into()),
+					None => Err(WasmError::Other(String::from(
+						"the linear memory is followed by unmapped memory rather than by guard pages",
+					))
+					.into()),
+				}
+			} else {
+				let _ = end;
+				Err(WasmError::Other(String::from(
+					"guard pages can only be checked on Linux",
+				))
+				.into())
+			}
+		}
+	}
+
+	/// Captures the state of the current instance, which must have just been instantiated.
+	///
+	/// The state is only valid for the current instance and must be captured again after the
+	/// next call to [`InstanceWrapper::instantiate`].
+	pub(crate) fn take_initial_state(
+		&mut self,
+		globals_snapshot: GlobalsSnapshot<Arc<Mutex<Global>>>,
+		data_segments_snapshot: Arc<DataSegmentsSnapshot>,
+	) -> InitialState {
+		InitialState {
+			memory_pages: self.memory().size(),
+			data_segments_snapshot,
+			globals_snapshot,
+		}
+	}
+
+	/// Brings the current instance back to the given initial state, which is cheaper than
+	/// instantiating the module again.
+	///
+	/// The linear memory is zeroed and the data segments are written into it again, and the
+	/// mutable globals are restored to their initial values.
+	///
+	/// Returns `false` without touching the instance if it can't be reset, which is the case if
+	/// its memory was grown since the instantiation, since memory can't be shrunk.
+	pub(crate) fn reset(&mut self, initial_state: &InitialState) -> Result<bool> {
+		if self.memory().size() != initial_state.memory_pages {
+			return Ok(false)
+		}
+
+		let started = Instant::now();
+		self.decommit();
+		initial_state.data_segments_snapshot.apply(|offset, contents| {
+			util::write_memory_from(
+				util::memory_slice_mut(self.memory_mut()),
+				Pointer::new(offset),
+				contents,
+			)
+		})?;
+		initial_state.globals_snapshot.apply(&mut InstanceGlobals { instance: self });
+		self.memory_dirty = true;
+		self.last_instantiate_duration = Some(started.elapsed());
+		#[cfg(test)]
+		RESETS.with(|resets| resets.set(resets.get() + 1));
+
+		log::trace!(target: LOG_TARGET, "Reset the instance to its initial state");
+
+		Ok(true)
+	}
+
 	pub fn call(
 		&mut self,
 		method: InvokeMethod,
@@ -72,16 +391,25 @@ impl InstanceWrapper {
 	) -> Result<u64> {
 		let data_ptr = WasmValue::from_i32(u32::from(data_ptr) as i32);
 		let data_len = WasmValue::from_i32(u32::from(data_len) as i32);
+		self.memory_dirty = true;
+		self.backtrace_frames.clear();
+		self.trapped_allocation_stats = None;
+		let is_export = matches!(method, InvokeMethod::Export(_));
 
 		let res = match method {
 			InvokeMethod::Export(method) => {
-				let func = self
-					.instance()
-					.func(method)
-					.ok_or(WasmError::Other(String::from("function is not found")))?;
+				if !self.export_funcs.contains_key(method) {
+					let func = self
+						.instance()
+						.func(method)
+						.ok_or(WasmError::Other(String::from("function is not found")))?;
+
+					check_signature1(&func)?;
 
-				check_signature1(&func)?;
+					self.export_funcs.insert(method.to_owned(), func);
+				}
 
+				let func = &self.export_funcs[method];
 				func.call(&mut self.executor, vec![data_ptr, data_len])
 			},
 			InvokeMethod::Table(func) => {
@@ -119,38 +447,106 @@ impl InstanceWrapper {
 			},
 		}
 		.map_err(|trap| {
-			let host_state = self.host_state_mut();
-
-			// The logic to print out a backtrace is somewhat complicated,
-			// so let's get wasmtime to print it out for us.
-			let mut backtrace_string = trap.to_string();
-			let suffix = "\nwasm backtrace:";
-			if let Some(index) = backtrace_string.find(suffix) {
-				// Get rid of the error message and just grab the backtrace,
-				// since we're storing the error message ourselves separately.
-				backtrace_string.replace_range(0..index + suffix.len(), "");
-			}
-
-			let backtrace = Backtrace { backtrace_string };
-			if let Some(error) = host_state.take_panic_message() {
-				Error::AbortedDueToPanic(MessageWithBacktrace {
-					message: error,
-					backtrace: Some(backtrace),
-				})
-			} else {
-				Error::AbortedDueToTrap(MessageWithBacktrace {
-					message: trap.to_string(),
-					backtrace: Some(backtrace),
-				})
-			}
+			self.trapped_allocation_stats =
+				self.host_state.as_ref().map(|host_state| host_state.allocation_stats());
+			self.trap_to_error(trap.to_string())
 		})?;
 
-		Ok(res[0].to_i64() as u64)
+		// An export returning nothing has an empty output, see `check_signature1`.
+		if is_export && res.is_empty() {
+			return Ok(0)
+		}
+		entry_point_result(&res)
+	}
+
+	/// Calls the exported function `name` with the given arguments and returns its results.
+	///
+	/// Unlike [`InstanceWrapper::call`] this doesn't follow the Substrate ABI, the function may
+	/// have any signature as long as it only uses the value types supported by [`Value`].
+	pub fn call_export_raw(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>> {
+		let func = self
+			.instance()
+			.func(name)
+			.ok_or_else(|| WasmError::Other(format!("function '{}' is not found", name)))?;
+
+		let args = args.iter().cloned().map(util::into_wasmedge_value).collect::<Vec<_>>();
+		self.memory_dirty = true;
+
+		let res = func
+			.call(&mut self.executor, args)
+			.map_err(|trap| self.trap_to_error(trap.to_string()))?;
+
+		res.into_iter().map(util::try_from_wasmedge_value).collect()
+	}
+
+	/// Whether a call trapped since the last instantiation, in which case the instance must be
+	/// instantiated again before it is used.
+	pub(crate) fn is_poisoned(&self) -> bool {
+		self.poisoned
+	}
+
+	/// Converts the error of a failed call into wasm into an [`Error`], taking the panic message
+	/// the runtime left in the host state into account.
+	///
+	/// Marks the instance as poisoned, see [`InstanceWrapper::is_poisoned`].
+	fn trap_to_error(&mut self, trap: String) -> Error {
+		self.poisoned = true;
+
+		// The error only tells the kind of the trap, where it happened is only ever logged by
+		// WasmEdge.
+		let backtrace_string = crate::logging::wasmedge_trap_log().unwrap_or_else(|| trap.clone());
+
+		self.backtrace_frames = parse_backtrace(&backtrace_string);
+		let backtrace = Backtrace { backtrace_string };
+		let panic_message =
+			self.host_state.as_mut().and_then(|host_state| host_state.take_panic_message());
+		if let Some(error) = panic_message {
+			return Error::AbortedDueToPanic(MessageWithBacktrace {
+				message: error,
+				backtrace: Some(backtrace),
+			})
+		}
+
+		// WasmEdge only gives us the stringified trap, so that's all we can go by.
+		let division_by_zero =
+			self.trap_policy.division_by_zero && trap.contains("integer divide by zero");
+		let integer_overflow =
+			self.trap_policy.integer_overflow && trap.contains("integer overflow");
+
+		let message = MessageWithBacktrace { message: trap, backtrace: Some(backtrace) };
+		if division_by_zero {
+			Error::AbortedDueToDivisionByZero(message)
+		} else if integer_overflow {
+			Error::AbortedDueToIntegerOverflow(message)
+		} else {
+			Error::AbortedDueToTrap(message)
+		}
+	}
+
+	/// Returns the frames parsed from the backtrace of the last call made by
+	/// [`InstanceWrapper::call`], empty if it didn't trap.
+	pub(crate) fn backtrace_frames(&self) -> &[Frame] {
+		&self.backtrace_frames
+	}
+
+	/// Returns the stats of the allocator at the moment the last call made by
+	/// [`InstanceWrapper::call`] trapped, `None` if it didn't trap.
+	pub(crate) fn trapped_allocation_stats(&self) -> Option<&AllocationStats> {
+		self.trapped_allocation_stats.as_ref()
+	}
+
+	/// Forgets all the exported functions resolved so far.
+	///
+	/// They will be looked up again by name on the next call.
+	pub(crate) fn clear_export_cache(&mut self) {
+		self.export_funcs.clear();
 	}
 
 	/// Reads `__heap_base: i32` global variable and returns it.
 	///
-	/// If it doesn't exist, not a global or of not i32 type returns an error.
+	/// If it doesn't exist, not a global or of not i32 type returns an error. An error is also
+	/// returned if the value is negative or points past the end of the linear memory, since the
+	/// allocator would hand out nonsense allocations starting there.
 	pub fn extract_heap_base(&mut self) -> Result<u32> {
 		let heap_base = self
 			.instance()
@@ -158,12 +554,44 @@ impl InstanceWrapper {
 			.ok_or(WasmError::Other(String::from("failed to get WASM global named '__heap_base'")))?
 			.get_value();
 
-		if let Val::I32(v) = heap_base {
-			Ok(v as u32)
+		let heap_base = if let Val::I32(v) = heap_base {
+			v
 		} else {
-			Err(Error::Other(String::from(
+			return Err(Error::Other(String::from(
 				"the type of WASM global named '__heap_base' is not i32",
 			)))
+		};
+
+		// The heap may start right at the end of the memory, e.g. of one without any pages, it's
+		// just empty then.
+		let memory_size = util::memory_len(self.memory());
+		if heap_base < 0 || heap_base as usize > memory_size {
+			return Err(Error::Other(format!(
+				"the value of WASM global named '__heap_base' is implausible: {} is outside of the \
+				 linear memory of {} bytes",
+				heap_base, memory_size,
+			)))
+		}
+
+		Ok(heap_base as u32)
+	}
+
+	/// Resets the global tracking the maximum stack depth, if the module has one. See
+	/// [`Semantics::track_max_stack_depth`](crate::Semantics::track_max_stack_depth).
+	pub(crate) fn reset_max_stack_depth(&mut self) {
+		if let Some(mut global) = self.instance().global(MAX_STACK_DEPTH_GLOBAL) {
+			global
+				.set_value(Val::I32(0))
+				.expect("the global is a mutable i32 added by the instrumentation; qed");
+		}
+	}
+
+	/// Returns the maximum stack depth reached since the last
+	/// [`InstanceWrapper::reset_max_stack_depth`], if the module tracks it.
+	pub(crate) fn max_stack_depth(&self) -> Option<u32> {
+		match self.instance().global(MAX_STACK_DEPTH_GLOBAL)?.get_value() {
+			Val::I32(depth) => Some(depth as u32),
+			_ => None,
 		}
 	}
 
@@ -175,13 +603,74 @@ impl InstanceWrapper {
 			.ok_or(Error::Other(String::from("failed to get WASM global")))?
 			.get_value();
 
-		match global {
-			Val::I32(v) => Ok(Some(Value::I32(v))),
-			Val::I64(v) => Ok(Some(Value::I64(v))),
-			Val::F32(v) => Ok(Some(Value::F32(v as u32))),
-			Val::F64(v) => Ok(Some(Value::F64(v as u64))),
-			_ => Err("Unknown value type".into()),
+		util::try_from_wasmedge_val(global).map(Some)
+	}
+
+	/// Captures the linear memory and the values of all the exported globals, see
+	/// [`WasmEdgeInstance::export_state`](crate::WasmEdgeInstance::export_state).
+	pub(crate) fn export_state(&self) -> Result<InstanceState> {
+		let mut global_names = self.instance().global_names().unwrap_or_default();
+		global_names.sort();
+
+		let globals = global_names
+			.into_iter()
+			.map(|name| {
+				let global = self
+					.instance()
+					.global(&name)
+					.ok_or_else(|| Error::Other(format!("failed to get WASM global '{}'", name)))?;
+				let value = util::try_from_wasmedge_val(global.get_value())?;
+				Ok((name, value))
+			})
+			.collect::<Result<_>>()?;
+
+		Ok(InstanceState { memory: util::memory_slice(self.memory()).to_vec(), globals })
+	}
+
+	/// Restores a state captured by [`InstanceWrapper::export_state`] from an instance of the
+	/// same module, growing the linear memory as needed.
+	///
+	/// Only the globals whose values differ are set, so the immutable globals, which always have
+	/// the same values for the same module, don't get in the way.
+	pub(crate) fn import_state(&mut self, state: &InstanceState) -> Result<()> {
+		if state.memory.len() % util::WASM_PAGE_SIZE != 0 {
+			return Err(Error::Other(format!(
+				"the memory of the state is {} bytes, which is not a whole number of wasm pages",
+				state.memory.len(),
+			)))
+		}
+
+		let pages = (state.memory.len() / util::WASM_PAGE_SIZE) as u32;
+		let current_pages = self.memory().size();
+		if pages < current_pages {
+			return Err(Error::Other(format!(
+				"the memory of the state has {} pages, but the instance already has {}",
+				pages, current_pages,
+			)))
+		}
+		if pages > current_pages {
+			self.memory_mut().grow(pages - current_pages).map_err(|e| {
+				Error::Other(format!("failed to grow the memory to {} pages: {}", pages, e))
+			})?;
 		}
+
+		util::memory_slice_mut(self.memory_mut()).copy_from_slice(&state.memory);
+		self.memory_dirty = true;
+		self.account_memory();
+
+		for (name, value) in &state.globals {
+			let mut global = self
+				.instance()
+				.global(name)
+				.ok_or_else(|| Error::Other(format!("failed to get WASM global '{}'", name)))?;
+			if util::try_from_wasmedge_val(global.get_value())? != *value {
+				global.set_value(util::into_wasmedge_val(*value)).map_err(|e| {
+					Error::Other(format!("failed to restore WASM global '{}': {}", name, e))
+				})?;
+			}
+		}
+
+		Ok(())
 	}
 
 	/// Returns the pointer to the first byte of the linear memory for this instance.
@@ -199,6 +688,12 @@ impl InstanceWrapper {
 		self.memory.as_mut().expect("memory is always set; qed")
 	}
 
+	/// Notes that the linear memory might have been written to, so that it is decommitted when
+	/// the instance is dropped.
+	pub(crate) fn mark_memory_dirty(&mut self) {
+		self.memory_dirty = true;
+	}
+
 	pub(crate) fn instance(&self) -> &Instance {
 		self.instance.as_ref().expect("wasmedge instance is always set; qed")
 	}
@@ -225,7 +720,93 @@ impl InstanceWrapper {
 	/// leads to returning the memory back to the system; this also zeroes the memory
 	/// as a side-effect.
 	pub fn decommit(&mut self) {
-		if self.memory().size() == 0 {
+		self.memory_dirty = false;
+		self.account_memory();
+
+		let len = util::memory_len(self.memory());
+		self.decommit_range(0..len);
+	}
+
+	/// The same as [`InstanceWrapper::decommit`], but only decommits the pages touched since the
+	/// memory was last decommitted, see
+	/// [`Semantics::decommit_only_dirty`](crate::Semantics::decommit_only_dirty).
+	///
+	/// On Linux the touched pages are the ones `/proc/self/pagemap` reports as present or swapped
+	/// out, any other page was never written to since it was decommitted and so reads as zero.
+	/// On the other platforms, or if the page map can't be read, the whole memory is
+	/// decommitted.
+	pub fn decommit_dirty(&mut self) {
+		#[cfg(target_os = "linux")]
+		if let Some(dirty_ranges) = self.dirty_ranges() {
+			self.memory_dirty = false;
+			self.account_memory();
+
+			log::trace!(
+				target: LOG_TARGET,
+				"Decommitting {} dirty ranges of linear memory",
+				dirty_ranges.len(),
+			);
+			for range in dirty_ranges {
+				self.decommit_range(range);
+			}
+			return
+		}
+
+		self.decommit()
+	}
+
+	/// Returns the ranges of the linear memory, in whole OS pages, which were touched since
+	/// they were last decommitted, or `None` if the page map can't be read.
+	#[cfg(target_os = "linux")]
+	fn dirty_ranges(&self) -> Option<Vec<Range<usize>>> {
+		use std::os::unix::fs::FileExt;
+
+		// A page which is present or swapped out, see the kernel's `pagemap` documentation.
+		const PAGE_TOUCHED: u64 = 0b11 << 62;
+
+		let len = util::memory_len(self.memory());
+		if len == 0 {
+			return Some(Vec::new())
+		}
+
+		let page_size = page_size()?;
+		let base = self.base_ptr() as usize;
+		if base % page_size != 0 {
+			return None
+		}
+
+		let mut entries = vec![0u8; (len + page_size - 1) / page_size * 8];
+		let pagemap = std::fs::File::open("/proc/self/pagemap").ok()?;
+		pagemap.read_exact_at(&mut entries, (base / page_size * 8) as u64).ok()?;
+
+		let mut ranges: Vec<Range<usize>> = Vec::new();
+		for (page, entry) in entries.chunks_exact(8).enumerate() {
+			let entry = u64::from_ne_bytes(entry.try_into().expect("the chunks have 8 bytes; qed"));
+			if entry & PAGE_TOUCHED == 0 {
+				continue
+			}
+
+			let start = page * page_size;
+			let end = (start + page_size).min(len);
+			match ranges.last_mut() {
+				Some(last) if last.end == start => last.end = end,
+				_ => ranges.push(start..end),
+			}
+		}
+		Some(ranges)
+	}
+
+	/// Decommits the given range of the linear memory, which must start at a page boundary.
+	fn decommit_range(&mut self, range: Range<usize>) {
+		if range.is_empty() {
+			return
+		}
+
+		log::debug!(target: LOG_TARGET, "Decommitting {} bytes of linear memory", range.len());
+
+		#[cfg(test)]
+		if FORCE_DECOMMIT_FALLBACK.with(|force| force.get()) {
+			self.zero_memory_range(range);
 			return
 		}
 
@@ -234,20 +815,24 @@ impl InstanceWrapper {
 				use std::sync::Once;
 
 				unsafe {
-					let ptr = self.base_ptr();
-					let len = (self.memory().size() * 64 * 1024) as usize;
+					let ptr = self.base_ptr().add(range.start);
 
 					// Linux handles MADV_DONTNEED reliably. The result is that the given area
 					// is unmapped and will be zeroed on the next pagefault.
-					if libc::madvise(ptr as _, len, libc::MADV_DONTNEED) != 0 {
+					if libc::madvise(ptr as _, range.len(), libc::MADV_DONTNEED) != 0 {
 						static LOGGED: Once = Once::new();
 						LOGGED.call_once(|| {
 							log::warn!(
+								target: LOG_TARGET,
 								"madvise(MADV_DONTNEED) failed: {}",
 								std::io::Error::last_os_error(),
 							);
 						});
 					} else {
+						if self.synchronous_decommit {
+							self.settle_decommit(range);
+						}
+						FAST_DECOMMITS.fetch_add(1, Ordering::Relaxed);
 						return;
 					}
 				}
@@ -255,12 +840,11 @@ impl InstanceWrapper {
 				use std::sync::Once;
 
 				unsafe {
-					let ptr = self.base_ptr();
-					let len = (self.memory().size() * 64 * 1024) as usize;
+					let ptr = self.base_ptr().add(range.start);
 
 					if libc::mmap(
 						ptr as _,
-						len,
+						range.len(),
 						libc::PROT_READ | libc::PROT_WRITE,
 						libc::MAP_FIXED | libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
 						-1,
@@ -269,11 +853,13 @@ impl InstanceWrapper {
 						static LOGGED: Once = Once::new();
 						LOGGED.call_once(|| {
 							log::warn!(
+								target: LOG_TARGET,
 								"Failed to decommit WASM instance memory through mmap: {}",
 								std::io::Error::last_os_error(),
 							);
 						});
 					} else {
+						FAST_DECOMMITS.fetch_add(1, Ordering::Relaxed);
 						return;
 					}
 				}
@@ -282,19 +868,181 @@ impl InstanceWrapper {
 
 		// If we're on an unsupported OS or the memory couldn't have been
 		// decommited for some reason then just manually zero it out.
-		util::memory_slice_mut(self.memory_mut()).fill(0);
+		self.zero_memory_range(range);
+	}
+
+	/// Advises the kernel again to drop the given range of the linear memory, which was just
+	/// decommitted, as long as any of its pages are still resident.
+	#[cfg(target_os = "linux")]
+	fn settle_decommit(&self, range: Range<usize>) {
+		const MAX_ATTEMPTS: usize = 8;
+
+		for _ in 0..MAX_ATTEMPTS {
+			match self.resident_memory_in(range.clone()) {
+				Some(0) => return,
+				Some(_) => unsafe {
+					libc::madvise(
+						self.base_ptr().add(range.start) as _,
+						range.len(),
+						libc::MADV_DONTNEED,
+					);
+				},
+				// The residency can't be checked, so there is nothing to wait for.
+				None => return,
+			}
+		}
+
+		log::debug!(
+			target: LOG_TARGET,
+			"{:?} bytes of linear memory are still resident after decommitting",
+			self.resident_memory_in(range),
+		);
+	}
+
+	/// Returns how many bytes among the first `len` bytes of the linear memory are resident,
+	/// or `None` if the OS doesn't tell.
+	#[cfg(target_os = "linux")]
+	pub(crate) fn resident_memory(&self, len: usize) -> Option<usize> {
+		self.resident_memory_in(0..len)
+	}
+
+	/// The same as [`InstanceWrapper::resident_memory`], but for the given range of the linear
+	/// memory, which must start at a page boundary.
+	#[cfg(target_os = "linux")]
+	fn resident_memory_in(&self, range: Range<usize>) -> Option<usize> {
+		let page_size = page_size()?;
+		let mut pages = vec![0u8; (range.len() + page_size - 1) / page_size];
+		let ptr = unsafe { self.base_ptr().add(range.start) };
+		if unsafe { libc::mincore(ptr as _, range.len(), pages.as_mut_ptr()) } != 0 {
+			return None
+		}
+		Some(pages.iter().filter(|page| *page & 1 != 0).count() * page_size)
+	}
+
+	/// Decommits the given range of the linear memory by zeroing it.
+	fn zero_memory_range(&mut self, range: Range<usize>) {
+		util::memory_slice_mut(self.memory_mut())[range].fill(0);
+		FALLBACK_DECOMMITS.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Decommits the linear memory unless that already happened since it was last written to,
+	/// e.g. after a call which failed before the memory could be decommitted.
+	///
+	/// This is called when the instance is dropped, so that the memory is given back to the OS
+	/// promptly however the instance's lifetime ends.
+	pub(crate) fn release_memory(&mut self) {
+		if self.memory_dirty && self.memory.is_some() {
+			self.decommit();
+		}
+	}
+
+	/// Accounts the current size of the linear memory, see [`total_instance_memory`].
+	fn account_memory(&mut self) {
+		let size = self.memory.as_ref().map_or(0, util::memory_len);
+		self.set_accounted_memory(size);
+	}
+
+	fn set_accounted_memory(&mut self, size: usize) {
+		let accounted = std::mem::replace(&mut self.accounted_memory, size);
+		let counters =
+			std::iter::once(&TOTAL_INSTANCE_MEMORY).chain(self.memory_counter.as_deref());
+		for counter in counters {
+			counter.fetch_add(size, Ordering::Relaxed);
+			counter.fetch_sub(accounted, Ordering::Relaxed);
+		}
 	}
 }
 
+impl Drop for InstanceWrapper {
+	fn drop(&mut self) {
+		self.release_memory();
+		self.set_accounted_memory(0);
+	}
+}
+
+/// Extracts the result of a call into an entry point following the Substrate ABI.
+///
+/// The signature of the entry point was checked to return a single `i64` before calling it, so
+/// anything else being returned means that WasmEdge misbehaved. This is reported as an error
+/// instead of silently dropping or misinterpreting the values.
+/// Returns the size of the OS pages, or `None` if the OS doesn't tell.
+#[cfg(target_os = "linux")]
+fn page_size() -> Option<usize> {
+	match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+		page_size if page_size > 0 => Some(page_size as usize),
+		_ => None,
+	}
+}
+
+pub(crate) fn entry_point_result(res: &[WasmValue]) -> Result<u64> {
+	match res {
+		[ret] if ret.ty() == ValType::I64 => Ok(ret.to_i64() as u64),
+		_ => Err(Error::Other(format!(
+			"the entry point is expected to return a single i64, but it returned {:?}",
+			res.iter().map(|value| value.ty()).collect::<Vec<_>>(),
+		))),
+	}
+}
+
+/// A frame of a backtrace, as parsed by [`parse_backtrace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+	/// The index of the function the frame is in, if known.
+	pub func_index: Option<u32>,
+	/// The offset of the instruction in the code section, if known.
+	pub offset: Option<u32>,
+}
+
+/// Parses the frames out of a backtrace in WasmEdge's format, e.g.
+///
+/// ```text
+/// In instruction: unreachable (0x00) , Bytecode offset: 0x0000286a
+/// When executing function index: 42
+/// ```
+///
+/// Every `Bytecode offset` starts a new frame, and the following `function index` is attributed
+/// to it. Anything else is skipped, so a backtrace in another format yields no frames rather
+/// than an error.
+pub fn parse_backtrace(backtrace: &str) -> Vec<Frame> {
+	fn value_after<'a>(line: &'a str, label: &str) -> Option<&'a str> {
+		let start = line.find(label)? + label.len();
+		line[start..].trim_start().split(|c: char| !c.is_ascii_alphanumeric()).next()
+	}
+
+	let mut frames = Vec::<Frame>::new();
+	for line in backtrace.lines() {
+		if let Some(offset) = value_after(line, "Bytecode offset:") {
+			let offset = offset.strip_prefix("0x").unwrap_or(offset);
+			frames.push(Frame { func_index: None, offset: u32::from_str_radix(offset, 16).ok() });
+		}
+		if let Some(func_index) =
+			value_after(line, "function index:").and_then(|index| index.parse().ok())
+		{
+			match frames.last_mut() {
+				Some(frame) if frame.func_index.is_none() => frame.func_index = Some(func_index),
+				_ => frames.push(Frame { func_index: Some(func_index), offset: None }),
+			}
+		}
+	}
+	frames
+}
+
+/// Whether the function type is the one of a direct entry point, i.e. `(i32, i32) -> i64`.
+pub(crate) fn is_entry_point_type(func_type: &FuncType) -> bool {
+	func_type.args().unwrap_or(&[]) == [ValType::I32, ValType::I32] &&
+		func_type.returns().unwrap_or(&[]) == [ValType::I64]
+}
+
+/// Checks the signature of an exported entry point, which may also be `(i32, i32) -> ()` for
+/// entry points without an output, e.g. maintenance ones.
 fn check_signature1(func: &Func) -> Result<()> {
 	let func_type = func
 		.ty()
 		.map_err(|error| WasmError::Other(format!("fail to get the function type: {}", error,)))?;
 
-	let params = func_type.args().unwrap_or(&[]);
-	let returns = func_type.returns().unwrap_or(&[]);
-
-	if params != [ValType::I32, ValType::I32] || returns != [ValType::I64] {
+	let returns_nothing = func_type.args().unwrap_or(&[]) == [ValType::I32, ValType::I32] &&
+		func_type.returns().unwrap_or(&[]).is_empty();
+	if !is_entry_point_type(&func_type) && !returns_nothing {
 		return Err(Error::Other("Invalid signature for direct entry point".to_string()))
 	}
 	Ok(())
@@ -327,3 +1075,25 @@ fn check_signature3(func_ref: &FuncRef) -> Result<()> {
 	}
 	Ok(())
 }
+
+/// Describes why the linear memory of an instance of `module` couldn't be found.
+///
+/// An export named `memory` which isn't a memory, e.g. a global, is reported as such instead of
+/// as a missing memory.
+fn missing_memory_error(module: &Module) -> WasmError {
+	let export = module.exports().into_iter().find(|export| export.name() == "memory");
+	let kind = match export.map(|export| export.ty()) {
+		Some(Ok(ExternalInstanceType::Func(_))) => Some("function"),
+		Some(Ok(ExternalInstanceType::Table(_))) => Some("table"),
+		Some(Ok(ExternalInstanceType::Global(_))) => Some("global"),
+		_ => None,
+	};
+
+	match kind {
+		Some(kind) => WasmError::Other(format!(
+			"export 'memory' exists but is a {}, expected a linear memory",
+			kind
+		)),
+		None => WasmError::Other(String::from("fail to get WASM memory named 'memory'")),
+	}
+}
@@ -1,16 +1,46 @@
 #![feature(never_type)]
 
+mod artifact;
+mod cache;
 mod host;
 mod imports;
 mod instance_wrapper;
+mod logging;
+mod plugin;
 mod runtime;
+mod swapper;
 mod util;
 
 #[cfg(test)]
 mod tests;
 
-pub use imports::HostFuncErrorWasmEdge;
+/// The log target used by this crate.
+pub(crate) const LOG_TARGET: &str = "wasmedge-executor";
+
+pub use artifact::{
+	blob_hash, is_artifact_shareable, prune_artifact_cache, wasmedge_version, BlobHash,
+	ARTIFACT_WRITE_GRACE_PERIOD,
+};
+pub use cache::{ArtifactCache, ArtifactCacheStats};
+pub use host::{current_allocator_stats, HostCall, SandboxError};
+pub use imports::{
+	AllocationPanic, DoubleFreePanic, HostFuncErrorWasmEdge, HostFuncPanic, ImportReport,
+	SpawnedTaskPanic,
+};
+pub use instance_wrapper::{
+	decommit_stats, parse_backtrace, total_instance_memory, DecommitStats, Frame,
+};
+pub use logging::{
+	redirect_wasmedge_logs, sync_wasmedge_log_level, WasmEdgeLogLevel, WASMEDGE_LOG_TARGET,
+};
+pub use plugin::load_plugins;
 pub use runtime::{
-	create_runtime, create_runtime_from_artifact, prepare_runtime_artifact, Config,
-	DeterministicStackLimit, Semantics,
+	compile_module, create_runtime, create_runtime_artifact_or_compile, create_runtime_detailed,
+	create_runtime_from_artifact, create_runtime_from_artifact_checked, prepare_runtime_artifact,
+	prepare_runtime_artifact_with_report, uses_imported_memory, AllocatorConfig, ArtifactFormat,
+	BlobTransform, CompileReport, CompiledModule, Config, CreateRuntimeError,
+	DeterministicStackLimit, Footprint, HostCallTracer, InstanceState, MemoryRef, OutputValidator,
+	PanicCallback, PoolCheckoutPolicy, Semantics, StatisticsConfig, TraceEvent, TraceSink,
+	TrapCallback, TrapPolicy, WasmEdgeInstance, WasmEdgeRuntime,
 };
+pub use swapper::RuntimeSwapper;
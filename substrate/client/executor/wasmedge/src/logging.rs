@@ -0,0 +1,304 @@
+use crate::LOG_TARGET;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	io::{self, BufRead, Write},
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Condvar, Mutex,
+	},
+	time::Duration,
+};
+
+/// The log target WasmEdge's own logs are forwarded to, see [`redirect_wasmedge_logs`].
+pub const WASMEDGE_LOG_TARGET: &str = "wasmedge";
+
+/// The levels of WasmEdge's own logging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WasmEdgeLogLevel {
+	/// Nothing is logged.
+	Off,
+	/// Only errors are logged, which is WasmEdge's default.
+	Error,
+	/// Everything is logged.
+	Debug,
+}
+
+/// Makes WasmEdge's own logging follow the `log` filter of [`WASMEDGE_LOG_TARGET`], e.g. as set
+/// by `RUST_LOG=wasmedge=debug`, and returns the level applied.
+///
+/// WasmEdge only knows a few levels, so it logs nothing unless the errors of the target are
+/// enabled, and everything if its debug logs are. This keeps WasmEdge from producing logs that
+/// would be filtered out after [`redirect_wasmedge_logs`] anyway. While the logs are forwarded
+/// the errors are logged regardless, since the backtraces of the traps are taken from them.
+///
+/// WasmEdge's logging is process-global. This is called by the first runtime creation, calling it
+/// again applies the filter anew, e.g. after the logger was reconfigured.
+pub fn sync_wasmedge_log_level() -> WasmEdgeLogLevel {
+	let level = match wasmedge_log_level(log::logger(), log::max_level()) {
+		WasmEdgeLogLevel::Off if FORWARDING.load(Ordering::Relaxed) => WasmEdgeLogLevel::Error,
+		level => level,
+	};
+
+	log::debug!(target: LOG_TARGET, "Setting the WasmEdge log level to {:?}", level);
+
+	match level {
+		WasmEdgeLogLevel::Off => wasmedge_sys::utils::log_off(),
+		WasmEdgeLogLevel::Error => wasmedge_sys::utils::log_error_info(),
+		WasmEdgeLogLevel::Debug => wasmedge_sys::utils::log_debug_info(),
+	}
+
+	level
+}
+
+/// Returns the level of WasmEdge's logging matching what `logger` enables for
+/// [`WASMEDGE_LOG_TARGET`] up to `max_level`.
+pub(crate) fn wasmedge_log_level(logger: &dyn Log, max_level: LevelFilter) -> WasmEdgeLogLevel {
+	let enabled = |level: Level| {
+		level <= max_level &&
+			logger.enabled(&Metadata::builder().level(level).target(WASMEDGE_LOG_TARGET).build())
+	};
+
+	if enabled(Level::Debug) {
+		WasmEdgeLogLevel::Debug
+	} else if enabled(Level::Error) {
+		WasmEdgeLogLevel::Error
+	} else {
+		WasmEdgeLogLevel::Off
+	}
+}
+
+/// The prefix of the lines written to stderr to find the end of the logs of a trap, see
+/// [`wasmedge_trap_log`]. It's followed by the number of the marker.
+const TRAP_LOG_MARKER: &str = "\u{1}wasmedge-trap-log ";
+/// How many of WasmEdge's messages logged since the last marker are kept.
+const MAX_TRAP_LOG_LINES: usize = 64;
+/// How long the logs of a trap are waited for.
+const TRAP_LOG_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether stderr is redirected and read by the forwarding thread.
+static FORWARDING: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+	/// The outcome of redirecting stderr, which is only done once.
+	static ref REDIRECTION: std::result::Result<(), String> =
+		redirect_stderr().map_err(|e| e.to_string());
+
+	/// The messages WasmEdge logged for the traps.
+	static ref TRAP_LOGS: TrapLogs = TrapLogs::default();
+}
+
+/// The messages WasmEdge logged, collected for the traps by the forwarding thread.
+#[derive(Default)]
+pub(crate) struct TrapLogs {
+	state: Mutex<TrapLogsState>,
+	reached: Condvar,
+}
+
+#[derive(Default)]
+struct TrapLogsState {
+	/// The messages logged since the last marker.
+	pending: VecDeque<String>,
+	/// The markers which are waited for.
+	awaited: HashSet<u64>,
+	/// The messages logged before each awaited marker which was reached, keyed by its number.
+	collected: HashMap<u64, Vec<String>>,
+}
+
+impl TrapLogs {
+	/// Announces that the marker `id` is going to be waited for.
+	pub(crate) fn expect(&self, id: u64) {
+		self.state.lock().expect("never poisoned; qed").awaited.insert(id);
+	}
+
+	/// Waits until the marker `id` is reached and returns the messages logged before it, `None`
+	/// if it isn't reached within `timeout`.
+	pub(crate) fn wait(&self, id: u64, timeout: Duration) -> Option<Vec<String>> {
+		let state = self.state.lock().expect("never poisoned; qed");
+		let (mut state, _) = self
+			.reached
+			.wait_timeout_while(state, timeout, |state| !state.collected.contains_key(&id))
+			.expect("never poisoned; qed");
+		state.awaited.remove(&id);
+		state.collected.remove(&id)
+	}
+
+	fn push(&self, message: &str) {
+		let mut state = self.state.lock().expect("never poisoned; qed");
+		if state.pending.len() == MAX_TRAP_LOG_LINES {
+			state.pending.pop_front();
+		}
+		state.pending.push_back(message.to_owned());
+	}
+
+	fn reach(&self, id: u64) {
+		let mut state = self.state.lock().expect("never poisoned; qed");
+		let messages = state.pending.drain(..).collect();
+		if state.awaited.contains(&id) {
+			state.collected.insert(id, messages);
+			self.reached.notify_all();
+		}
+	}
+}
+
+/// Returns what WasmEdge logged for the trap the calling thread just ran into, e.g. the offsets
+/// of the instructions it was executing, which aren't reported anywhere else.
+///
+/// A marker is written to stderr after the trap, and the messages WasmEdge logged before it are
+/// returned once the forwarding thread reaches it. The messages can't be told apart by thread,
+/// so traps happening at the same time may get each other's. `None` unless the logs are
+/// forwarded, see [`redirect_wasmedge_logs`], or if the forwarding thread doesn't keep up.
+pub(crate) fn wasmedge_trap_log() -> Option<String> {
+	static NEXT_MARKER: AtomicU64 = AtomicU64::new(0);
+
+	if !FORWARDING.load(Ordering::Relaxed) {
+		return None
+	}
+
+	let id = NEXT_MARKER.fetch_add(1, Ordering::Relaxed);
+	TRAP_LOGS.expect(id);
+	// The line is short enough to reach the pipe in one piece even if others are writing.
+	if io::stderr()
+		.write_all(format!("{}{}\n", TRAP_LOG_MARKER, id).as_bytes())
+		.is_err()
+	{
+		// Stops waiting for the marker.
+		TRAP_LOGS.wait(id, Duration::ZERO);
+		return None
+	}
+	TRAP_LOGS.wait(id, TRAP_LOG_TIMEOUT).map(|messages| messages.join("\n"))
+}
+
+/// Forwards WasmEdge's own logs (e.g. `[2022-08-21 12:00:00.000] [error] execution failed`) into
+/// `log` under [`WASMEDGE_LOG_TARGET`], so that they are filtered and formatted like the logs of
+/// the node.
+///
+/// WasmEdge writes its logs straight to stderr and the version in use doesn't offer a callback
+/// for intercepting them. So stderr is redirected into a pipe read by a background thread, which
+/// forwards the lines in WasmEdge's format and writes everything else to the original stderr
+/// unchanged. The lines only reach stderr once they are complete, so what the process writes
+/// right before it aborts may be lost.
+///
+/// Only the first call redirects stderr, later ones return its outcome. This is called by the
+/// first runtime creation. Only supported on unix, elsewhere WasmEdge keeps logging to stderr.
+pub fn redirect_wasmedge_logs() -> io::Result<()> {
+	REDIRECTION.clone().map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+#[cfg(unix)]
+fn redirect_stderr() -> io::Result<()> {
+	use std::{fs::File, io::BufReader, os::unix::io::FromRawFd};
+
+	let mut fds = [0; 2];
+	// SAFETY: `fds` has room for the two descriptors of the pipe.
+	if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+		return Err(io::Error::last_os_error())
+	}
+	let [read_fd, write_fd] = fds;
+	// SAFETY: the read end of the pipe isn't used anywhere else.
+	let input = BufReader::new(unsafe { File::from_raw_fd(read_fd) });
+
+	// SAFETY: only duplicates stderr, which is always open.
+	let original_fd = unsafe { libc::dup(libc::STDERR_FILENO) };
+	if original_fd < 0 {
+		let error = io::Error::last_os_error();
+		// SAFETY: the write end of the pipe isn't used anywhere else.
+		unsafe { libc::close(write_fd) };
+		return Err(error)
+	}
+	// SAFETY: the duplicate is owned by the forwarding thread from now on.
+	let original = unsafe { File::from_raw_fd(original_fd) };
+
+	// The thread is started first, stderr must never end up in a pipe nobody reads.
+	let spawned = std::thread::Builder::new().name(String::from("wasmedge-log")).spawn(move || {
+		forward_lines(input, original, || (log::logger(), log::max_level()), &TRAP_LOGS)
+	});
+
+	// SAFETY: the write end of the pipe replaces stderr, it's closed afterwards either way. If
+	// stderr isn't replaced, closing it makes the thread see the end of the input and exit.
+	unsafe {
+		let redirected = match spawned {
+			Ok(_) if libc::dup2(write_fd, libc::STDERR_FILENO) >= 0 => {
+				FORWARDING.store(true, Ordering::Relaxed);
+				Ok(())
+			},
+			Ok(_) => Err(io::Error::last_os_error()),
+			Err(e) => Err(e),
+		};
+		libc::close(write_fd);
+		redirected
+	}
+}
+
+#[cfg(not(unix))]
+fn redirect_stderr() -> io::Result<()> {
+	Err(io::Error::new(io::ErrorKind::Other, "redirecting stderr is only supported on unix"))
+}
+
+/// Reads the lines of `input` until its end, forwarding the ones WasmEdge logged to the logger
+/// returned by `logger` and writing the others to `passthrough`.
+///
+/// The messages WasmEdge logged are also collected into `trap_logs`, which the markers written
+/// by [`wasmedge_trap_log`] are reported to rather than passed through.
+pub(crate) fn forward_lines<'a>(
+	mut input: impl BufRead,
+	mut passthrough: impl Write,
+	logger: impl Fn() -> (&'a dyn Log, LevelFilter),
+	trap_logs: &TrapLogs,
+) {
+	let mut line = Vec::new();
+	loop {
+		line.clear();
+		match input.read_until(b'\n', &mut line) {
+			Ok(0) | Err(_) => return,
+			Ok(_) => (),
+		}
+
+		let text = String::from_utf8_lossy(&line);
+		let text = text.trim_end_matches(&['\r', '\n'][..]);
+		if let Some(id) = text.strip_prefix(TRAP_LOG_MARKER).and_then(|id| id.parse().ok()) {
+			trap_logs.reach(id);
+			continue
+		}
+
+		let forwarded = match parse_wasmedge_log_line(text) {
+			Some((level, message)) => {
+				trap_logs.push(message);
+				let (logger, max_level) = logger();
+				if level <= max_level {
+					logger.log(
+						&Record::builder()
+							.level(level)
+							.target(WASMEDGE_LOG_TARGET)
+							.args(format_args!("{}", message))
+							.build(),
+					);
+				}
+				true
+			},
+			None => false,
+		};
+
+		if !forwarded {
+			// There is nowhere else to report the failure to.
+			let _ = passthrough.write_all(&line).and_then(|()| passthrough.flush());
+		}
+	}
+}
+
+/// Splits a line in WasmEdge's log format, e.g. `[2022-08-21 12:00:00.000] [error] execution
+/// failed`, into its level and its message.
+fn parse_wasmedge_log_line(line: &str) -> Option<(Level, &str)> {
+	let (_timestamp, rest) = line.strip_prefix('[')?.split_once("] [")?;
+	let (level, message) = rest.split_once("] ")?;
+	let level = match level {
+		"critical" | "error" => Level::Error,
+		"warning" => Level::Warn,
+		"info" => Level::Info,
+		"debug" => Level::Debug,
+		"trace" => Level::Trace,
+		_ => return None,
+	};
+
+	Some((level, message))
+}
@@ -0,0 +1,58 @@
+use crate::LOG_TARGET;
+use sc_executor_common::error::WasmError;
+use std::{
+	collections::HashSet,
+	ffi::CString,
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+extern "C" {
+	fn WasmEdge_PluginLoadFromPath(path: *const libc::c_char);
+}
+
+lazy_static::lazy_static! {
+	/// The paths of the plugins loaded so far.
+	static ref LOADED_PLUGINS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+/// Loads the WasmEdge plugins at the given paths. A path may point either to a plugin library
+/// or to a directory containing plugins.
+///
+/// Plugins are process-global, once loaded they are available to all the runtimes created
+/// afterwards. Loading is idempotent: paths which have already been loaded are skipped.
+///
+/// Since WasmEdge doesn't report failures to load a plugin, only a path which doesn't exist is
+/// detected as an error.
+pub fn load_plugins<P: AsRef<Path>>(paths: &[P]) -> Result<(), WasmError> {
+	let mut loaded_plugins = LOADED_PLUGINS
+		.lock()
+		.map_err(|_| WasmError::Other(String::from("failed to lock the loaded plugins")))?;
+
+	for path in paths {
+		let path = path.as_ref().canonicalize().map_err(|e| {
+			WasmError::Other(format!(
+				"cannot load WasmEdge plugin {}: {}",
+				path.as_ref().display(),
+				e
+			))
+		})?;
+
+		if loaded_plugins.contains(&path) {
+			continue
+		}
+
+		let c_path = path.to_str().and_then(|path| CString::new(path).ok()).ok_or_else(|| {
+			WasmError::Other(format!("invalid WasmEdge plugin path: {}", path.display()))
+		})?;
+
+		log::debug!(target: LOG_TARGET, "Loading WasmEdge plugins from {}", path.display());
+
+		// SAFETY: the path is a valid NUL-terminated string which outlives the call.
+		unsafe { WasmEdge_PluginLoadFromPath(c_path.as_ptr()) };
+
+		loaded_plugins.insert(path);
+	}
+
+	Ok(())
+}
@@ -1,32 +1,494 @@
-use crate::{host::HostState, instance_wrapper::InstanceWrapper, util};
+use crate::{
+	artifact::{self, ArtifactHeader},
+	host::{HostCall, HostCallMode, HostState, SandboxError},
+	imports::{host_function_registry, HostFunctionRegistry, ImportReport},
+	instance_wrapper::{Frame, InitialState, InstanceWrapper},
+	util, LOG_TARGET,
+};
+use codec::{Decode, Encode};
 use sc_allocator::{AllocationStats, FreeingBumpHeapAllocator};
 use sc_executor_common::{
-	error::{Result, WasmError},
+	error::{Error, Result, WasmError},
 	runtime_blob::{
 		self, DataSegmentsSnapshot, ExposedMutableGlobalsSet, GlobalsSnapshot, RuntimeBlob,
 	},
+	util::checked_range,
 	wasm_runtime::{InvokeMethod, WasmInstance, WasmModule},
 };
 use sp_runtime_interface::unpack_ptr_and_len;
 use sp_wasm_interface::{Function, HostFunctions, Pointer, Value, WordSize};
 use std::{
-	path::Path,
-	sync::{Arc, Mutex},
+	collections::{HashMap, HashSet},
+	ops::Range,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Condvar, Mutex, Once,
+	},
+	time::{Duration, Instant},
 };
 use wasmedge_sdk::{
-	config::{CommonConfigOptions, CompilerConfigOptions, ConfigBuilder, RuntimeConfigOptions},
-	Compiler, CompilerOptimizationLevel, CompilerOutputFormat, Global, Module,
+	config::{
+		CommonConfigOptions, CompilerConfigOptions, ConfigBuilder, RuntimeConfigOptions,
+		StatisticsConfigOptions,
+	},
+	Compiler, CompilerOptimizationLevel, CompilerOutputFormat, ExternalInstanceType, Global,
+	ImportObject, Module, Mutability, Statistics, ValType,
 };
 
+/// A callback invoked with the name and the arguments of every host function called by the
+/// runtime, right before the host function is executed.
+pub type HostCallTracer = Arc<dyn Fn(&str, &[Value]) + Send + Sync>;
+
+/// A callback invoked with the error of every failed call into the runtime, see
+/// [`Config::on_trap`].
+pub type TrapCallback = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// A callback invoked with the message of every runtime panic, see [`Config::on_runtime_panic`].
+pub type PanicCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// A consumer of the [`TraceEvent`]s of every call, see [`Config::trace_sink`].
+pub type TraceSink = Arc<dyn Fn(TraceEvent) + Send + Sync>;
+
+/// An event of the call trace passed to the [`Config::trace_sink`].
+///
+/// The events of a call are properly nested: every enter event is followed by the matching exit
+/// event, with the events of the host functions called in between.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+	/// A call into the runtime started. The `method` is the name of the called export, or a
+	/// description of the called table entry.
+	CallEnter { method: String, time: Instant },
+	/// A call into the runtime finished, whether it succeeded or not.
+	CallExit { method: String, time: Instant },
+	/// The runtime called a host function.
+	HostEnter { name: &'static str, time: Instant },
+	/// A host function returned to the runtime, whether it succeeded or not.
+	HostExit { name: &'static str, time: Instant },
+}
+
+/// A check of the output of a call, see [`Config::output_validator`].
+pub type OutputValidator = Arc<dyn Fn(&[u8]) -> std::result::Result<(), String> + Send + Sync>;
+
+/// A custom pass rewriting the runtime code before it is compiled, see [`Config::blob_transform`].
+pub type BlobTransform =
+	Arc<dyn Fn(RuntimeBlob) -> std::result::Result<RuntimeBlob, WasmError> + Send + Sync>;
+
 pub struct Config {
 	/// The WebAssembly standard requires all imports of an instantiated module to be resolved,
 	/// otherwise, the instantiation fails. If this option is set to `true`, then this behavior is
 	/// overriden and imports that are requested by the module and not provided by the host
 	/// functions will be resolved using stubs. These stubs will trap upon a call.
+	///
+	/// The default is `false`.
 	pub allow_missing_func_imports: bool,
 
 	/// Tuning of various semantics of the wasmedge executor.
+	///
+	/// The default is [`Semantics::default`].
 	pub semantics: Semantics,
+
+	/// The offset in linear memory at which the allocator's heap starts.
+	///
+	/// By default the heap starts at the value of the `__heap_base` global exported by the
+	/// runtime. Setting this allows embedders to reserve the memory right after `__heap_base`
+	/// for their own use (e.g. for shared buffers). The override must not be lower than
+	/// `__heap_base`, otherwise the instance won't be created.
+	///
+	/// The default is `None`.
+	pub heap_base_override: Option<u32>,
+
+	/// The maximum number of instances of a runtime which can be alive at the same time.
+	///
+	/// WasmEdge fails in a rather cryptic way once its store runs out of instances. Setting this
+	/// makes [`WasmModule::new_instance`] return a clear error instead once the limit is reached.
+	/// A slot is freed up as soon as an instance is dropped.
+	///
+	/// The default is `None`, i.e. unlimited.
+	pub max_concurrent_instances: Option<usize>,
+
+	/// What happens when a new instance is requested while [`Config::max_concurrent_instances`]
+	/// instances are alive, i.e. when the pool of instance slots is exhausted.
+	///
+	/// The default is [`PoolCheckoutPolicy::Fail`].
+	pub pool_checkout_policy: PoolCheckoutPolicy,
+
+	/// If specified, the tracer is invoked on every host function call made by the runtime.
+	///
+	/// This is a lightweight way to see which host functions a runtime calls and with what
+	/// arguments. The arguments are only decoded for the tracer when it is set, so leaving this
+	/// as `None` has no overhead.
+	///
+	/// The default is `None`.
+	pub host_call_tracer: Option<HostCallTracer>,
+
+	/// Paths of WasmEdge plugins (e.g. WASI-NN) to load before the runtime is created. A path
+	/// may point either to a plugin library or to a directory containing plugins.
+	///
+	/// Note that plugins are process-global: once loaded they stay loaded for the rest of the
+	/// process and are visible to all the runtimes. Loading is idempotent, a path which has
+	/// already been loaded is skipped. See also [`load_plugins`](crate::load_plugins).
+	///
+	/// The default is empty.
+	pub plugins: Vec<PathBuf>,
+
+	/// Key-value pairs the runtime can query, e.g. to pass feature flags or endpoints to
+	/// off-chain workers without recompiling the runtime.
+	///
+	/// The runtime reads a value by importing the host function
+	/// `ext_wasmedge_environment_get_version_1(key: i64) -> i64` from the `env` module, which is
+	/// provided automatically. The key is passed as a packed pointer and length of its UTF-8
+	/// bytes. The value is returned as a packed pointer and length of a buffer allocated with
+	/// the runtime's allocator, or `-1` if there is no such key.
+	///
+	/// The default is empty.
+	pub environment: HashMap<String, String>,
+
+	/// Record every host function call made by the runtime, i.e. the name of the host function,
+	/// its arguments and its result, which can then be read with
+	/// [`WasmEdgeInstance::host_calls`].
+	///
+	/// The recorded calls can be fed to [`WasmEdgeInstance::replay`] to reproduce a failing call
+	/// without the node environment the host functions rely on.
+	///
+	/// The default is `false`.
+	pub record_host_calls: bool,
+
+	/// Which traps are reported by their own [`Error`] variant rather than as a generic
+	/// [`Error::AbortedDueToTrap`].
+	///
+	/// This only affects how a trap is reported to the caller, the execution itself always
+	/// traps as required by the WebAssembly standard.
+	///
+	/// The default is to report all traps as generic traps.
+	pub trap_policy: TrapPolicy,
+
+	/// A custom pass applied to the runtime code before it is compiled, e.g. for gas metering or
+	/// coverage instrumentation.
+	///
+	/// The transform runs after all the built-in passes: the deterministic stack limit
+	/// instrumentation, the exposure of the mutable globals for snapshotting them, the conversion
+	/// of the memory import into an export and the addition of the
+	/// [`Semantics::extra_heap_pages`]. Hence the code it adds isn't covered by the stack limit,
+	/// and the mutable globals it adds aren't restored between the calls with
+	/// [`Semantics::fast_instance_reuse`]. Without it, such globals prevent resetting the
+	/// instances, which are instantiated again for every call instead.
+	///
+	/// The transform only applies to runtimes compiled by [`create_runtime`]. The precompiled
+	/// artifacts and the [`CompiledModule`]s are compiled without it.
+	///
+	/// The default is `None`.
+	pub blob_transform: Option<BlobTransform>,
+
+	/// Let a deallocation of memory which isn't allocated, e.g. a double free, be logged and
+	/// ignored instead of failing the call.
+	///
+	/// This is meant for debugging runtimes misusing the allocator, so that the execution can
+	/// continue past the first offending deallocation.
+	///
+	/// The default is `false`.
+	pub ignore_double_free: bool,
+
+	/// The names of the exports which are checked to be entry points with the right signature
+	/// when the runtime is created, e.g. `Core_version`.
+	///
+	/// Otherwise a bad entry point is only detected once it is called, which might be in the
+	/// middle of a block import. Listing the entry points the node relies on moves the failure to
+	/// the startup, where it is easier to handle.
+	///
+	/// The default is empty.
+	pub validate_entry_points: Vec<String>,
+
+	/// If specified, the callback is invoked with the error of every call into the runtime which
+	/// fails, e.g. because of a trap.
+	///
+	/// A precompiled artifact which traps where the same runtime compiled afresh doesn't is
+	/// likely corrupt or was produced by a different version of WasmEdge. This lets a node
+	/// notice such traps and e.g. remove the artifact from its cache to have it recompiled.
+	///
+	/// The callback is invoked in the middle of the call, so it must not call into the executor
+	/// itself.
+	///
+	/// The default is `None`.
+	pub on_trap: Option<TrapCallback>,
+
+	/// Measure the time spent in host functions during every call, which is returned by
+	/// [`WasmInstance::call_with_allocation_stats`] along with the allocation stats, and can be
+	/// read with [`WasmEdgeInstance::host_time`] as well.
+	///
+	/// This helps to diagnose runtimes which leave too much of the work, e.g. the hashing, to the
+	/// host functions. Only the execution of the host functions themselves is measured.
+	///
+	/// The default is `false`.
+	pub measure_host_time: bool,
+
+	/// If specified, a call is aborted with an error once the host functions called by it took
+	/// longer than this in total. Implies [`Config::measure_host_time`].
+	///
+	/// The limit is checked whenever a host function returns, so a single slow host function
+	/// isn't interrupted, only the call is aborted afterwards.
+	///
+	/// The default is `None`.
+	pub max_host_time: Option<Duration>,
+
+	/// If specified, the validator is invoked with the output of every successful call, and an
+	/// error returned by it fails the call.
+	///
+	/// This allows rejecting a malformed output, e.g. a truncated one or one with a wrong
+	/// checksum appended by the runtime, with a descriptive error before it is used. The output
+	/// fed to the sink of [`WasmEdgeInstance::call_streaming`] isn't validated, since it is
+	/// processed before the whole output is seen.
+	///
+	/// The default is `None`.
+	pub output_validator: Option<OutputValidator>,
+
+	/// If specified, the maximum number of sandbox invocations which may be nested, e.g. by a
+	/// supervisor function invoking a sandboxed instance again. An invocation exceeding it fails
+	/// with `ERR_EXECUTION`, and [`WasmEdgeInstance::last_sandbox_error`] returns
+	/// [`SandboxError::DepthExceeded`](crate::SandboxError::DepthExceeded).
+	///
+	/// Unlike the [`Semantics::deterministic_stack_limit`], this protects the native stack of the
+	/// host, which every nested invocation takes a share of.
+	///
+	/// The default is `None`.
+	pub max_sandbox_depth: Option<u32>,
+
+	/// If specified, the maximum number of arguments a sandbox invocation may pass. An
+	/// invocation passing more fails with `ERR_EXECUTION` before its arguments are decoded.
+	///
+	/// This protects the host against crafted argument buffers claiming a huge number of
+	/// arguments.
+	///
+	/// The default is `None`.
+	pub max_sandbox_args: Option<u32>,
+
+	/// If specified, the callback is invoked with the message of every panic of the runtime, as
+	/// soon as the runtime reports it.
+	///
+	/// This lets the embedder route the panics to e.g. its crash reporting. The call still fails
+	/// with [`Error::AbortedDueToPanic`] as usual.
+	///
+	/// The default is `None`.
+	pub on_runtime_panic: Option<PanicCallback>,
+
+	/// Keep the code WasmEdge compiled, i.e. the code after all the instrumentation passes and
+	/// the [`Config::blob_transform`] were applied, see [`WasmEdgeRuntime::prepared_blob_bytes`].
+	///
+	/// This lets developers extract exactly what was compiled for offline analysis, at the cost
+	/// of keeping the whole code in memory for the lifetime of the runtime. Only has an effect on
+	/// [`create_runtime`], the code isn't available to the other ways of creating a runtime.
+	///
+	/// The default is `false`.
+	pub retain_prepared_blob: bool,
+
+	/// If specified, the sink receives a [`TraceEvent`] whenever a call into the runtime or a
+	/// host function called by the runtime starts or finishes.
+	///
+	/// The events carry timestamps, so that they can be aggregated into e.g. a flamegraph
+	/// showing how the time of a call is split between the runtime and the host. Leaving this
+	/// as `None` has no overhead.
+	///
+	/// The default is `None`.
+	pub trace_sink: Option<TraceSink>,
+
+	/// If specified, the sandbox store of every call starts with room for this many sandbox
+	/// instances and as many sandbox memories.
+	///
+	/// The store grows as needed either way. Runtimes creating many sandbox instances during a
+	/// call, e.g. executing contracts in batches, avoid reallocating it over and over by
+	/// reserving its capacity upfront.
+	///
+	/// The default is `None`.
+	pub sandbox_store_capacity: Option<usize>,
+
+	/// Tunes the allocator serving the allocations of the runtime.
+	///
+	/// The default is [`AllocatorConfig::default`].
+	pub allocator_config: AllocatorConfig,
+
+	/// If specified, the maximum number of sandbox memories which may exist at the same time
+	/// during a call. Creating another one fails with `ERR_MODULE` until one of them is torn
+	/// down.
+	///
+	/// Together with the maximum number of pages of every memory, this bounds the memory all
+	/// the sandboxes of a call may use, e.g. when a contract creates many small memories.
+	///
+	/// The default is `None`.
+	pub max_sandbox_memories: Option<u32>,
+
+	/// If the code doesn't pass WasmEdge's validation, validate it again with `wasmparser` and
+	/// add the rule it violates, the function and the offset to the error.
+	///
+	/// WasmEdge only reports which kind of problem it found, which makes it hard to track down
+	/// in a malformed runtime. The second validation only happens when the first one fails, so
+	/// this costs nothing for valid code.
+	///
+	/// The default is `false`.
+	pub detailed_validation: bool,
+
+	/// Resolve function imports from any module by their name, rather than only from `env`.
+	///
+	/// By default a runtime importing anything from another module can't be instantiated. Some
+	/// toolchains emit imports from other modules, e.g. `wasi_snapshot_preview1`; with this set
+	/// they're resolved against the host functions like the ones from `env`, or stubbed if
+	/// [`Config::allow_missing_func_imports`] is set. The [`ImportReport`] names them
+	/// `module:name`.
+	///
+	/// The default is `false`.
+	pub accept_any_import_module: bool,
+
+	/// Provide the host function `ext_wasmedge_host_time_version_1() -> i64`, which returns the
+	/// current Unix time in milliseconds, to the runtimes importing it from the `env` module.
+	///
+	/// This is meant for off-chain workers, which need a wall clock, so that every runtime
+	/// doesn't have to wire its own. **The time is non-deterministic**, it differs between the
+	/// nodes and between the calls, so this must never be enabled for executing consensus code.
+	///
+	/// The default is `false`.
+	pub provide_host_time: bool,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			allow_missing_func_imports: false,
+			semantics: Default::default(),
+			heap_base_override: None,
+			max_concurrent_instances: None,
+			pool_checkout_policy: Default::default(),
+			host_call_tracer: None,
+			plugins: Vec::new(),
+			environment: Default::default(),
+			record_host_calls: false,
+			trap_policy: Default::default(),
+			blob_transform: None,
+			ignore_double_free: false,
+			validate_entry_points: Vec::new(),
+			on_trap: None,
+			measure_host_time: false,
+			max_host_time: None,
+			output_validator: None,
+			max_sandbox_depth: None,
+			max_sandbox_args: None,
+			on_runtime_panic: None,
+			retain_prepared_blob: false,
+			trace_sink: None,
+			sandbox_store_capacity: None,
+			allocator_config: Default::default(),
+			max_sandbox_memories: None,
+			detailed_validation: false,
+			accept_any_import_module: false,
+			provide_host_time: false,
+		}
+	}
+}
+
+impl Config {
+	/// Returns the maximum size in bytes of the linear memory actually applied, i.e. the
+	/// [`Semantics::max_memory_size`] rounded down to a whole number of wasm pages, or `None`
+	/// if unlimited.
+	pub fn effective_max_memory_bytes(&self) -> Option<usize> {
+		self.semantics.max_memory_size.map(|max_memory_size| {
+			max_memory_pages(max_memory_size) as usize * util::WASM_PAGE_SIZE
+		})
+	}
+}
+
+/// Converts the [`Semantics::max_memory_size`] into the number of wasm pages WasmEdge limits
+/// the linear memory to.
+fn max_memory_pages(max_memory_size: usize) -> u32 {
+	(max_memory_size / util::WASM_PAGE_SIZE) as u32
+}
+
+/// What happens when a new instance is requested while all the slots allowed by
+/// [`Config::max_concurrent_instances`] are taken, see [`Config::pool_checkout_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoolCheckoutPolicy {
+	/// Fail with an "instance limit reached" error right away.
+	#[default]
+	Fail,
+	/// Create the instance anyway, exceeding the limit. The instances over the limit still
+	/// count as alive, so the limit only applies again once enough of them are dropped.
+	CreateUnbounded,
+	/// Wait up to the given time for an instance to be dropped, and fail with an "instance limit
+	/// reached" error if none is. Useful for riding out load spikes.
+	Block(Duration),
+}
+
+/// Selects the trap classes reported by a dedicated [`Error`] variant, see
+/// [`Config::trap_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrapPolicy {
+	/// Report an integer division (or remainder) by zero as
+	/// [`Error::AbortedDueToDivisionByZero`].
+	pub division_by_zero: bool,
+
+	/// Report an integer overflow, e.g. dividing the minimal signed integer by `-1`, as
+	/// [`Error::AbortedDueToIntegerOverflow`].
+	pub integer_overflow: bool,
+}
+
+/// Tunes the allocator serving the allocations of the runtime, see [`Config::allocator_config`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorConfig {
+	/// Fill the memory freed by the runtime with [`AllocatorConfig::POISON`], so that a runtime
+	/// using memory after freeing it reads garbage rather than the data it expects, and fails
+	/// early rather than subtly.
+	///
+	/// Only the memory allocated and freed through the host functions is poisoned. This is a
+	/// debugging aid: it changes the contents of the linear memory a runtime observes, so a
+	/// runtime using memory after freeing it behaves differently depending on this setting.
+	/// **It must stay disabled on nodes taking part in consensus.**
+	pub poison_freed_memory: bool,
+}
+
+impl AllocatorConfig {
+	/// The byte filling the freed memory if [`AllocatorConfig::poison_freed_memory`] is enabled.
+	pub const POISON: u8 = 0xdd;
+}
+
+/// Selects the statistics WasmEdge gathers during the execution, see [`Semantics::statistics`].
+///
+/// Every statistic slows the execution down, so only the ones needed should be enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatisticsConfig {
+	/// Count the executed instructions, required by [`WasmEdgeInstance::call_with_metering`].
+	pub instr_count: bool,
+
+	/// Sum up the cost of the executed instructions, required by
+	/// [`WasmEdgeInstance::total_cost`].
+	pub cost: bool,
+
+	/// Measure the execution time, required by [`WasmEdgeInstance::instructions_per_second`].
+	pub time: bool,
+}
+
+/// The format of the artifacts compiled by [`prepare_runtime_artifact`], see
+/// [`Semantics::artifact_format`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArtifactFormat {
+	/// A native shared library.
+	///
+	/// The library is only valid on the machine which compiled it, so such artifacts are marked
+	/// as not shareable, see [`is_artifact_shareable`](crate::is_artifact_shareable).
+	#[default]
+	Native,
+	/// A WebAssembly module with the compiled code embedded in a custom section.
+	///
+	/// WasmEdge only uses the compiled code if it's compatible with the machine loading the
+	/// artifact and falls back to interpreting the module otherwise, so such artifacts are marked
+	/// as shareable.
+	Universal,
+}
+
+impl ArtifactFormat {
+	/// Whether artifacts of this format may be distributed to other machines.
+	pub fn is_shareable(self) -> bool {
+		match self {
+			ArtifactFormat::Native => false,
+			ArtifactFormat::Universal => true,
+		}
+	}
 }
 
 /// Knobs for deterministic stack height limiting.
@@ -74,6 +536,8 @@ pub struct Semantics {
 	///
 	/// Since this feature depends on instrumentation, it can be set only if runtime is
 	/// instantiated using the runtime blob, e.g. using [`create_runtime`].
+	///
+	/// The default is `false`.
 	// I.e. if [`CodeSupplyMode::Verbatim`] is used.
 	pub fast_instance_reuse: bool,
 
@@ -86,11 +550,15 @@ pub struct Semantics {
 	///
 	/// Since this feature depends on instrumentation, it can be set only if runtime is
 	/// instantiated using the runtime blob, e.g. using [`create_runtime`].
+	///
+	/// The default is `None`.
 	// I.e. if [`CodeSupplyMode::Verbatim`] is used.
 	pub deterministic_stack_limit: Option<DeterministicStackLimit>,
 
 	/// The number of extra WASM pages which will be allocated
 	/// on top of what is requested by the WASM blob itself.
+	///
+	/// The default is `0`.
 	pub extra_heap_pages: u64,
 
 	/// The total amount of memory in bytes an instance can request.
@@ -108,6 +576,270 @@ pub struct Semantics {
 	///
 	/// The default is `None`.
 	pub max_memory_size: Option<usize>,
+
+	/// When [`Semantics::fast_instance_reuse`] is enabled, limit the decommit performed after
+	/// each call to the pages of the linear memory the call dirtied, instead of the whole memory.
+	///
+	/// On Linux the dirtied pages are tracked by the kernel and read from `/proc/self/pagemap`,
+	/// so every page the call wrote to is decommitted wherever it is, while a call touching a
+	/// small part of a large memory only pays for that part. On the other platforms the whole
+	/// memory is decommitted as without this option.
+	///
+	/// The default is `false`.
+	pub decommit_only_dirty: bool,
+
+	/// When [`Semantics::fast_instance_reuse`] is enabled, decommit the linear memory after each
+	/// call.
+	///
+	/// Decommitting requires a syscall (or zeroing the whole memory where no suitable syscall is
+	/// available), which can be skipped for short-lived processes or when memory pressure is not
+	/// a concern.
+	///
+	/// Note that disabling this means that the memory touched by a call is never given back to
+	/// the OS: the resident memory of every instance grows up to the largest amount of memory
+	/// any call has used and stays there until the instance is dropped. With many concurrent
+	/// instances this can add up quickly.
+	///
+	/// The default is `true`.
+	pub decommit_after_call: bool,
+
+	/// When [`Semantics::fast_instance_reuse`] is enabled, zero the linear memory of a new
+	/// instance before its first call.
+	///
+	/// Only the data segments are written into the memory before every call, so the first call
+	/// otherwise observes whatever else the memory contained after the instantiation, e.g. the
+	/// writes of a start function or anything WasmEdge leaves there. Enabling this makes the
+	/// first call see the same memory as all the following ones, regardless of how the memory
+	/// is initialized by the WasmEdge version in use.
+	///
+	/// The default is `false`.
+	pub zero_memory_on_first_use: bool,
+
+	/// Skip the work done around every call which isn't part of executing the runtime, so that
+	/// weight benchmarks measure a cost closer to the execution itself.
+	///
+	/// This skips the decommit after each call and the bounds check of the output performed
+	/// before allocating the output buffer, regardless of the other settings.
+	///
+	/// **This must never be enabled in production**: the memory of the instances is never given
+	/// back to the OS, and a call with the fast instance reuse enabled observes the memory left
+	/// behind by the previous calls. Hence this only has an effect if the crate is built with the
+	/// `benchmarking` feature and is ignored otherwise.
+	///
+	/// The default is `false`.
+	pub benchmark_unchecked: bool,
+
+	/// Record the maximum logical stack depth reached during each call, which can then be read
+	/// with [`WasmEdgeInstance::max_stack_depth`].
+	///
+	/// This shows runtime authors how much headroom is left below the
+	/// [`Semantics::deterministic_stack_limit`], so that a runtime getting close to the limit is
+	/// noticed before a minor change makes it overflow. The tracking is implemented by another
+	/// instrumentation pass and thus only has an effect if the deterministic stack limit is
+	/// enabled.
+	///
+	/// The default is `false`.
+	pub track_max_stack_depth: bool,
+
+	/// The maximum size in bytes of an artifact compiled by [`prepare_runtime_artifact`].
+	///
+	/// A runtime can compile to a pathologically large artifact, which matters e.g. for nodes
+	/// compiling runtimes submitted by others. An artifact exceeding the limit is deleted and the
+	/// compilation fails.
+	///
+	/// The default is `None`.
+	pub max_artifact_size: Option<usize>,
+
+	/// Enable the sign-extension operators proposal, i.e. the `i32.extend8_s` family of
+	/// instructions, which LLVM emits by default.
+	///
+	/// This is set explicitly rather than relying on the defaults of the WasmEdge version in use,
+	/// so that whether a runtime is valid doesn't depend on it.
+	///
+	/// The default is `true`.
+	pub enable_sign_extension: bool,
+
+	/// Enable the non-trapping float-to-int conversions proposal, i.e. the
+	/// `i32.trunc_sat_f32_s` family of instructions.
+	///
+	/// Like [`Semantics::enable_sign_extension`], this is always passed to WasmEdge explicitly.
+	/// Note that [`RuntimeBlob`] can't parse these instructions yet, so only modules which
+	/// don't go through it can actually use them.
+	///
+	/// The default is `true`.
+	pub enable_saturating_float_to_int: bool,
+
+	/// The statistics WasmEdge gathers during the execution.
+	///
+	/// Counting the instructions and measuring their cost is compiled into the code, so these two
+	/// are part of the [`Semantics::artifact_key`].
+	///
+	/// The default is to gather none.
+	pub statistics: StatisticsConfig,
+
+	/// The format of the artifacts compiled by [`prepare_runtime_artifact`].
+	///
+	/// The default is [`ArtifactFormat::Native`].
+	pub artifact_format: ArtifactFormat,
+
+	/// Make sure the pages of the linear memory are actually given back to the OS by the time a
+	/// decommit finishes.
+	///
+	/// On Linux the memory is decommitted with `madvise(MADV_DONTNEED)`, which only advises the
+	/// kernel and may leave some of the pages resident for a while, e.g. when they are backed
+	/// by a transparent huge page. With this enabled the decommitted range is checked with
+	/// `mincore` afterwards, and advised again as long as any of its pages stay resident. This
+	/// makes the memory accounting of the process accurate right after every decommit, which
+	/// matters for tests and deployments with tight memory limits, at the cost of a few more
+	/// syscalls per decommit. It has no effect on the other platforms.
+	///
+	/// The default is `false`.
+	pub synchronous_decommit: bool,
+
+	/// Require the linear memory to be followed by inaccessible guard pages, so that an access
+	/// past its end which slips through the bounds checks faults rather than reading or writing
+	/// whatever memory happens to be adjacent.
+	///
+	/// The memory is mapped by WasmEdge, which reserves such a region after it on 64-bit
+	/// platforms. This is a hardening and debugging aid making sure that's the case: every
+	/// instantiation checks that the memory is followed by a mapping without any access
+	/// permissions, and fails otherwise. The check reads `/proc/self/maps`, so it's only
+	/// available on Linux and instantiating fails on the other platforms.
+	///
+	/// The default is `false`.
+	pub guard_pages: bool,
+
+	/// Replace every NaN produced by a floating point instruction with the canonical NaN, i.e.
+	/// the positive quiet NaN without any payload.
+	///
+	/// The WebAssembly specification leaves the sign and the payload of such a NaN up to the
+	/// platform, and WasmEdge doesn't canonicalize them: e.g. `0.0 / 0.0` results in a negative
+	/// NaN on x86, but in a positive one on ARM, and a NaN operand's payload may or may not be
+	/// propagated. A runtime which observes the bits of a NaN, e.g. by storing it in memory or
+	/// in a global, or by reinterpreting it as an integer, can thus end up in a different state
+	/// on different validators. With this enabled the results of the arithmetic instructions,
+	/// the float conversions and the calls into float returning host functions are rewritten by
+	/// an instrumentation pass, so that the bits are the same everywhere, at the cost of a few
+	/// instructions after each of them.
+	///
+	/// The instrumentation is compiled into the code, so this is a part of the
+	/// [`Semantics::artifact_key`].
+	///
+	/// The default is `false`.
+	pub canonicalize_nans: bool,
+
+	/// Make the calls interruptible, so that a call can be aborted once it runs for too long,
+	/// see [`WasmEdgeInstance::call_streaming_with_timeout`].
+	///
+	/// WasmEdge can't interrupt a running call by itself, so the code is instrumented to call
+	/// into the host at the start of every block of straight-line code, where the host aborts
+	/// the call if its deadline passed. This makes the calls considerably slower and is meant
+	/// for debugging runtimes which hang. The instrumentation is compiled into the code, so this
+	/// is a part of the [`Semantics::artifact_key`].
+	///
+	/// The default is `false`.
+	pub interruptible: bool,
+}
+
+impl Default for Semantics {
+	fn default() -> Self {
+		Semantics {
+			fast_instance_reuse: false,
+			deterministic_stack_limit: None,
+			extra_heap_pages: 0,
+			max_memory_size: None,
+			decommit_only_dirty: false,
+			decommit_after_call: true,
+			zero_memory_on_first_use: false,
+			benchmark_unchecked: false,
+			track_max_stack_depth: false,
+			max_artifact_size: None,
+			enable_sign_extension: true,
+			enable_saturating_float_to_int: true,
+			statistics: Default::default(),
+			artifact_format: Default::default(),
+			synchronous_decommit: false,
+			guard_pages: false,
+			canonicalize_nans: false,
+			interruptible: false,
+		}
+	}
+}
+
+impl Semantics {
+	/// Returns a key identifying the artifacts compiled with these semantics.
+	///
+	/// An artifact can only be loaded with semantics having the same key as the ones it was
+	/// compiled with. Only the settings which affect the compiled output are taken into account,
+	/// so e.g. semantics differing only in [`Semantics::max_memory_size`] share the same key. This
+	/// makes the key suitable for naming cached artifacts.
+	///
+	/// The key is the hash of [`Semantics::compilation_settings`].
+	pub fn artifact_key(&self) -> [u8; 32] {
+		sp_core_hashing::blake2_256(&self.compilation_settings())
+	}
+
+	/// Returns the serialization of the settings which affect the compiled output.
+	///
+	/// Every setting is written explicitly in a fixed order, the flags as a single byte and the
+	/// numbers in little endian, so the serialization doesn't depend on the layout of this
+	/// struct or on the platform. Hence artifacts compiled by different builds, even big endian
+	/// ones, remain comparable. Any change of the format must bump its leading version byte.
+	pub fn compilation_settings(&self) -> Vec<u8> {
+		// Destructure exhaustively, so that adding a field requires deciding whether it belongs
+		// to the serialization.
+		let Semantics {
+			fast_instance_reuse,
+			deterministic_stack_limit,
+			extra_heap_pages,
+			track_max_stack_depth,
+			enable_sign_extension,
+			enable_saturating_float_to_int,
+			statistics,
+			artifact_format,
+			canonicalize_nans,
+			interruptible,
+			max_memory_size: _,
+			synchronous_decommit: _,
+			guard_pages: _,
+			decommit_only_dirty: _,
+			decommit_after_call: _,
+			zero_memory_on_first_use: _,
+			benchmark_unchecked: _,
+			max_artifact_size: _,
+		} = self;
+
+		let mut bytes = vec![COMPILATION_SETTINGS_VERSION];
+		bytes.extend_from_slice(&(COMPILER_SETTINGS.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(COMPILER_SETTINGS.as_bytes());
+		bytes.push(*fast_instance_reuse as u8);
+		match deterministic_stack_limit {
+			Some(limit) => {
+				bytes.push(1);
+				bytes.extend_from_slice(&limit.logical_max.to_le_bytes());
+			},
+			None => bytes.push(0),
+		}
+		bytes.extend_from_slice(&extra_heap_pages.to_le_bytes());
+		bytes.push((deterministic_stack_limit.is_some() && *track_max_stack_depth) as u8);
+		bytes.push(*enable_sign_extension as u8);
+		bytes.push(*enable_saturating_float_to_int as u8);
+		bytes.push(statistics.instr_count as u8);
+		bytes.push(statistics.cost as u8);
+		bytes.push(match artifact_format {
+			ArtifactFormat::Native => 0,
+			ArtifactFormat::Universal => 1,
+		});
+		bytes.push(*canonicalize_nans as u8);
+		bytes.push(*interruptible as u8);
+		bytes
+	}
+
+	/// Whether [`Semantics::benchmark_unchecked`] is in effect, which requires the
+	/// `benchmarking` feature.
+	fn benchmark_unchecked(&self) -> bool {
+		cfg!(feature = "benchmarking") && self.benchmark_unchecked
+	}
 }
 
 /// Data required for creating instances with the fast instance reuse strategy.
@@ -119,92 +851,635 @@ struct InstanceSnapshotData {
 /// A `WasmModule` implementation using wasmtime to compile the runtime module to machine code
 /// and execute the compiled code.
 pub struct WasmEdgeRuntime {
-	snapshot_data: Option<InstanceSnapshotData>,
+	snapshot_data: Option<Arc<InstanceSnapshotData>>,
+	/// The data used for resetting instances instead of instantiating them again when the fast
+	/// instance reuse is disabled, if the module allows it. See [`reset_data`].
+	reset_data: Option<Arc<InstanceSnapshotData>>,
 	host_functions: Vec<&'static dyn Function>,
+	/// The `host_functions` keyed by their names, for resolving the imports of the instances.
+	host_function_registry: HostFunctionRegistry,
+	/// The [`Config::environment`], shared by the instances.
+	environment: Arc<HashMap<String, String>>,
 	module: Arc<Module>,
+	/// The size in bytes of the code the module was compiled from.
+	code_size: usize,
+	/// The code the module was compiled from, see [`Config::retain_prepared_blob`].
+	prepared_blob: Option<Arc<[u8]>>,
 	config: Config,
+	instance_slots: Arc<InstanceSlots>,
+	/// The total size in bytes of the linear memories of the live instances.
+	instance_memory: Arc<AtomicUsize>,
 }
 
-impl WasmModule for WasmEdgeRuntime {
-	fn new_instance(&self) -> Result<Box<dyn WasmInstance>> {
-		let mut instance_wrapper = Box::new(InstanceWrapper::new(&self.config.semantics)?);
-
-		crate::imports::prepare_imports(
-			&mut instance_wrapper,
-			&self.module,
-			&self.host_functions,
-			self.config.allow_missing_func_imports,
-		)
-		.map_err(|e| WasmError::Other(format!("fail to register imports: {}", e)))?;
+impl WasmEdgeRuntime {
+	/// Creates a new runtime out of a module compiled by [`compile_module`].
+	///
+	/// Any number of runtimes can be created from the same [`CompiledModule`], all of them share
+	/// the compiled code in memory, while their instances are completely independent.
+	///
+	/// `config.semantics` must be compatible with the semantics the module was compiled with (see
+	/// [`Semantics::artifact_key`]), otherwise an error is returned.
+	pub fn from_compiled_module<H>(
+		module: &CompiledModule,
+		config: Config,
+	) -> std::result::Result<WasmEdgeRuntime, WasmError>
+	where
+		H: HostFunctions,
+	{
+		crate::plugin::load_plugins(&config.plugins)?;
+
+		runtime_from_compiled_module::<H>(module.clone(), config)
+	}
 
-		let strategy = if let Some(ref snapshot_data) = self.snapshot_data {
-			instance_wrapper.instantiate(&self.module)?;
-			let heap_base = instance_wrapper.extract_heap_base()?;
+	/// Tears the runtime down, releasing all the WasmEdge resources it holds.
+	///
+	/// Unlike simply dropping the runtime, this guarantees that the underlying WasmEdge module
+	/// is freed by the time this function returns, which matters e.g. before unloading a
+	/// WasmEdge plugin.
+	///
+	/// All the instances spawned from this runtime must be dropped before calling this, since
+	/// they keep the module alive. Otherwise an error is returned and nothing is torn down.
+	///
+	/// If the runtime was created by [`WasmEdgeRuntime::from_compiled_module`], the module is only
+	/// freed once the [`CompiledModule`] and all the other runtimes created from it are gone.
+	pub fn shutdown(self) -> std::result::Result<(), WasmError> {
+		let live_instances = self.instance_slots.live();
+		if live_instances != 0 {
+			return Err(WasmError::Other(format!(
+				"cannot shut down the runtime while {} of its instances are alive",
+				live_instances,
+			)))
+		}
 
-			// This function panics if the instance was created from a runtime blob different from
-			// which the mutable globals were collected. Here, it is easy to see that there is only
-			// a single runtime blob and thus it's the same that was used for both creating the
-			// instance and collecting the mutable globals.
-			let globals_snapshot = GlobalsSnapshot::take(
-				&snapshot_data.mutable_globals,
-				&mut InstanceGlobals { instance: &mut instance_wrapper },
-			);
+		let WasmEdgeRuntime {
+			snapshot_data,
+			reset_data,
+			host_functions,
+			host_function_registry,
+			environment,
+			module,
+			code_size: _,
+			prepared_blob,
+			config,
+			instance_slots,
+			instance_memory,
+		} = self;
+
+		// There are no instances left, so this is the last reference to the module unless it's
+		// shared through a `CompiledModule`.
+		drop(module);
+		drop((snapshot_data, reset_data));
+		drop((host_functions, host_function_registry, environment, config, prepared_blob));
+		drop((instance_slots, instance_memory));
+
+		log::debug!(target: LOG_TARGET, "Runtime shut down");
+
+		Ok(())
+	}
 
-			Strategy::FastInstanceReuse {
-				instance_wrapper,
-				globals_snapshot,
-				data_segments_snapshot: snapshot_data.data_segments_snapshot.clone(),
-				heap_base,
-			}
-		} else {
-			Strategy::RecreateInstance(InstanceCreator {
-				instance_wrapper,
-				module: self.module.clone(),
-			})
-		};
+	/// Returns the snapshot of the data segments which is reapplied to the linear memory before
+	/// every call.
+	///
+	/// The snapshot is only taken when [`Semantics::fast_instance_reuse`] is enabled, otherwise
+	/// `None` is returned.
+	pub fn data_segments(&self) -> Option<&DataSegmentsSnapshot> {
+		self.snapshot_data.as_ref().map(|data| &*data.data_segments_snapshot)
+	}
 
-		Ok(Box::new(WasmEdgeInstance { strategy }))
+	/// Returns the `(offset, len)` spans of linear memory covered by the data segments, in the
+	/// order they are applied.
+	///
+	/// The same as [`WasmEdgeRuntime::data_segments`], this is `None` unless
+	/// [`Semantics::fast_instance_reuse`] is enabled.
+	pub fn data_segment_spans(&self) -> Option<Vec<(u32, u32)>> {
+		self.data_segments().map(|snapshot| {
+			let mut spans = Vec::new();
+			let _ = snapshot.apply(|offset, contents| -> std::result::Result<(), !> {
+				spans.push((offset, contents.len() as u32));
+				Ok(())
+			});
+			spans
+		})
 	}
-}
 
-struct InstanceGlobals<'a> {
-	instance: &'a mut InstanceWrapper,
-}
+	/// Checks that the runtime provides everything the Substrate ABI requires: the
+	/// `Core_version` entry point, the `__heap_base` global used by the allocator and the
+	/// `memory` export.
+	///
+	/// This is meant to be called at node startup, so that a malformed runtime is reported with
+	/// a single error listing everything that is missing, rather than by a failure deep in the
+	/// first call.
+	pub fn verify_substrate_abi(&self) -> std::result::Result<(), WasmError> {
+		let mut core_version = false;
+		let mut heap_base = false;
+		let mut memory = false;
+		let mut problems = Vec::new();
+
+		for export in self.module.exports() {
+			match (export.name().as_ref(), export.ty()) {
+				("Core_version", Ok(ExternalInstanceType::Func(func_ty))) => {
+					core_version = true;
+					if !crate::instance_wrapper::is_entry_point_type(&func_ty) {
+						problems.push(String::from(
+							"`Core_version` doesn't have the signature `(i32, i32) -> i64`",
+						));
+					}
+				},
+				("__heap_base", Ok(ExternalInstanceType::Global(global_ty))) => {
+					heap_base = true;
+					if global_ty.value_ty() != ValType::I32 {
+						problems.push(String::from("`__heap_base` is not an i32 global"));
+					}
+				},
+				("memory", Ok(ExternalInstanceType::Memory(_))) => memory = true,
+				_ => (),
+			}
+		}
 
-impl<'a> runtime_blob::InstanceGlobals for InstanceGlobals<'a> {
-	type Global = Arc<Mutex<Global>>;
+		if !core_version {
+			problems.push(String::from("the `Core_version` function is not exported"));
+		}
+		if !heap_base {
+			problems.push(String::from("the `__heap_base` global is not exported"));
+		}
+		if !memory {
+			problems.push(String::from("the `memory` is not exported"));
+		}
 
-	fn get_global(&mut self, export_name: &str) -> Self::Global {
-		Arc::new(Mutex::new(
-			self.instance.instance().global(export_name).expect(
-				"get_global is guaranteed to be called with an export name of a global; qed",
-			),
-		))
+		if problems.is_empty() {
+			Ok(())
+		} else {
+			Err(WasmError::Other(format!(
+				"the runtime doesn't follow the Substrate ABI: {}",
+				problems.join(", "),
+			)))
+		}
 	}
 
-	fn get_global_value(&mut self, global: &Self::Global) -> Value {
-		util::from_wasmedge_val(global.lock().expect("failed to lock").get_value())
+	/// Returns an estimate of the memory used by the runtime, see [`Footprint`].
+	///
+	/// This doesn't instantiate the runtime. Summing the footprints of several runtimes allows
+	/// rejecting a configuration which would run out of memory before it starts.
+	pub fn estimated_footprint(&self) -> Footprint {
+		let data_segments = self.snapshot_data.as_ref().or(self.reset_data.as_ref()).map(|data| {
+			let mut size = 0;
+			let _ =
+				data.data_segments_snapshot.apply(|_, contents| -> std::result::Result<(), !> {
+					size += contents.len();
+					Ok(())
+				});
+			size
+		});
+
+		Footprint {
+			code_size: self.code_size,
+			max_memory_size: self.config.semantics.max_memory_size,
+			data_segments_size: data_segments.unwrap_or(0),
+		}
 	}
 
-	fn set_global_value(&mut self, global: &Self::Global, value: Value) {
+	/// Returns the code the module was compiled from, if retained due to
+	/// [`Config::retain_prepared_blob`].
+	pub fn prepared_blob_bytes(&self) -> Option<&[u8]> {
+		self.prepared_blob.as_deref()
+	}
+
+	/// Returns the total size in bytes of the linear memories of the live instances of this
+	/// runtime.
+	///
+	/// The same as [`total_instance_memory`](crate::total_instance_memory), but limited to a
+	/// single runtime.
+	pub fn instance_memory(&self) -> usize {
+		self.instance_memory.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of host functions registered for the runtime.
+	pub fn host_function_count(&self) -> usize {
+		self.host_functions.len()
+	}
+
+	/// Returns the names of the host functions registered for the runtime.
+	///
+	/// Useful for checking at startup that the expected set of host functions was registered.
+	pub fn host_function_names(&self) -> Vec<&str> {
+		self.host_functions.iter().map(|host_func| host_func.name()).collect()
+	}
+
+	/// Returns how the imports of the module are resolved against the registered host functions.
+	///
+	/// Useful for diagnosing why a host function isn't called, e.g. because the runtime imports
+	/// it with a different signature and so can't be instantiated.
+	pub fn import_report(&self) -> ImportReport {
+		crate::imports::import_report(
+			&self.module,
+			&self.host_function_registry,
+			self.config.accept_any_import_module,
+			self.config.provide_host_time,
+			self.config.semantics.interruptible,
+		)
+	}
+
+	/// The same as [`WasmModule::new_instance`], but returns the concrete instance type, which
+	/// gives access to WasmEdge specific details of the calls, e.g.
+	/// [`WasmEdgeInstance::last_sandbox_error`].
+	pub fn new_wasmedge_instance(&self) -> Result<WasmEdgeInstance> {
+		self.new_wasmedge_instance_with(None)
+	}
+
+	/// The same as [`WasmEdgeRuntime::new_wasmedge_instance`], but also provides the imports of
+	/// the given import object to the instance, e.g. host functions which don't fit the
+	/// [`HostFunctions`] the runtime was created with.
+	///
+	/// The host functions of the runtime are registered first, under the `env` module, and the
+	/// import object after them. Their names can't collide: an import object named `env` is
+	/// rejected with an error. The runtime may only import from the module of the import object
+	/// what it provides, the missing imports aren't stubbed even if
+	/// [`Config::allow_missing_func_imports`] is enabled.
+	pub fn new_instance_with_imports(&self, extra: ImportObject) -> Result<WasmEdgeInstance> {
+		if extra.name() == crate::imports::IMPORT_MODULE_NAME {
+			return Err(WasmError::Other(format!(
+				"the import object is named '{}', which collides with the host functions",
+				extra.name(),
+			))
+			.into())
+		}
+
+		self.new_wasmedge_instance_with(Some(extra))
+	}
+
+	fn new_wasmedge_instance_with(&self, extra: Option<ImportObject>) -> Result<WasmEdgeInstance> {
+		let slot = self.acquire_instance_slot()?;
+
+		let mut instance_wrapper = Box::new(InstanceWrapper::new(&self.config.semantics)?);
+		instance_wrapper.set_trap_policy(self.config.trap_policy);
+		instance_wrapper.set_on_trap(self.config.on_trap.clone());
+		instance_wrapper.set_memory_counter(self.instance_memory.clone());
+
+		crate::imports::prepare_imports(
+			&mut instance_wrapper,
+			&self.module,
+			&self.host_function_registry,
+			self.config.allow_missing_func_imports,
+			self.config.accept_any_import_module,
+			self.config.provide_host_time,
+			self.config.semantics.interruptible,
+			self.config.host_call_tracer.as_ref(),
+			&self.environment,
+			extra.as_ref().map(|extra| extra.name().to_string()).as_deref(),
+		)
+		.map_err(|e| WasmError::Other(format!("fail to register imports: {}", e)))?;
+
+		if let Some(extra) = extra {
+			instance_wrapper.register_extra_import(extra)?;
+		}
+
+		let strategy = if let Some(ref snapshot_data) = self.snapshot_data {
+			instance_wrapper.instantiate(&self.module)?;
+			let heap_base = heap_base(&mut instance_wrapper, self.config.heap_base_override)?;
+
+			if self.config.semantics.zero_memory_on_first_use {
+				// The data segments are written back before the call.
+				instance_wrapper.decommit();
+			}
+
+			// The instance was created from the same runtime blob the mutable globals were
+			// collected from, there is only a single one.
+			let globals_snapshot = take_globals_snapshot(
+				&self.module,
+				&snapshot_data.mutable_globals,
+				&mut instance_wrapper,
+			)?;
+
+			Strategy::FastInstanceReuse {
+				instance_wrapper,
+				module: self.module.clone(),
+				globals_snapshot,
+				snapshot_data: snapshot_data.clone(),
+				heap_base,
+				decommit_only_dirty: self.config.semantics.decommit_only_dirty,
+				decommit_after_call: self.config.semantics.decommit_after_call &&
+					!self.config.semantics.benchmark_unchecked(),
+				imported_state: None,
+			}
+		} else {
+			Strategy::RecreateInstance(InstanceCreator {
+				instance_wrapper,
+				module: self.module.clone(),
+				heap_base_override: self.config.heap_base_override,
+				reset_data: self.reset_data.clone(),
+				initial_state: None,
+			})
+		};
+
+		Ok(WasmEdgeInstance {
+			strategy,
+			last_call: CallRecord::default(),
+			record_host_calls: self.config.record_host_calls,
+			ignore_double_free: self.config.ignore_double_free,
+			check_output_bounds: !self.config.semantics.benchmark_unchecked(),
+			output_validator: self.config.output_validator.clone(),
+			measure_host_time: self.config.measure_host_time || self.config.max_host_time.is_some(),
+			max_host_time: self.config.max_host_time,
+			max_sandbox_depth: self.config.max_sandbox_depth,
+			max_sandbox_args: self.config.max_sandbox_args,
+			on_runtime_panic: self.config.on_runtime_panic.clone(),
+			trace_sink: self.config.trace_sink.clone(),
+			sandbox_store_capacity: self.config.sandbox_store_capacity,
+			allocator_config: self.config.allocator_config,
+			max_sandbox_memories: self.config.max_sandbox_memories,
+			preloaded_memory: Vec::new(),
+			call_count: 0,
+			failed_call_count: 0,
+			statistics: self.config.semantics.statistics,
+			interruptible: self.config.semantics.interruptible,
+			call_deadline: None,
+			_slot: slot,
+		})
+	}
+
+	/// Creates a new instance holding the given state, as captured by
+	/// [`WasmEdgeInstance::export_state`] from an instance of the same runtime.
+	///
+	/// The imported state takes the place of the snapshot taken after the instantiation, i.e.
+	/// the memory and the globals are restored to it before every call instead. Fails without
+	/// [`Semantics::fast_instance_reuse`], the same as the export.
+	pub fn import_state(&self, state: &InstanceState) -> Result<Box<dyn WasmInstance>> {
+		let mut instance = self.new_wasmedge_instance()?;
+		match &mut instance.strategy {
+			Strategy::FastInstanceReuse {
+				instance_wrapper,
+				module,
+				globals_snapshot,
+				snapshot_data,
+				imported_state,
+				..
+			} => {
+				instance_wrapper.import_state(state)?;
+				*globals_snapshot = take_globals_snapshot(
+					module,
+					&snapshot_data.mutable_globals,
+					instance_wrapper,
+				)?;
+				*imported_state = Some(Box::new(state.clone()));
+			},
+			Strategy::RecreateInstance(_) => return Err(state_not_kept_error()),
+		}
+
+		Ok(Box::new(instance))
+	}
+
+	/// Takes up a slot for a new instance. What happens if `max_concurrent_instances` is
+	/// reached depends on the `pool_checkout_policy`.
+	fn acquire_instance_slot(&self) -> Result<InstanceSlot> {
+		let mut live = self.instance_slots.live.lock().expect("failed to lock");
+
+		if let Some(max) = self.config.max_concurrent_instances {
+			match self.config.pool_checkout_policy {
+				PoolCheckoutPolicy::Fail if *live >= max =>
+					return Err(WasmError::Other(String::from("instance limit reached")).into()),
+				PoolCheckoutPolicy::Fail | PoolCheckoutPolicy::CreateUnbounded => (),
+				PoolCheckoutPolicy::Block(timeout) => {
+					let (guard, wait_result) = self
+						.instance_slots
+						.released
+						.wait_timeout_while(live, timeout, |live| *live >= max)
+						.expect("failed to lock");
+					live = guard;
+					if wait_result.timed_out() && *live >= max {
+						return Err(WasmError::Other(String::from("instance limit reached")).into())
+					}
+				},
+			}
+		}
+
+		*live += 1;
+		Ok(InstanceSlot { instance_slots: self.instance_slots.clone() })
+	}
+}
+
+/// An estimate of the memory used by a runtime, as returned by
+/// [`WasmEdgeRuntime::estimated_footprint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footprint {
+	/// The size in bytes of the code the runtime was compiled from, i.e. the instrumented wasm
+	/// code or the precompiled artifact. The compiled module held in memory is roughly of the
+	/// same order.
+	pub code_size: usize,
+
+	/// The maximum size in bytes of the linear memory of every instance, as configured by
+	/// [`Semantics::max_memory_size`]. `None` if unlimited.
+	pub max_memory_size: Option<usize>,
+
+	/// The size in bytes of the snapshot of the data segments kept for cleaning up the instances
+	/// between calls, or zero if no snapshot is kept.
+	pub data_segments_size: usize,
+}
+
+/// The state of an instance left behind by its calls, as captured by
+/// [`WasmEdgeInstance::export_state`].
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct InstanceState {
+	/// The contents of the whole linear memory.
+	pub memory: Vec<u8>,
+
+	/// The values of all the exported globals, including the mutable globals exposed for
+	/// snapshotting, keyed by their export names and sorted by them.
+	pub globals: Vec<(String, Value)>,
+}
+
+/// The number of the live instances of a runtime, see [`Config::max_concurrent_instances`].
+#[derive(Default)]
+struct InstanceSlots {
+	live: Mutex<usize>,
+	/// Notified whenever a slot is freed up.
+	released: Condvar,
+}
+
+impl InstanceSlots {
+	fn live(&self) -> usize {
+		*self.live.lock().expect("failed to lock")
+	}
+}
+
+/// Keeps track of a single live instance. The slot is freed when this is dropped.
+struct InstanceSlot {
+	instance_slots: Arc<InstanceSlots>,
+}
+
+impl Drop for InstanceSlot {
+	fn drop(&mut self) {
+		*self.instance_slots.live.lock().expect("failed to lock") -= 1;
+		self.instance_slots.released.notify_one();
+	}
+}
+
+impl WasmModule for WasmEdgeRuntime {
+	fn new_instance(&self) -> Result<Box<dyn WasmInstance>> {
+		Ok(Box::new(self.new_wasmedge_instance()?))
+	}
+}
+
+pub(crate) struct InstanceGlobals<'a> {
+	pub(crate) instance: &'a mut InstanceWrapper,
+}
+
+impl<'a> runtime_blob::InstanceGlobals for InstanceGlobals<'a> {
+	type Global = Arc<Mutex<Global>>;
+
+	fn get_global(&mut self, export_name: &str) -> Self::Global {
+		Arc::new(Mutex::new(
+			self.instance.instance().global(export_name).expect(
+				"get_global is guaranteed to be called with an export name of a global; qed",
+			),
+		))
+	}
+
+	fn get_global_value(&mut self, global: &Self::Global) -> Value {
+		util::try_from_wasmedge_val(global.lock().expect("failed to lock").get_value()).expect(
+			"only globals of the types supported by Substrate are exposed for snapshotting; qed",
+		)
+	}
+
+	fn set_global_value(&mut self, global: &Self::Global, value: Value) {
 		global.lock().expect("failed to lock").set_value(util::into_wasmedge_val(value)).expect(
 			"the value is guaranteed to be of the same value; the global is guaranteed to be mutable; qed",
 		);
 	}
 }
 
+/// Takes the snapshot of the mutable globals of an instance of `module`.
+///
+/// Unlike [`GlobalsSnapshot::take`], which panics, this returns an error if a global can't be
+/// snapshotted or restored, e.g. if the module exports an immutable global under a name
+/// reserved for the exposed mutable globals.
+fn take_globals_snapshot(
+	module: &Module,
+	mutable_globals: &ExposedMutableGlobalsSet,
+	instance_wrapper: &mut InstanceWrapper,
+) -> std::result::Result<GlobalsSnapshot<Arc<Mutex<Global>>>, WasmError> {
+	let global_types = module
+		.exports()
+		.into_iter()
+		.filter_map(|export| match export.ty() {
+			Ok(ExternalInstanceType::Global(global_ty)) =>
+				Some((export.name().to_string(), global_ty)),
+			_ => None,
+		})
+		.collect::<HashMap<_, _>>();
+
+	for name in mutable_globals.names() {
+		let problem = match global_types.get(name) {
+			None => "it isn't an exported global",
+			Some(global_ty) if !matches!(global_ty.mutability(), Mutability::Var) =>
+				"it is immutable",
+			Some(global_ty)
+				if !matches!(
+					global_ty.value_ty(),
+					ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64
+				) =>
+				"its type isn't supported",
+			Some(_) => continue,
+		};
+
+		return Err(WasmError::Other(format!("cannot snapshot the global `{}`: {}", name, problem)))
+	}
+
+	Ok(GlobalsSnapshot::take(mutable_globals, &mut InstanceGlobals { instance: instance_wrapper }))
+}
+
 /// A `WasmInstance` implementation that reuses compiled module and spawns instances
 /// to execute the compiled code.
 pub struct WasmEdgeInstance {
 	strategy: Strategy,
+	/// What was recorded about the last call.
+	last_call: CallRecord,
+	/// Whether the host function calls are recorded, see [`Config::record_host_calls`].
+	record_host_calls: bool,
+	/// Whether deallocating memory which isn't allocated is ignored, see
+	/// [`Config::ignore_double_free`].
+	ignore_double_free: bool,
+	/// Whether the bounds of the output are checked before allocating the buffer for it. Only
+	/// disabled by [`Semantics::benchmark_unchecked`].
+	check_output_bounds: bool,
+	/// The validator of the output, see [`Config::output_validator`].
+	output_validator: Option<OutputValidator>,
+	/// Whether the time spent in host functions is measured, see
+	/// [`Config::measure_host_time`].
+	measure_host_time: bool,
+	/// The time the host functions may take during a call, see [`Config::max_host_time`].
+	max_host_time: Option<Duration>,
+	/// How deep the sandbox invocations may be nested, see [`Config::max_sandbox_depth`].
+	max_sandbox_depth: Option<u32>,
+	/// How many arguments a sandbox invocation may pass, see [`Config::max_sandbox_args`].
+	max_sandbox_args: Option<u32>,
+	/// The callback invoked when the runtime panics, see [`Config::on_runtime_panic`].
+	on_runtime_panic: Option<PanicCallback>,
+	/// The consumer of the call trace, see [`Config::trace_sink`].
+	trace_sink: Option<TraceSink>,
+	/// The capacity the sandbox store starts with, see [`Config::sandbox_store_capacity`].
+	sandbox_store_capacity: Option<usize>,
+	/// How the allocator is tuned, see [`Config::allocator_config`].
+	allocator_config: AllocatorConfig,
+	/// How many sandbox memories may exist at the same time, see
+	/// [`Config::max_sandbox_memories`].
+	max_sandbox_memories: Option<u32>,
+	/// The data written into the linear memory before every call, see
+	/// [`WasmEdgeInstance::preload_memory`].
+	preloaded_memory: Vec<(u32, Vec<u8>)>,
+	/// The number of calls made into this instance so far, see [`WasmEdgeInstance::call_count`].
+	call_count: u64,
+	/// The number of those calls which failed.
+	failed_call_count: u64,
+	/// The statistics WasmEdge gathers, see [`Semantics::statistics`].
+	statistics: StatisticsConfig,
+	/// Whether the calls can be interrupted, see [`Semantics::interruptible`].
+	interruptible: bool,
+	/// The deadline of the next call, see [`WasmEdgeInstance::call_streaming_with_timeout`].
+	call_deadline: Option<Instant>,
+	_slot: InstanceSlot,
+}
+
+/// The output of a call borrowed right from the linear memory of the instance, as returned by
+/// [`WasmEdgeInstance::call_ref`].
+///
+/// Dereferences to the output. The instance's memory is cleaned up once this is dropped.
+pub struct MemoryRef<'a> {
+	instance: &'a mut WasmEdgeInstance,
+	range: Range<usize>,
+}
+
+impl<'a> std::ops::Deref for MemoryRef<'a> {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		match &self.instance.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } =>
+				&util::memory_slice(instance_wrapper.memory())[self.range.clone()],
+			Strategy::RecreateInstance(_) =>
+				unreachable!("the output is only borrowed with the fast instance reuse; qed"),
+		}
+	}
+}
+
+impl<'a> Drop for MemoryRef<'a> {
+	fn drop(&mut self) {
+		self.instance.decommit_after_call();
+	}
 }
 
 enum Strategy {
 	FastInstanceReuse {
 		instance_wrapper: Box<InstanceWrapper>,
+		/// The module the instance is instantiated from again once it's poisoned.
+		module: Arc<Module>,
 		globals_snapshot: GlobalsSnapshot<Arc<Mutex<Global>>>,
-		data_segments_snapshot: Arc<DataSegmentsSnapshot>,
+		snapshot_data: Arc<InstanceSnapshotData>,
 		heap_base: u32,
+		decommit_only_dirty: bool,
+		decommit_after_call: bool,
+		/// The state imported with [`WasmEdgeRuntime::import_state`], if any. It replaces the
+		/// data segments snapshot, the memory is restored from it before every call.
+		imported_state: Option<Box<InstanceState>>,
 	},
 	RecreateInstance(InstanceCreator),
 }
@@ -212,63 +1487,645 @@ enum Strategy {
 struct InstanceCreator {
 	instance_wrapper: Box<InstanceWrapper>,
 	module: Arc<Module>,
+	heap_base_override: Option<u32>,
+	reset_data: Option<Arc<InstanceSnapshotData>>,
+	/// The state of the current instance right after its instantiation. Only captured if
+	/// `reset_data` is available.
+	initial_state: Option<InitialState>,
 }
 
 impl InstanceCreator {
+	/// Brings the instance into its initial state, resetting the current instance if possible
+	/// and instantiating the module again otherwise.
 	fn instantiate(&mut self) -> Result<()> {
-		self.instance_wrapper.instantiate(&self.module)
+		if let Some(initial_state) = &self.initial_state {
+			// Resetting only restores what reusing an instance does, see
+			// `InstanceWrapper::is_poisoned`.
+			if !self.instance_wrapper.is_poisoned() && self.instance_wrapper.reset(initial_state)? {
+				return Ok(())
+			}
+
+			log::debug!(target: LOG_TARGET, "Cannot reset the instance, instantiating it again");
+		}
+
+		self.instance_wrapper.instantiate(&self.module)?;
+
+		// The initial state refers to the instance it was captured from.
+		self.initial_state =
+			self.reset_data.as_ref().and_then(|reset_data| {
+				let globals_snapshot = take_globals_snapshot(
+					&self.module,
+					&reset_data.mutable_globals,
+					&mut self.instance_wrapper,
+				)
+				.map_err(|e| {
+					log::debug!(target: LOG_TARGET, "Cannot reset the instances: {}", e);
+				})
+				.ok()?;
+
+				Some(self.instance_wrapper.take_initial_state(
+					globals_snapshot,
+					reset_data.data_segments_snapshot.clone(),
+				))
+			});
+
+		Ok(())
 	}
 }
 
 impl WasmEdgeInstance {
-	fn call_impl(
+	/// Returns why the last call into a sandboxed instance made during the last call failed, if
+	/// it did.
+	///
+	/// The sandboxed code itself only learns that the call failed, this tells apart e.g. a trap
+	/// from a failed supervisor function.
+	pub fn last_sandbox_error(&self) -> Option<&SandboxError> {
+		self.last_call.sandbox_error.as_ref()
+	}
+
+	/// Returns the maximum logical stack depth reached during the last call.
+	///
+	/// This is only tracked if [`Semantics::track_max_stack_depth`] and
+	/// [`Semantics::deterministic_stack_limit`] are enabled, otherwise `None` is returned. The
+	/// depth is measured in the same units as [`DeterministicStackLimit::logical_max`].
+	pub fn max_stack_depth(&self) -> Option<u32> {
+		self.last_call.max_stack_depth
+	}
+
+	/// Returns the time spent in host functions during the last call.
+	///
+	/// This is only measured if [`Config::measure_host_time`] or [`Config::max_host_time`] is
+	/// enabled, otherwise `None` is returned.
+	pub fn host_time(&self) -> Option<Duration> {
+		self.last_call.host_time
+	}
+
+	/// Returns the number of calls made into this instance so far, whether they succeeded or
+	/// not.
+	///
+	/// With the fast instance reuse a single instance serves all these calls, so this tells how
+	/// well the instances are reused.
+	pub fn call_count(&self) -> u64 {
+		self.call_count
+	}
+
+	/// Returns the number of calls made into this instance so far which failed.
+	pub fn failed_call_count(&self) -> u64 {
+		self.failed_call_count
+	}
+
+	/// Returns the frames parsed from the backtrace of the last call, see
+	/// [`parse_backtrace`](crate::parse_backtrace).
+	///
+	/// The backtrace is what WasmEdge logged for the trap, which is only available while its logs
+	/// are forwarded, see [`redirect_wasmedge_logs`](crate::redirect_wasmedge_logs). Empty if the
+	/// last call didn't trap, or if its backtrace didn't contain any frames in the format
+	/// understood by the parser, e.g. because the code ran precompiled.
+	pub fn last_backtrace_frames(&self) -> &[Frame] {
+		match &self.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } =>
+				instance_wrapper.backtrace_frames(),
+			Strategy::RecreateInstance(instance_creator) =>
+				instance_creator.instance_wrapper.backtrace_frames(),
+		}
+	}
+
+	/// Returns the stats of the allocator captured at the moment the last call trapped, `None`
+	/// if it didn't trap.
+	///
+	/// These reflect a partial call: the allocations the runtime made up to the trap, including
+	/// the ones it would have freed later. They help debugging memory issues on the failure
+	/// path, e.g. a call running out of memory.
+	pub fn trapped_allocation_stats(&self) -> Option<&AllocationStats> {
+		match &self.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } =>
+				instance_wrapper.trapped_allocation_stats(),
+			Strategy::RecreateInstance(instance_creator) =>
+				instance_creator.instance_wrapper.trapped_allocation_stats(),
+		}
+	}
+
+	/// Returns how long bringing the instance into its initial state took the last time, i.e.
+	/// instantiating the module or resetting the instance, if that happened yet.
+	///
+	/// Without [`Semantics::fast_instance_reuse`] this happens before every call, so it's a
+	/// part of the latency of every call, separate from the one-time compilation. With the fast
+	/// instance reuse the instance is only instantiated when created and after a call trapped.
+	pub fn last_instantiate_duration(&self) -> Option<Duration> {
+		match &self.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } =>
+				instance_wrapper.last_instantiate_duration(),
+			Strategy::RecreateInstance(instance_creator) =>
+				instance_creator.instance_wrapper.last_instantiate_duration(),
+		}
+	}
+
+	/// Calls the given method the same as [`WasmInstance::call`], but also returns the number of
+	/// instructions the call executed.
+	///
+	/// Requires [`StatisticsConfig::instr_count`], otherwise an error is returned without making
+	/// the call.
+	pub fn call_with_metering(
+		&mut self,
+		method: InvokeMethod,
+		data: &[u8],
+	) -> Result<(Vec<u8>, u64)> {
+		let instructions_before =
+			self.enabled_statistics(self.statistics.instr_count, "instr_count")?.count();
+		let output = self.call(method, data)?;
+		let instructions_after =
+			self.enabled_statistics(self.statistics.instr_count, "instr_count")?.count();
+
+		Ok((output, instructions_after.saturating_sub(instructions_before)))
+	}
+
+	/// Returns the total cost of the instructions executed by all the calls made into this
+	/// instance so far.
+	///
+	/// Requires [`StatisticsConfig::cost`], otherwise an error is returned.
+	pub fn total_cost(&self) -> Result<u64> {
+		Ok(self.enabled_statistics(self.statistics.cost, "cost")?.cost())
+	}
+
+	/// Returns the number of instructions executed per second by the calls made into this
+	/// instance so far.
+	///
+	/// Requires [`StatisticsConfig::time`] and [`StatisticsConfig::instr_count`], otherwise an
+	/// error is returned.
+	pub fn instructions_per_second(&self) -> Result<f64> {
+		self.enabled_statistics(self.statistics.instr_count, "instr_count")?;
+		Ok(self.enabled_statistics(self.statistics.time, "time")?.instr_per_sec())
+	}
+
+	/// Returns the statistics gathered by WasmEdge, or an error naming the `statistic` if it
+	/// isn't `enabled`.
+	fn enabled_statistics(&self, enabled: bool, statistic: &str) -> Result<&Statistics> {
+		let instance_wrapper = match &self.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } => instance_wrapper,
+			Strategy::RecreateInstance(instance_creator) => &instance_creator.instance_wrapper,
+		};
+
+		instance_wrapper.statistics().filter(|_| enabled).ok_or_else(|| {
+			Error::Other(format!(
+				"statistics not enabled: `{}` must be set in `Semantics::statistics`",
+				statistic
+			))
+		})
+	}
+
+	/// Returns the host function calls made during the last call, in order.
+	///
+	/// The calls are only recorded if [`Config::record_host_calls`] is enabled, otherwise this
+	/// is always empty.
+	pub fn host_calls(&self) -> &[HostCall] {
+		&self.last_call.host_calls
+	}
+
+	/// Calls the given method the same as [`WasmInstance::call`], but instead of executing the
+	/// host functions, answers the host function calls with the results of `host_calls`, as
+	/// previously recorded by [`WasmEdgeInstance::host_calls`].
+	///
+	/// The results of the host functions are replayed together with their writes into the linear
+	/// memory, see [`HostCall::memory_writes`]. Their other side effects, e.g. on the state of
+	/// the allocator or on the externalities, aren't. The call fails as soon as the runtime makes a
+	/// host function call which doesn't match the next recorded one.
+	pub fn replay(
 		&mut self,
 		method: InvokeMethod,
 		data: &[u8],
-		allocation_stats: &mut Option<AllocationStats>,
+		host_calls: Vec<HostCall>,
 	) -> Result<Vec<u8>> {
+		let check_output_bounds = self.check_output_bounds;
+		let output_validator = self.output_validator.clone();
+		self.call_impl(
+			method,
+			HostCallMode::Replay(host_calls.into_iter()),
+			data.len(),
+			|input| input.copy_from_slice(data),
+			|instance_wrapper, ptr, len| {
+				extract_output_data(
+					instance_wrapper,
+					ptr,
+					len,
+					check_output_bounds,
+					output_validator.as_ref(),
+				)
+			},
+			false,
+		)
+	}
+
+	/// Calls the given method the same as [`WasmInstance::call`], but instead of returning the
+	/// output as a single buffer, feeds it to `sink` in consecutive chunks of at most 64 KiB.
+	///
+	/// The output is still located through the pointer and length returned by the method (see
+	/// [`unpack_ptr_and_len`]). The chunks are views into the linear memory, so the output is
+	/// never copied into a buffer of its own, which helps with large outputs processed
+	/// incrementally.
+	pub fn call_streaming(
+		&mut self,
+		method: InvokeMethod,
+		data: &[u8],
+		mut sink: impl FnMut(&[u8]),
+	) -> Result<()> {
+		let host_call_mode = self.host_call_mode();
+		self.call_impl(
+			method,
+			host_call_mode,
+			data.len(),
+			|input| input.copy_from_slice(data),
+			|instance_wrapper, ptr, len| stream_output_data(instance_wrapper, ptr, len, &mut sink),
+			false,
+		)
+	}
+
+	/// Calls the given method the same as [`WasmEdgeInstance::call_streaming`], but aborts the
+	/// call with [`Error::Timeout`] once it runs for longer than `timeout`.
+	///
+	/// Requires [`Semantics::interruptible`], otherwise an error is returned without making the
+	/// call. The deadline is only checked while the code of the runtime runs, a host function
+	/// taking long isn't interrupted, see [`Config::max_host_time`] for that. The instance is
+	/// instantiated again before the next call, the same as after a trap.
+	///
+	/// If the call times out, the region of the most recent allocation the runtime made through
+	/// the host functions is fed to `sink` before the error is returned, since that's where a
+	/// runtime usually builds its output. This is a best-effort aid for debugging a runtime
+	/// which hangs after producing some output: the region may as well hold something else, and
+	/// how much of it was written depends on the timing, so it isn't deterministic.
+	pub fn call_streaming_with_timeout(
+		&mut self,
+		method: InvokeMethod,
+		data: &[u8],
+		timeout: Duration,
+		mut sink: impl FnMut(&[u8]),
+	) -> Result<()> {
+		if !self.interruptible {
+			return Err(Error::Other(String::from(
+				"calls can only time out with `Semantics::interruptible` enabled",
+			)))
+		}
+
+		self.call_deadline = Some(Instant::now() + timeout);
+		let host_call_mode = self.host_call_mode();
+		let result = self.call_impl(
+			method,
+			host_call_mode,
+			data.len(),
+			|input| input.copy_from_slice(data),
+			|instance_wrapper, ptr, len| stream_output_data(instance_wrapper, ptr, len, &mut sink),
+			true,
+		);
+
+		if let (Err(Error::Timeout), Some((ptr, len))) = (&result, self.last_call.last_allocation) {
+			let instance_wrapper = match &self.strategy {
+				Strategy::FastInstanceReuse { instance_wrapper, .. } => instance_wrapper,
+				Strategy::RecreateInstance(instance_creator) => &instance_creator.instance_wrapper,
+			};
+			// The allocation was made in this memory, so it's within the bounds.
+			let _ = stream_output_data(instance_wrapper, ptr, len, &mut sink);
+		}
+
+		self.decommit_after_call();
+		result
+	}
+
+	/// Calls the given method the same as [`WasmInstance::call`], but instead of taking the input
+	/// as a buffer, allocates `len` bytes for it in the linear memory and lets `writer` write the
+	/// input there directly.
+	///
+	/// This allows e.g. encoding a large input in place, without encoding it into a buffer of its
+	/// own first. The slice passed to `writer` is zeroed, unless it is reused memory of the
+	/// previous call with [`Semantics::decommit_after_call`] disabled.
+	pub fn call_with_writer(
+		&mut self,
+		method: InvokeMethod,
+		len: usize,
+		writer: impl FnOnce(&mut [u8]),
+	) -> Result<Vec<u8>> {
+		let check_output_bounds = self.check_output_bounds;
+		let output_validator = self.output_validator.clone();
+		let host_call_mode = self.host_call_mode();
+		self.call_impl(
+			method,
+			host_call_mode,
+			len,
+			writer,
+			|instance_wrapper, ptr, len| {
+				extract_output_data(
+					instance_wrapper,
+					ptr,
+					len,
+					check_output_bounds,
+					output_validator.as_ref(),
+				)
+			},
+			false,
+		)
+	}
+
+	/// Calls the exported function `name` with the given arguments and returns its results.
+	///
+	/// Unlike [`WasmInstance::call`] this doesn't follow the Substrate ABI, the function may have
+	/// any signature as long as it only uses the value types supported by [`Value`]. Nothing is
+	/// written into the linear memory for the call, otherwise the instance is prepared for the
+	/// call and cleaned up after it the same as for any other call.
+	pub fn call_export_raw(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>> {
+		let host_call_mode = self.host_call_mode();
+		self.with_prepared_instance(
+			host_call_mode,
+			false,
+			|host_state, instance_wrapper, last_call| {
+				instance_wrapper.reset_max_stack_depth();
+				instance_wrapper.set_host_state(Some(host_state));
+				let result = instance_wrapper.call_export_raw(name, args);
+				finish_call(instance_wrapper, last_call, result)
+			},
+		)
+	}
+
+	/// Makes the linear memory hold `data` at `offset` at the start of every following call.
+	///
+	/// This allows seeding the memory with e.g. a large lookup table without recompiling the
+	/// runtime. The data is written after the instance was brought into its initial state, so it
+	/// survives the cleanup between the calls. Fails if the data doesn't fit into the memory.
+	///
+	/// The data must be placed where the runtime doesn't expect anything else, e.g. in the
+	/// memory reserved with [`Config::heap_base_override`]. In particular, with
+	/// [`Semantics::fast_instance_reuse`] the data segments (see
+	/// [`WasmEdgeRuntime::data_segment_spans`]) are written after the preloaded data and
+	/// overwrite it where they overlap.
+	pub fn preload_memory(&mut self, offset: u32, data: &[u8]) -> Result<()> {
+		let instance_wrapper = match &mut self.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } => instance_wrapper,
+			Strategy::RecreateInstance(instance_creator) => {
+				instance_creator.instantiate()?;
+				&mut instance_creator.instance_wrapper
+			},
+		};
+
+		let memory_len = util::memory_slice(instance_wrapper.memory()).len();
+		checked_range(offset as usize, data.len(), memory_len).ok_or_else(|| {
+			Error::Other(format!(
+				"cannot preload {} bytes at {}: out of the linear memory bounds",
+				data.len(),
+				offset,
+			))
+		})?;
+
+		self.preloaded_memory.push((offset, data.to_vec()));
+		Ok(())
+	}
+
+	/// What is done with the host function calls made during a regular call.
+	fn host_call_mode(&self) -> HostCallMode {
+		if self.record_host_calls {
+			HostCallMode::Record
+		} else {
+			HostCallMode::Execute
+		}
+	}
+
+	/// Runs `f` with mutable access to the linear memory of the instance, e.g. for inspecting or
+	/// patching the memory between calls.
+	///
+	/// This is only supported with [`Semantics::fast_instance_reuse`], since otherwise the
+	/// memory doesn't persist between the calls and an error is returned. Note that the data
+	/// segments (and the [`WasmEdgeInstance::preload_memory`]) are written into the memory again
+	/// before every call, overwriting what `f` wrote there.
+	pub fn with_memory<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
 		match &mut self.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } => {
+				instance_wrapper.mark_memory_dirty();
+				Ok(f(util::memory_slice_mut(instance_wrapper.memory_mut())))
+			},
+			Strategy::RecreateInstance(_) => Err(Error::Other(String::from(
+				"the linear memory is only accessible with the fast instance reuse enabled",
+			))),
+		}
+	}
+
+	/// Captures the whole state the calls made so far left behind in the instance, i.e. its
+	/// linear memory and the values of all its globals.
+	///
+	/// The state can be restored into a fresh instance of the same runtime with
+	/// [`WasmEdgeRuntime::import_state`], e.g. by chain fork tooling. Unlike the snapshots taken
+	/// for the [`Semantics::fast_instance_reuse`], which capture the state right after the
+	/// instantiation, this captures the state as it is now.
+	///
+	/// This is only supported with the fast instance reuse, since otherwise the state doesn't
+	/// persist between the calls and an error is returned.
+	pub fn export_state(&self) -> Result<InstanceState> {
+		match &self.strategy {
+			Strategy::FastInstanceReuse { instance_wrapper, .. } => instance_wrapper.export_state(),
+			Strategy::RecreateInstance(_) => Err(state_not_kept_error()),
+		}
+	}
+
+	/// Calls the given method the same as [`WasmInstance::call`], but instead of copying the
+	/// output out of the linear memory, returns a view of it right in the memory.
+	///
+	/// This avoids allocating and copying a large output, e.g. one which is only handed over
+	/// to another task. The view borrows the instance, so it must be dropped before the next
+	/// call and can't outlive the instance; copy the output out if that is needed. The memory
+	/// is only cleaned up (see [`Semantics::decommit_after_call`]) once the view is dropped.
+	///
+	/// This is only supported with [`Semantics::fast_instance_reuse`], since otherwise the
+	/// memory doesn't persist past the call and an error is returned.
+	pub fn call_ref(&mut self, method: InvokeMethod, data: &[u8]) -> Result<MemoryRef<'_>> {
+		if let Strategy::RecreateInstance(_) = self.strategy {
+			return Err(Error::Other(String::from(
+				"the output can only be borrowed with the fast instance reuse enabled",
+			)))
+		}
+
+		let host_call_mode = self.host_call_mode();
+		let output_validator = self.output_validator.clone();
+		let result = self.call_impl(
+			method,
+			host_call_mode,
+			data.len(),
+			|input| input.copy_from_slice(data),
+			|instance_wrapper, ptr, len| {
+				let memory_slice = util::memory_slice(instance_wrapper.memory());
+				let range = output_range(memory_slice, ptr, len)?;
+				validate_output(output_validator.as_ref(), &memory_slice[range.clone()])?;
+				Ok(range)
+			},
+			true,
+		);
+
+		match result {
+			Ok(range) => Ok(MemoryRef { instance: self, range }),
+			Err(error) => {
+				self.decommit_after_call();
+				Err(error)
+			},
+		}
+	}
+
+	/// Signals to the OS that we are done with the linear memory and that it can be reclaimed,
+	/// if the instance is reused and [`Semantics::decommit_after_call`] is enabled.
+	fn decommit_after_call(&mut self) {
+		if let Strategy::FastInstanceReuse {
+			instance_wrapper,
+			decommit_only_dirty,
+			decommit_after_call: true,
+			..
+		} = &mut self.strategy
+		{
+			if *decommit_only_dirty {
+				instance_wrapper.decommit_dirty();
+			} else {
+				instance_wrapper.decommit();
+			}
+		}
+	}
+
+	/// Performs the call with `input_len` bytes of input written by `write_input`, handing the
+	/// location of the output to `read_output` while the instance's memory still holds it.
+	///
+	/// If `defer_decommit` is set, the memory is left as is after the call, and the caller is
+	/// responsible for calling [`WasmEdgeInstance::decommit_after_call`] later.
+	fn call_impl<R>(
+		&mut self,
+		method: InvokeMethod,
+		host_call_mode: HostCallMode,
+		input_len: usize,
+		write_input: impl FnOnce(&mut [u8]),
+		read_output: impl FnOnce(&InstanceWrapper, u32, u32) -> Result<R>,
+		defer_decommit: bool,
+	) -> Result<R> {
+		self.with_prepared_instance(
+			host_call_mode,
+			defer_decommit,
+			|host_state, instance_wrapper, last_call| {
+				perform_call(
+					host_state,
+					input_len,
+					write_input,
+					instance_wrapper,
+					method,
+					last_call,
+					read_output,
+				)
+			},
+		)
+	}
+
+	/// Brings the instance into the state a call starts in and runs `call` with the host state
+	/// for the call, see [`WasmEdgeInstance::call_impl`].
+	fn with_prepared_instance<R>(
+		&mut self,
+		host_call_mode: HostCallMode,
+		defer_decommit: bool,
+		call: impl FnOnce(HostState, &mut InstanceWrapper, &mut CallRecord) -> Result<R>,
+	) -> Result<R> {
+		let last_call = &mut self.last_call;
+		*last_call = CallRecord::default();
+		let preloaded_memory = &self.preloaded_memory;
+		let ignore_double_free = self.ignore_double_free;
+		let (measure_host_time, max_host_time) = (self.measure_host_time, self.max_host_time);
+		let (max_sandbox_depth, max_sandbox_args) = (self.max_sandbox_depth, self.max_sandbox_args);
+		let on_runtime_panic = self.on_runtime_panic.clone();
+		let trace_sink = self.trace_sink.clone();
+		let sandbox_store_capacity = self.sandbox_store_capacity;
+		let allocator_config = self.allocator_config;
+		let max_sandbox_memories = self.max_sandbox_memories;
+		let call_deadline = self.call_deadline.take();
+		let new_host_state = move |heap_base| {
+			let mut host_state = HostState::new(FreeingBumpHeapAllocator::new(heap_base));
+			host_state.set_host_call_mode(host_call_mode);
+			host_state.set_ignore_double_free(ignore_double_free);
+			host_state.set_max_sandbox_depth(max_sandbox_depth);
+			host_state.set_max_sandbox_args(max_sandbox_args);
+			host_state.set_on_runtime_panic(on_runtime_panic);
+			host_state.set_trace_sink(trace_sink);
+			if let Some(capacity) = sandbox_store_capacity {
+				host_state.set_sandbox_store_capacity(capacity);
+			}
+			host_state.set_allocator_config(allocator_config);
+			host_state.set_max_sandbox_memories(max_sandbox_memories);
+			if measure_host_time {
+				host_state.measure_host_time(max_host_time);
+			}
+			if let Some(deadline) = call_deadline {
+				host_state.set_deadline(deadline);
+			}
+			host_state
+		};
+
+		let result = match &mut self.strategy {
 			Strategy::FastInstanceReuse {
 				instance_wrapper,
+				module,
 				globals_snapshot,
-				data_segments_snapshot,
+				snapshot_data,
 				heap_base,
+				imported_state,
+				..
 			} => {
-				data_segments_snapshot.apply(|offset, contents| {
-					util::write_memory_from(
+				if instance_wrapper.is_poisoned() {
+					log::debug!(
+						target: LOG_TARGET,
+						"The previous call trapped, instantiating the module again",
+					);
+					instance_wrapper.instantiate(module)?;
+					if let Some(state) = imported_state {
+						instance_wrapper.import_state(state)?;
+					}
+
+					// The snapshot refers to the globals of the instance it was taken from. The
+					// new instance is in the same state as the snapshot.
+					*globals_snapshot = take_globals_snapshot(
+						module,
+						&snapshot_data.mutable_globals,
+						instance_wrapper,
+					)?;
+				}
+
+				write_preloaded_memory(instance_wrapper, preloaded_memory)?;
+				match imported_state {
+					// The memory never shrinks, so it's at least as large as the imported one.
+					Some(state) => util::write_memory_from(
 						util::memory_slice_mut(instance_wrapper.memory_mut()),
-						Pointer::new(offset),
-						contents,
-					)
-				})?;
+						Pointer::new(0),
+						&state.memory,
+					)?,
+					None => snapshot_data.data_segments_snapshot.apply(|offset, contents| {
+						util::write_memory_from(
+							util::memory_slice_mut(instance_wrapper.memory_mut()),
+							Pointer::new(offset),
+							contents,
+						)
+					})?,
+				}
 
 				globals_snapshot.apply(&mut InstanceGlobals { instance: instance_wrapper });
-				let allocator = FreeingBumpHeapAllocator::new(*heap_base);
-
-				let result =
-					perform_call(data, instance_wrapper, method, allocator, allocation_stats);
-
-				// Signal to the OS that we are done with the linear memory and that it can be
-				// reclaimed.
-				instance_wrapper.decommit();
 
-				result
+				call(new_host_state(*heap_base), instance_wrapper, last_call)
 			},
 			Strategy::RecreateInstance(instance_creator) => {
 				instance_creator.instantiate()?;
-				let heap_base = instance_creator.instance_wrapper.extract_heap_base()?;
-
-				let allocator = FreeingBumpHeapAllocator::new(heap_base);
-
-				perform_call(
-					data,
+				write_preloaded_memory(&mut instance_creator.instance_wrapper, preloaded_memory)?;
+				let heap_base = heap_base(
 					&mut instance_creator.instance_wrapper,
-					method,
-					allocator,
-					allocation_stats,
-				)
+					instance_creator.heap_base_override,
+				)?;
+
+				call(new_host_state(heap_base), &mut instance_creator.instance_wrapper, last_call)
 			},
+		};
+
+		self.call_count += 1;
+		if result.is_err() {
+			self.failed_call_count += 1;
 		}
+
+		if !defer_decommit {
+			self.decommit_after_call();
+		}
+
+		result
 	}
 }
 
@@ -277,10 +2134,27 @@ impl WasmInstance for WasmEdgeInstance {
 		&mut self,
 		method: InvokeMethod,
 		data: &[u8],
-	) -> (Result<Vec<u8>>, Option<AllocationStats>) {
-		let mut allocation_stats = None;
-		let result = self.call_impl(method, data, &mut allocation_stats);
-		(result, allocation_stats)
+	) -> (Result<Vec<u8>>, Option<AllocationStats>, Option<Duration>) {
+		let check_output_bounds = self.check_output_bounds;
+		let output_validator = self.output_validator.clone();
+		let host_call_mode = self.host_call_mode();
+		let result = self.call_impl(
+			method,
+			host_call_mode,
+			data.len(),
+			|input| input.copy_from_slice(data),
+			|instance_wrapper, ptr, len| {
+				extract_output_data(
+					instance_wrapper,
+					ptr,
+					len,
+					check_output_bounds,
+					output_validator.as_ref(),
+				)
+			},
+			false,
+		);
+		(result, self.last_call.allocation_stats.clone(), self.last_call.host_time)
 	}
 
 	fn get_global_const(&mut self, name: &str) -> Result<Option<Value>> {
@@ -321,6 +2195,142 @@ enum CodeSupplyMode<'a> {
 	Precompiled(&'a Path),
 }
 
+/// Describes at which stage creating a runtime failed, see [`create_runtime_detailed`].
+#[derive(Debug)]
+pub enum CreateRuntimeError {
+	/// The WasmEdge configuration couldn't be created, which points to a problem with the
+	/// environment rather than with the code.
+	Config(String),
+	/// WasmEdge couldn't load the code into a module, e.g. because it's malformed or doesn't
+	/// pass the validation. WasmEdge parses and validates the code in one go, so the two aren't
+	/// told apart.
+	Module(String),
+	/// The snapshot of the data segments, used to reset the memory between calls, couldn't be
+	/// taken.
+	Snapshot(String),
+	/// The metadata of the compiled artifact couldn't be read.
+	ArtifactMetadata(String),
+	/// Creating the runtime failed for another reason, e.g. the code couldn't be prepared for the
+	/// compilation.
+	Other(WasmError),
+}
+
+impl std::fmt::Display for CreateRuntimeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			CreateRuntimeError::Config(e) =>
+				write!(f, "fail to create a WasmEdge Config context: {}", e),
+			CreateRuntimeError::Module(e) =>
+				write!(f, "fail to create a WasmEdge Module context: {}", e),
+			CreateRuntimeError::Snapshot(e) =>
+				write!(f, "cannot take data segments snapshot: {}", e),
+			CreateRuntimeError::ArtifactMetadata(e) =>
+				write!(f, "cannot read the compiled artifact metadata: {}", e),
+			CreateRuntimeError::Other(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for CreateRuntimeError {}
+
+impl From<WasmError> for CreateRuntimeError {
+	fn from(error: WasmError) -> Self {
+		CreateRuntimeError::Other(error)
+	}
+}
+
+impl From<CreateRuntimeError> for WasmError {
+	fn from(error: CreateRuntimeError) -> Self {
+		match error {
+			CreateRuntimeError::Other(error) => error,
+			error => WasmError::Other(error.to_string()),
+		}
+	}
+}
+
+/// A runtime module which was compiled once and can be shared by several runtimes, see
+/// [`WasmEdgeRuntime::from_compiled_module`].
+///
+/// Cloning it is cheap, all the clones refer to the same compiled code.
+#[derive(Clone)]
+pub struct CompiledModule {
+	module: Arc<Module>,
+	/// The size in bytes of the code the module was compiled from.
+	code_size: usize,
+	/// The [`Semantics::artifact_key`] of the semantics the module was compiled with.
+	artifact_key: [u8; 32],
+	/// The code the module was compiled from, see [`Config::retain_prepared_blob`].
+	prepared_blob: Option<Arc<[u8]>>,
+	snapshot_data: Option<Arc<InstanceSnapshotData>>,
+	reset_data: Option<Arc<InstanceSnapshotData>>,
+}
+
+/// Compiles the given code into a [`CompiledModule`], which any number of runtimes can then be
+/// created from without compiling the code again.
+///
+/// This is useful e.g. for a node running several chains with the same runtime code.
+pub fn compile_module(
+	blob: RuntimeBlob,
+	semantics: &Semantics,
+) -> std::result::Result<CompiledModule, WasmError> {
+	Ok(do_compile_module(blob, semantics, None, false, false)?)
+}
+
+/// The same as [`compile_module`], but also applies the given [`Config::blob_transform`],
+/// retains the compiled code if asked to, see [`Config::retain_prepared_blob`], and details
+/// validation errors if asked to, see [`Config::detailed_validation`].
+fn do_compile_module(
+	blob: RuntimeBlob,
+	semantics: &Semantics,
+	blob_transform: Option<&BlobTransform>,
+	retain_prepared_blob: bool,
+	detailed_validation: bool,
+) -> std::result::Result<CompiledModule, CreateRuntimeError> {
+	let config_wasmedge = wasmedge_config(semantics)?;
+
+	let blob = prepare_blob_for_compilation(blob, semantics, blob_transform)?;
+	let serialized_blob = blob.clone().serialize();
+
+	log::debug!(target: LOG_TARGET, "Loading runtime module from a fresh blob");
+	let time = Instant::now();
+
+	let module = Module::from_bytes(Some(&config_wasmedge), &serialized_blob).map_err(|e| {
+		let details = detailed_validation
+			.then(|| validation_details(&serialized_blob, semantics))
+			.flatten();
+		CreateRuntimeError::Module(match details {
+			Some(details) => format!("{} ({})", e, details),
+			None => e.to_string(),
+		})
+	})?;
+
+	log::debug!(
+		target: LOG_TARGET,
+		"Loaded runtime module in {} ms",
+		time.elapsed().as_millis(),
+	);
+
+	let (snapshot_data, reset_data) = if semantics.fast_instance_reuse {
+		let data_segments_snapshot = DataSegmentsSnapshot::take(&blob)
+			.map_err(|e| CreateRuntimeError::Snapshot(e.to_string()))?;
+		let data_segments_snapshot = Arc::new(data_segments_snapshot);
+		let mutable_globals = ExposedMutableGlobalsSet::collect(&blob);
+
+		(Some(Arc::new(InstanceSnapshotData { data_segments_snapshot, mutable_globals })), None)
+	} else {
+		(None, reset_data(&blob))
+	};
+
+	Ok(CompiledModule {
+		module: Arc::new(module),
+		code_size: serialized_blob.len(),
+		artifact_key: semantics.artifact_key(),
+		prepared_blob: retain_prepared_blob.then(|| Arc::from(serialized_blob)),
+		snapshot_data,
+		reset_data,
+	})
+}
+
 /// Create a new `WasmEdgeRuntime` given the code. This function performs translation from Wasm to
 /// machine code, which can be computationally heavy.
 ///
@@ -330,6 +2340,17 @@ pub fn create_runtime<H>(
 	blob: RuntimeBlob,
 	config: Config,
 ) -> std::result::Result<WasmEdgeRuntime, WasmError>
+where
+	H: HostFunctions,
+{
+	Ok(create_runtime_detailed::<H>(blob, config)?)
+}
+
+/// The same as [`create_runtime`], but tells at which stage creating the runtime failed.
+pub fn create_runtime_detailed<H>(
+	blob: RuntimeBlob,
+	config: Config,
+) -> std::result::Result<WasmEdgeRuntime, CreateRuntimeError>
 where
 	H: HostFunctions,
 {
@@ -337,49 +2358,250 @@ where
 	unsafe { do_create_runtime::<H>(CodeSupplyMode::Fresh(blob), config) }
 }
 
-/// The same as [`create_runtime`] but takes a path to a precompiled artifact,
-/// which makes this function considerably faster than [`create_runtime`].
+/// The same as [`create_runtime`] but takes a path to a precompiled artifact,
+/// which makes this function considerably faster than [`create_runtime`].
+///
+/// # Safety
+///
+/// The caller must ensure that the compiled artifact passed here was:
+///   1) produced by [`prepare_runtime_artifact`],
+///   2) written to the disk as a file,
+///   3) was not modified,
+///   4) will not be modified while any runtime using this artifact is alive, or is being
+///      instantiated.
+///
+/// Failure to adhere to these requirements might lead to crashes and arbitrary code execution.
+///
+/// It is ok though if the compiled artifact was created by code of another version or with
+/// different configuration flags. In such case the caller will receive an `Err` deterministically.
+pub unsafe fn create_runtime_from_artifact<H>(
+	compiled_artifact_path: &Path,
+	config: Config,
+) -> std::result::Result<WasmEdgeRuntime, WasmError>
+where
+	H: HostFunctions,
+{
+	Ok(do_create_runtime::<H>(CodeSupplyMode::Precompiled(compiled_artifact_path), config)?)
+}
+
+/// The same as [`create_runtime_from_artifact`], but also makes sure that the loaded runtime is
+/// functional by creating an instance, reading its `__heap_base` and calling `Core_version`.
+///
+/// This is meant to be called at node startup, so that a broken artifact results in an
+/// immediate and descriptive error instead of a failure during the first block import.
+///
+/// # Safety
+///
+/// The same requirements as for [`create_runtime_from_artifact`] apply.
+pub unsafe fn create_runtime_from_artifact_checked<H>(
+	compiled_artifact_path: &Path,
+	config: Config,
+) -> std::result::Result<WasmEdgeRuntime, WasmError>
+where
+	H: HostFunctions,
+{
+	let runtime =
+		create_runtime_from_artifact::<H>(compiled_artifact_path, config).map_err(|e| {
+			WasmError::Other(format!("artifact check failed: cannot load the artifact: {}", e))
+		})?;
+
+	let mut instance = runtime.new_instance().map_err(|e| {
+		WasmError::Other(format!("artifact check failed: cannot instantiate the runtime: {}", e))
+	})?;
+
+	match instance.get_global_const("__heap_base") {
+		Ok(Some(Value::I32(_))) => (),
+		Ok(_) =>
+			return Err(WasmError::Other(String::from(
+				"artifact check failed: `__heap_base` is not an i32 global",
+			))),
+		Err(e) =>
+			return Err(WasmError::Other(format!(
+				"artifact check failed: cannot read `__heap_base`: {}",
+				e
+			))),
+	}
+
+	instance.call_export("Core_version", &[]).map_err(|e| {
+		WasmError::Other(format!("artifact check failed: cannot call `Core_version`: {}", e))
+	})?;
+
+	drop(instance);
+	Ok(runtime)
+}
+
+/// Loads the runtime from the artifact at `compiled_artifact_path` if it is usable, otherwise
+/// compiles `blob` like [`create_runtime`] does.
 ///
-/// # Safety
-///
-/// The caller must ensure that the compiled artifact passed here was:
-///   1) produced by [`prepare_runtime_artifact`],
-///   2) written to the disk as a file,
-///   3) was not modified,
-///   4) will not be modified while any runtime using this artifact is alive, or is being
-///      instantiated.
+/// The artifact is deemed unusable if it is missing, if it has no header, e.g. because it was
+/// written by an older version of this crate, if its header is malformed, or if it was compiled
+/// by another version of WasmEdge, with incompatible semantics or from code other than `blob`.
+/// This makes node upgrades seamless. Any other error, e.g. a corrupt artifact
+/// with a valid header, is returned as is. If `rewrite_artifact` is `true`, an unusable artifact
+/// is replaced by one compiled from `blob`, so that it can be loaded next time.
 ///
-/// Failure to adhere to these requirements might lead to crashes and arbitrary code execution.
+/// # Safety
 ///
-/// It is ok though if the compiled artifact was created by code of another version or with
-/// different configuration flags. In such case the caller will receive an `Err` deterministically.
-pub unsafe fn create_runtime_from_artifact<H>(
+/// The same requirements as for [`create_runtime_from_artifact`] apply.
+pub unsafe fn create_runtime_artifact_or_compile<H>(
+	blob: RuntimeBlob,
 	compiled_artifact_path: &Path,
 	config: Config,
+	rewrite_artifact: bool,
 ) -> std::result::Result<WasmEdgeRuntime, WasmError>
 where
 	H: HostFunctions,
 {
-	do_create_runtime::<H>(CodeSupplyMode::Precompiled(compiled_artifact_path), config)
+	let usable = if compiled_artifact_path.exists() {
+		artifact::read_header(compiled_artifact_path).and_then(|header| {
+			header.check_compatible(&config.semantics)?;
+			if header.blob_hash != artifact::blob_hash(&blob) {
+				return Err(WasmError::Other(String::from(
+					"the artifact was compiled from another code",
+				)))
+			}
+			Ok(())
+		})
+	} else {
+		Err(WasmError::Other(String::from("the artifact doesn't exist")))
+	};
+
+	let e = match usable {
+		Ok(()) => return create_runtime_from_artifact::<H>(compiled_artifact_path, config),
+		Err(e) => e,
+	};
+
+	log::info!(
+		target: LOG_TARGET,
+		"Cannot use artifact {}: {}, compiling the runtime instead",
+		compiled_artifact_path.display(),
+		e,
+	);
+
+	if rewrite_artifact {
+		if let Err(e) =
+			prepare_runtime_artifact(blob.clone(), &config.semantics, compiled_artifact_path)
+		{
+			log::warn!(
+				target: LOG_TARGET,
+				"Cannot rewrite artifact {}: {}",
+				compiled_artifact_path.display(),
+				e,
+			);
+		}
+	}
+
+	create_runtime::<H>(blob, config)
 }
 
 /// Takes a [`RuntimeBlob`] and precompiles it returning the serialized result of compilation. It
 /// can then be used for calling [`create_runtime`] avoiding long compilation times.
+///
+/// A small header is stored at the end of the artifact, recording the version of WasmEdge
+/// (see [`wasmedge_version`](crate::wasmedge_version)) that produced it. The artifact is written
+/// to a temporary file first and moved into place together with its header. Loading an artifact
+/// compiled by a different version fails with an error. The header also records the
+/// [`blob_hash`](crate::blob_hash) of `blob`, see
+/// [`prune_artifact_cache`](crate::prune_artifact_cache).
 pub fn prepare_runtime_artifact(
 	blob: RuntimeBlob,
 	semantics: &Semantics,
 	compiled_artifact_path: &Path,
 ) -> std::result::Result<(), WasmError> {
-	let blob = prepare_blob_for_compilation(blob, semantics)?;
+	prepare_runtime_artifact_with_report(blob, semantics, compiled_artifact_path).map(|_| ())
+}
+
+/// Statistics about a single AOT compilation performed by
+/// [`prepare_runtime_artifact_with_report`].
+#[derive(Debug, Clone)]
+pub struct CompileReport {
+	/// The size in bytes of the wasm code passed to the compiler, i.e. after all the
+	/// instrumentation passes were applied.
+	pub input_size: usize,
+
+	/// The size in bytes of the compiled artifact written to the disk.
+	pub output_size: u64,
+
+	/// The number of functions defined by the module, not counting the imported ones.
+	pub function_count: usize,
+
+	/// How long the compilation took.
+	pub compile_duration: Duration,
+}
+
+/// The same as [`prepare_runtime_artifact`] but also returns a [`CompileReport`] describing the
+/// compilation.
+pub fn prepare_runtime_artifact_with_report(
+	blob: RuntimeBlob,
+	semantics: &Semantics,
+	compiled_artifact_path: &Path,
+) -> std::result::Result<CompileReport, WasmError> {
+	let blob_hash = artifact::blob_hash(&blob);
+	let blob = prepare_blob_for_compilation(blob, semantics, None)?;
+
+	let function_count = blob
+		.clone()
+		.into_inner()
+		.function_section()
+		.map_or(0, |section| section.entries().len());
+	let serialized_blob = blob.serialize();
+
+	log::debug!(
+		target: LOG_TARGET,
+		"Compiling runtime artifact to {}",
+		compiled_artifact_path.display(),
+	);
+	let time = Instant::now();
+
+	// The artifact is compiled into a temporary file and moved into place together with its
+	// header, so that nobody ever sees a partially written artifact or one without a header.
+	let temporary_path = artifact::temporary_artifact(compiled_artifact_path)?;
 
 	Compiler::new(Some(&common_config(semantics)?))
 		.map_err(|e| {
 			WasmError::Other(format!("fail to create a WasmEdge Compiler context: {}", e))
 		})?
-		.compile_from_bytes(&blob.serialize(), compiled_artifact_path)
+		.compile_from_bytes(&serialized_blob, &temporary_path)
 		.map_err(|e| WasmError::Other(format!("fail to compile the input WASM file: {}", e)))?;
 
-	Ok(())
+	let compile_duration = time.elapsed();
+
+	let output_size = std::fs::metadata(&temporary_path)
+		.map_err(|e| {
+			WasmError::Other(format!("cannot read the compiled artifact metadata: {}", e))
+		})?
+		.len();
+
+	if let Some(max_artifact_size) = semantics.max_artifact_size {
+		if output_size > max_artifact_size as u64 {
+			return Err(WasmError::Other(format!(
+				"artifact exceeds size limit: {} bytes, but at most {} bytes are allowed",
+				output_size, max_artifact_size,
+			)))
+		}
+	}
+
+	artifact::append_header(&temporary_path, &ArtifactHeader::current(semantics, blob_hash))?;
+	temporary_path.persist(compiled_artifact_path).map_err(|e| {
+		WasmError::Other(format!(
+			"cannot move the compiled artifact to {}: {}",
+			compiled_artifact_path.display(),
+			e.error,
+		))
+	})?;
+
+	log::debug!(
+		target: LOG_TARGET,
+		"Compiled runtime artifact in {} ms",
+		compile_duration.as_millis(),
+	);
+
+	Ok(CompileReport {
+		input_size: serialized_blob.len(),
+		output_size,
+		function_count,
+		compile_duration,
+	})
 }
 
 /// # Safety
@@ -389,64 +2611,421 @@ pub fn prepare_runtime_artifact(
 unsafe fn do_create_runtime<H>(
 	code_supply_mode: CodeSupplyMode<'_>,
 	config: Config,
-) -> std::result::Result<WasmEdgeRuntime, WasmError>
+) -> std::result::Result<WasmEdgeRuntime, CreateRuntimeError>
 where
 	H: HostFunctions,
 {
-	println!("========================WasmEdge========================");
+	static SETUP_WASMEDGE_LOGGING: Once = Once::new();
+	SETUP_WASMEDGE_LOGGING.call_once(|| {
+		if let Err(e) = crate::logging::redirect_wasmedge_logs() {
+			log::warn!(target: LOG_TARGET, "Failed to forward the WasmEdge logs: {}", e);
+		}
+		crate::logging::sync_wasmedge_log_level();
+	});
+
+	crate::plugin::load_plugins(&config.plugins)?;
+
+	if let (Some(requested), Some(effective)) =
+		(config.semantics.max_memory_size, config.effective_max_memory_bytes())
+	{
+		if requested != effective {
+			log::warn!(
+				target: LOG_TARGET,
+				"The maximum memory size of {} bytes isn't a multiple of the wasm page size, \
+				 the linear memory is limited to {} bytes instead",
+				requested,
+				effective,
+			);
+		}
+	}
+
+	let module = match code_supply_mode {
+		CodeSupplyMode::Fresh(blob) => do_compile_module(
+			blob,
+			&config.semantics,
+			config.blob_transform.as_ref(),
+			config.retain_prepared_blob,
+			config.detailed_validation,
+		)?,
+		CodeSupplyMode::Precompiled(compiled_artifact_path) => {
+			log::debug!(
+				target: LOG_TARGET,
+				"Loading runtime module from artifact {}",
+				compiled_artifact_path.display(),
+			);
+
+			let module = load_artifact(
+				&wasmedge_config(&config.semantics)?,
+				&config.semantics,
+				compiled_artifact_path,
+			)?;
+			let code_size = std::fs::metadata(compiled_artifact_path)
+				.map_err(|e| CreateRuntimeError::ArtifactMetadata(e.to_string()))?
+				.len() as usize;
+
+			CompiledModule {
+				module: Arc::new(module),
+				code_size,
+				artifact_key: config.semantics.artifact_key(),
+				prepared_blob: None,
+				snapshot_data: None,
+				reset_data: None,
+			}
+		},
+	};
+
+	Ok(runtime_from_compiled_module::<H>(module, config)?)
+}
 
-	let config_wasmedge = common_config(&config.semantics)?;
+/// Creates a runtime out of the given module, which must have been compiled with semantics
+/// compatible with `config.semantics`.
+fn runtime_from_compiled_module<H>(
+	module: CompiledModule,
+	config: Config,
+) -> std::result::Result<WasmEdgeRuntime, WasmError>
+where
+	H: HostFunctions,
+{
+	if module.artifact_key != config.semantics.artifact_key() {
+		return Err(WasmError::Other(String::from(
+			"the module was compiled with semantics incompatible with the ones in use",
+		)))
+	}
 
-	let (module, snapshot_data) = match code_supply_mode {
-		CodeSupplyMode::Fresh(blob) => {
-			let blob = prepare_blob_for_compilation(blob, &config.semantics)?;
-			let serialized_blob = blob.clone().serialize();
+	if config.semantics.benchmark_unchecked {
+		if config.semantics.benchmark_unchecked() {
+			log::warn!(
+				target: LOG_TARGET,
+				"Runtime created with `benchmark_unchecked`, it must not be used in production",
+			);
+		} else {
+			log::warn!(
+				target: LOG_TARGET,
+				"Ignoring `benchmark_unchecked` since the `benchmarking` feature is disabled",
+			);
+		}
+	}
 
-			let module =
-				Module::from_bytes(Some(&config_wasmedge), &serialized_blob).map_err(|e| {
-					WasmError::Other(format!("fail to create a WasmEdge Module context: {}", e))
-				})?;
+	let CompiledModule {
+		module,
+		code_size,
+		artifact_key: _,
+		prepared_blob,
+		snapshot_data,
+		reset_data,
+	} = module;
 
-			if config.semantics.fast_instance_reuse {
-				let data_segments_snapshot = DataSegmentsSnapshot::take(&blob).map_err(|e| {
-					WasmError::Other(format!("cannot take data segments snapshot: {}", e))
-				})?;
-				let data_segments_snapshot = Arc::new(data_segments_snapshot);
-				let mutable_globals = ExposedMutableGlobalsSet::collect(&blob);
+	validate_entry_points(&module, &config.validate_entry_points)?;
 
-				(module, Some(InstanceSnapshotData { data_segments_snapshot, mutable_globals }))
-			} else {
-				(module, None)
-			}
-		},
-		CodeSupplyMode::Precompiled(compiled_artifact_path) => {
-			let module = Module::from_file(Some(&config_wasmedge), compiled_artifact_path)
-				.map_err(|e| {
-					WasmError::Other(format!("fail to create a WasmEdge Module context: {}", e))
-				})?;
+	let host_functions = H::host_functions();
+	let host_function_registry = host_function_registry(&host_functions);
 
-			(module, None)
-		},
-	};
+	let import_report = crate::imports::import_report(
+		&module,
+		&host_function_registry,
+		config.accept_any_import_module,
+		config.provide_host_time,
+		config.semantics.interruptible,
+	);
+	log::debug!(
+		target: LOG_TARGET,
+		"Runtime imports: {} satisfied, {} stubbed {:?}, {} mismatched {:?}",
+		import_report.satisfied.len(),
+		import_report.stubbed.len(),
+		import_report.stubbed,
+		import_report.mismatched.len(),
+		import_report.mismatched,
+	);
 
 	Ok(WasmEdgeRuntime {
 		snapshot_data,
-		host_functions: H::host_functions(),
-		module: Arc::new(module),
+		reset_data,
+		host_functions,
+		host_function_registry,
+		environment: Arc::new(config.environment.clone()),
+		module,
+		code_size,
+		prepared_blob,
 		config,
+		instance_slots: Default::default(),
+		instance_memory: Arc::new(AtomicUsize::new(0)),
+	})
+}
+
+/// Checks that the given exports of the module are entry points with the right signature, see
+/// [`Config::validate_entry_points`].
+fn validate_entry_points(
+	module: &Module,
+	entry_points: &[String],
+) -> std::result::Result<(), WasmError> {
+	if entry_points.is_empty() {
+		return Ok(())
+	}
+
+	let exports = module.exports();
+	let mut problems = Vec::new();
+	for name in entry_points {
+		match exports
+			.iter()
+			.find(|export| export.name() == name.as_str())
+			.map(|export| export.ty())
+		{
+			Some(Ok(ExternalInstanceType::Func(func_ty))) =>
+				if !crate::instance_wrapper::is_entry_point_type(&func_ty) {
+					problems
+						.push(format!("`{}` doesn't have the signature `(i32, i32) -> i64`", name));
+				},
+			Some(_) => problems.push(format!("`{}` is not a function", name)),
+			None => problems.push(format!("`{}` is not exported", name)),
+		}
+	}
+
+	if problems.is_empty() {
+		Ok(())
+	} else {
+		Err(WasmError::Other(format!("invalid entry points: {}", problems.join(", "))))
+	}
+}
+
+/// Returns the data needed for resetting the instances of the given module, or `None` if they
+/// can't be reset.
+///
+/// An instance can only be reset if its whole state right after the instantiation can be
+/// restored. The mutable globals exposed by [`prepare_blob_for_compilation`] are snapshotted like
+/// for the fast instance reuse, so the module must not define any other mutable globals, e.g.
+/// ones added by a [`Config::blob_transform`]. Nor may it have a start function, whose effects on
+/// the memory aren't captured by the data segments snapshot.
+fn reset_data(blob: &RuntimeBlob) -> Option<Arc<InstanceSnapshotData>> {
+	let module = blob.clone().into_inner();
+	if module.start_section().is_some() {
+		return None
+	}
+
+	let exported_globals = module.export_section().map_or(HashSet::new(), |section| {
+		section
+			.entries()
+			.iter()
+			.filter_map(|export| match export.internal() {
+				parity_wasm::elements::Internal::Global(index) => Some(*index),
+				_ => None,
+			})
+			.collect()
+	});
+	let imported_globals = blob.imported_globals_count();
+	let has_unexposed_mutable_globals = module.global_section().map_or(false, |section| {
+		section.entries().iter().enumerate().any(|(index, global)| {
+			global.global_type().is_mutable() &&
+				!exported_globals.contains(&(imported_globals + index as u32))
+		})
+	});
+	if has_unexposed_mutable_globals {
+		return None
+	}
+
+	// Passive data segments aren't supported by the snapshot, fall back to instantiating.
+	let data_segments_snapshot = DataSegmentsSnapshot::take(blob).ok()?;
+
+	Some(Arc::new(InstanceSnapshotData {
+		data_segments_snapshot: Arc::new(data_segments_snapshot),
+		mutable_globals: ExposedMutableGlobalsSet::collect(blob),
+	}))
+}
+
+/// How many times loading an artifact is attempted before giving up on a transient error.
+pub(crate) const ARTIFACT_LOAD_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry of loading an artifact. Doubled on every subsequent retry.
+const ARTIFACT_LOAD_BACKOFF: Duration = Duration::from_millis(20);
+
+/// Why an attempt to load an artifact failed, see [`load_artifact`].
+pub(crate) enum ArtifactLoadError {
+	/// The artifact changed while it was being loaded, e.g. because another process was still
+	/// writing or replacing it, so loading it again may succeed.
+	Transient(CreateRuntimeError),
+	/// Loading the artifact again would fail the same way, e.g. because it doesn't exist or is
+	/// malformed.
+	Fatal(CreateRuntimeError),
+}
+
+/// Loads a precompiled artifact from the given path.
+///
+/// The artifact might be caught while another process is writing or replacing it in the cache
+/// directory, which WasmEdge reports as a malformed module. Hence a failed load is retried a few
+/// times with an exponential backoff if the file changed during the attempt. Any other error,
+/// including a missing, malformed or incompatible artifact, fails immediately.
+fn load_artifact(
+	config: &wasmedge_sdk::config::Config,
+	semantics: &Semantics,
+	compiled_artifact_path: &Path,
+) -> std::result::Result<Module, CreateRuntimeError> {
+	retry_artifact_load(compiled_artifact_path, ARTIFACT_LOAD_BACKOFF, || {
+		load_artifact_once(config, semantics, compiled_artifact_path)
+	})
+}
+
+/// Calls `load` until it succeeds or fails with an [`ArtifactLoadError::Fatal`] error, at most
+/// [`ARTIFACT_LOAD_ATTEMPTS`] times, sleeping for `backoff` doubled after every attempt in
+/// between.
+pub(crate) fn retry_artifact_load<T>(
+	compiled_artifact_path: &Path,
+	mut backoff: Duration,
+	mut load: impl FnMut() -> std::result::Result<T, ArtifactLoadError>,
+) -> std::result::Result<T, CreateRuntimeError> {
+	let mut attempt = 1;
+	loop {
+		match load() {
+			Ok(loaded) => return Ok(loaded),
+			Err(ArtifactLoadError::Transient(e)) if attempt < ARTIFACT_LOAD_ATTEMPTS => {
+				log::debug!(
+					target: LOG_TARGET,
+					"Cannot load artifact {} (attempt {}): {}, retrying in {} ms",
+					compiled_artifact_path.display(),
+					attempt,
+					e,
+					backoff.as_millis(),
+				);
+
+				std::thread::sleep(backoff);
+				backoff *= 2;
+				attempt += 1;
+			},
+			Err(ArtifactLoadError::Transient(e) | ArtifactLoadError::Fatal(e)) => return Err(e),
+		}
+	}
+}
+
+/// Makes a single attempt to load the artifact, see [`load_artifact`].
+pub(crate) fn load_artifact_once(
+	config: &wasmedge_sdk::config::Config,
+	semantics: &Semantics,
+	compiled_artifact_path: &Path,
+) -> std::result::Result<Module, ArtifactLoadError> {
+	let before = file_identity(compiled_artifact_path).map_err(|e| {
+		let error = WasmError::Other(format!(
+			"cannot open artifact {}: {}",
+			compiled_artifact_path.display(),
+			e
+		))
+		.into();
+		if is_transient_io_error(&e) {
+			ArtifactLoadError::Transient(error)
+		} else {
+			ArtifactLoadError::Fatal(error)
+		}
+	})?;
+
+	let loaded = artifact::read_header(compiled_artifact_path)
+		.and_then(|header| header.check_compatible(semantics))
+		.map_err(CreateRuntimeError::from)
+		.and_then(|()| {
+			Module::from_file(Some(config), compiled_artifact_path)
+				.map_err(|e| CreateRuntimeError::Module(e.to_string()))
+		});
+
+	loaded.map_err(|error| match file_identity(compiled_artifact_path) {
+		Ok(after) if after == before => ArtifactLoadError::Fatal(error),
+		// The file was written to, replaced or removed in the meantime.
+		_ => ArtifactLoadError::Transient(error),
+	})
+}
+
+/// Identifies the contents of a file, which change whenever it's written to or replaced.
+fn file_identity(path: &Path) -> std::io::Result<(u64, Option<std::time::SystemTime>, u64)> {
+	let metadata = std::fs::metadata(path)?;
+
+	#[cfg(unix)]
+	let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+	#[cfg(not(unix))]
+	let inode = 0;
+
+	Ok((metadata.len(), metadata.modified().ok(), inode))
+}
+
+/// Validates the code with `wasmparser` using the features enabled by the semantics, and
+/// describes the first rule it violates along with where, see [`Config::detailed_validation`].
+///
+/// Returns `None` if `wasmparser` finds nothing wrong with the code.
+fn validation_details(code: &[u8], semantics: &Semantics) -> Option<String> {
+	// The same features as enabled by `common_config`.
+	let features = wasmparser::WasmFeatures {
+		mutable_global: true,
+		saturating_float_to_int: semantics.enable_saturating_float_to_int,
+		sign_extension: semantics.enable_sign_extension,
+		reference_types: false,
+		multi_value: false,
+		bulk_memory: false,
+		simd: false,
+		threads: false,
+		..Default::default()
+	};
+	let error = wasmparser::Validator::new_with_features(features).validate_all(code).err()?;
+
+	Some(match function_at(code, error.offset()) {
+		Some(func_index) => format!(
+			"{} in function {} at offset {:#x}",
+			error.message(),
+			func_index,
+			error.offset()
+		),
+		None => format!("{} at offset {:#x}", error.message(), error.offset()),
 	})
 }
 
+/// Returns the index of the function whose body contains the given offset of the code, counting
+/// the imported functions first as the function index space does.
+fn function_at(code: &[u8], offset: usize) -> Option<u32> {
+	let mut func_index = 0;
+	for payload in wasmparser::Parser::new(0).parse_all(code) {
+		match payload.ok()? {
+			wasmparser::Payload::ImportSection(imports) =>
+				for import in imports {
+					if let wasmparser::TypeRef::Func(_) = import.ok()?.ty {
+						func_index += 1;
+					}
+				},
+			wasmparser::Payload::CodeSectionEntry(body) => {
+				if body.range().contains(&offset) {
+					return Some(func_index)
+				}
+				func_index += 1;
+			},
+			_ => {},
+		}
+	}
+	None
+}
+
+fn is_transient_io_error(error: &std::io::Error) -> bool {
+	use std::io::ErrorKind;
+
+	matches!(error.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// The version of the format of [`Semantics::compilation_settings`].
+const COMPILATION_SETTINGS_VERSION: u8 = 6;
+
+/// A description of the compiler settings hardcoded in [`common_config`], which is a part of
+/// [`Semantics::compilation_settings`]. Must be updated whenever those settings change.
+const COMPILER_SETTINGS: &str =
+	"opt_level=Os;generic_binary;interruptible;proposals=mutable_globals";
+
 pub fn common_config(
 	semantics: &Semantics,
 ) -> std::result::Result<wasmedge_sdk::config::Config, WasmError> {
+	Ok(wasmedge_config(semantics)?)
+}
+
+/// The same as [`common_config`], but returns a [`CreateRuntimeError::Config`] on failure.
+fn wasmedge_config(
+	semantics: &Semantics,
+) -> std::result::Result<wasmedge_sdk::config::Config, CreateRuntimeError> {
 	let common_options = CommonConfigOptions::default()
 		.bulk_memory_operations(false)
 		.multi_value(false)
 		.mutable_globals(true)
-		.non_trap_conversions(true)
+		.non_trap_conversions(semantics.enable_saturating_float_to_int)
 		.reference_types(false)
-		.sign_extension_operators(true)
+		.sign_extension_operators(semantics.enable_sign_extension)
 		.simd(false)
 		.threads(false);
 
@@ -455,37 +3034,73 @@ pub fn common_config(
 		.generic_binary(true)
 		.interruptible(true)
 		.optimization_level(CompilerOptimizationLevel::Os)
-		.out_format(CompilerOutputFormat::Native);
+		.out_format(match semantics.artifact_format {
+			ArtifactFormat::Native => CompilerOutputFormat::Native,
+			ArtifactFormat::Universal => CompilerOutputFormat::Wasm,
+		});
 
 	let mut runtime_options = RuntimeConfigOptions::default();
 	if let Some(max_memory_size) = semantics.max_memory_size {
-		runtime_options = runtime_options.max_memory_pages((max_memory_size / 64 / 1024) as u32);
+		runtime_options = runtime_options.max_memory_pages(max_memory_pages(max_memory_size));
 	}
 
+	let statistics_options = StatisticsConfigOptions::default()
+		.count_instructions(semantics.statistics.instr_count)
+		.measure_cost(semantics.statistics.cost)
+		.measure_time(semantics.statistics.time);
+
 	let wasmedge_config = ConfigBuilder::new(common_options)
 		.with_compiler_config(compiler_options)
 		.with_runtime_config(runtime_options)
+		.with_statistics_config(statistics_options)
 		.build()
-		.map_err(|e| {
-			WasmError::Other(format!("fail to create a WasmEdge Config context: {}", e))
-		})?;
+		.map_err(|e| CreateRuntimeError::Config(e.to_string()))?;
 
 	Ok(wasmedge_config)
 }
 
-fn prepare_blob_for_compilation(
+/// Returns whether the given code imports its linear memory rather than defining it.
+///
+/// The memory import is converted into an export before the compilation, so this has to be asked
+/// of the original code, e.g. the one passed to [`create_runtime`].
+pub fn uses_imported_memory(blob: &RuntimeBlob) -> bool {
+	blob.clone().into_inner().import_section().map_or(false, |section| {
+		section
+			.entries()
+			.iter()
+			.any(|entry| matches!(entry.external(), parity_wasm::elements::External::Memory(_)))
+	})
+}
+
+/// Applies all the passes required before compiling the code, followed by the given
+/// [`Config::blob_transform`].
+pub(crate) fn prepare_blob_for_compilation(
 	mut blob: RuntimeBlob,
 	semantics: &Semantics,
+	blob_transform: Option<&BlobTransform>,
 ) -> std::result::Result<RuntimeBlob, WasmError> {
+	if semantics.interruptible {
+		blob = inject_deadline_checks(blob)?;
+	}
+
 	if let Some(DeterministicStackLimit { logical_max }) = semantics.deterministic_stack_limit {
+		// The stack limiter adds the global holding the current stack height after all the others.
+		let stack_height_global = blob.imported_globals_count() + blob.declared_globals_count();
 		blob = blob.inject_stack_depth_metering(logical_max)?;
+
+		if semantics.track_max_stack_depth {
+			blob = inject_max_stack_depth_tracking(blob, stack_height_global)?;
+		}
 	}
 
-	// If enabled, this should happen after all other passes that may introduce global variables.
-	if semantics.fast_instance_reuse {
-		blob.expose_mutable_globals();
+	if semantics.canonicalize_nans {
+		blob = inject_nan_canonicalization(blob)?;
 	}
 
+	// This should happen after all other passes that may introduce global variables. The exposed
+	// globals are snapshotted both for reusing and for resetting the instances.
+	blob.expose_mutable_globals();
+
 	// We don't actually need the memory to be imported so we can just convert any memory
 	// import into an export with impunity. This simplifies our code since `wasmedge` will
 	// now automatically take care of creating the memory for us, and it is also necessary
@@ -498,45 +3113,365 @@ fn prepare_blob_for_compilation(
 			.map_err(|e| WasmError::Other(format!("invalid `extra_heap_pages`: {}", e)))?,
 	)?;
 
-	Ok(blob)
+	match blob_transform {
+		Some(blob_transform) => blob_transform(blob),
+		None => Ok(blob),
+	}
+}
+
+/// Makes the code call into the host at the start of every metered block, where the host aborts
+/// a call which passed its deadline, see [`Semantics::interruptible`].
+///
+/// The calls are injected by the gas metering of `wasm-instrument`, the host checks the time
+/// rather than counting the gas.
+fn inject_deadline_checks(blob: RuntimeBlob) -> std::result::Result<RuntimeBlob, WasmError> {
+	use wasm_instrument::gas_metering::{self, ConstantCostRules};
+
+	let module = gas_metering::inject(
+		blob.into_inner(),
+		&ConstantCostRules::default(),
+		crate::imports::DEADLINE_MODULE_NAME,
+	)
+	.map_err(|_| WasmError::Other(String::from("cannot inject the deadline checks")))?;
+
+	let wasm = parity_wasm::serialize(module)
+		.map_err(|e| WasmError::Other(format!("cannot serialize the module: {}", e)))?;
+	RuntimeBlob::new(&wasm)
+}
+
+/// The name of the global holding the maximum stack depth reached during the current call, see
+/// [`Semantics::track_max_stack_depth`].
+pub(crate) const MAX_STACK_DEPTH_GLOBAL: &str = "__wasmedge_max_stack_depth";
+
+/// Adds a global tracking the maximum value the stack height global (as maintained by the stack
+/// limiter) reached, exported as [`MAX_STACK_DEPTH_GLOBAL`].
+///
+/// The maximum is updated right after every write to the stack height global.
+fn inject_max_stack_depth_tracking(
+	blob: RuntimeBlob,
+	stack_height_global: u32,
+) -> std::result::Result<RuntimeBlob, WasmError> {
+	use parity_wasm::elements::{
+		BlockType, ExportEntry, GlobalEntry, GlobalType, InitExpr, Instruction, Internal, ValueType,
+	};
+
+	let mut module = blob.into_inner();
+
+	let imported_globals = module.import_section().map_or(0, |section| section.globals() as u32);
+	let global_section = module
+		.global_section_mut()
+		.ok_or_else(|| WasmError::Other(String::from("the stack height global is missing")))?;
+	let max_global = imported_globals + global_section.entries().len() as u32;
+	if max_global != stack_height_global + 1 {
+		return Err(WasmError::Other(String::from("cannot locate the stack height global")))
+	}
+	global_section.entries_mut().push(GlobalEntry::new(
+		GlobalType::new(ValueType::I32, true),
+		InitExpr::new(vec![Instruction::I32Const(0), Instruction::End]),
+	));
+
+	let bodies = module.code_section_mut().map(|section| section.bodies_mut().iter_mut());
+	for body in bodies.into_iter().flatten() {
+		let code = body.code_mut().elements_mut();
+		let mut instrumented = Vec::with_capacity(code.len());
+		for instruction in code.drain(..) {
+			let is_height_update = instruction == Instruction::SetGlobal(stack_height_global);
+			instrumented.push(instruction);
+
+			if is_height_update {
+				instrumented.extend([
+					Instruction::GetGlobal(stack_height_global),
+					Instruction::GetGlobal(max_global),
+					Instruction::I32GtU,
+					Instruction::If(BlockType::NoResult),
+					Instruction::GetGlobal(stack_height_global),
+					Instruction::SetGlobal(max_global),
+					Instruction::End,
+				]);
+			}
+		}
+		*code = instrumented;
+	}
+
+	module
+		.export_section_mut()
+		.ok_or_else(|| WasmError::Other(String::from("the module has no exports")))?
+		.entries_mut()
+		.push(ExportEntry::new(MAX_STACK_DEPTH_GLOBAL.into(), Internal::Global(max_global)));
+
+	let wasm = parity_wasm::serialize(module)
+		.map_err(|e| WasmError::Other(format!("cannot serialize the module: {}", e)))?;
+	RuntimeBlob::new(&wasm)
+}
+
+/// The bits of the canonical NaN of `f32`, see [`Semantics::canonicalize_nans`].
+const CANONICAL_NAN_F32: u32 = 0x7fc0_0000;
+
+/// The bits of the canonical NaN of `f64`, see [`Semantics::canonicalize_nans`].
+const CANONICAL_NAN_F64: u64 = 0x7ff8_0000_0000_0000;
+
+/// Replaces the NaNs produced by the floating point instructions with the canonical NaN, see
+/// [`Semantics::canonicalize_nans`].
+///
+/// Every function gets an `f32` and an `f64` local, which the result of such an instruction is
+/// copied to in order to compare it with itself: only a NaN isn't equal to itself, and is then
+/// replaced with the canonical NaN by a `select`.
+fn inject_nan_canonicalization(blob: RuntimeBlob) -> std::result::Result<RuntimeBlob, WasmError> {
+	use parity_wasm::elements::{Instruction, Local, Type, ValueType};
+
+	let mut module = blob.into_inner();
+
+	let types = module.type_section().map_or(&[][..], |section| section.types());
+	let result_of = |type_index: u32| match types.get(type_index as usize) {
+		Some(Type::Function(func_ty)) => func_ty.results().first().copied(),
+		None => None,
+	};
+	let params_of = |type_index: u32| match types.get(type_index as usize) {
+		Some(Type::Function(func_ty)) => func_ty.params().len() as u32,
+		None => 0,
+	};
+
+	// Only the results of the host functions are canonicalized, the functions of the module
+	// return canonical NaNs already.
+	let imported_func_results = module
+		.import_section()
+		.map_or(&[][..], |section| section.entries())
+		.iter()
+		.filter_map(|entry| match entry.external() {
+			parity_wasm::elements::External::Function(type_index) => Some(result_of(*type_index)),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+	let func_params = module
+		.function_section()
+		.map_or(&[][..], |section| section.entries())
+		.iter()
+		.map(|func| params_of(func.type_ref()))
+		.collect::<Vec<_>>();
+
+	let bodies = module.code_section_mut().map(|section| section.bodies_mut().iter_mut());
+	for (body, params) in bodies.into_iter().flatten().zip(func_params) {
+		let locals = params + body.locals().iter().map(|local| local.count()).sum::<u32>();
+		let (f32_local, f64_local) = (locals, locals + 1);
+		body.locals_mut()
+			.extend([Local::new(1, ValueType::F32), Local::new(1, ValueType::F64)]);
+
+		let code = body.code_mut().elements_mut();
+		let mut instrumented = Vec::with_capacity(code.len());
+		for instruction in code.drain(..) {
+			let result = match &instruction {
+				Instruction::F32Add |
+				Instruction::F32Sub |
+				Instruction::F32Mul |
+				Instruction::F32Div |
+				Instruction::F32Min |
+				Instruction::F32Max |
+				Instruction::F32Sqrt |
+				Instruction::F32Ceil |
+				Instruction::F32Floor |
+				Instruction::F32Trunc |
+				Instruction::F32Nearest |
+				Instruction::F32DemoteF64 => Some(ValueType::F32),
+				Instruction::F64Add |
+				Instruction::F64Sub |
+				Instruction::F64Mul |
+				Instruction::F64Div |
+				Instruction::F64Min |
+				Instruction::F64Max |
+				Instruction::F64Sqrt |
+				Instruction::F64Ceil |
+				Instruction::F64Floor |
+				Instruction::F64Trunc |
+				Instruction::F64Nearest |
+				Instruction::F64PromoteF32 => Some(ValueType::F64),
+				Instruction::Call(func_index) =>
+					imported_func_results.get(*func_index as usize).copied().flatten(),
+				_ => None,
+			};
+			instrumented.push(instruction);
+
+			match result {
+				Some(ValueType::F32) => instrumented.extend([
+					Instruction::TeeLocal(f32_local),
+					Instruction::F32Const(CANONICAL_NAN_F32),
+					Instruction::GetLocal(f32_local),
+					Instruction::GetLocal(f32_local),
+					Instruction::F32Eq,
+					Instruction::Select,
+				]),
+				Some(ValueType::F64) => instrumented.extend([
+					Instruction::TeeLocal(f64_local),
+					Instruction::F64Const(CANONICAL_NAN_F64),
+					Instruction::GetLocal(f64_local),
+					Instruction::GetLocal(f64_local),
+					Instruction::F64Eq,
+					Instruction::Select,
+				]),
+				_ => (),
+			}
+		}
+		*code = instrumented;
+	}
+
+	let wasm = parity_wasm::serialize(module)
+		.map_err(|e| WasmError::Other(format!("cannot serialize the module: {}", e)))?;
+	RuntimeBlob::new(&wasm)
 }
 
-fn perform_call(
-	data: &[u8],
+/// Returns the offset at which the allocator's heap should start.
+///
+/// This is the value of `__heap_base` unless `heap_base_override` is specified, in which case
+/// the override is checked to not overlap with the memory below `__heap_base`.
+fn heap_base(
 	instance_wrapper: &mut InstanceWrapper,
-	method: InvokeMethod,
-	mut allocator: FreeingBumpHeapAllocator,
-	allocation_stats: &mut Option<AllocationStats>,
-) -> Result<Vec<u8>> {
-	let (data_ptr, data_len) = inject_input_data(instance_wrapper, &mut allocator, data)?;
+	heap_base_override: Option<u32>,
+) -> Result<u32> {
+	let heap_base = instance_wrapper.extract_heap_base()?;
+
+	match heap_base_override {
+		Some(heap_base_override) if heap_base_override < heap_base =>
+			Err(WasmError::Other(format!(
+				"heap base override {} is below the runtime's `__heap_base` {}",
+				heap_base_override, heap_base,
+			))
+			.into()),
+		Some(heap_base_override) => Ok(heap_base_override),
+		None => Ok(heap_base),
+	}
+}
+
+/// What is recorded about a call besides its output.
+#[derive(Default)]
+struct CallRecord {
+	allocation_stats: Option<AllocationStats>,
+	/// The reason of the last failed call into a sandboxed instance made during the call.
+	sandbox_error: Option<SandboxError>,
+	/// The maximum stack depth reached during the call, if tracked.
+	max_stack_depth: Option<u32>,
+	/// The host function calls made during the call, if recorded.
+	host_calls: Vec<HostCall>,
+	/// The time spent in host functions during the call, if measured.
+	host_time: Option<Duration>,
+	/// The address and the size of the most recent allocation made through the host functions.
+	last_allocation: Option<(u32, WordSize)>,
+}
 
-	let host_state = HostState::new(allocator);
+fn perform_call<R>(
+	mut host_state: HostState,
+	input_len: usize,
+	write_input: impl FnOnce(&mut [u8]),
+	instance_wrapper: &mut InstanceWrapper,
+	method: InvokeMethod,
+	record: &mut CallRecord,
+	read_output: impl FnOnce(&InstanceWrapper, u32, u32) -> Result<R>,
+) -> Result<R> {
+	let (data_ptr, data_len) =
+		inject_input_data(instance_wrapper, host_state.allocator(), input_len, write_input)?;
+	instance_wrapper.reset_max_stack_depth();
+
+	log::debug!(target: LOG_TARGET, "Calling into runtime with {} bytes of input", data_len);
+
+	let trace = host_state.trace_sink().cloned().map(|trace_sink| {
+		let method = match &method {
+			InvokeMethod::Export(name) => name.to_string(),
+			InvokeMethod::Table(index) => format!("table[{}]", index),
+			InvokeMethod::TableWithWrapper { dispatcher_ref, func } =>
+				format!("table[{}]({})", dispatcher_ref, func),
+		};
+		trace_sink(TraceEvent::CallEnter { method: method.clone(), time: Instant::now() });
+		(trace_sink, method)
+	});
 
 	// Set the host state before calling into wasm.
 	instance_wrapper.set_host_state(Some(host_state));
 	let ret = instance_wrapper.call(method, data_ptr, data_len).map(unpack_ptr_and_len);
 
-	// Reset the host state
-	let host_state = instance_wrapper.take_host_state().expect(
-		"the host state is always set before calling into WASM so it can't be None here; qed",
+	if let Some((trace_sink, method)) = trace {
+		trace_sink(TraceEvent::CallExit { method, time: Instant::now() });
+	}
+
+	let ret = finish_call(instance_wrapper, record, ret);
+
+	if let (Err(error), Some(on_trap)) = (&ret, instance_wrapper.on_trap()) {
+		on_trap(error);
+	}
+
+	log::debug!(
+		target: LOG_TARGET,
+		"Runtime call finished {}",
+		if ret.is_ok() { "successfully" } else { "with an error" },
 	);
-	*allocation_stats = Some(host_state.allocation_stats());
 
 	let (output_ptr, output_len) = ret?;
-	let output = extract_output_data(instance_wrapper, output_ptr, output_len)?;
+	read_output(instance_wrapper, output_ptr, output_len)
+}
 
-	Ok(output)
+/// Takes the host state back from the instance after a call, recording what the call did, and
+/// returns the result of the call.
+fn finish_call<T>(
+	instance_wrapper: &mut InstanceWrapper,
+	record: &mut CallRecord,
+	result: Result<T>,
+) -> Result<T> {
+	let mut host_state = instance_wrapper.take_host_state().expect(
+		"the host state is always set before calling into WASM so it can't be None here; qed",
+	);
+	record.allocation_stats = Some(host_state.allocation_stats());
+	record.sandbox_error = host_state.take_sandbox_error();
+	record.max_stack_depth = instance_wrapper.max_stack_depth();
+	record.host_calls = host_state.take_host_calls();
+	record.host_time = host_state.host_time();
+	record.last_allocation = host_state.last_allocation();
+
+	// The host functions exceeding the time limit or the call passing its deadline only make the
+	// call trap, report why.
+	result.map_err(|error| {
+		host_state
+			.host_time_error()
+			.or_else(|| host_state.deadline_error())
+			.unwrap_or(error)
+	})
+}
+
+/// The error returned when exporting or importing the state of an instance which doesn't keep
+/// any state between the calls.
+fn state_not_kept_error() -> Error {
+	Error::Other(String::from(
+		"the state of an instance is only kept with the fast instance reuse enabled",
+	))
+}
+
+fn write_preloaded_memory(
+	instance_wrapper: &mut InstanceWrapper,
+	preloaded_memory: &[(u32, Vec<u8>)],
+) -> Result<()> {
+	let memory_slice = util::memory_slice_mut(instance_wrapper.memory_mut());
+	for (offset, data) in preloaded_memory {
+		util::write_memory_from(memory_slice, Pointer::new(*offset), data)?;
+	}
+	Ok(())
 }
 
+/// Allocates `len` bytes for the input data and lets `write` write the data there.
 fn inject_input_data(
 	instance_wrapper: &mut InstanceWrapper,
 	allocator: &mut FreeingBumpHeapAllocator,
-	data: &[u8],
+	len: usize,
+	write: impl FnOnce(&mut [u8]),
 ) -> Result<(Pointer<u8>, WordSize)> {
+	let data_len = checked_data_len(len)?;
 	let memory_slice = util::memory_slice_mut(instance_wrapper.memory_mut());
-	let data_len = data.len() as WordSize;
+	if memory_slice.is_empty() {
+		// The allocator would only report that it ran out of space.
+		return Err(Error::Other(String::from(
+			"cannot allocate the input: the linear memory has no pages, the runtime must declare \
+			at least one initial page or `Semantics::extra_heap_pages` must be non-zero",
+		)))
+	}
 	let data_ptr = allocator.allocate(memory_slice, data_len)?;
-	util::write_memory_from(memory_slice, data_ptr, data)?;
+	let range = checked_range(data_ptr.into(), len, memory_slice.len())
+		.ok_or_else(|| Error::Other("memory write is out of bounds".into()))?;
+	write(&mut memory_slice[range]);
 	Ok((data_ptr, data_len))
 }
 
@@ -544,12 +3479,66 @@ fn extract_output_data(
 	instance_wrapper: &InstanceWrapper,
 	output_ptr: u32,
 	output_len: u32,
+	check_bounds: bool,
+	output_validator: Option<&OutputValidator>,
 ) -> Result<Vec<u8>> {
+	let memory_slice = util::memory_slice(instance_wrapper.memory());
+
+	// Check the bounds before allocating the buffer, so that a bogus length returned by the
+	// runtime can't make us allocate up to 4 GiB only to fail afterwards. The read below is
+	// bounds checked regardless.
+	if check_bounds {
+		output_range(memory_slice, output_ptr, output_len)?;
+	}
+
 	let mut output = vec![0; output_len as usize];
-	util::read_memory_into(
-		util::memory_slice(instance_wrapper.memory()),
-		Pointer::new(output_ptr),
-		&mut output,
-	)?;
+	util::read_memory_into(memory_slice, Pointer::new(output_ptr), &mut output)?;
+	validate_output(output_validator, &output)?;
 	Ok(output)
 }
+
+/// Checks the output with the [`Config::output_validator`], if any.
+fn validate_output(output_validator: Option<&OutputValidator>, output: &[u8]) -> Result<()> {
+	match output_validator {
+		Some(output_validator) => output_validator(output)
+			.map_err(|e| Error::Other(format!("the output of the call is malformed: {}", e))),
+		None => Ok(()),
+	}
+}
+
+/// The size of the chunks the output is fed to the sink in by [`stream_output_data`].
+const OUTPUT_CHUNK_SIZE: usize = 64 * 1024;
+
+fn stream_output_data(
+	instance_wrapper: &InstanceWrapper,
+	output_ptr: u32,
+	output_len: u32,
+	sink: &mut impl FnMut(&[u8]),
+) -> Result<()> {
+	let memory_slice = util::memory_slice(instance_wrapper.memory());
+	let range = output_range(memory_slice, output_ptr, output_len)?;
+
+	for chunk in memory_slice[range].chunks(OUTPUT_CHUNK_SIZE) {
+		sink(chunk);
+	}
+
+	Ok(())
+}
+
+/// Returns the range of the linear memory holding the output, failing if it's out of bounds.
+fn output_range(memory: &[u8], output_ptr: u32, output_len: u32) -> Result<Range<usize>> {
+	checked_range(output_ptr as usize, output_len as usize, memory.len()).ok_or_else(|| {
+		Error::Other(format!(
+			"output data of {} bytes at {} is out of the linear memory bounds",
+			output_len, output_ptr,
+		))
+	})
+}
+
+/// Converts the length of the data passed into the runtime into a [`WordSize`], failing if it
+/// doesn't fit into the 32-bit address space of the runtime.
+pub(crate) fn checked_data_len(len: usize) -> Result<WordSize> {
+	WordSize::try_from(len).map_err(|_| {
+		Error::Other(format!("input data too large: {} bytes exceeds the 4 GiB limit", len))
+	})
+}
@@ -0,0 +1,58 @@
+use crate::{
+	runtime::{create_runtime, Config, WasmEdgeRuntime},
+	LOG_TARGET,
+};
+use arc_swap::ArcSwap;
+use sc_executor_common::{error::WasmError, runtime_blob::RuntimeBlob, wasm_runtime::WasmModule};
+use sp_wasm_interface::HostFunctions;
+use std::sync::Arc;
+
+/// Holds the runtime currently in use and allows replacing it with a new version while it keeps
+/// serving calls.
+///
+/// The new runtime is compiled and instantiated once before it's swapped in, without blocking the
+/// callers of [`RuntimeSwapper::current`]. If that fails, the old runtime stays in place, so there
+/// is no window in which no runtime is available. Calls which already got the old runtime finish
+/// with it; it's freed once the last of them is done.
+pub struct RuntimeSwapper {
+	current: ArcSwap<WasmEdgeRuntime>,
+}
+
+impl RuntimeSwapper {
+	/// Creates a swapper serving the given runtime.
+	pub fn new(runtime: WasmEdgeRuntime) -> Self {
+		Self { current: ArcSwap::from_pointee(runtime) }
+	}
+
+	/// Returns the runtime currently in use.
+	pub fn current(&self) -> Arc<WasmEdgeRuntime> {
+		self.current.load_full()
+	}
+
+	/// Replaces the runtime in use with the given one, returning the old runtime.
+	pub fn swap(&self, runtime: Arc<WasmEdgeRuntime>) -> Arc<WasmEdgeRuntime> {
+		self.current.swap(runtime)
+	}
+
+	/// Compiles the given code into a new runtime and swaps it in, returning the old runtime.
+	///
+	/// The new runtime is instantiated once to check that it's usable. On any error the runtime in
+	/// use is kept and the error is returned.
+	pub fn prepare_and_swap<H>(
+		&self,
+		blob: RuntimeBlob,
+		config: Config,
+	) -> std::result::Result<Arc<WasmEdgeRuntime>, WasmError>
+	where
+		H: HostFunctions,
+	{
+		let runtime = create_runtime::<H>(blob, config)?;
+		runtime.new_instance().map_err(|error| {
+			WasmError::Other(format!("cannot instantiate the new runtime: {}", error))
+		})?;
+
+		let old = self.swap(Arc::new(runtime));
+		log::debug!(target: LOG_TARGET, "Swapped in a new runtime");
+		Ok(old)
+	}
+}
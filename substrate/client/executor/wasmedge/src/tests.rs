@@ -1,5 +1,15 @@
-use sc_executor_common::{error::Error, runtime_blob::RuntimeBlob, wasm_runtime::WasmModule};
+use crate::{
+	host::{HostContext, HostState},
+	instance_wrapper::InstanceWrapper,
+};
+use sc_allocator::FreeingBumpHeapAllocator;
+use sc_executor_common::{
+	error::Error,
+	runtime_blob::RuntimeBlob,
+	wasm_runtime::{InvokeMethod, WasmInstance, WasmModule},
+};
 use sc_runtime_test::wasm_binary_unwrap;
+use sp_wasm_interface::{HostFunctions as _, Pointer, Value};
 use std::sync::Arc;
 
 type HostFunctions = sp_io::SubstrateHostFunctions;
@@ -10,6 +20,36 @@ struct RuntimeBuilder {
 	deterministic_stack: bool,
 	extra_heap_pages: u64,
 	max_memory_size: Option<usize>,
+	heap_base_override: Option<u32>,
+	max_concurrent_instances: Option<usize>,
+	pool_checkout_policy: crate::PoolCheckoutPolicy,
+	host_call_tracer: Option<crate::HostCallTracer>,
+	decommit_only_dirty: bool,
+	decommit_after_call: bool,
+	zero_memory_on_first_use: bool,
+	benchmark_unchecked: bool,
+	environment: std::collections::HashMap<String, String>,
+	track_max_stack_depth: bool,
+	record_host_calls: bool,
+	trap_policy: crate::TrapPolicy,
+	blob_transform: Option<crate::BlobTransform>,
+	ignore_double_free: bool,
+	on_trap: Option<crate::TrapCallback>,
+	output_validator: Option<crate::OutputValidator>,
+	max_sandbox_depth: Option<u32>,
+	max_sandbox_args: Option<u32>,
+	on_runtime_panic: Option<crate::PanicCallback>,
+	retain_prepared_blob: bool,
+	statistics: crate::StatisticsConfig,
+	trace_sink: Option<crate::TraceSink>,
+	sandbox_store_capacity: Option<usize>,
+	allocator_config: crate::AllocatorConfig,
+	max_sandbox_memories: Option<u32>,
+	accept_any_import_module: bool,
+	provide_host_time: bool,
+	guard_pages: bool,
+	canonicalize_nans: bool,
+	interruptible: bool,
 	precompile_runtime: bool,
 	tmpdir: Option<tempfile::TempDir>,
 }
@@ -24,6 +64,36 @@ impl RuntimeBuilder {
 			deterministic_stack: false,
 			extra_heap_pages: 1024,
 			max_memory_size: None,
+			heap_base_override: None,
+			max_concurrent_instances: None,
+			pool_checkout_policy: Default::default(),
+			host_call_tracer: None,
+			decommit_only_dirty: false,
+			decommit_after_call: true,
+			zero_memory_on_first_use: false,
+			benchmark_unchecked: false,
+			environment: Default::default(),
+			track_max_stack_depth: false,
+			record_host_calls: false,
+			trap_policy: Default::default(),
+			blob_transform: None,
+			ignore_double_free: false,
+			on_trap: None,
+			output_validator: None,
+			max_sandbox_depth: None,
+			max_sandbox_args: None,
+			on_runtime_panic: None,
+			retain_prepared_blob: false,
+			statistics: Default::default(),
+			trace_sink: None,
+			sandbox_store_capacity: None,
+			allocator_config: Default::default(),
+			max_sandbox_memories: None,
+			accept_any_import_module: false,
+			provide_host_time: false,
+			guard_pages: false,
+			canonicalize_nans: false,
+			interruptible: false,
 			precompile_runtime: false,
 			tmpdir: None,
 		}
@@ -34,6 +104,11 @@ impl RuntimeBuilder {
 		self
 	}
 
+	fn fast_instance_reuse(&mut self, fast_instance_reuse: bool) -> &mut Self {
+		self.fast_instance_reuse = fast_instance_reuse;
+		self
+	}
+
 	fn deterministic_stack(&mut self, deterministic_stack: bool) -> &mut Self {
 		self.deterministic_stack = deterministic_stack;
 		self
@@ -49,7 +124,176 @@ impl RuntimeBuilder {
 		self
 	}
 
+	fn heap_base_override(&mut self, heap_base_override: Option<u32>) -> &mut Self {
+		self.heap_base_override = heap_base_override;
+		self
+	}
+
+	fn max_concurrent_instances(&mut self, max_concurrent_instances: Option<usize>) -> &mut Self {
+		self.max_concurrent_instances = max_concurrent_instances;
+		self
+	}
+
+	fn pool_checkout_policy(
+		&mut self,
+		pool_checkout_policy: crate::PoolCheckoutPolicy,
+	) -> &mut Self {
+		self.pool_checkout_policy = pool_checkout_policy;
+		self
+	}
+
+	fn host_call_tracer(&mut self, host_call_tracer: crate::HostCallTracer) -> &mut Self {
+		self.host_call_tracer = Some(host_call_tracer);
+		self
+	}
+
+	fn on_trap(&mut self, on_trap: crate::TrapCallback) -> &mut Self {
+		self.on_trap = Some(on_trap);
+		self
+	}
+
+	fn output_validator(&mut self, output_validator: crate::OutputValidator) -> &mut Self {
+		self.output_validator = Some(output_validator);
+		self
+	}
+
+	fn max_sandbox_depth(&mut self, max_sandbox_depth: u32) -> &mut Self {
+		self.max_sandbox_depth = Some(max_sandbox_depth);
+		self
+	}
+
+	fn max_sandbox_args(&mut self, max_sandbox_args: u32) -> &mut Self {
+		self.max_sandbox_args = Some(max_sandbox_args);
+		self
+	}
+
+	fn on_runtime_panic(&mut self, on_runtime_panic: crate::PanicCallback) -> &mut Self {
+		self.on_runtime_panic = Some(on_runtime_panic);
+		self
+	}
+
+	fn retain_prepared_blob(&mut self, retain_prepared_blob: bool) -> &mut Self {
+		self.retain_prepared_blob = retain_prepared_blob;
+		self
+	}
+
+	fn statistics(&mut self, statistics: crate::StatisticsConfig) -> &mut Self {
+		self.statistics = statistics;
+		self
+	}
+
+	fn trace_sink(&mut self, trace_sink: crate::TraceSink) -> &mut Self {
+		self.trace_sink = Some(trace_sink);
+		self
+	}
+
+	fn sandbox_store_capacity(&mut self, capacity: usize) -> &mut Self {
+		self.sandbox_store_capacity = Some(capacity);
+		self
+	}
+
+	fn allocator_config(&mut self, allocator_config: crate::AllocatorConfig) -> &mut Self {
+		self.allocator_config = allocator_config;
+		self
+	}
+
+	fn guard_pages(&mut self, guard_pages: bool) -> &mut Self {
+		self.guard_pages = guard_pages;
+		self
+	}
+
+	fn canonicalize_nans(&mut self, canonicalize_nans: bool) -> &mut Self {
+		self.canonicalize_nans = canonicalize_nans;
+		self
+	}
+
+	fn interruptible(&mut self, interruptible: bool) -> &mut Self {
+		self.interruptible = interruptible;
+		self
+	}
+
+	fn max_sandbox_memories(&mut self, max_sandbox_memories: u32) -> &mut Self {
+		self.max_sandbox_memories = Some(max_sandbox_memories);
+		self
+	}
+
+	fn accept_any_import_module(&mut self, accept_any_import_module: bool) -> &mut Self {
+		self.accept_any_import_module = accept_any_import_module;
+		self
+	}
+
+	fn provide_host_time(&mut self, provide_host_time: bool) -> &mut Self {
+		self.provide_host_time = provide_host_time;
+		self
+	}
+
+	fn extra_heap_pages(&mut self, extra_heap_pages: u64) -> &mut Self {
+		self.extra_heap_pages = extra_heap_pages;
+		self
+	}
+
+	fn decommit_only_dirty(&mut self, decommit_only_dirty: bool) -> &mut Self {
+		self.decommit_only_dirty = decommit_only_dirty;
+		self
+	}
+
+	fn decommit_after_call(&mut self, decommit_after_call: bool) -> &mut Self {
+		self.decommit_after_call = decommit_after_call;
+		self
+	}
+
+	fn zero_memory_on_first_use(&mut self, zero_memory_on_first_use: bool) -> &mut Self {
+		self.zero_memory_on_first_use = zero_memory_on_first_use;
+		self
+	}
+
+	fn benchmark_unchecked(&mut self, benchmark_unchecked: bool) -> &mut Self {
+		self.benchmark_unchecked = benchmark_unchecked;
+		self
+	}
+
+	fn environment(&mut self, key: &str, value: &str) -> &mut Self {
+		self.environment.insert(key.to_string(), value.to_string());
+		self
+	}
+
+	fn track_max_stack_depth(&mut self, track_max_stack_depth: bool) -> &mut Self {
+		self.track_max_stack_depth = track_max_stack_depth;
+		self
+	}
+
+	fn record_host_calls(&mut self, record_host_calls: bool) -> &mut Self {
+		self.record_host_calls = record_host_calls;
+		self
+	}
+
+	fn trap_policy(&mut self, trap_policy: crate::TrapPolicy) -> &mut Self {
+		self.trap_policy = trap_policy;
+		self
+	}
+
+	fn blob_transform(&mut self, blob_transform: crate::BlobTransform) -> &mut Self {
+		self.blob_transform = Some(blob_transform);
+		self
+	}
+
+	fn ignore_double_free(&mut self, ignore_double_free: bool) -> &mut Self {
+		self.ignore_double_free = ignore_double_free;
+		self
+	}
+
 	fn build(&mut self) -> Arc<dyn WasmModule> {
+		Arc::new(self.build_runtime()) as Arc<dyn WasmModule>
+	}
+
+	/// Builds the runtime limited to `size` instances and creates all of them up-front, to be
+	/// reused for the calls rather than creating an instance for each of them.
+	fn build_pool(&mut self, size: usize) -> Vec<Box<dyn WasmInstance>> {
+		let runtime = self.max_concurrent_instances(Some(size)).build();
+		(0..size).map(|_| runtime.new_instance().unwrap()).collect()
+	}
+
+	fn build_runtime(&mut self) -> crate::WasmEdgeRuntime {
 		let blob = {
 			let wasm: Vec<u8>;
 
@@ -75,10 +319,42 @@ impl RuntimeBuilder {
 				},
 				extra_heap_pages: self.extra_heap_pages,
 				max_memory_size: self.max_memory_size,
+				decommit_only_dirty: self.decommit_only_dirty,
+				decommit_after_call: self.decommit_after_call,
+				zero_memory_on_first_use: self.zero_memory_on_first_use,
+				benchmark_unchecked: self.benchmark_unchecked,
+				track_max_stack_depth: self.track_max_stack_depth,
+				statistics: self.statistics,
+				guard_pages: self.guard_pages,
+				canonicalize_nans: self.canonicalize_nans,
+				interruptible: self.interruptible,
+				..Default::default()
 			},
+			heap_base_override: self.heap_base_override,
+			max_concurrent_instances: self.max_concurrent_instances,
+			pool_checkout_policy: self.pool_checkout_policy,
+			host_call_tracer: self.host_call_tracer.clone(),
+			environment: self.environment.clone(),
+			record_host_calls: self.record_host_calls,
+			trap_policy: self.trap_policy,
+			blob_transform: self.blob_transform.clone(),
+			ignore_double_free: self.ignore_double_free,
+			on_trap: self.on_trap.clone(),
+			output_validator: self.output_validator.clone(),
+			max_sandbox_depth: self.max_sandbox_depth,
+			max_sandbox_args: self.max_sandbox_args,
+			on_runtime_panic: self.on_runtime_panic.clone(),
+			retain_prepared_blob: self.retain_prepared_blob,
+			trace_sink: self.trace_sink.clone(),
+			sandbox_store_capacity: self.sandbox_store_capacity,
+			allocator_config: self.allocator_config,
+			max_sandbox_memories: self.max_sandbox_memories,
+			accept_any_import_module: self.accept_any_import_module,
+			provide_host_time: self.provide_host_time,
+			..Default::default()
 		};
 
-		let rt = if self.precompile_runtime {
+		if self.precompile_runtime {
 			let dir = tempfile::tempdir().unwrap();
 			let path = dir.path().join("runtime.wasm");
 
@@ -90,9 +366,45 @@ impl RuntimeBuilder {
 		} else {
 			crate::create_runtime::<HostFunctions>(blob, config)
 		}
-		.expect("cannot create runtime");
+		.expect("cannot create runtime")
+	}
+}
 
-		Arc::new(rt) as Arc<dyn WasmModule>
+/// Calls `method` of both runtimes with each of the `inputs` in turn, and asserts that both
+/// produce the same output or fail with the same kind of error.
+///
+/// Each runtime serves all the calls with a single instance, so that whatever an instance keeps
+/// between the calls is covered as well.
+fn assert_runtimes_equivalent(
+	a: &dyn WasmModule,
+	b: &dyn WasmModule,
+	method: &str,
+	inputs: &[&[u8]],
+) {
+	let mut instance_a = a.new_instance().expect("failed to instantiate the first runtime");
+	let mut instance_b = b.new_instance().expect("failed to instantiate the second runtime");
+
+	for (index, input) in inputs.iter().enumerate() {
+		match (instance_a.call_export(method, input), instance_b.call_export(method, input)) {
+			(Ok(output_a), Ok(output_b)) => assert_eq!(
+				output_a, output_b,
+				"`{}` returned different outputs for the input #{}",
+				method, index
+			),
+			(Err(error_a), Err(error_b)) => assert_eq!(
+				std::mem::discriminant(&error_a),
+				std::mem::discriminant(&error_b),
+				"`{}` failed differently for the input #{}: {:?} vs {:?}",
+				method,
+				index,
+				error_a,
+				error_b
+			),
+			(result_a, result_b) => panic!(
+				"`{}` only failed in one runtime for the input #{}: {:?} vs {:?}",
+				method, index, result_a, result_b
+			),
+		}
 	}
 }
 
@@ -135,6 +447,178 @@ fn test_max_memory_pages_exported_memory_with_precompilation() {
 	test_max_memory_pages(false, true);
 }
 
+#[test]
+fn test_runtime_swapper() {
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	// The `main` of each version returns its version number.
+	let blob = |version: u8, imports: &str| {
+		let wat = format!(
+			r#"
+			(module
+				(import "env" "memory" (memory 1))
+				{}
+				(data (i32.const 0) "\{:02x}")
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(func (export "main") (param i32 i32) (result i64)
+					(i64.const 1)
+				)
+			)
+			"#,
+			imports, version
+		);
+		RuntimeBlob::new(&wat::parse_str(wat).unwrap()).unwrap()
+	};
+	let config = || crate::Config {
+		semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+		..Default::default()
+	};
+	let call = |runtime: &crate::WasmEdgeRuntime| {
+		runtime.new_instance().unwrap().call_export("main", &[]).unwrap()
+	};
+
+	let runtime = crate::create_runtime::<HostFunctions>(blob(1, ""), config()).unwrap();
+	let swapper = Arc::new(crate::RuntimeSwapper::new(runtime));
+	let stop = Arc::new(AtomicBool::new(false));
+
+	// Keep calling into whichever runtime is current while the versions are swapped.
+	let threads = (0..4)
+		.map(|_| {
+			let swapper = swapper.clone();
+			let stop = stop.clone();
+			std::thread::spawn(move || {
+				while !stop.load(Ordering::Relaxed) {
+					let output = call(&swapper.current());
+					assert!(output == [1] || output == [2], "unexpected output: {:?}", output);
+				}
+			})
+		})
+		.collect::<Vec<_>>();
+
+	for round in 0..10 {
+		let (old_version, new_version) = if round % 2 == 0 { (1, 2) } else { (2, 1) };
+		let old = swapper
+			.prepare_and_swap::<HostFunctions>(blob(new_version, ""), config())
+			.unwrap();
+		assert_eq!(call(&old), [old_version]);
+		assert_eq!(call(&swapper.current()), [new_version]);
+	}
+
+	stop.store(true, Ordering::Relaxed);
+	for thread in threads {
+		thread.join().expect("the calls don't fail");
+	}
+
+	// A runtime which can't be created is rejected and the old one keeps serving.
+	let unresolvable = blob(2, r#"(import "env" "ext_does_not_exist" (func))"#);
+	assert!(swapper.prepare_and_swap::<HostFunctions>(unresolvable, config()).is_err());
+	assert_eq!(call(&swapper.current()), [1]);
+}
+
+#[test]
+fn test_prepared_blob_bytes() {
+	const WAT: &str = r#"
+		(module
+			(import "env" "memory" (memory 1))
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build_runtime();
+	assert!(runtime.prepared_blob_bytes().is_none());
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.retain_prepared_blob(true)
+		.build_runtime();
+	let prepared_blob = RuntimeBlob::new(runtime.prepared_blob_bytes().unwrap()).unwrap();
+
+	// The retained code is the one after the preparation, i.e. the memory is exported and has
+	// got the extra heap pages.
+	assert!(!crate::uses_imported_memory(&prepared_blob));
+	let module = prepared_blob.into_inner();
+	let memory = &module.memory_section().unwrap().entries()[0];
+	assert_eq!(memory.limits().initial(), 1 + 1024);
+}
+
+#[test]
+fn test_uses_imported_memory() {
+	let blob = |memory: &str| {
+		let wat = format!(
+			r#"
+			(module
+				{}
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(func (export "main") (param i32 i32) (result i64)
+					(i64.const 0)
+				)
+			)
+			"#,
+			memory
+		);
+		RuntimeBlob::new(&wat::parse_str(wat).unwrap()).unwrap()
+	};
+
+	let imported = blob(r#"(import "env" "memory" (memory 1))"#);
+	let exported = blob(r#"(memory (export "memory") 1)"#);
+	assert!(crate::uses_imported_memory(&imported));
+	assert!(!crate::uses_imported_memory(&exported));
+
+	// The preparation converts the imported memory into an exported one.
+	let semantics = crate::Semantics::default();
+	let prepared =
+		crate::runtime::prepare_blob_for_compilation(imported, &semantics, None).unwrap();
+	assert!(!crate::uses_imported_memory(&prepared));
+}
+
+#[test]
+fn test_effective_max_memory_bytes() {
+	use crate::util::WASM_PAGE_SIZE;
+
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 2)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let config = |max_memory_size| crate::Config {
+		allow_missing_func_imports: true,
+		semantics: crate::Semantics { max_memory_size, ..Default::default() },
+		..Default::default()
+	};
+	assert_eq!(config(None).effective_max_memory_bytes(), None);
+	assert_eq!(
+		config(Some(2 * WASM_PAGE_SIZE)).effective_max_memory_bytes(),
+		Some(2 * WASM_PAGE_SIZE)
+	);
+	assert_eq!(
+		config(Some(2 * WASM_PAGE_SIZE + 100)).effective_max_memory_bytes(),
+		Some(2 * WASM_PAGE_SIZE)
+	);
+	assert_eq!(
+		config(Some(2 * WASM_PAGE_SIZE - 1)).effective_max_memory_bytes(),
+		Some(WASM_PAGE_SIZE)
+	);
+
+	// The effective limit is the one applied.
+	let instantiate = |max_memory_size| {
+		let blob = RuntimeBlob::uncompress_if_needed(&wat::parse_str(WAT).unwrap()).unwrap();
+		crate::create_runtime::<HostFunctions>(blob, config(max_memory_size))
+			.unwrap()
+			.new_instance()
+			.map(drop)
+	};
+	instantiate(Some(2 * WASM_PAGE_SIZE + 100)).unwrap();
+	assert!(instantiate(Some(2 * WASM_PAGE_SIZE - 1)).is_err());
+}
+
 fn test_max_memory_pages(import_memory: bool, precompile_runtime: bool) {
 	fn try_instantiate(
 		max_memory_size: Option<usize>,
@@ -165,7 +649,7 @@ fn test_max_memory_pages(import_memory: bool, precompile_runtime: bool) {
 		}
 	}
 
-	const WASM_PAGE_SIZE: usize = 65536;
+	use crate::util::WASM_PAGE_SIZE;
 
 	// check the old behavior if preserved. That is, if no limit is set we allow 4 GiB of memory.
 	try_instantiate(
@@ -308,32 +792,4616 @@ fn test_max_memory_pages(import_memory: bool, precompile_runtime: bool) {
 	.unwrap();
 }
 
-// This test takes quite a while to execute in a debug build (over 6 minutes on a TR 3970x)
-// so it's ignored by default unless it was compiled with `--release`.
-#[cfg_attr(build_type = "debug", ignore)]
 #[test]
-fn test_instances_without_reuse_are_not_leaked() {
-	let runtime = crate::create_runtime::<HostFunctions>(
-		RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap(),
-		crate::Config {
-			allow_missing_func_imports: true,
-			semantics: crate::Semantics {
-				fast_instance_reuse: false,
-				deterministic_stack_limit: None,
-				extra_heap_pages: 2048,
-				max_memory_size: None,
+fn test_heap_base_override() {
+	// Stores the pointer to the input data at address 0 and returns it as the output.
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(i32.store (i32.const 0) (local.get 0))
+
+				;; ptr = 0, len = 4
+				(i64.const 17179869184)
+			)
+		)
+		"#;
+
+	fn input_ptr(heap_base_override: Option<u32>) -> Result<u32, Error> {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.heap_base_override(heap_base_override)
+			.build();
+		let mut instance = runtime.new_instance()?;
+		let output = instance.call_export("main", &[1, 2, 3])?;
+		Ok(u32::from_le_bytes(output.try_into().unwrap()))
+	}
+
+	// The first allocation is placed right after its 8 byte header.
+	assert_eq!(input_ptr(None).unwrap(), 1024 + 8);
+	assert_eq!(input_ptr(Some(4096)).unwrap(), 4096 + 8);
+
+	// The override cannot be below `__heap_base`.
+	assert!(input_ptr(Some(512)).is_err());
+}
+
+#[test]
+fn test_implausible_heap_base() {
+	fn call(heap_base: i32, fast_instance_reuse: bool) -> Result<Vec<u8>, Error> {
+		let wat = format!(
+			r#"
+			(module
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const {}))
+				(func (export "main") (param i32 i32) (result i64)
+					(i64.const 0)
+				)
+			)
+			"#,
+			heap_base,
+		);
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(wat)
+			.fast_instance_reuse(fast_instance_reuse)
+			.extra_heap_pages(0)
+			.build();
+		runtime.new_instance()?.call_export("main", &[])
+	}
+
+	for fast_instance_reuse in [false, true] {
+		assert!(call(1024, fast_instance_reuse).unwrap().is_empty());
+		// The heap may start right at the end of the memory, it's just too small for the input.
+		let error = call(64 * 1024, fast_instance_reuse).unwrap_err().to_string();
+		assert!(!error.contains("'__heap_base' is implausible"), "{}", error);
+
+		for heap_base in [i32::MAX, 64 * 1024 + 1, -1] {
+			let error = call(heap_base, fast_instance_reuse).unwrap_err().to_string();
+			assert!(error.contains("'__heap_base' is implausible"), "{}", error);
+		}
+	}
+}
+
+#[test]
+fn test_max_concurrent_instances() {
+	const MAX_INSTANCES: usize = 4;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.max_concurrent_instances(Some(MAX_INSTANCES))
+		.build();
+
+	let mut instances = (0..MAX_INSTANCES)
+		.map(|_| runtime.new_instance().expect("the limit is not reached yet"))
+		.collect::<Vec<_>>();
+
+	match runtime.new_instance() {
+		Err(Error::RuntimeConstruction(error)) =>
+			assert_eq!(error.to_string(), "instance limit reached"),
+		Err(error) => panic!("unexpected error: {:?}", error),
+		Ok(_) => panic!("the instance limit should have been reached"),
+	}
+
+	// Dropping an instance frees up a slot.
+	instances.pop();
+	runtime.new_instance().expect("a slot was freed up");
+}
+
+#[test]
+fn test_pool_checkout_policy() {
+	use std::time::Duration;
+
+	const MAX_INSTANCES: usize = 2;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.max_concurrent_instances(Some(MAX_INSTANCES))
+		.pool_checkout_policy(crate::PoolCheckoutPolicy::Block(Duration::from_secs(60)))
+		.build();
+
+	let mut instances = (0..MAX_INSTANCES)
+		.map(|_| runtime.new_instance().expect("the limit is not reached yet"))
+		.collect::<Vec<_>>();
+
+	let (checked_out_tx, checked_out_rx) = std::sync::mpsc::channel();
+	let blocked = std::thread::spawn({
+		let runtime = runtime.clone();
+		move || {
+			let instance = runtime.new_instance();
+			checked_out_tx.send(()).unwrap();
+			instance
+		}
+	});
+
+	// The checkout is blocked until an instance is returned.
+	assert!(checked_out_rx.recv_timeout(Duration::from_millis(200)).is_err());
+	instances.pop();
+	checked_out_rx.recv_timeout(Duration::from_secs(60)).unwrap();
+	let mut instance = blocked.join().unwrap().expect("a slot was freed up");
+	instance.call_export("test_empty_return", &[]).unwrap();
+
+	// A blocked checkout gives up after the timeout.
+	let runtime = RuntimeBuilder::new_on_demand()
+		.max_concurrent_instances(Some(1))
+		.pool_checkout_policy(crate::PoolCheckoutPolicy::Block(Duration::from_millis(10)))
+		.build();
+	let _instance = runtime.new_instance().unwrap();
+	match runtime.new_instance() {
+		Err(Error::RuntimeConstruction(error)) =>
+			assert_eq!(error.to_string(), "instance limit reached"),
+		Err(error) => panic!("unexpected error: {:?}", error),
+		Ok(_) => panic!("the instance limit should have been reached"),
+	}
+
+	// The limit is exceeded instead of failing.
+	let runtime = RuntimeBuilder::new_on_demand()
+		.max_concurrent_instances(Some(1))
+		.pool_checkout_policy(crate::PoolCheckoutPolicy::CreateUnbounded)
+		.build();
+	let _instances = (0..3).map(|_| runtime.new_instance().unwrap()).collect::<Vec<_>>();
+}
+
+#[test]
+fn test_prepare_runtime_artifact_with_report() {
+	let blob = RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+	let expected_function_count = blob
+		.clone()
+		.into_inner()
+		.function_section()
+		.map_or(0, |section| section.entries().len());
+
+	let semantics = crate::Semantics { extra_heap_pages: 1024, ..Default::default() };
+
+	let dir = tempfile::tempdir().unwrap();
+	let path = dir.path().join("runtime.wasm");
+	let report = crate::prepare_runtime_artifact_with_report(blob, &semantics, &path).unwrap();
+
+	assert_eq!(report.function_count, expected_function_count);
+	assert!(report.input_size > 0);
+	assert_eq!(report.output_size, std::fs::metadata(&path).unwrap().len());
+}
+
+#[test]
+fn test_shutdown() {
+	fn create_runtime() -> crate::WasmEdgeRuntime {
+		crate::create_runtime::<HostFunctions>(
+			RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap(),
+			crate::Config {
+				allow_missing_func_imports: true,
+				semantics: crate::Semantics {
+					fast_instance_reuse: true,
+					extra_heap_pages: 1024,
+					..Default::default()
+				},
+				..Default::default()
 			},
-		},
-	)
-	.unwrap();
+		)
+		.unwrap()
+	}
 
-	// As long as the `wasmtime`'s `Store` lives the instances spawned through it
-	// will live indefinitely. Currently it has a maximum limit of 10k instances,
-	// so let's spawn 10k + 1 of them to make sure our code doesn't keep the `Store`
-	// alive longer than it is necessary. (And since we disabled instance reuse
-	// a new instance will be spawned on each call.)
+	let runtime = create_runtime();
 	let mut instance = runtime.new_instance().unwrap();
-	for _ in 0..10001 {
-		instance.call_export("test_empty_return", &[0]).unwrap();
+	instance.call_export("test_empty_return", &[0]).unwrap();
+
+	drop(instance);
+	runtime.shutdown().unwrap();
+
+	// A runtime created after the shutdown works just as well.
+	let runtime = create_runtime();
+	let mut instance = runtime.new_instance().unwrap();
+	instance.call_export("test_empty_return", &[0]).unwrap();
+
+	// The runtime cannot be shut down while an instance is alive.
+	assert!(runtime.shutdown().is_err());
+}
+
+#[test]
+fn test_data_segment_spans() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(data (i32.const 16) "abc")
+			(data (i32.const 100) "hello")
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.fast_instance_reuse(true)
+		.build_runtime();
+	assert_eq!(runtime.data_segment_spans(), Some(vec![(16, 3), (100, 5)]));
+
+	// The snapshot is only taken when the fast instance reuse is enabled.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build_runtime();
+	assert!(runtime.data_segments().is_none());
+	assert_eq!(runtime.data_segment_spans(), None);
+}
+
+#[test]
+fn test_checked_data_len() {
+	assert_eq!(crate::runtime::checked_data_len(0).unwrap(), 0);
+	assert_eq!(crate::runtime::checked_data_len(u32::MAX as usize).unwrap(), u32::MAX);
+
+	match crate::runtime::checked_data_len(u32::MAX as usize + 1) {
+		Err(Error::Other(message)) => assert!(message.starts_with("input data too large")),
+		result => panic!("unexpected result: {:?}", result),
 	}
 }
+
+#[test]
+fn test_create_runtime_from_artifact_checked() {
+	fn wat(entry_point: &str) -> String {
+		format!(
+			r#"
+			(module
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(func (export "{}")
+					(param i32 i32) (result i64)
+					(i64.const 0)
+				)
+			)
+			"#,
+			entry_point
+		)
+	}
+
+	fn prepare_artifact(dir: &tempfile::TempDir, wat: &str) -> std::path::PathBuf {
+		let blob = RuntimeBlob::uncompress_if_needed(&wat::parse_str(wat).unwrap()).unwrap();
+		let semantics = crate::Semantics { extra_heap_pages: 1024, ..Default::default() };
+
+		let path = dir.path().join("runtime.wasm");
+		crate::prepare_runtime_artifact(blob, &semantics, &path).unwrap();
+		path
+	}
+
+	fn create_runtime(path: &std::path::Path) -> Result<crate::WasmEdgeRuntime, String> {
+		let config = crate::Config {
+			allow_missing_func_imports: true,
+			semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+			..Default::default()
+		};
+
+		unsafe { crate::create_runtime_from_artifact_checked::<HostFunctions>(path, config) }
+			.map_err(|e| e.to_string())
+	}
+
+	let dir = tempfile::tempdir().unwrap();
+	let path = prepare_artifact(&dir, &wat("Core_version"));
+	create_runtime(&path).unwrap();
+
+	// A runtime without `Core_version` is rejected.
+	let dir = tempfile::tempdir().unwrap();
+	let path = prepare_artifact(&dir, &wat("main"));
+	let error = create_runtime(&path).err().unwrap();
+	assert!(error.contains("cannot call `Core_version`"), "{}", error);
+
+	// So is an artifact with a corrupted body.
+	let dir = tempfile::tempdir().unwrap();
+	let path = prepare_artifact(&dir, &wat("Core_version"));
+	let artifact = std::fs::read(&path).unwrap();
+	std::fs::write(&path, &artifact[..artifact.len() / 2]).unwrap();
+	let error = create_runtime(&path).err().unwrap();
+	assert!(error.starts_with("artifact check failed"), "{}", error);
+}
+
+#[test]
+fn test_host_call_tracer() {
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(import "env" "ext_allocator_free_version_1" (func $free (param i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(call $free (call $malloc (i32.const 16)))
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.host_call_tracer({
+			let calls = calls.clone();
+			Arc::new(move |name: &str, args: &[Value]| {
+				calls.lock().unwrap().push((name.to_owned(), args.to_vec()))
+			})
+		})
+		.build();
+
+	let mut instance = runtime.new_instance().unwrap();
+	instance.call_export("main", &[]).unwrap();
+
+	let calls = calls.lock().unwrap();
+	assert_eq!(calls.len(), 2);
+	assert_eq!(calls[0], ("ext_allocator_malloc_version_1".to_owned(), vec![Value::I32(16)]));
+	assert_eq!(calls[1].0, "ext_allocator_free_version_1");
+}
+
+#[test]
+fn test_on_trap() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.const 0)
+			)
+			(func (export "trap") (param i32 i32) (result i64)
+				unreachable
+			)
+		)
+		"#;
+
+	for fast_instance_reuse in [false, true] {
+		let traps = Arc::new(std::sync::Mutex::new(Vec::new()));
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.on_trap({
+				let traps = traps.clone();
+				Arc::new(move |error: &Error| traps.lock().unwrap().push(error.to_string()))
+			})
+			.build();
+		let mut instance = runtime.new_instance().unwrap();
+
+		instance.call_export("main", &[]).unwrap();
+		assert!(traps.lock().unwrap().is_empty());
+
+		let error = instance.call_export("trap", &[]).unwrap_err();
+		assert!(matches!(error, Error::AbortedDueToTrap(_)));
+		assert_eq!(*traps.lock().unwrap(), vec![error.to_string()]);
+	}
+}
+
+#[test]
+fn test_call_count() {
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.fast_instance_reuse(fast_instance_reuse)
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+		assert_eq!((instance.call_count(), instance.failed_call_count()), (0, 0));
+
+		for _ in 0..3 {
+			instance.call_export("test_empty_return", &[0]).unwrap();
+		}
+		instance.call_export("test_abort_on_panic", &[]).unwrap_err();
+		instance.call_export("missing", &[]).unwrap_err();
+		instance.call_export("test_empty_return", &[0]).unwrap();
+
+		assert_eq!((instance.call_count(), instance.failed_call_count()), (6, 2));
+
+		// Every instance counts its own calls.
+		let other_instance = runtime.new_wasmedge_instance().unwrap();
+		assert_eq!(other_instance.call_count(), 0);
+	}
+}
+
+#[test]
+fn test_statistics() {
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.fast_instance_reuse(fast_instance_reuse)
+			.statistics(crate::StatisticsConfig { instr_count: true, ..Default::default() })
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+		let (output, instructions) =
+			instance.call_with_metering("test_empty_return".into(), &[0]).unwrap();
+		assert!(output.is_empty());
+		assert!(instructions > 0);
+
+		// The same call executes the same instructions.
+		let (_, instructions_again) =
+			instance.call_with_metering("test_empty_return".into(), &[0]).unwrap();
+		assert_eq!(instructions_again, instructions);
+
+		let error = instance.instructions_per_second().unwrap_err();
+		assert!(error.to_string().contains("statistics not enabled: `time`"), "{}", error);
+		let error = instance.total_cost().unwrap_err();
+		assert!(error.to_string().contains("statistics not enabled: `cost`"), "{}", error);
+	}
+
+	// Without the instruction count, the metered call isn't made at all.
+	let runtime = RuntimeBuilder::new_on_demand().build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+	let error = instance.call_with_metering("test_empty_return".into(), &[0]).unwrap_err();
+	assert!(error.to_string().contains("statistics not enabled: `instr_count`"), "{}", error);
+	assert_eq!(instance.call_count(), 0);
+}
+
+#[test]
+fn test_trace_sink() {
+	use crate::TraceEvent;
+
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(import "env" "ext_allocator_free_version_1" (func $free (param i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(call $free (call $malloc (i32.const 16)))
+				(call $free (call $malloc (i32.const 32)))
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.trace_sink({
+			let events = events.clone();
+			Arc::new(move |event: TraceEvent| events.lock().unwrap().push(event))
+		})
+		.build();
+
+	let mut instance = runtime.new_instance().unwrap();
+	instance.call_export("main", &[]).unwrap();
+
+	let events = events.lock().unwrap();
+	let mut stack = Vec::new();
+	let mut entered = Vec::new();
+	let mut last_time = None;
+	for event in events.iter() {
+		let time = match event {
+			TraceEvent::CallEnter { method, time } => {
+				stack.push(method.to_string());
+				entered.push(method.to_string());
+				time
+			},
+			TraceEvent::HostEnter { name, time } => {
+				stack.push(name.to_string());
+				entered.push(name.to_string());
+				time
+			},
+			TraceEvent::CallExit { method, time } => {
+				assert_eq!(stack.pop().as_deref(), Some(method.as_str()));
+				time
+			},
+			TraceEvent::HostExit { name, time } => {
+				assert_eq!(stack.pop().as_deref(), Some(*name));
+				time
+			},
+		};
+		assert!(last_time.map_or(true, |last_time| last_time <= *time));
+		last_time = Some(*time);
+	}
+	assert!(stack.is_empty());
+	assert_eq!(
+		entered,
+		[
+			"main",
+			"ext_allocator_malloc_version_1",
+			"ext_allocator_free_version_1",
+			"ext_allocator_malloc_version_1",
+			"ext_allocator_free_version_1",
+		],
+	);
+
+	// The host functions are nested in the call.
+	assert!(matches!(events.first(), Some(TraceEvent::CallEnter { .. })));
+	assert!(matches!(events.last(), Some(TraceEvent::CallExit { .. })));
+}
+
+#[test]
+fn test_zero_page_memory() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 0)
+			(global (export "__heap_base") i32 (i32.const 0))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.extra_heap_pages(0)
+			.build();
+		let mut instance = runtime.new_instance().unwrap();
+
+		let error = instance.call_export("main", &[1, 2, 3]).unwrap_err();
+		assert!(error.to_string().contains("the linear memory has no pages"), "{}", error);
+
+		// The extra heap pages give the memory room for the input.
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.build();
+		let mut instance = runtime.new_instance().unwrap();
+		assert_eq!(instance.call_export("main", &[1, 2, 3]).unwrap(), Vec::<u8>::new());
+	}
+}
+
+#[test]
+fn test_on_runtime_panic() {
+	let panics = Arc::new(std::sync::Mutex::new(Vec::new()));
+	let runtime = RuntimeBuilder::new_on_demand()
+		.on_runtime_panic({
+			let panics = panics.clone();
+			Arc::new(move |message: &str| panics.lock().unwrap().push(message.to_owned()))
+		})
+		.build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	instance.call_export("test_empty_return", &[0]).unwrap();
+	assert!(panics.lock().unwrap().is_empty());
+
+	match instance.call_export("test_abort_on_panic", &[]).unwrap_err() {
+		Error::AbortedDueToPanic(error) => assert_eq!(error.message, "test_abort_on_panic called"),
+		error => panic!("unexpected error: {:?}", error),
+	}
+	assert_eq!(*panics.lock().unwrap(), vec![String::from("test_abort_on_panic called")]);
+}
+
+#[test]
+fn test_output_validator() {
+	// `main` returns the first `len` bytes of the memory, with `len` being the first byte of the
+	// input.
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param $ptr i32) (param $len i32) (result i64)
+				(i64.extend_i32_u (i32.load8_u (local.get $ptr)))
+				(i64.const 32)
+				(i64.shl)
+			)
+		)
+		"#;
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.output_validator(Arc::new(|output: &[u8]| match output.len() {
+				len if len < 4 => Err(format!("expected at least 4 bytes, got {}", len)),
+				_ => Ok(()),
+			}))
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+		assert_eq!(instance.call_export("main", &[4]).unwrap().len(), 4);
+		assert_eq!(instance.call_ref(InvokeMethod::Export("main"), &[4]).unwrap().len(), 4);
+
+		let error = instance.call_export("main", &[2]).unwrap_err();
+		assert!(error.to_string().contains("expected at least 4 bytes, got 2"), "{}", error);
+		assert!(instance.call_ref(InvokeMethod::Export("main"), &[2]).is_err());
+
+		// The instance is still usable afterwards.
+		assert_eq!(instance.call_export("main", &[8]).unwrap().len(), 8);
+	}
+}
+
+#[test]
+fn test_host_time() {
+	use sp_wasm_interface::{Function, FunctionContext, HostFunctionRegistry, Signature};
+	use std::time::Duration;
+
+	const SLEEP: Duration = Duration::from_millis(10);
+
+	// A host function which takes a while, e.g. hashing a lot of data.
+	struct SlowHostFunction;
+
+	impl Function for SlowHostFunction {
+		fn name(&self) -> &str {
+			"ext_test_slow_version_1"
+		}
+
+		fn signature(&self) -> Signature {
+			Signature::new_with_args(&[][..])
+		}
+
+		fn execute(
+			&self,
+			_: &mut dyn FunctionContext,
+			_: &mut dyn Iterator<Item = Value>,
+		) -> sp_wasm_interface::Result<Option<Value>> {
+			std::thread::sleep(SLEEP);
+			Ok(None)
+		}
+	}
+
+	struct SlowHostFunctions;
+
+	impl sp_wasm_interface::HostFunctions for SlowHostFunctions {
+		fn host_functions() -> Vec<&'static dyn Function> {
+			vec![&SlowHostFunction]
+		}
+
+		fn register_static<T>(_: &mut T) -> Result<(), T::Error>
+		where
+			T: HostFunctionRegistry,
+		{
+			Ok(())
+		}
+	}
+
+	// Calls the slow host function 3 times.
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_test_slow_version_1" (func $slow))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(call $slow)
+				(call $slow)
+				(call $slow)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let new_instance = |measure_host_time: bool, max_host_time: Option<Duration>| {
+		let blob = RuntimeBlob::uncompress_if_needed(&wat::parse_str(WAT).unwrap()).unwrap();
+		let config = crate::Config { measure_host_time, max_host_time, ..Default::default() };
+		crate::create_runtime::<SlowHostFunctions>(blob, config)
+			.unwrap()
+			.new_wasmedge_instance()
+			.unwrap()
+	};
+
+	let mut instance = new_instance(false, None);
+	let (result, _, host_time) = instance.call_with_allocation_stats("main".into(), &[]);
+	result.unwrap();
+	assert_eq!(host_time, None);
+	assert_eq!(instance.host_time(), None);
+
+	let mut instance = new_instance(true, None);
+	let (result, allocation_stats, host_time) =
+		instance.call_with_allocation_stats("main".into(), &[]);
+	result.unwrap();
+	assert!(allocation_stats.is_some());
+	assert!(host_time.unwrap() >= 3 * SLEEP);
+	assert_eq!(instance.host_time(), host_time);
+
+	// The call is aborted by the call of the host function exceeding the limit, at the latest
+	// the second one.
+	let max_host_time = SLEEP + SLEEP / 2;
+	let mut instance = new_instance(false, Some(max_host_time));
+	let error = instance.call_export("main", &[]).unwrap_err().to_string();
+	assert!(error.contains("the host functions took"), "{}", error);
+	assert!(instance.host_time().unwrap() > max_host_time);
+}
+
+#[test]
+fn test_new_instance_with_imports() {
+	use wasmedge_sdk::{
+		error::HostFuncError, host_function, Caller, FuncType, ImportObjectBuilder, ValType,
+		WasmValue,
+	};
+
+	// Stores `double(21)` at address 0 and returns it as the output.
+	const WAT: &str = r#"
+		(module
+			(import "extra" "double" (func $double (param i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i32.store (i32.const 0) (call $double (i32.const 21)))
+
+				;; ptr = 0, len = 4
+				(i64.const 17179869184)
+			)
+		)
+		"#;
+
+	#[host_function]
+	fn double(
+		_: Caller,
+		inputs: Vec<WasmValue>,
+	) -> std::result::Result<Vec<WasmValue>, HostFuncError> {
+		Ok(vec![WasmValue::from_i32(inputs[0].to_i32() * 2)])
+	}
+
+	let import_object = |name: &str| {
+		ImportObjectBuilder::new()
+			.with_func_by_type::<!>(
+				"double",
+				FuncType::new(Some(vec![ValType::I32]), Some(vec![ValType::I32])),
+				double,
+				None,
+			)
+			.unwrap()
+			.build(name)
+			.unwrap()
+	};
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.build_runtime();
+
+		let mut instance = runtime.new_instance_with_imports(import_object("extra")).unwrap();
+		assert_eq!(instance.call_export("main", &[]).unwrap(), 42u32.to_le_bytes());
+		assert_eq!(instance.call_export("main", &[]).unwrap(), 42u32.to_le_bytes());
+
+		// The imports from `extra` aren't provided otherwise.
+		assert!(runtime.new_wasmedge_instance().is_err());
+
+		// The import object can't take the place of the host functions.
+		let error = runtime.new_instance_with_imports(import_object("env")).err().unwrap();
+		assert!(error.to_string().contains("collides with the host functions"), "{}", error);
+	}
+}
+
+#[test]
+fn test_artifact_loading_is_retried() {
+	use crate::runtime::ArtifactLoadError;
+	use std::time::Duration;
+
+	let blob = RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+	let config = || crate::Config {
+		allow_missing_func_imports: true,
+		semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+		..Default::default()
+	};
+
+	let dir = tempfile::tempdir().unwrap();
+	let staging_path = dir.path().join("runtime.wasm.tmp");
+	let path = dir.path().join("runtime.wasm");
+	crate::prepare_runtime_artifact(blob, &config().semantics, &staging_path).unwrap();
+
+	let wasmedge_config = crate::runtime::common_config(&config().semantics).unwrap();
+	let load = |path: &std::path::Path| {
+		crate::runtime::load_artifact_once(&wasmedge_config, &config().semantics, path)
+	};
+	let module_error = || crate::CreateRuntimeError::Module(String::from("truncated"));
+
+	// A complete artifact loads, a missing or malformed one isn't worth retrying.
+	assert!(load(&staging_path).is_ok());
+	assert!(matches!(load(&path), Err(ArtifactLoadError::Fatal(_))));
+	std::fs::write(&path, b"definitely not an artifact").unwrap();
+	let header = crate::artifact::read_header(&staging_path).unwrap();
+	crate::artifact::append_header(&path, &header).unwrap();
+	assert!(matches!(load(&path), Err(ArtifactLoadError::Fatal(_))));
+
+	// A load which failed while the artifact was being written is retried until it succeeds.
+	let mut attempts = 0;
+	let loaded = crate::runtime::retry_artifact_load(&path, Duration::ZERO, || {
+		attempts += 1;
+		if attempts < 3 {
+			Err(ArtifactLoadError::Transient(module_error()))
+		} else {
+			Ok(())
+		}
+	});
+	assert!(loaded.is_ok());
+	assert_eq!(attempts, 3);
+
+	// But only a few times.
+	let mut attempts = 0;
+	let loaded = crate::runtime::retry_artifact_load(&path, Duration::ZERO, || {
+		attempts += 1;
+		Err::<(), _>(ArtifactLoadError::Transient(module_error()))
+	});
+	assert!(loaded.is_err());
+	assert_eq!(attempts, crate::runtime::ARTIFACT_LOAD_ATTEMPTS);
+
+	// Any other failure isn't retried at all.
+	let mut attempts = 0;
+	let loaded = crate::runtime::retry_artifact_load(&path, Duration::ZERO, || {
+		attempts += 1;
+		Err::<(), _>(ArtifactLoadError::Fatal(module_error()))
+	});
+	assert!(loaded.is_err());
+	assert_eq!(attempts, 1);
+}
+
+#[test]
+fn test_artifact_records_wasmedge_version() {
+	assert!(!crate::wasmedge_version().is_empty());
+
+	let blob = RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+	let config = || crate::Config {
+		allow_missing_func_imports: true,
+		semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+		..Default::default()
+	};
+
+	let dir = tempfile::tempdir().unwrap();
+	let path = dir.path().join("runtime.wasm");
+	crate::prepare_runtime_artifact(blob, &config().semantics, &path).unwrap();
+
+	let header = crate::artifact::read_header(&path).unwrap();
+	assert_eq!(header.wasmedge_version, crate::wasmedge_version());
+
+	// Pretend the artifact was compiled by another version of WasmEdge.
+	crate::artifact::append_header(
+		&path,
+		&crate::artifact::ArtifactHeader { wasmedge_version: "0.0.0-old".into(), ..header },
+	)
+	.unwrap();
+
+	let error = unsafe { crate::create_runtime_from_artifact::<HostFunctions>(&path, config()) }
+		.err()
+		.unwrap();
+	assert!(error.to_string().contains("compiled by WasmEdge 0.0.0-old"), "{}", error);
+}
+
+#[test]
+fn test_artifact_shareable() {
+	let blob = RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+	let config = |artifact_format| crate::Config {
+		allow_missing_func_imports: true,
+		semantics: crate::Semantics {
+			extra_heap_pages: 1024,
+			artifact_format,
+			..Default::default()
+		},
+		..Default::default()
+	};
+
+	let dir = tempfile::tempdir().unwrap();
+	for (artifact_format, shareable) in
+		[(crate::ArtifactFormat::Native, false), (crate::ArtifactFormat::Universal, true)]
+	{
+		let path = dir.path().join(format!("{:?}.wasm", artifact_format));
+		crate::prepare_runtime_artifact(blob.clone(), &config(artifact_format).semantics, &path)
+			.unwrap();
+		assert_eq!(crate::is_artifact_shareable(&path).unwrap(), shareable);
+
+		let runtime = unsafe {
+			crate::create_runtime_from_artifact::<HostFunctions>(&path, config(artifact_format))
+		}
+		.unwrap();
+		runtime.new_instance().unwrap().call_export("test_empty_return", &[0]).unwrap();
+	}
+
+	// The format is a part of the artifact key, so an artifact isn't loaded in the other format.
+	assert_ne!(
+		config(crate::ArtifactFormat::Native).semantics.artifact_key(),
+		config(crate::ArtifactFormat::Universal).semantics.artifact_key(),
+	);
+}
+
+#[test]
+fn test_create_runtime_detailed_error() {
+	let create_runtime = |wat: &str| {
+		let blob = RuntimeBlob::new(&wat::parse_str(wat).unwrap()).unwrap();
+		crate::create_runtime_detailed::<HostFunctions>(
+			blob,
+			crate::Config {
+				allow_missing_func_imports: true,
+				semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+				..Default::default()
+			},
+		)
+	};
+
+	// `main` returns an `i32` although it's declared to return an `i64`, which only the
+	// validation catches.
+	let error = create_runtime(
+		r#"
+		(module
+			(import "env" "memory" (memory 1))
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i32.const 0)
+			)
+		)
+		"#,
+	)
+	.err()
+	.unwrap();
+	assert!(matches!(error, crate::CreateRuntimeError::Module(_)), "{:?}", error);
+
+	// The conversion keeps the message the error used to have.
+	let error = sc_executor_common::error::WasmError::from(error);
+	assert!(error.to_string().starts_with("fail to create a WasmEdge Module context: "));
+
+	create_runtime(
+		r#"
+		(module
+			(import "env" "memory" (memory 1))
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.const 0)
+			)
+		)
+		"#,
+	)
+	.unwrap();
+}
+
+#[test]
+fn test_detailed_validation() {
+	let create_runtime = |wat: &str, detailed_validation| {
+		let blob = RuntimeBlob::new(&wat::parse_str(wat).unwrap()).unwrap();
+		crate::create_runtime_detailed::<HostFunctions>(
+			blob,
+			crate::Config {
+				allow_missing_func_imports: true,
+				semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+				detailed_validation,
+				..Default::default()
+			},
+		)
+	};
+
+	// `main` returns an `i32` although it's declared to return an `i64`. It's preceded by an
+	// imported function, so its index is 1.
+	const WAT: &str = r#"
+		(module
+			(import "env" "memory" (memory 1))
+			(import "env" "ext_allocator_free_version_1" (func $free (param i32)))
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i32.const 0)
+			)
+		)
+		"#;
+
+	let error = create_runtime(WAT, false).err().unwrap().to_string();
+	assert!(!error.contains("at offset"), "{}", error);
+
+	let error = create_runtime(WAT, true).err().unwrap().to_string();
+	assert!(error.starts_with("fail to create a WasmEdge Module context: "), "{}", error);
+	assert!(error.contains("type mismatch"), "{}", error);
+	assert!(error.contains("in function 1 at offset 0x"), "{}", error);
+}
+
+#[test]
+fn test_create_runtime_artifact_or_compile() {
+	let blob = RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+	let config = || crate::Config {
+		allow_missing_func_imports: true,
+		semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+		..Default::default()
+	};
+	let create_runtime = |path: &std::path::Path, rewrite_artifact: bool| unsafe {
+		crate::create_runtime_artifact_or_compile::<HostFunctions>(
+			blob.clone(),
+			path,
+			config(),
+			rewrite_artifact,
+		)
+	};
+
+	let dir = tempfile::tempdir().unwrap();
+	let path = dir.path().join("runtime.wasm");
+	crate::prepare_runtime_artifact(blob.clone(), &config().semantics, &path).unwrap();
+
+	// Pretend the artifact was compiled by another version of WasmEdge.
+	let header = crate::artifact::read_header(&path).unwrap();
+	crate::artifact::append_header(
+		&path,
+		&crate::artifact::ArtifactHeader { wasmedge_version: "0.0.0-old".into(), ..header.clone() },
+	)
+	.unwrap();
+
+	let runtime = create_runtime(&path, false).unwrap();
+	runtime.new_instance().unwrap().call_export("test_empty_return", &[]).unwrap();
+	assert_eq!(crate::artifact::read_header(&path).unwrap().wasmedge_version, "0.0.0-old");
+
+	// The stale artifact is replaced if asked to.
+	let runtime = create_runtime(&path, true).unwrap();
+	runtime.new_instance().unwrap().call_export("test_empty_return", &[]).unwrap();
+	assert_eq!(crate::artifact::read_header(&path).unwrap(), header);
+	unsafe { crate::create_runtime_from_artifact::<HostFunctions>(&path, config()) }.unwrap();
+
+	// A missing artifact is compiled as well.
+	let missing_path = dir.path().join("missing.wasm");
+	create_runtime(&missing_path, true).unwrap();
+	assert!(missing_path.exists());
+
+	// So is an artifact written before the header was stored in it.
+	std::fs::write(&path, blob.clone().serialize()).unwrap();
+	assert!(crate::artifact::read_header(&path).is_err());
+	create_runtime(&path, true).unwrap();
+	assert_eq!(crate::artifact::read_header(&path).unwrap(), header);
+
+	// A corrupt artifact with a valid header isn't a compatibility problem.
+	std::fs::write(&path, b"definitely not an artifact").unwrap();
+	crate::artifact::append_header(&path, &header).unwrap();
+	assert!(create_runtime(&path, true).is_err());
+}
+
+#[test]
+fn test_prune_artifact_cache() {
+	use std::time::Duration;
+
+	let semantics = crate::Semantics::default();
+	// Modules which differ only in the returned value.
+	let blob = |value: u32| {
+		let wat = format!(
+			r#"
+			(module
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(data (i32.const 0) "{}")
+				(func (export "main") (param i32 i32) (result i64)
+					(i64.shl (i64.const 1) (i64.const 32))
+				)
+			)
+			"#,
+			char::from_digit(value, 10).unwrap(),
+		);
+		RuntimeBlob::uncompress_if_needed(&wat::parse_str(wat).unwrap()).unwrap()
+	};
+
+	let dir = tempfile::tempdir().unwrap();
+	let path = |name: &str| dir.path().join(name);
+
+	crate::prepare_runtime_artifact(blob(1), &semantics, &path("kept.wasm")).unwrap();
+	crate::prepare_runtime_artifact(blob(2), &semantics, &path("replaced.wasm")).unwrap();
+
+	// Pretend an artifact was compiled by another version of WasmEdge.
+	crate::prepare_runtime_artifact(blob(1), &semantics, &path("old.wasm")).unwrap();
+	let header = crate::artifact::read_header(&path("old.wasm")).unwrap();
+	crate::artifact::append_header(
+		&path("old.wasm"),
+		&crate::artifact::ArtifactHeader { wasmedge_version: "0.0.0-old".into(), ..header },
+	)
+	.unwrap();
+
+	// Artifacts whose header is missing or malformed.
+	std::fs::write(path("headerless.wasm"), b"artifact").unwrap();
+	std::fs::write(path("corrupt.wasm"), b"artifact\xff\xff\xff\xffwasmedge").unwrap();
+
+	// A header stored next to its artifact by an older version.
+	std::fs::write(path("kept.wasm.header"), b"header").unwrap();
+
+	// A temporary file left behind by an interrupted compilation.
+	std::fs::write(path(".kept.wasm.tmp"), b"artifa").unwrap();
+
+	let keep = [crate::blob_hash(&blob(1))];
+	let prune = |grace_period| {
+		crate::artifact::prune_artifact_cache_with_grace_period(dir.path(), &keep, grace_period)
+			.unwrap()
+	};
+
+	// The files just written might still be being written, so nothing is removed yet.
+	assert_eq!(crate::prune_artifact_cache(dir.path(), &keep).unwrap(), 0);
+	assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 7);
+
+	assert_eq!(prune(Duration::ZERO), 4);
+
+	let mut names = std::fs::read_dir(dir.path())
+		.unwrap()
+		.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+		.collect::<Vec<_>>();
+	names.sort();
+	assert_eq!(names, ["kept.wasm"]);
+
+	// Nothing is left to remove and the kept artifact is still loadable.
+	assert_eq!(prune(Duration::ZERO), 0);
+	crate::artifact::read_header(&path("kept.wasm"))
+		.unwrap()
+		.check_compatible(&semantics)
+		.unwrap();
+}
+
+#[test]
+fn test_semantics_compilation_settings() {
+	let semantics = crate::Semantics {
+		fast_instance_reuse: true,
+		deterministic_stack_limit: Some(crate::DeterministicStackLimit { logical_max: 65536 }),
+		extra_heap_pages: 2048,
+		max_memory_size: Some(64 * 1024 * 1024),
+		track_max_stack_depth: true,
+		enable_saturating_float_to_int: false,
+		statistics: crate::StatisticsConfig { instr_count: true, cost: false, time: true },
+		canonicalize_nans: true,
+		interruptible: true,
+		..Default::default()
+	};
+	let settings = semantics.compilation_settings();
+
+	// The version, followed by the length prefixed description of the compiler settings.
+	assert_eq!(settings[0], 6);
+	let compiler_settings_len = u32::from_le_bytes(settings[1..5].try_into().unwrap()) as usize;
+	assert!(std::str::from_utf8(&settings[5..5 + compiler_settings_len])
+		.unwrap()
+		.starts_with("opt_level="));
+
+	#[rustfmt::skip]
+	let expected: &[u8] = &[
+		// fast_instance_reuse
+		1,
+		// deterministic_stack_limit
+		1, 0x00, 0x00, 0x01, 0x00,
+		// extra_heap_pages
+		0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+		// track_max_stack_depth
+		1,
+		// enable_sign_extension
+		1,
+		// enable_saturating_float_to_int
+		0,
+		// statistics.instr_count
+		1,
+		// statistics.cost
+		0,
+		// artifact_format
+		0,
+		// canonicalize_nans
+		1,
+		// interruptible
+		1,
+	];
+	assert_eq!(&settings[5 + compiler_settings_len..], expected);
+
+	assert_eq!(semantics.artifact_key(), sp_core_hashing::blake2_256(&settings));
+}
+
+#[test]
+fn test_semantics_artifact_key() {
+	let semantics = crate::Semantics { extra_heap_pages: 1024, ..Default::default() };
+
+	// Fields which don't affect the compiled output don't affect the key.
+	let same = crate::Semantics {
+		max_memory_size: Some(64 * 1024 * 1024),
+		decommit_only_dirty: true,
+		decommit_after_call: false,
+		zero_memory_on_first_use: true,
+		benchmark_unchecked: false,
+		track_max_stack_depth: false,
+		max_artifact_size: None,
+		statistics: crate::StatisticsConfig { time: true, ..Default::default() },
+		..semantics.clone()
+	};
+	assert_eq!(semantics.artifact_key(), same.artifact_key());
+
+	let different = [
+		crate::Semantics { fast_instance_reuse: true, ..semantics.clone() },
+		crate::Semantics {
+			deterministic_stack_limit: Some(crate::DeterministicStackLimit { logical_max: 65536 }),
+			..semantics.clone()
+		},
+		crate::Semantics { extra_heap_pages: 2048, ..semantics.clone() },
+		crate::Semantics { enable_sign_extension: false, ..semantics.clone() },
+		crate::Semantics { enable_saturating_float_to_int: false, ..semantics.clone() },
+		crate::Semantics {
+			statistics: crate::StatisticsConfig { instr_count: true, ..Default::default() },
+			..semantics.clone()
+		},
+		crate::Semantics {
+			statistics: crate::StatisticsConfig { cost: true, ..Default::default() },
+			..semantics.clone()
+		},
+		crate::Semantics { interruptible: true, ..semantics.clone() },
+	];
+	for other in &different {
+		assert_ne!(semantics.artifact_key(), other.artifact_key());
+	}
+
+	// An artifact can't be loaded with semantics it wasn't compiled with.
+	let blob = RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+	let dir = tempfile::tempdir().unwrap();
+	let path = dir.path().join("runtime.wasm");
+	crate::prepare_runtime_artifact(blob, &semantics, &path).unwrap();
+
+	let config = |semantics| crate::Config {
+		allow_missing_func_imports: true,
+		semantics,
+		..Default::default()
+	};
+	unsafe { crate::create_runtime_from_artifact::<HostFunctions>(&path, config(same)) }.unwrap();
+	let error = unsafe {
+		crate::create_runtime_from_artifact::<HostFunctions>(&path, config(different[2].clone()))
+	}
+	.err()
+	.unwrap();
+	assert!(error.to_string().contains("incompatible"), "{}", error);
+}
+
+#[test]
+fn test_host_memory_access_across_grow() {
+	use sp_wasm_interface::FunctionContext;
+
+	const WASM_PAGE_SIZE: u32 = 65536;
+
+	let semantics = crate::Semantics::default();
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let heap_base = instance_wrapper.extract_heap_base().unwrap();
+
+	let mut host_state = HostState::new(FreeingBumpHeapAllocator::new(heap_base));
+	let mut context = HostContext::new(
+		instance_wrapper.instance().memory("memory").unwrap(),
+		None,
+		&mut host_state,
+	);
+
+	context.write_memory(Pointer::new(16), b"before").unwrap();
+	assert!(context.write_memory(Pointer::new(WASM_PAGE_SIZE), b"after").is_err());
+
+	// Grow the memory behind the context's back, which might move its backing store.
+	instance_wrapper.memory_mut().grow(16).unwrap();
+
+	context.write_memory(Pointer::new(WASM_PAGE_SIZE), b"after").unwrap();
+
+	let mut buffer = [0; 6];
+	context.read_memory_into(Pointer::new(16), &mut buffer).unwrap();
+	assert_eq!(&buffer, b"before");
+
+	let mut buffer = [0; 5];
+	context.read_memory_into(Pointer::new(WASM_PAGE_SIZE), &mut buffer).unwrap();
+	assert_eq!(&buffer, b"after");
+
+	// An allocation only fitting into the grown memory succeeds.
+	let ptr = context.allocate_memory(4 * WASM_PAGE_SIZE).unwrap();
+	context.deallocate_memory(ptr).unwrap();
+}
+
+#[test]
+fn test_memory_len() {
+	use crate::util::{memory_len, memory_slice, WASM_PAGE_SIZE};
+
+	let semantics = crate::Semantics::default();
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(memory (export "memory") 3)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+
+	// In debug builds this also checks that WasmEdge agrees on the size in bytes.
+	assert_eq!(memory_len(instance_wrapper.memory()), 3 * WASM_PAGE_SIZE);
+	assert_eq!(memory_slice(instance_wrapper.memory()).len(), 3 * WASM_PAGE_SIZE);
+
+	instance_wrapper.memory_mut().grow(2).unwrap();
+	assert_eq!(memory_len(instance_wrapper.memory()), 5 * WASM_PAGE_SIZE);
+	assert_eq!(memory_slice(instance_wrapper.memory()).len(), 5 * WASM_PAGE_SIZE);
+}
+
+#[test]
+fn test_read_misaligned() {
+	use sp_wasm_interface::FunctionContext;
+
+	let semantics = crate::Semantics::default();
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let heap_base = instance_wrapper.extract_heap_base().unwrap();
+
+	let mut host_state = HostState::new(FreeingBumpHeapAllocator::new(heap_base));
+	let mut context = HostContext::new(
+		instance_wrapper.instance().memory("memory").unwrap(),
+		None,
+		&mut host_state,
+	);
+
+	context.write_memory(Pointer::new(17), &0xdeadbeef_u32.to_le_bytes()).unwrap();
+	context
+		.write_memory(Pointer::new(33), &0x0123456789abcdef_u64.to_le_bytes())
+		.unwrap();
+
+	// Misaligned reads still work, they are only reported in debug builds.
+	assert_eq!(context.read_u32(Pointer::new(17)).unwrap(), 0xdeadbeef);
+	assert_eq!(context.read_u64(Pointer::new(33)).unwrap(), 0x0123456789abcdef);
+	assert_eq!(crate::util::check_alignment(Pointer::new(17), 4), !cfg!(debug_assertions));
+	assert_eq!(crate::util::check_alignment(Pointer::new(33), 8), !cfg!(debug_assertions));
+	assert!(crate::util::check_alignment(Pointer::new(16), 4));
+	assert!(crate::util::check_alignment(Pointer::new(32), 8));
+
+	assert!(context.read_u32(Pointer::new(65534)).is_err());
+}
+
+#[test]
+fn test_recreated_instance_is_reset() {
+	// `bump` increments the byte at address 0 and a mutable global and returns their sum, `grow`
+	// does the same after growing the memory, which makes resetting the instance impossible.
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(global $counter (mut i32) (i32.const 0))
+			(data (i32.const 0) "\01")
+			(func $bump (export "bump") (param i32 i32) (result i64)
+				(global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+				(i32.store8
+					(i32.const 0)
+					(i32.add (i32.load8_u (i32.const 0)) (global.get $counter))
+				)
+				;; ptr = 0, len = 1
+				(i64.const 4294967296)
+			)
+			(func (export "grow") (param i32 i32) (result i64)
+				(drop (memory.grow (i32.const 1)))
+				(call $bump (local.get 0) (local.get 1))
+			)
+		)
+	"#;
+
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	let resets = || crate::instance_wrapper::RESETS.with(|resets| resets.get());
+	let initial_resets = resets();
+	for method in ["bump", "bump", "grow", "bump", "grow", "grow", "bump"] {
+		assert_eq!(instance.call_export(method, &[]).unwrap(), vec![2], "{}", method);
+	}
+	// The first call and the calls following a `grow` instantiate the instance instead.
+	assert_eq!(resets() - initial_resets, 3);
+}
+
+#[test]
+fn test_recreated_test_runtime_is_reset() {
+	use codec::{Decode, Encode};
+
+	// The test runtime keeps its stack pointer in a mutable global, which must be restored.
+	let runtime = RuntimeBuilder::new_on_demand().deterministic_stack(true).build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	let resets = || crate::instance_wrapper::RESETS.with(|resets| resets.get());
+	let initial_resets = resets();
+	for _ in 0..3 {
+		let result = instance.call_export("returns_mutable_static", &[0u8; 0]).unwrap();
+		assert_eq!(u64::decode(&mut &result[..]).unwrap(), 33);
+
+		let output =
+			instance.call_export("test_data_in", &b"Hello world".to_vec().encode()).unwrap();
+		assert_eq!(output, b"all ok!".to_vec().encode());
+	}
+	assert_eq!(resets() - initial_resets, 5);
+}
+
+#[test]
+fn test_sandbox_errors() {
+	use codec::Encode;
+
+	let runtime = RuntimeBuilder::new_on_demand().build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let mut sandbox_error = |code: &str| {
+		let code = wat::parse_str(code).unwrap();
+		let result = instance.call_export("test_sandbox_host", &code.encode()).unwrap();
+		let error = instance.last_sandbox_error().cloned();
+		assert_eq!(result, error.is_none().encode());
+		error
+	};
+
+	assert_eq!(
+		sandbox_error(
+			r#"
+			(module
+				(import "env" "assert" (func $assert (param i32)))
+				(func (export "call")
+					(call $assert (i32.const 1))
+				)
+			)
+			"#
+		),
+		None
+	);
+
+	assert!(matches!(
+		sandbox_error(
+			r#"
+			(module
+				(func (export "call")
+					unreachable
+				)
+			)
+			"#
+		),
+		Some(crate::SandboxError::Trap(_))
+	));
+
+	assert!(matches!(
+		sandbox_error(
+			r#"
+			(module
+				(import "env" "assert" (func $assert (param i32)))
+				(func (export "call")
+					(call $assert (i32.const 0))
+				)
+			)
+			"#
+		),
+		Some(crate::SandboxError::HostFunction(_))
+	));
+
+	assert!(matches!(
+		sandbox_error(
+			r#"
+			(module
+				(memory 1)
+				(func (export "call")
+					(drop (i32.load (i32.const 65536)))
+				)
+			)
+			"#
+		),
+		Some(crate::SandboxError::MemoryFault(_))
+	));
+
+	assert!(matches!(
+		sandbox_error(
+			r#"
+			(module
+				(func (export "not_call"))
+			)
+			"#
+		),
+		Some(crate::SandboxError::Other(_))
+	));
+}
+
+#[test]
+fn test_classify_sandbox_error() {
+	use crate::SandboxError;
+	use sc_executor_common::error::Error;
+
+	let classify = |message: &str| SandboxError::from_invoke_error(Error::Sandbox(message.into()));
+
+	// As reported by the wasmi backend.
+	assert!(matches!(classify("Trap: Code(Unreachable)"), SandboxError::Trap(_)));
+	assert!(matches!(
+		classify("Trap: Code(MemoryAccessOutOfBounds)"),
+		SandboxError::MemoryFault(_)
+	));
+	assert!(matches!(
+		classify("Function: Module doesn't have export call"),
+		SandboxError::Other(_)
+	));
+
+	// As reported by the wasmer backend.
+	assert!(matches!(classify("unreachable"), SandboxError::Trap(_)));
+	assert!(matches!(classify("out of bounds memory access"), SandboxError::MemoryFault(_)));
+	assert!(matches!(classify("Missing export call"), SandboxError::Other(_)));
+
+	assert!(matches!(SandboxError::from_invoke_error(Error::NoTable), SandboxError::Other(_)));
+}
+
+#[test]
+fn test_call_streaming() {
+	const OUTPUT_LEN: u32 = 200_000;
+
+	// Fills the first `OUTPUT_LEN` bytes of the memory with a pattern and returns them.
+	let wat = format!(
+		r#"
+		(module
+			(memory (export "memory") 4)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "fill") (param i32 i32) (result i64)
+				(local $i i32)
+				(block $done
+					(loop $loop
+						(br_if $done (i32.ge_u (local.get $i) (i32.const {len})))
+						(i32.store8 (local.get $i) (i32.rem_u (local.get $i) (i32.const 251)))
+						(local.set $i (i32.add (local.get $i) (i32.const 1)))
+						(br $loop)
+					)
+				)
+				;; ptr = 0, len = {len}
+				(i64.shl (i64.const {len}) (i64.const 32))
+			)
+		)
+		"#,
+		len = OUTPUT_LEN,
+	);
+
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(wat).build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let mut chunks = Vec::new();
+	instance
+		.call_streaming(InvokeMethod::Export("fill"), &[], |chunk| chunks.push(chunk.to_vec()))
+		.unwrap();
+
+	assert!(chunks.len() > 1);
+	assert!(chunks.iter().all(|chunk| !chunk.is_empty() && chunk.len() <= 64 * 1024));
+
+	let output = instance.call_export("fill", &[]).unwrap();
+	assert_eq!(output.len(), OUTPUT_LEN as usize);
+	assert_eq!(chunks.concat(), output);
+}
+
+#[test]
+fn test_call_streaming_with_timeout() {
+	use std::time::Duration;
+
+	// `hang` writes "hello" into an allocation and loops forever, `quick` returns it right away.
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func $hello (result i32)
+				(local $ptr i32)
+				(local.set $ptr (call $malloc (i32.const 5)))
+				;; "hell" and "o"
+				(i32.store (local.get $ptr) (i32.const 0x6c6c6568))
+				(i32.store8 offset=4 (local.get $ptr) (i32.const 0x6f))
+				(local.get $ptr)
+			)
+			(func (export "hang") (param i32 i32) (result i64)
+				(drop (call $hello))
+				(loop $forever (br $forever))
+				(unreachable)
+			)
+			(func (export "quick") (param i32 i32) (result i64)
+				(i64.or
+					(i64.extend_i32_u (call $hello))
+					(i64.shl (i64.const 5) (i64.const 32)))
+			)
+		)
+		"#;
+
+	for fast_instance_reuse in [true, false] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.interruptible(true)
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+		let mut output = Vec::new();
+		let result = instance.call_streaming_with_timeout(
+			InvokeMethod::Export("hang"),
+			&[],
+			Duration::from_millis(100),
+			|chunk| output.extend_from_slice(chunk),
+		);
+		assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+		assert_eq!(output, b"hello");
+
+		// The instance is usable after the timeout, and a call finishing in time isn't affected.
+		let mut output = Vec::new();
+		instance
+			.call_streaming_with_timeout(
+				InvokeMethod::Export("quick"),
+				&[],
+				Duration::from_secs(60),
+				|chunk| output.extend_from_slice(chunk),
+			)
+			.unwrap();
+		assert_eq!(output, b"hello");
+		assert_eq!(instance.call_export("quick", &[]).unwrap(), b"hello");
+	}
+
+	// The calls can't time out without the instrumentation.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+	let result = instance.call_streaming_with_timeout(
+		InvokeMethod::Export("quick"),
+		&[],
+		Duration::from_secs(60),
+		|_| (),
+	);
+	assert!(result.is_err());
+}
+
+#[test]
+fn test_register_import_error_context() {
+	let semantics = crate::Semantics { extra_heap_pages: 1024, ..Default::default() };
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(import "env" "ext_first" (func (param i32)))
+			(import "env" "ext_second" (func (result i64)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	crate::imports::prepare_imports(
+		&mut instance_wrapper,
+		&module,
+		&Default::default(),
+		true,
+		false,
+		false,
+		false,
+		None,
+		&Default::default(),
+		None,
+	)
+	.unwrap();
+
+	// Registering the imports again conflicts with the module registered above.
+	let error = crate::imports::prepare_imports(
+		&mut instance_wrapper,
+		&module,
+		&Default::default(),
+		true,
+		false,
+		false,
+		false,
+		None,
+		&Default::default(),
+		None,
+	)
+	.unwrap_err();
+	assert!(
+		error
+			.to_string()
+			.contains("failed to register import module 'env' with 2 functions"),
+		"{}",
+		error,
+	);
+}
+
+#[test]
+fn test_missing_import_with_signature() {
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(
+			r#"
+			(module
+				(import "env" "ext_missing_version_1" (func $missing (param i32 i32) (result i64)))
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(func (export "main") (param i32 i32) (result i64)
+					(call $missing (local.get 0) (local.get 1))
+				)
+			)
+			"#
+			.to_string(),
+		)
+		.build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	match instance.call_export("main", &[1, 2, 3]).unwrap_err() {
+		Error::AbortedDueToTrap(error) =>
+			assert_eq!(error.message, crate::HostFuncErrorWasmEdge::MissingHostFunc.to_string()),
+		error => panic!("unexpected error: {:?}", error),
+	}
+}
+
+#[test]
+fn test_import_report() {
+	let code = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1" (func (param i32) (result i32)))
+			(import "env" "ext_missing_version_1" (func))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#;
+	let expected = crate::ImportReport {
+		satisfied: vec!["ext_allocator_malloc_version_1".to_string()],
+		stubbed: vec!["ext_missing_version_1".to_string()],
+		mismatched: vec![],
+	};
+
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(code.to_string()).build_runtime();
+	assert_eq!(runtime.import_report(), expected);
+
+	// The imports registered for an instance are the same as reported for the runtime.
+	let semantics = crate::Semantics::default();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wat::parse_str(code).unwrap(),
+	)
+	.unwrap();
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	let report = crate::imports::prepare_imports(
+		&mut instance_wrapper,
+		&module,
+		&crate::imports::host_function_registry(&HostFunctions::host_functions()),
+		true,
+		false,
+		false,
+		false,
+		None,
+		&Default::default(),
+		None,
+	)
+	.unwrap();
+	assert_eq!(report, expected);
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(
+			r#"
+			(module
+				(import "env" "ext_allocator_malloc_version_1" (func (param i64) (result i32)))
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const 1024))
+			)
+			"#
+			.to_string(),
+		)
+		.build_runtime();
+	assert_eq!(
+		runtime.import_report().mismatched,
+		vec!["ext_allocator_malloc_version_1".to_string()],
+	);
+	assert!(runtime.new_wasmedge_instance().is_err());
+}
+
+#[test]
+fn test_accept_any_import_module() {
+	let code = r#"
+		(module
+			(import "wasi_snapshot_preview1" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(import "wasi_snapshot_preview1" "fd_write"
+				(func (param i32 i32 i32 i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.extend_i32_u (call $malloc (i32.const 16)))
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(code.to_string()).build_runtime();
+	assert_eq!(
+		runtime.import_report().mismatched,
+		vec![
+			"wasi_snapshot_preview1:ext_allocator_malloc_version_1".to_string(),
+			"wasi_snapshot_preview1:fd_write".to_string(),
+		],
+	);
+	let error = runtime.new_wasmedge_instance().unwrap_err();
+	assert!(error.to_string().contains("non-env module"), "{}", error);
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(code.to_string())
+		.accept_any_import_module(true)
+		.build_runtime();
+	assert_eq!(
+		runtime.import_report(),
+		crate::ImportReport {
+			satisfied: vec!["wasi_snapshot_preview1:ext_allocator_malloc_version_1".to_string()],
+			stubbed: vec!["wasi_snapshot_preview1:fd_write".to_string()],
+			mismatched: vec![],
+		},
+	);
+
+	// The host function is resolved by its name, even though it's imported from another module.
+	let mut instance = runtime.new_instance().unwrap();
+	instance.call_export("main", &[]).unwrap();
+}
+
+#[test]
+fn test_deadline_imports_require_interruptible() {
+	let code = r#"
+		(module
+			(import "wasmedge_deadline" "gas" (func (param i64)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#;
+
+	// Without the deadline checks the module is as unknown as any other.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(code.to_string()).build_runtime();
+	assert_eq!(runtime.import_report().mismatched, vec!["wasmedge_deadline:gas".to_string()]);
+	let error = runtime.new_wasmedge_instance().unwrap_err();
+	assert!(error.to_string().contains("non-env module"), "{}", error);
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(code.to_string())
+		.interruptible(true)
+		.build_runtime();
+	assert!(runtime.import_report().mismatched.is_empty());
+	assert!(runtime.import_report().satisfied.contains(&"wasmedge_deadline:gas".to_string()));
+	runtime.new_wasmedge_instance().unwrap();
+}
+
+#[test]
+fn test_canonicalize_nans() {
+	// Stores the results of `0.0 / 0.0` and of an arithmetic operation on a NaN with a payload,
+	// which x86 propagates, as `f32` and `f64` bits.
+	let code = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(f32.store (i32.const 0)
+					(f32.div (f32.convert_i32_s (local.get 1)) (f32.convert_i32_s (local.get 1))))
+				(f32.store (i32.const 4)
+					(f32.add (f32.reinterpret_i32 (i32.const 0x7fa00001)) (f32.const 1)))
+				(f64.store (i32.const 8)
+					(f64.div (f64.convert_i32_s (local.get 1)) (f64.convert_i32_s (local.get 1))))
+				(f64.store (i32.const 16)
+					(f64.add
+						(f64.reinterpret_i64 (i64.const 0x7ff4000000000001))
+						(f64.const 1)))
+				(i64.const 0x1800000000)
+			)
+		)
+		"#;
+
+	let mut expected = Vec::new();
+	expected.extend_from_slice(&0x7fc0_0000u32.to_le_bytes());
+	expected.extend_from_slice(&0x7fc0_0000u32.to_le_bytes());
+	expected.extend_from_slice(&0x7ff8_0000_0000_0000u64.to_le_bytes());
+	expected.extend_from_slice(&0x7ff8_0000_0000_0000u64.to_le_bytes());
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(code.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.canonicalize_nans(true)
+			.build();
+
+		// The bits are the same on every call, and are the canonical ones on any platform.
+		for _ in 0..2 {
+			let mut instance = runtime.new_instance().unwrap();
+			assert_eq!(instance.call_export("main", &[]).unwrap(), expected);
+			assert_eq!(instance.call_export("main", &[]).unwrap(), expected);
+		}
+	}
+}
+
+#[test]
+fn test_trapped_allocation_stats() {
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(
+			r#"
+			(module
+				(import "env" "ext_allocator_malloc_version_1" (func $malloc (param i32) (result i32)))
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(func (export "main") (param i32 i32) (result i64)
+					(drop (call $malloc (i32.const 100)))
+					(if (local.get 1) (then unreachable))
+					(i64.const 0)
+				)
+			)
+			"#
+			.to_string(),
+		)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	instance.call_export("main", &[1]).unwrap_err();
+	let stats = instance.trapped_allocation_stats().unwrap();
+	assert!(stats.bytes_allocated >= 100, "{:?}", stats);
+
+	instance.call_export("main", &[]).unwrap();
+	assert!(instance.trapped_allocation_stats().is_none());
+}
+
+#[test]
+fn test_validate_entry_points() {
+	let config = |validate_entry_points: &[&str]| crate::Config {
+		allow_missing_func_imports: true,
+		semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+		validate_entry_points: validate_entry_points.iter().map(|name| name.to_string()).collect(),
+		..Default::default()
+	};
+	let blob = || RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+
+	crate::create_runtime::<HostFunctions>(blob(), config(&["Core_version"])).unwrap();
+
+	let error = crate::create_runtime::<HostFunctions>(blob(), config(&["Core_version", "bogus"]))
+		.err()
+		.unwrap()
+		.to_string();
+	assert!(error.contains("`bogus` is not exported"), "{}", error);
+	assert!(!error.contains("Core_version"), "{}", error);
+
+	let blob = RuntimeBlob::uncompress_if_needed(
+		&wat::parse_str(
+			r#"
+			(module
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(func (export "Core_version") (param i32) (result i64)
+					(i64.const 0)
+				)
+			)
+			"#,
+		)
+		.unwrap(),
+	)
+	.unwrap();
+	let error =
+		crate::create_runtime::<HostFunctions>(blob, config(&["Core_version", "__heap_base"]))
+			.err()
+			.unwrap()
+			.to_string();
+	assert!(
+		error.contains("`Core_version` doesn't have the signature `(i32, i32) -> i64`"),
+		"{}",
+		error,
+	);
+	assert!(error.contains("`__heap_base` is not a function"), "{}", error);
+}
+
+/// Keeps the records logged to it, enabling only the logs of the WasmEdge target up to `level`.
+struct CapturingLogger {
+	level: log::Level,
+	records: std::sync::Mutex<Vec<(log::Level, String)>>,
+}
+
+impl CapturingLogger {
+	fn new(level: log::Level) -> Self {
+		Self { level, records: Default::default() }
+	}
+}
+
+impl log::Log for CapturingLogger {
+	fn enabled(&self, metadata: &log::Metadata) -> bool {
+		metadata.target() == crate::WASMEDGE_LOG_TARGET && metadata.level() <= self.level
+	}
+
+	fn log(&self, record: &log::Record) {
+		if self.enabled(record.metadata()) {
+			self.records.lock().unwrap().push((record.level(), record.args().to_string()));
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+#[test]
+fn test_sync_wasmedge_log_level() {
+	use crate::{logging::wasmedge_log_level, WasmEdgeLogLevel};
+	use log::{Level, LevelFilter};
+
+	let errors = CapturingLogger::new(Level::Error);
+	assert_eq!(wasmedge_log_level(&errors, LevelFilter::Trace), WasmEdgeLogLevel::Error);
+	assert_eq!(wasmedge_log_level(&errors, LevelFilter::Off), WasmEdgeLogLevel::Off);
+
+	let everything = CapturingLogger::new(Level::Trace);
+	assert_eq!(wasmedge_log_level(&everything, LevelFilter::Trace), WasmEdgeLogLevel::Debug);
+	assert_eq!(wasmedge_log_level(&everything, LevelFilter::Warn), WasmEdgeLogLevel::Error);
+
+	// Runtimes work regardless of WasmEdge's logging.
+	let runtime = RuntimeBuilder::new_on_demand().build();
+	runtime.new_instance().unwrap().call_export("test_empty_return", &[]).unwrap();
+}
+
+#[test]
+fn test_forward_wasmedge_logs() {
+	use crate::logging::TrapLogs;
+	use log::{Level, LevelFilter};
+
+	let stderr = concat!(
+		"[2022-08-21 12:00:00.000] [error] execution failed: unreachable, Code: 0x89\n",
+		"thread 'main' panicked at 'not a WasmEdge log'\n",
+		"[2022-08-21 12:00:00.001] [error]     When executing function name: \"test\"\n",
+		"[2022-08-21 12:00:00.002] [debug] filtered out\n",
+		"[not] [a level] either\n",
+		"\u{1}wasmedge-trap-log 7\n",
+		"[2022-08-21 12:00:00.003] [error] after the marker\n",
+	);
+
+	let logger = CapturingLogger::new(Level::Trace);
+	let trap_logs = TrapLogs::default();
+	trap_logs.expect(7);
+	let mut passthrough = Vec::new();
+	crate::logging::forward_lines(
+		stderr.as_bytes(),
+		&mut passthrough,
+		|| (&logger as &dyn log::Log, LevelFilter::Info),
+		&trap_logs,
+	);
+
+	assert_eq!(
+		*logger.records.lock().unwrap(),
+		[
+			(Level::Error, String::from("execution failed: unreachable, Code: 0x89")),
+			(Level::Error, String::from("    When executing function name: \"test\"")),
+			(Level::Error, String::from("after the marker")),
+		],
+	);
+	assert_eq!(
+		String::from_utf8(passthrough).unwrap(),
+		"thread 'main' panicked at 'not a WasmEdge log'\n[not] [a level] either\n",
+	);
+	// The marker collects everything WasmEdge logged before it, even what isn't forwarded.
+	assert_eq!(
+		trap_logs.wait(7, std::time::Duration::ZERO).unwrap(),
+		[
+			"execution failed: unreachable, Code: 0x89",
+			"    When executing function name: \"test\"",
+			"filtered out",
+		],
+	);
+}
+
+#[test]
+fn test_load_plugins() {
+	let config = |plugins| crate::Config {
+		allow_missing_func_imports: true,
+		semantics: crate::Semantics { extra_heap_pages: 1024, ..Default::default() },
+		plugins,
+		..Default::default()
+	};
+	let blob = || RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+
+	// A directory without any plugins in it is a no-op, even when loaded repeatedly.
+	let dir = tempfile::tempdir().unwrap();
+	crate::load_plugins(&[dir.path()]).unwrap();
+	crate::create_runtime::<HostFunctions>(blob(), config(vec![dir.path().to_owned()])).unwrap();
+
+	let missing = dir.path().join("missing");
+	let error = crate::create_runtime::<HostFunctions>(blob(), config(vec![missing.clone()]))
+		.err()
+		.unwrap();
+	assert!(error.to_string().contains(&missing.display().to_string()), "{}", error);
+}
+
+#[test]
+fn test_current_allocator_stats() {
+	use sp_wasm_interface::{Function, FunctionContext, HostFunctionRegistry, Signature};
+	use std::sync::Mutex;
+
+	lazy_static::lazy_static! {
+		// The `bytes_allocated` seen by the host function before and after its allocations.
+		static ref OBSERVED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+	}
+
+	// A host function which checks the allocator headroom around a temporary allocation.
+	struct AllocatingHostFunction;
+
+	impl Function for AllocatingHostFunction {
+		fn name(&self) -> &str {
+			"ext_test_allocating_version_1"
+		}
+
+		fn signature(&self) -> Signature {
+			Signature::new_with_args(&[][..])
+		}
+
+		fn execute(
+			&self,
+			context: &mut dyn FunctionContext,
+			_: &mut dyn Iterator<Item = Value>,
+		) -> sp_wasm_interface::Result<Option<Value>> {
+			let bytes_allocated = || crate::current_allocator_stats().unwrap().bytes_allocated;
+			let mut observed = OBSERVED.lock().unwrap();
+			observed.push(bytes_allocated());
+			let ptr = context.allocate_memory(1000)?;
+			observed.push(bytes_allocated());
+			context.deallocate_memory(ptr)?;
+			observed.push(bytes_allocated());
+			Ok(None)
+		}
+	}
+
+	struct AllocatingHostFunctions;
+
+	impl sp_wasm_interface::HostFunctions for AllocatingHostFunctions {
+		fn host_functions() -> Vec<&'static dyn Function> {
+			vec![&AllocatingHostFunction]
+		}
+
+		fn register_static<T>(_: &mut T) -> Result<(), T::Error>
+		where
+			T: HostFunctionRegistry,
+		{
+			Ok(())
+		}
+	}
+
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_test_allocating_version_1" (func $allocating))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(call $allocating)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let blob = RuntimeBlob::uncompress_if_needed(&wat::parse_str(WAT).unwrap()).unwrap();
+	let config = crate::Config::default();
+	let mut instance = crate::create_runtime::<AllocatingHostFunctions>(blob, config)
+		.unwrap()
+		.new_wasmedge_instance()
+		.unwrap();
+
+	assert!(crate::current_allocator_stats().is_none());
+	// The input data of the call is allocated as well.
+	instance.call_export("main", &[0; 100]).unwrap();
+	assert!(crate::current_allocator_stats().is_none());
+
+	let observed = OBSERVED.lock().unwrap().clone();
+	assert_eq!(observed.len(), 3);
+	assert!(observed[0] >= 100, "{:?}", observed);
+	assert!(observed[1] >= observed[0] + 1000, "{:?}", observed);
+	assert_eq!(observed[2], observed[0]);
+}
+
+#[test]
+fn test_memory_is_released_after_errored_call() {
+	const DIRTY_ADDRESS: u32 = 70_000;
+
+	let semantics = crate::Semantics::default();
+	let wasm = wat::parse_str(format!(
+		r#"
+		(module
+			(memory (export "memory") 2)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "dirty_and_trap") (param i32 i32) (result i64)
+				(i32.store8 (i32.const {}) (i32.const 255))
+				unreachable
+			)
+		)
+		"#,
+		DIRTY_ADDRESS,
+	))
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let heap_base = instance_wrapper.extract_heap_base().unwrap();
+
+	let read_dirty_byte = |instance_wrapper: &InstanceWrapper| {
+		crate::util::memory_slice(instance_wrapper.memory())[DIRTY_ADDRESS as usize]
+	};
+
+	instance_wrapper.set_host_state(Some(HostState::new(FreeingBumpHeapAllocator::new(heap_base))));
+	assert!(instance_wrapper
+		.call(InvokeMethod::Export("dirty_and_trap"), Pointer::new(0), 0)
+		.is_err());
+	instance_wrapper.take_host_state();
+	assert_eq!(read_dirty_byte(&instance_wrapper), 255);
+
+	// This is what happens when the instance is dropped.
+	instance_wrapper.release_memory();
+	assert_eq!(read_dirty_byte(&instance_wrapper), 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_synchronous_decommit() {
+	let semantics = crate::Semantics { synchronous_decommit: true, ..Default::default() };
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(memory (export "memory") 64)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let len = crate::util::memory_len(instance_wrapper.memory());
+
+	crate::util::memory_slice_mut(instance_wrapper.memory_mut()).fill(1);
+	assert_eq!(instance_wrapper.resident_memory(len), Some(len));
+
+	instance_wrapper.decommit();
+	assert_eq!(instance_wrapper.resident_memory(len), Some(0));
+	assert!(crate::util::memory_slice(instance_wrapper.memory())
+		.iter()
+		.all(|byte| *byte == 0));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_guard_pages() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "read_last") (param i32 i32) (result i64)
+				(drop (i32.load (i32.const 65532)))
+				(i64.const 0)
+			)
+			(func (export "read_past_end") (param i32 i32) (result i64)
+				(drop (i32.load (i32.const 65534)))
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.guard_pages(true)
+			.build();
+		let mut instance = runtime.new_instance().unwrap();
+
+		assert_eq!(instance.call_export("read_last", &[]).unwrap(), Vec::<u8>::new());
+		match instance.call_export("read_past_end", &[]).unwrap_err() {
+			Error::AbortedDueToTrap(_) => {},
+			error => panic!("unexpected error: {:?}", error),
+		}
+		// The instance is still usable after the fault.
+		assert_eq!(instance.call_export("read_last", &[]).unwrap(), Vec::<u8>::new());
+	}
+}
+
+#[test]
+fn test_unsnapshottable_global() {
+	// The immutable global is exported under a name reserved for the exposed mutable globals, so
+	// it would be restored before every call, which is impossible.
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(global (export "exported_internal_global_immutable") i32 (i32.const 1))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.fast_instance_reuse(true)
+		.build_runtime();
+	let error = runtime.new_wasmedge_instance().err().unwrap();
+	assert!(
+		error
+			.to_string()
+			.contains("cannot snapshot the global `exported_internal_global_immutable`"),
+		"{}",
+		error
+	);
+
+	// Without the fast instance reuse the instances just aren't reset.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build_runtime();
+	runtime.new_wasmedge_instance().unwrap().call_export("main", &[]).unwrap();
+}
+
+#[test]
+fn test_instance_is_usable_after_trap() {
+	// Both functions bump the counter, `main` returns it.
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(global $counter (mut i32) (i32.const 0))
+			(func $bump
+				(global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+				(i32.store (i32.const 0) (global.get $counter))
+			)
+			(func (export "main") (param i32 i32) (result i64)
+				(call $bump)
+
+				;; ptr = 0, len = 4
+				(i64.const 17179869184)
+			)
+			(func (export "trap") (param i32 i32) (result i64)
+				(call $bump)
+				unreachable
+			)
+		)
+		"#;
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.build();
+		let mut instance = runtime.new_instance().unwrap();
+
+		assert_eq!(instance.call_export("main", &[]).unwrap(), 1u32.to_le_bytes());
+		assert!(instance.call_export("trap", &[]).is_err());
+		assert_eq!(instance.call_export("main", &[]).unwrap(), 1u32.to_le_bytes());
+		assert_eq!(instance.call_export("main", &[]).unwrap(), 1u32.to_le_bytes());
+	}
+
+	// Only a trap poisons the instance, until it's instantiated again.
+	let semantics = crate::Semantics::default();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wat::parse_str(WAT).unwrap(),
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let heap_base = instance_wrapper.extract_heap_base().unwrap();
+	let call = |instance_wrapper: &mut InstanceWrapper, name: &str| {
+		instance_wrapper
+			.set_host_state(Some(HostState::new(FreeingBumpHeapAllocator::new(heap_base))));
+		let result = instance_wrapper.call(InvokeMethod::Export(name), Pointer::new(0), 0);
+		instance_wrapper.take_host_state();
+		result
+	};
+
+	assert!(call(&mut instance_wrapper, "main").is_ok());
+	assert!(call(&mut instance_wrapper, "missing").is_err());
+	assert!(!instance_wrapper.is_poisoned());
+
+	assert!(call(&mut instance_wrapper, "trap").is_err());
+	assert!(instance_wrapper.is_poisoned());
+
+	instance_wrapper.instantiate(&module).unwrap();
+	assert!(!instance_wrapper.is_poisoned());
+}
+
+#[test]
+fn test_call_export_raw() {
+	// `bump` increments the byte at address 0 and returns it.
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "add") (param i32 i32) (result i32)
+				(i32.add (local.get 0) (local.get 1))
+			)
+			(func (export "bump") (result i32)
+				(i32.store8 (i32.const 0) (i32.add (i32.load8_u (i32.const 0)) (i32.const 1)))
+				(i32.load8_u (i32.const 0))
+			)
+		)
+		"#;
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+		assert_eq!(
+			instance.call_export_raw("add", &[Value::I32(40), Value::I32(2)]).unwrap(),
+			vec![Value::I32(42)],
+		);
+
+		// Every call starts from the initial state, the same as with the Substrate ABI.
+		for _ in 0..2 {
+			assert_eq!(instance.call_export_raw("bump", &[]).unwrap(), vec![Value::I32(1)]);
+		}
+
+		// The Substrate ABI entry point refuses a function with this signature.
+		assert!(instance.call_export("add", &[]).is_err());
+
+		assert!(instance.call_export_raw("missing", &[]).is_err());
+		assert!(instance.call_export_raw("add", &[Value::I32(1)]).is_err());
+	}
+}
+
+#[test]
+fn test_sign_extension_and_saturating_float_to_int() {
+	let semantics = |enable_sign_extension, enable_saturating_float_to_int| crate::Semantics {
+		enable_sign_extension,
+		enable_saturating_float_to_int,
+		..Default::default()
+	};
+	let sign_extension = wat::parse_str(
+		r#"
+		(module
+			(memory (export "memory") 1)
+			(func (export "extend8_s") (param i32) (result i32)
+				(i32.extend8_s (local.get 0))
+			)
+			(func (export "extend32_s") (param i64) (result i64)
+				(i64.extend32_s (local.get 0))
+			)
+		)
+		"#,
+	)
+	.unwrap();
+	let saturating_float_to_int = wat::parse_str(
+		r#"
+		(module
+			(memory (export "memory") 1)
+			(func (export "trunc_sat_f32_s_overflow") (result i32)
+				(i32.trunc_sat_f32_s (f32.const 1e10))
+			)
+			(func (export "trunc_sat_f32_s_nan") (result i32)
+				(i32.trunc_sat_f32_s (f32.const nan))
+			)
+			(func (export "trunc_sat_f64_u_negative") (result i64)
+				(i64.trunc_sat_f64_u (f64.const -1.5))
+			)
+		)
+		"#,
+	)
+	.unwrap();
+	let module = |semantics: &crate::Semantics, wasm: &[u8]| {
+		wasmedge_sdk::Module::from_bytes(
+			Some(&crate::runtime::common_config(semantics).unwrap()),
+			wasm,
+		)
+	};
+
+	let semantics_enabled = semantics(true, true);
+	let mut instance_wrapper = InstanceWrapper::new(&semantics_enabled).unwrap();
+	instance_wrapper
+		.instantiate(&module(&semantics_enabled, &sign_extension).unwrap())
+		.unwrap();
+	assert_eq!(
+		instance_wrapper.call_export_raw("extend8_s", &[Value::I32(0x80)]).unwrap(),
+		vec![Value::I32(-128)],
+	);
+	assert_eq!(
+		instance_wrapper
+			.call_export_raw("extend32_s", &[Value::I64(0xffff_ffff)])
+			.unwrap(),
+		vec![Value::I64(-1)],
+	);
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics_enabled).unwrap();
+	instance_wrapper
+		.instantiate(&module(&semantics_enabled, &saturating_float_to_int).unwrap())
+		.unwrap();
+	assert_eq!(
+		instance_wrapper.call_export_raw("trunc_sat_f32_s_overflow", &[]).unwrap(),
+		vec![Value::I32(i32::MAX)],
+	);
+	assert_eq!(
+		instance_wrapper.call_export_raw("trunc_sat_f32_s_nan", &[]).unwrap(),
+		vec![Value::I32(0)],
+	);
+	assert_eq!(
+		instance_wrapper.call_export_raw("trunc_sat_f64_u_negative", &[]).unwrap(),
+		vec![Value::I64(0)],
+	);
+
+	// The sign-extension operators also make it through `RuntimeBlob`.
+	RuntimeBlob::new(&sign_extension).unwrap();
+
+	// Each proposal is rejected when it's disabled, regardless of the library's defaults.
+	assert!(module(&semantics(false, true), &sign_extension).is_err());
+	assert!(module(&semantics(false, true), &saturating_float_to_int).is_ok());
+	assert!(module(&semantics(true, false), &saturating_float_to_int).is_err());
+	assert!(module(&semantics(true, false), &sign_extension).is_ok());
+}
+
+#[test]
+fn test_runtimes_share_compiled_module() {
+	use codec::Encode;
+
+	let semantics = crate::Semantics {
+		fast_instance_reuse: true,
+		extra_heap_pages: 1024,
+		..Default::default()
+	};
+	let config = |semantics: crate::Semantics| crate::Config {
+		allow_missing_func_imports: true,
+		semantics,
+		..Default::default()
+	};
+
+	let blob = RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+	let module = crate::compile_module(blob, &semantics).unwrap();
+
+	let runtime_a = crate::WasmEdgeRuntime::from_compiled_module::<HostFunctions>(
+		&module,
+		config(semantics.clone()),
+	)
+	.unwrap();
+	let runtime_b = crate::WasmEdgeRuntime::from_compiled_module::<HostFunctions>(
+		&module,
+		config(semantics.clone()),
+	)
+	.unwrap();
+	drop(module);
+
+	let mut instance_a = runtime_a.new_instance().unwrap();
+	let mut instance_b = runtime_b.new_instance().unwrap();
+
+	for input in [&b"first"[..], b"second"] {
+		let expected = sp_core_hashing::blake2_256(input).to_vec().encode();
+		assert_eq!(
+			instance_a.call_export("test_blake2_256", &input.to_vec().encode()).unwrap(),
+			expected,
+		);
+		assert_eq!(
+			instance_b.call_export("test_blake2_256", &input.to_vec().encode()).unwrap(),
+			expected,
+		);
+	}
+
+	// The runtimes keep working after one of them is gone.
+	drop(instance_a);
+	drop(runtime_a);
+	instance_b.call_export("test_empty_return", &[0]).unwrap();
+
+	// The semantics must match the ones the module was compiled with.
+	let module = crate::compile_module(
+		RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap(),
+		&semantics,
+	)
+	.unwrap();
+	let other_semantics = crate::Semantics { fast_instance_reuse: false, ..semantics };
+	assert!(crate::WasmEdgeRuntime::from_compiled_module::<HostFunctions>(
+		&module,
+		config(other_semantics)
+	)
+	.is_err());
+}
+
+#[test]
+fn test_instance_reuse_equivalence() {
+	use codec::Encode;
+
+	let reused = RuntimeBuilder::new_on_demand().fast_instance_reuse(true).build();
+	let recreated = RuntimeBuilder::new_on_demand().build();
+
+	let inputs = [vec![], b"first".to_vec(), vec![0; 10_000], b"second".to_vec()]
+		.iter()
+		.map(|input| input.encode())
+		.collect::<Vec<_>>();
+	let inputs = inputs.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+	for method in ["test_blake2_256", "test_conditional_panic"] {
+		assert_runtimes_equivalent(&*reused, &*recreated, method, &inputs);
+	}
+	assert_runtimes_equivalent(
+		&*reused,
+		&*recreated,
+		"test_allocate_vec",
+		&[&16u32.encode()[..], &1_000_000u32.encode(), &16u32.encode()],
+	);
+}
+
+#[test]
+fn test_last_instantiate_duration() {
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.fast_instance_reuse(fast_instance_reuse)
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+		// The instance is only instantiated up front with the fast instance reuse.
+		assert_eq!(instance.last_instantiate_duration().is_some(), fast_instance_reuse);
+
+		instance.call_export("test_empty_return", &[]).unwrap();
+		let duration = instance.last_instantiate_duration().unwrap();
+		assert!(duration > std::time::Duration::ZERO);
+	}
+}
+
+#[test]
+fn test_void_export() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "maintain") (param i32 i32))
+			(func (export "invalid") (param i32 i32) (result i32)
+				(i32.const 0)
+			)
+		)
+		"#;
+
+	let mut instance = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.build()
+		.new_instance()
+		.unwrap();
+
+	assert_eq!(instance.call_export("maintain", &[1, 2, 3]).unwrap(), Vec::<u8>::new());
+	assert_eq!(instance.call_export("maintain", &[]).unwrap(), Vec::<u8>::new());
+
+	match instance.call_export("invalid", &[]).unwrap_err() {
+		Error::Other(message) => assert_eq!(message, "Invalid signature for direct entry point"),
+		error => panic!("unexpected error: {:?}", error),
+	}
+}
+
+#[test]
+fn test_parse_backtrace() {
+	use crate::{parse_backtrace, Frame};
+
+	let backtrace = "\
+		[error] execution failed: unreachable, Code: 0x89\n\
+		[error]     In instruction: unreachable (0x00) , Bytecode offset: 0x0000286a\n\
+		[error]     When executing function index: 42\n\
+		[error]     In instruction: call (0x10) , Bytecode offset: 0x00001000\n\
+		[error]     When executing function index: 7\n\
+		[error]     When executing function index: 3";
+	assert_eq!(
+		parse_backtrace(backtrace),
+		vec![
+			Frame { func_index: Some(42), offset: Some(0x286a) },
+			Frame { func_index: Some(7), offset: Some(0x1000) },
+			Frame { func_index: Some(3), offset: None },
+		]
+	);
+
+	assert!(parse_backtrace("unreachable").is_empty());
+	assert!(parse_backtrace("").is_empty());
+}
+
+#[test]
+fn test_last_backtrace_frames() {
+	let runtime = RuntimeBuilder::new_on_demand().fast_instance_reuse(true).build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let error = instance.call_export("test_unreachable_intrinsic", &[]).unwrap_err();
+	let backtrace = match error {
+		Error::AbortedDueToTrap(error) => error.backtrace.unwrap().to_string(),
+		error => panic!("unexpected error: {:?}", error),
+	};
+	// The frames are whatever the backtrace of the trap contains in the parsed format.
+	assert_eq!(instance.last_backtrace_frames(), crate::parse_backtrace(&backtrace).as_slice());
+	// The backtrace is taken from WasmEdge's logs, which are only forwarded on unix.
+	if cfg!(unix) {
+		let frames = instance.last_backtrace_frames();
+		assert!(frames.iter().any(|frame| frame.offset.is_some()), "{:?}", frames);
+	}
+
+	instance.call_export("test_empty_return", &[]).unwrap();
+	assert!(instance.last_backtrace_frames().is_empty());
+}
+
+#[test]
+fn test_entry_point_result_arity() {
+	use crate::instance_wrapper::entry_point_result;
+	use wasmedge_sdk::WasmValue;
+
+	let runtime = RuntimeBuilder::new_on_demand().build();
+	let mut instance = runtime.new_instance().unwrap();
+	assert_eq!(instance.call_export("test_empty_return", &[0]).unwrap(), Vec::<u8>::new());
+
+	assert_eq!(entry_point_result(&[WasmValue::from_i64(42)]).unwrap(), 42);
+	assert!(entry_point_result(&[]).is_err());
+	assert!(entry_point_result(&[WasmValue::from_i32(42)]).is_err());
+	assert!(entry_point_result(&[WasmValue::from_i64(1), WasmValue::from_i64(2)]).is_err());
+}
+
+#[test]
+fn test_zero_memory_on_first_use() {
+	// The start function leaves a byte behind, outside of the data segment.
+	let wat = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 4096))
+			(data (i32.const 1024) "data")
+			(func $init
+				(i32.store8 (i32.const 2048) (i32.const 171))
+			)
+			(start $init)
+			;; Returns the byte found at 2048, copied to 2047.
+			(func (export "read") (param i32 i32) (result i64)
+				(i32.store8 (i32.const 2047) (i32.load8_u (i32.const 2048)))
+				(i64.or
+					(i64.const 2047)
+					(i64.shl (i64.const 1) (i64.const 32))
+				)
+			)
+		)
+	"#;
+
+	for zero_memory_on_first_use in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(wat.to_string())
+			.fast_instance_reuse(true)
+			.zero_memory_on_first_use(zero_memory_on_first_use)
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+		let memory = unsafe {
+			std::slice::from_raw_parts(instance.linear_memory_base_ptr().unwrap(), 64 * 1024)
+		};
+		let leftover = if zero_memory_on_first_use { 0 } else { 171 };
+		assert_eq!(memory[2048], leftover);
+
+		assert_eq!(instance.call_export("read", &[]).unwrap(), vec![leftover]);
+
+		// The following calls never observe the leftover.
+		assert_eq!(instance.call_export("read", &[]).unwrap(), vec![0]);
+	}
+}
+
+#[test]
+fn test_try_from_wasmedge_value() {
+	use crate::util::{try_from_wasmedge_val, try_from_wasmedge_value};
+	use wasmedge_sdk::{types::Val, WasmValue};
+
+	assert_eq!(try_from_wasmedge_val(Val::I64(-1)).unwrap(), Value::I64(-1));
+	assert_eq!(try_from_wasmedge_value(WasmValue::from_i32(7)).unwrap(), Value::I32(7));
+
+	let error = try_from_wasmedge_val(Val::ExternRef(None)).unwrap_err();
+	assert!(error.to_string().contains("unsupported by Substrate"), "{}", error);
+	assert!(try_from_wasmedge_val(Val::V128(0)).is_err());
+
+	let error = try_from_wasmedge_value(WasmValue::from_v128(0)).unwrap_err();
+	assert!(error.to_string().contains("unsupported by Substrate"), "{}", error);
+}
+
+#[test]
+fn test_host_function_names() {
+	let runtime = RuntimeBuilder::new_on_demand().build_runtime();
+
+	assert_eq!(runtime.host_function_count(), HostFunctions::host_functions().len());
+
+	let names = runtime.host_function_names();
+	assert_eq!(names.len(), runtime.host_function_count());
+	assert!(names.contains(&"ext_allocator_malloc_version_1"));
+	assert!(names.contains(&"ext_storage_get_version_1"));
+}
+
+#[cfg(not(feature = "benchmarking"))]
+#[test]
+fn test_benchmark_unchecked_requires_feature() {
+	let wat = r#"
+		(module
+			(memory (export "memory") 2)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			;; Leaves a byte behind, outside of the allocator's heap.
+			(func (export "write") (param i32 i32) (result i64)
+				(i32.store8 (i32.const 70000) (i32.const 171))
+				(i64.const 0)
+			)
+			;; Returns the byte left behind by `write`, copied to 69999.
+			(func (export "read") (param i32 i32) (result i64)
+				(i32.store8 (i32.const 69999) (i32.load8_u (i32.const 70000)))
+				(i64.or
+					(i64.const 69999)
+					(i64.shl (i64.const 1) (i64.const 32))
+				)
+			)
+		)
+	"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(wat.to_string())
+		.fast_instance_reuse(true)
+		.benchmark_unchecked(true)
+		.build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	// The memory is still decommitted after every call.
+	instance.call_export("write", &[]).unwrap();
+	assert_eq!(instance.call_export("read", &[]).unwrap(), vec![0]);
+}
+
+#[test]
+fn test_preload_memory() {
+	let wat = r#"
+		(module
+			(memory (export "memory") 2)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			;; Returns the 4 bytes at 70000.
+			(func (export "read") (param i32 i32) (result i64)
+				(i64.or
+					(i64.const 70000)
+					(i64.shl (i64.const 4) (i64.const 32))
+				)
+			)
+		)
+	"#;
+
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(wat.to_string())
+			.fast_instance_reuse(fast_instance_reuse)
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+		assert_eq!(instance.call_export("read", &[]).unwrap(), vec![0; 4]);
+
+		instance.preload_memory(70000, b"seed").unwrap();
+		assert!(instance.preload_memory(2 * 64 * 1024 - 2, b"seed").is_err());
+
+		// The data is there for every call.
+		assert_eq!(instance.call_export("read", &[]).unwrap(), b"seed");
+		assert_eq!(instance.call_export("read", &[]).unwrap(), b"seed");
+	}
+}
+
+#[test]
+fn test_verify_substrate_abi() {
+	RuntimeBuilder::new_on_demand().build_runtime().verify_substrate_abi().unwrap();
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(
+			r#"
+			(module
+				(memory 1)
+				(func (export "main") (param i32 i32) (result i64)
+					(i64.const 0)
+				)
+			)
+			"#
+			.to_string(),
+		)
+		.build_runtime();
+	let error = runtime.verify_substrate_abi().unwrap_err().to_string();
+	assert!(error.contains("`Core_version` function is not exported"), "{}", error);
+	assert!(error.contains("`__heap_base` global is not exported"), "{}", error);
+	assert!(error.contains("`memory` is not exported"), "{}", error);
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(
+			r#"
+			(module
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i64 (i64.const 1024))
+				(func (export "Core_version") (param i32) (result i64)
+					(i64.const 0)
+				)
+			)
+			"#
+			.to_string(),
+		)
+		.build_runtime();
+	let error = runtime.verify_substrate_abi().unwrap_err().to_string();
+	assert!(error.contains("`Core_version` doesn't have the signature"), "{}", error);
+	assert!(error.contains("`__heap_base` is not an i32 global"), "{}", error);
+	assert!(!error.contains("`memory`"), "{}", error);
+}
+
+#[test]
+fn test_memory_export_of_wrong_kind() {
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(
+			r#"
+			(module
+				(global (export "memory") i32 (i32.const 0))
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(func (export "main") (param i32 i32) (result i64)
+					(i64.const 0)
+				)
+			)
+			"#
+			.to_string(),
+		)
+		.build();
+
+	let error = runtime.new_instance().err().unwrap().to_string();
+	assert!(
+		error.contains("export 'memory' exists but is a global, expected a linear memory"),
+		"{}",
+		error
+	);
+}
+
+#[test]
+fn test_artifact_cache() {
+	let semantics = crate::Semantics::default();
+	// Modules which differ only in the returned value.
+	let blob = |value: u32| {
+		let wat = format!(
+			r#"
+			(module
+				(memory (export "memory") 1)
+				(global (export "__heap_base") i32 (i32.const 1024))
+				(data (i32.const 0) "{}")
+				(func (export "main") (param i32 i32) (result i64)
+					(i64.shl (i64.const 1) (i64.const 32))
+				)
+			)
+			"#,
+			char::from_digit(value, 10).unwrap(),
+		);
+		RuntimeBlob::uncompress_if_needed(&wat::parse_str(wat).unwrap()).unwrap()
+	};
+	let stats = |hits, misses, evictions| crate::ArtifactCacheStats { hits, misses, evictions };
+
+	let cache = crate::ArtifactCache::new(2);
+	assert!(cache.is_empty());
+
+	cache.get_or_compile(blob(1), &semantics).unwrap();
+	assert_eq!(cache.stats(), stats(0, 1, 0));
+	cache.get_or_compile(blob(1), &semantics).unwrap();
+	assert_eq!(cache.stats(), stats(1, 1, 0));
+
+	// The same code compiled with different semantics is a different module.
+	cache
+		.get_or_compile(blob(1), &crate::Semantics { extra_heap_pages: 1, ..semantics.clone() })
+		.unwrap();
+	assert_eq!(cache.stats(), stats(1, 2, 0));
+	assert_eq!(cache.len(), 2);
+
+	// Makes `1` the most recently used module, so the other one is evicted.
+	cache.get_or_compile(blob(1), &semantics).unwrap();
+	cache.get_or_compile(blob(2), &semantics).unwrap();
+	assert_eq!(cache.stats(), stats(2, 3, 1));
+	assert_eq!(cache.len(), 2);
+
+	cache.get_or_compile(blob(1), &semantics).unwrap();
+	cache.get_or_compile(blob(2), &semantics).unwrap();
+	assert_eq!(cache.stats(), stats(4, 3, 1));
+
+	// The cached module is usable.
+	let module = cache.get_or_compile(blob(2), &semantics).unwrap();
+	let config =
+		crate::Config { allow_missing_func_imports: true, semantics, ..Default::default() };
+	let runtime =
+		crate::WasmEdgeRuntime::from_compiled_module::<HostFunctions>(&module, config).unwrap();
+	assert_eq!(runtime.new_instance().unwrap().call_export("main", &[]).unwrap(), b"2");
+}
+
+#[test]
+fn test_sandbox_executor_reuse() {
+	use crate::host::SANDBOX_EXECUTORS_CREATED;
+	use codec::Encode;
+
+	let runtime = RuntimeBuilder::new_on_demand().build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	// Calls a supervisor function many times in a row.
+	let code = wat::parse_str(
+		r#"
+		(module
+			(import "env" "inc_counter" (func $inc_counter (param i32) (result i32)))
+			(func (export "call")
+				(local $i i32)
+				(block $done
+					(loop $loop
+						(br_if $done (i32.ge_u (local.get $i) (i32.const 100)))
+						(drop (call $inc_counter (i32.const 1)))
+						(local.set $i (i32.add (local.get $i) (i32.const 1)))
+						(br $loop)
+					)
+				)
+			)
+		)
+		"#,
+	)
+	.unwrap();
+
+	let created_before = SANDBOX_EXECUTORS_CREATED.with(|created| created.get());
+	let result = instance.call_export("test_sandbox_host", &code.encode()).unwrap();
+	assert_eq!(result, true.encode());
+	assert_eq!(instance.last_sandbox_error(), None);
+
+	// The calls are made one after another, so a single executor serves all of them.
+	let created = SANDBOX_EXECUTORS_CREATED.with(|created| created.get()) - created_before;
+	assert_eq!(created, 1);
+}
+
+#[test]
+fn test_sandbox_memory_transfer() {
+	use sp_sandbox::env::{ERR_OK, ERR_OUT_OF_BOUNDS};
+
+	// Copies the input into a sandboxed memory of two pages and back, followed by transfers at
+	// the bounds of both memories. Returns the result codes of the transfers followed by the
+	// data copied back.
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_sandbox_memory_new_version_1"
+				(func $memory_new (param i32 i32) (result i32)))
+			(import "env" "ext_sandbox_memory_get_version_1"
+				(func $memory_get (param i32 i32 i32 i32) (result i32)))
+			(import "env" "ext_sandbox_memory_set_version_1"
+				(func $memory_set (param i32 i32 i32 i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param $ptr i32) (param $len i32) (result i64)
+				(local $memory i32)
+				(local $out i32)
+				(local.set $memory (call $memory_new (i32.const 2) (i32.const 2)))
+				(local.set $out (i32.add (local.get $ptr) (local.get $len)))
+
+				(i32.store offset=0 (local.get $out) (call $memory_set
+					(local.get $memory) (i32.const 1000) (local.get $ptr) (local.get $len)))
+				(i32.store offset=4 (local.get $out) (call $memory_get
+					(local.get $memory)
+					(i32.const 1000)
+					(i32.add (local.get $out) (i32.const 24))
+					(local.get $len)
+				))
+
+				;; Right up to the end of the sandboxed memory, and one byte past it.
+				(i32.store offset=8 (local.get $out) (call $memory_set
+					(local.get $memory) (i32.const 131070) (local.get $ptr) (i32.const 2)))
+				(i32.store offset=12 (local.get $out) (call $memory_set
+					(local.get $memory) (i32.const 131071) (local.get $ptr) (i32.const 2)))
+				(i32.store offset=16 (local.get $out) (call $memory_get
+					(local.get $memory) (i32.const 131071) (local.get $out) (i32.const 2)))
+
+				;; One byte past the end of our memory.
+				(i32.store offset=20 (local.get $out) (call $memory_get
+					(local.get $memory)
+					(i32.const 0)
+					(i32.sub (i32.mul (memory.size) (i32.const 65536)) (i32.const 1))
+					(i32.const 2)
+				))
+
+				(i64.or
+					(i64.extend_i32_u (local.get $out))
+					(i64.shl
+						(i64.extend_i32_u (i32.add (local.get $len) (i32.const 24)))
+						(i64.const 32)
+					)
+				)
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	let input = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+	let output = instance.call_export("main", &input).unwrap();
+
+	let codes = output[..24]
+		.chunks(4)
+		.map(|code| u32::from_le_bytes(code.try_into().unwrap()))
+		.collect::<Vec<_>>();
+	assert_eq!(
+		codes,
+		vec![ERR_OK, ERR_OK, ERR_OK, ERR_OUT_OF_BOUNDS, ERR_OUT_OF_BOUNDS, ERR_OUT_OF_BOUNDS]
+	);
+	assert!(output[24..] == input[..], "the data didn't survive the round trip");
+}
+
+#[test]
+fn test_sandbox_store_capacity() {
+	use sp_sandbox::env::ERR_OK;
+
+	// Creates many sandboxed memories, then copies the input into the last of them and back.
+	// Returns the number of memories which got the expected index, followed by the result codes
+	// of the transfers and the data copied back.
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_sandbox_memory_new_version_1"
+				(func $memory_new (param i32 i32) (result i32)))
+			(import "env" "ext_sandbox_memory_get_version_1"
+				(func $memory_get (param i32 i32 i32 i32) (result i32)))
+			(import "env" "ext_sandbox_memory_set_version_1"
+				(func $memory_set (param i32 i32 i32 i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param $ptr i32) (param $len i32) (result i64)
+				(local $i i32)
+				(local $out i32)
+				(local.set $out (i32.add (local.get $ptr) (local.get $len)))
+				(block $done
+					(loop $loop
+						(br_if $done (i32.ge_u (local.get $i) (i32.const 64)))
+						(br_if $done (i32.ne
+							(call $memory_new (i32.const 1) (i32.const 1))
+							(local.get $i)
+						))
+						(local.set $i (i32.add (local.get $i) (i32.const 1)))
+						(br $loop)
+					)
+				)
+
+				(i32.store offset=0 (local.get $out) (local.get $i))
+				(i32.store offset=4 (local.get $out) (call $memory_set
+					(i32.const 63) (i32.const 100) (local.get $ptr) (local.get $len)))
+				(i32.store offset=8 (local.get $out) (call $memory_get
+					(i32.const 63)
+					(i32.const 100)
+					(i32.add (local.get $out) (i32.const 12))
+					(local.get $len)
+				))
+
+				(i64.or
+					(i64.extend_i32_u (local.get $out))
+					(i64.shl
+						(i64.extend_i32_u (i32.add (local.get $len) (i32.const 12)))
+						(i64.const 32)
+					)
+				)
+			)
+		)
+		"#;
+
+	let input = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+	// Both fewer and more memories than are created by the call.
+	for capacity in [None, Some(16), Some(128)] {
+		let mut builder = RuntimeBuilder::new_on_demand();
+		builder.use_wat(WAT.to_string());
+		if let Some(capacity) = capacity {
+			builder.sandbox_store_capacity(capacity);
+		}
+		let mut instance = builder.build().new_instance().unwrap();
+
+		let output = instance.call_export("main", &input).unwrap();
+		let values = output[..12]
+			.chunks(4)
+			.map(|value| u32::from_le_bytes(value.try_into().unwrap()))
+			.collect::<Vec<_>>();
+		assert_eq!(values, vec![64, ERR_OK, ERR_OK], "capacity {:?}", capacity);
+		assert!(output[12..] == input[..], "the data didn't survive the round trip");
+	}
+}
+
+#[test]
+fn test_max_sandbox_memories() {
+	use sp_sandbox::env::ERR_MODULE;
+
+	// Creates four memories, tears down the first one and creates another one. Returns the
+	// results of the creations.
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_sandbox_memory_new_version_1"
+				(func $memory_new (param i32 i32) (result i32)))
+			(import "env" "ext_sandbox_memory_teardown_version_1"
+				(func $memory_teardown (param i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i32.store offset=0 (i32.const 0) (call $memory_new (i32.const 1) (i32.const 1)))
+				(i32.store offset=4 (i32.const 0) (call $memory_new (i32.const 1) (i32.const 1)))
+				(i32.store offset=8 (i32.const 0) (call $memory_new (i32.const 1) (i32.const 1)))
+				(i32.store offset=12 (i32.const 0) (call $memory_new (i32.const 1) (i32.const 1)))
+				(call $memory_teardown (i32.const 0))
+				(i32.store offset=16 (i32.const 0) (call $memory_new (i32.const 1) (i32.const 1)))
+				(i64.const 0x1400000000)
+			)
+		)
+		"#;
+
+	let run = |max_sandbox_memories: Option<u32>| {
+		let mut builder = RuntimeBuilder::new_on_demand();
+		builder.use_wat(WAT.to_string());
+		if let Some(max_sandbox_memories) = max_sandbox_memories {
+			builder.max_sandbox_memories(max_sandbox_memories);
+		}
+		let mut instance = builder.build_runtime().new_wasmedge_instance().unwrap();
+		let output = instance.call_export("main", &[]).unwrap();
+		output
+			.chunks(4)
+			.map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+			.collect::<Vec<_>>()
+	};
+
+	assert_eq!(run(None), vec![0, 1, 2, 3, 4]);
+	// Tearing down a memory makes room for another one.
+	assert_eq!(run(Some(3)), vec![0, 1, 2, ERR_MODULE, 3]);
+}
+
+#[test]
+fn test_max_sandbox_depth() {
+	use codec::Encode;
+	use sp_sandbox::env::{Entry, EnvironmentDefinition, ExternEntity, ERR_EXECUTION};
+
+	// Calls the supervisor function, which invokes the sandboxed instance again.
+	let guest = wat::parse_str(
+		r#"
+		(module
+			(import "env" "recurse" (func $recurse))
+			(func (export "call") (call $recurse))
+		)
+		"#,
+	)
+	.unwrap();
+	let env_def = EnvironmentDefinition {
+		entries: vec![Entry {
+			module_name: b"env".to_vec(),
+			field_name: b"recurse".to_vec(),
+			entity: ExternEntity::Function(0),
+		}],
+	}
+	.encode();
+	let data = |bytes: &[u8]| bytes.iter().map(|b| format!("\\{:02x}", b)).collect::<String>();
+	let ptr_and_len = |ptr: u64, len: usize| (len as u64) << 32 | ptr;
+
+	// Returns the first error code returned by an invocation of the sandboxed instance, followed by
+	// the number of times the supervisor function was called.
+	let wat = format!(
+		r#"
+		(module
+			(import "env" "ext_sandbox_instantiate_version_1"
+				(func $instantiate (param i32 i64 i64 i32) (result i32)))
+			(import "env" "ext_sandbox_invoke_version_1"
+				(func $invoke (param i32 i64 i64 i32 i32 i32) (result i32)))
+			(import "env" "ext_allocator_malloc_version_1" (func $malloc (param i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(global $instance (mut i32) (i32.const 0))
+			(table (export "__indirect_function_table") 1 funcref)
+			(elem (i32.const 0) $dispatch_thunk)
+			(data (i32.const 16) "call")
+			(data (i32.const 20) "\00")
+			(data (i32.const 64) "{env_def}")
+			(data (i32.const 256) "{guest}")
+
+			(func $invoke_guest
+				(local $result i32)
+				(local.set $result (call $invoke
+					(global.get $instance)
+					(i64.const {function})
+					(i64.const {args})
+					(i32.const 32)
+					(i32.const 16)
+					(i32.const 0)
+				))
+				(if (i32.and
+						(i32.ne (local.get $result) (i32.const 0))
+						(i32.eqz (i32.load (i32.const 0)))
+					)
+					(then (i32.store (i32.const 0) (local.get $result)))
+				)
+			)
+
+			(func $dispatch_thunk (param i32 i32 i32 i32) (result i64)
+				(local $ptr i32)
+				(i32.store (i32.const 4) (i32.add (i32.load (i32.const 4)) (i32.const 1)))
+				(call $invoke_guest)
+
+				;; The encoded `Ok(ReturnValue::Unit)`, with the pointer in the upper half.
+				(local.set $ptr (call $malloc (i32.const 2)))
+				(i32.store16 (local.get $ptr) (i32.const 0))
+				(i64.or (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32)) (i64.const 2))
+			)
+
+			(func (export "main") (param i32 i32) (result i64)
+				(global.set $instance (call $instantiate
+					(i32.const 0)
+					(i64.const {guest_code})
+					(i64.const {env})
+					(i32.const 0)
+				))
+				(call $invoke_guest)
+				(i64.const {output})
+			)
+		)
+		"#,
+		env_def = data(&env_def),
+		guest = data(&guest),
+		function = ptr_and_len(16, 4),
+		args = ptr_and_len(20, 1),
+		guest_code = ptr_and_len(256, guest.len()),
+		env = ptr_and_len(64, env_def.len()),
+		output = ptr_and_len(0, 8),
+	);
+
+	for max_sandbox_depth in [1, 4] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(wat.clone())
+			.max_sandbox_depth(max_sandbox_depth)
+			.build_runtime();
+		let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+		let output = instance.call_export("main", &[]).unwrap();
+		assert_eq!(u32::from_le_bytes(output[..4].try_into().unwrap()), ERR_EXECUTION);
+		assert_eq!(u32::from_le_bytes(output[4..].try_into().unwrap()), max_sandbox_depth);
+		assert_eq!(
+			instance.last_sandbox_error(),
+			Some(&crate::SandboxError::DepthExceeded(max_sandbox_depth))
+		);
+	}
+}
+
+#[test]
+fn test_max_sandbox_args() {
+	use codec::{Compact, Encode};
+	use sp_sandbox::env::ERR_EXECUTION;
+
+	// The arguments claim a billion values, but none follow.
+	let args = Compact(1_000_000_000u32).encode();
+	let wat = format!(
+		r#"
+		(module
+			(import "env" "ext_sandbox_invoke_version_1"
+				(func $invoke (param i32 i64 i64 i32 i32 i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(data (i32.const 16) "call")
+			(data (i32.const 32) "{args}")
+			(func (export "main") (param i32 i32) (result i64)
+				(i32.store (i32.const 0) (call $invoke
+					(i32.const 0)
+					(i64.const {function})
+					(i64.const {args_ptr_and_len})
+					(i32.const 0)
+					(i32.const 0)
+					(i32.const 0)
+				))
+
+				;; ptr = 0, len = 4
+				(i64.const 17179869184)
+			)
+		)
+		"#,
+		args = args.iter().map(|b| format!("\\{:02x}", b)).collect::<String>(),
+		function = 4u64 << 32 | 16,
+		args_ptr_and_len = (args.len() as u64) << 32 | 32,
+	);
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(wat)
+		.max_sandbox_args(16)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let output = instance.call_export("main", &[]).unwrap();
+	assert_eq!(output, ERR_EXECUTION.to_le_bytes());
+	match instance.last_sandbox_error() {
+		Some(crate::SandboxError::Other(message)) =>
+			assert!(message.contains("passes 1000000000 arguments, but at most 16"), "{}", message),
+		error => panic!("unexpected error: {:?}", error),
+	}
+}
+
+#[test]
+fn test_environment() {
+	let wat = r#"
+		(module
+			(import "env" "ext_wasmedge_environment_get_version_1"
+				(func $environment_get (param i64) (result i64)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(data (i32.const 0) "endpoint")
+			(data (i32.const 16) "missing")
+			(data (i32.const 32) "N")
+			;; Returns the value of `endpoint`.
+			(func (export "get") (param i32 i32) (result i64)
+				(call $environment_get
+					(i64.shl (i64.const 8) (i64.const 32))
+				)
+			)
+			;; Returns "N" if `missing` isn't set.
+			(func (export "get_missing") (param i32 i32) (result i64)
+				(if (result i64)
+					(i64.eq
+						(call $environment_get
+							(i64.or (i64.const 16) (i64.shl (i64.const 7) (i64.const 32)))
+						)
+						(i64.const -1)
+					)
+					(then (i64.or (i64.const 32) (i64.shl (i64.const 1) (i64.const 32))))
+					(else (i64.const 0))
+				)
+			)
+		)
+	"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(wat.to_string())
+		.environment("endpoint", "http://localhost:9944")
+		.build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	assert_eq!(instance.call_export("get", &[]).unwrap(), b"http://localhost:9944");
+	assert_eq!(instance.call_export("get_missing", &[]).unwrap(), b"N");
+}
+
+#[test]
+fn test_environment_dropped_with_instance() {
+	let semantics = crate::Semantics { extra_heap_pages: 1024, ..Default::default() };
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(import "env" "ext_wasmedge_environment_get_version_1" (func (param i64) (result i64)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+	let environment =
+		Arc::new(std::collections::HashMap::from([("key".to_string(), "value".to_string())]));
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	crate::imports::prepare_imports(
+		&mut instance_wrapper,
+		&module,
+		&Default::default(),
+		false,
+		false,
+		false,
+		false,
+		None,
+		&environment,
+		None,
+	)
+	.unwrap();
+	assert_eq!(Arc::strong_count(&environment), 2);
+
+	// The data of the host functions doesn't outlive the instance.
+	drop(instance_wrapper);
+	assert_eq!(Arc::strong_count(&environment), 1);
+}
+
+#[test]
+fn test_host_time() {
+	let wat = r#"
+		(module
+			(import "env" "ext_wasmedge_host_time_version_1" (func $host_time (result i64)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.store (i32.const 0) (call $host_time))
+
+				;; ptr = 0, len = 8
+				(i64.const 34359738368)
+			)
+		)
+	"#;
+	let now = || {
+		std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap()
+			.as_millis() as i64
+	};
+
+	// Without the option the import isn't provided by the host.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(wat.to_string()).build_runtime();
+	assert_eq!(
+		runtime.import_report().stubbed,
+		vec!["ext_wasmedge_host_time_version_1".to_string()],
+	);
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(wat.to_string())
+		.provide_host_time(true)
+		.build_runtime();
+	assert!(runtime.import_report().stubbed.is_empty());
+	let mut instance = runtime.new_instance().unwrap();
+
+	let before = now();
+	let output = instance.call_export("main", &[]).unwrap();
+	let after = now();
+	let host_time = i64::from_le_bytes(output.try_into().unwrap());
+	assert!(
+		before <= host_time && host_time <= after,
+		"{} not in {}..={}",
+		host_time,
+		before,
+		after
+	);
+}
+
+#[test]
+fn test_max_stack_depth() {
+	const LIMIT: u32 = 65536;
+
+	// Recurses as many times as the input says.
+	let wat = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func $recurse (param $n i32)
+				(if (local.get $n)
+					(then (call $recurse (i32.sub (local.get $n) (i32.const 1))))
+				)
+			)
+			(func (export "main") (param $ptr i32) (param $len i32) (result i64)
+				(call $recurse (i32.load (local.get $ptr)))
+				(i64.const 0)
+			)
+		)
+	"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(wat.to_string())
+		.deterministic_stack(true)
+		.track_max_stack_depth(true)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+	let mut max_stack_depth = |recursion: u32| {
+		instance
+			.call_export("main", &recursion.to_le_bytes())
+			.map(|_| instance.max_stack_depth().expect("the stack depth is tracked"))
+	};
+
+	let shallow = max_stack_depth(100).unwrap();
+	let deep = max_stack_depth(200).unwrap();
+	assert!(shallow > 0 && deep > shallow);
+	let frame = (deep - shallow) / 100;
+
+	// Recurse so deep that 90% of the limit is used.
+	let recursion = 100 + (LIMIT / 10 * 9 - shallow) / frame;
+	let close = max_stack_depth(recursion).unwrap();
+	assert!(close > LIMIT / 10 * 8 && close <= LIMIT, "{}", close);
+
+	// The depth is reset for every call.
+	assert_eq!(max_stack_depth(100).unwrap(), shallow);
+
+	// Going twice as deep overflows the stack.
+	assert!(max_stack_depth(recursion * 2).is_err());
+
+	// Nothing is tracked if not asked to.
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(wat.to_string())
+		.deterministic_stack(true)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+	instance.call_export("main", &100u32.to_le_bytes()).unwrap();
+	assert_eq!(instance.max_stack_depth(), None);
+}
+
+#[test]
+fn test_host_function_registry() {
+	let host_functions = HostFunctions::host_functions();
+	let registry = crate::imports::host_function_registry(&host_functions);
+
+	assert_eq!(registry.len(), host_functions.len());
+	for host_func in &host_functions {
+		assert_eq!(registry[host_func.name()].name(), host_func.name());
+	}
+	assert!(registry.get("ext_missing_version_1").is_none());
+
+	// The imports are resolved against the registry.
+	let semantics = crate::Semantics::default();
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1" (func (param i32) (result i32)))
+			(import "env" "ext_missing_version_1" (func))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	let error = crate::imports::prepare_imports(
+		&mut instance_wrapper,
+		&module,
+		&registry,
+		false,
+		false,
+		false,
+		false,
+		None,
+		&Default::default(),
+		None,
+	)
+	.unwrap_err()
+	.to_string();
+	assert!(error.contains("'env:ext_missing_version_1'"), "{}", error);
+	assert!(!error.contains("ext_allocator_malloc_version_1"), "{}", error);
+}
+
+#[test]
+fn test_estimated_footprint() {
+	let footprint = RuntimeBuilder::new_on_demand().build_runtime().estimated_footprint();
+	assert!(footprint.code_size > 0);
+	assert_eq!(footprint.max_memory_size, None);
+
+	let footprint = RuntimeBuilder::new_on_demand()
+		.fast_instance_reuse(true)
+		.max_memory_size(Some(128 * 1024 * 1024))
+		.build_runtime()
+		.estimated_footprint();
+	assert!(footprint.code_size > 0);
+	assert_eq!(footprint.max_memory_size, Some(128 * 1024 * 1024));
+	assert!(footprint.data_segments_size > 0);
+
+	let footprint = RuntimeBuilder::new_on_demand()
+		.precompile_runtime(true)
+		.build_runtime()
+		.estimated_footprint();
+	assert!(footprint.code_size > 0);
+}
+
+#[test]
+fn test_call_with_writer() {
+	use codec::Encode;
+
+	let runtime = RuntimeBuilder::new_on_demand().build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let input = vec![0xab_u8; 100_000].encode();
+	let expected = instance.call_export("test_blake2_256", &input).unwrap();
+
+	let output = instance
+		.call_with_writer(InvokeMethod::Export("test_blake2_256"), input.len(), |memory| {
+			vec![0xab_u8; 100_000].encode_to(&mut &mut memory[..])
+		})
+		.unwrap();
+	assert_eq!(output, expected);
+
+	// An empty input works as well.
+	let output = instance
+		.call_with_writer(InvokeMethod::Export("test_empty_return"), 0, |_| ())
+		.unwrap();
+	assert!(output.is_empty());
+}
+
+#[test]
+fn test_record_and_replay_host_calls() {
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			;; Returns the pointer returned by `malloc`.
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(i32.store (i32.const 0) (call $malloc (i32.const 16)))
+				(i64.shl (i64.const 4) (i64.const 32))
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.record_host_calls(true)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let output = instance.call_export("main", &[]).unwrap();
+	let ptr = u32::from_le_bytes(output.clone().try_into().unwrap());
+	let host_calls = instance.host_calls().to_vec();
+	assert_eq!(
+		host_calls,
+		vec![crate::HostCall {
+			name: "ext_allocator_malloc_version_1".to_owned(),
+			args: vec![Value::I32(16)],
+			result: Ok(Some(Value::I32(ptr as i32))),
+			memory_writes: Vec::new(),
+		}],
+	);
+
+	// Replaying the recording gives the same output.
+	let replayed = instance.replay(InvokeMethod::Export("main"), &[], host_calls.clone()).unwrap();
+	assert_eq!(replayed, output);
+
+	// The host function isn't executed, the recorded result is returned instead.
+	let mut tampered = host_calls.clone();
+	tampered[0].result = Ok(Some(Value::I32(1234)));
+	let replayed = instance.replay(InvokeMethod::Export("main"), &[], tampered).unwrap();
+	assert_eq!(replayed, 1234u32.to_le_bytes());
+	assert!(instance.host_calls().is_empty());
+
+	// A call missing from the recording fails.
+	assert!(instance.replay(InvokeMethod::Export("main"), &[], Vec::new()).is_err());
+}
+
+#[test]
+fn test_replay_host_memory_writes() {
+	// Returns the hash of the input, which the host function writes into the memory.
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_hashing_blake2_256_version_1"
+				(func $blake2_256 (param i64) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(i64.or
+					(i64.extend_i32_u
+						(call $blake2_256
+							(i64.or
+								(i64.extend_i32_u (local.get 0))
+								(i64.shl (i64.extend_i32_u (local.get 1)) (i64.const 32))
+							)
+						)
+					)
+					(i64.shl (i64.const 32) (i64.const 32))
+				)
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.record_host_calls(true)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let output = instance.call_export("main", b"data").unwrap();
+	assert_eq!(output, sp_core_hashing::blake2_256(b"data"));
+	let host_calls = instance.host_calls().to_vec();
+	assert_eq!(host_calls.len(), 1);
+	assert_eq!(host_calls[0].memory_writes.len(), 1);
+	assert_eq!(host_calls[0].memory_writes[0].1, output);
+
+	// The hash is written into the memory by the replay, even though it isn't computed.
+	let replayed = instance
+		.replay(InvokeMethod::Export("main"), b"data", host_calls.clone())
+		.unwrap();
+	assert_eq!(replayed, output);
+
+	let mut tampered = host_calls;
+	tampered[0].memory_writes[0].1 = vec![0xff; 32];
+	let replayed = instance.replay(InvokeMethod::Export("main"), b"data", tampered).unwrap();
+	assert_eq!(replayed, vec![0xff; 32]);
+}
+
+#[test]
+fn test_trap_policy() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "div_by_zero") (param i32 i32) (result i64)
+				(drop (i32.div_u (i32.const 1) (i32.const 0)))
+				(i64.const 0)
+			)
+			(func (export "overflow") (param i32 i32) (result i64)
+				(drop (i32.div_s (i32.const 0x80000000) (i32.const -1)))
+				(i64.const 0)
+			)
+			(func (export "unreachable") (param i32 i32) (result i64)
+				unreachable
+			)
+		)
+		"#;
+
+	let mut instance = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.trap_policy(crate::TrapPolicy { division_by_zero: true, integer_overflow: true })
+		.build()
+		.new_instance()
+		.unwrap();
+
+	assert!(matches!(
+		instance.call_export("div_by_zero", &[]),
+		Err(Error::AbortedDueToDivisionByZero(_))
+	));
+	assert!(matches!(
+		instance.call_export("overflow", &[]),
+		Err(Error::AbortedDueToIntegerOverflow(_))
+	));
+	assert!(matches!(instance.call_export("unreachable", &[]), Err(Error::AbortedDueToTrap(_))));
+
+	// By default all of them are generic traps.
+	let mut instance = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.build()
+		.new_instance()
+		.unwrap();
+	for method in ["div_by_zero", "overflow", "unreachable"] {
+		assert!(matches!(instance.call_export(method, &[]), Err(Error::AbortedDueToTrap(_))));
+	}
+}
+
+#[test]
+fn test_blob_transform() {
+	use parity_wasm::elements::{ExportEntry, Internal};
+
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.blob_transform(Arc::new(|blob: RuntimeBlob| {
+			// Export `main` once more under another name.
+			let mut module = blob.into_inner();
+			module
+				.export_section_mut()
+				.unwrap()
+				.entries_mut()
+				.push(ExportEntry::new("custom".into(), Internal::Function(0)));
+			RuntimeBlob::new(&parity_wasm::serialize(module).unwrap())
+		}))
+		.build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	assert_eq!(instance.call_export("custom", &[]).unwrap(), Vec::<u8>::new());
+
+	// An error of the transform fails the creation of the runtime.
+	let blob = RuntimeBlob::uncompress_if_needed(&wat::parse_str(WAT).unwrap()).unwrap();
+	let config = crate::Config {
+		allow_missing_func_imports: true,
+		blob_transform: Some(Arc::new(|_: RuntimeBlob| {
+			Err(sc_executor_common::error::WasmError::Other("nope".into()))
+		})),
+		..Default::default()
+	};
+	let error = crate::create_runtime::<HostFunctions>(blob, config).err().unwrap();
+	assert_eq!(error.to_string(), "nope");
+}
+
+#[test]
+fn test_instance_memory_accounting() {
+	for fast_instance_reuse in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.fast_instance_reuse(fast_instance_reuse)
+			.build_runtime();
+		assert_eq!(runtime.instance_memory(), 0);
+
+		let mut instances =
+			(0..3).map(|_| runtime.new_wasmedge_instance().unwrap()).collect::<Vec<_>>();
+		for instance in &mut instances {
+			instance.call_export("test_empty_return", &[0]).unwrap();
+		}
+
+		// Every instance accounts at least its extra heap pages.
+		let instance_memory = runtime.instance_memory();
+		assert!(instance_memory >= 3 * 1024 * 64 * 1024, "{}", instance_memory);
+		// Other tests running concurrently only ever add to the total.
+		assert!(crate::total_instance_memory() >= instance_memory);
+
+		drop(instances);
+		assert_eq!(runtime.instance_memory(), 0);
+	}
+}
+
+#[test]
+fn test_decommit_stats() {
+	use crate::instance_wrapper::FORCE_DECOMMIT_FALLBACK;
+
+	let runtime = RuntimeBuilder::new_on_demand().decommit_after_call(true).build();
+	let mut instance = runtime.new_instance().unwrap();
+
+	// Other tests running concurrently only ever add to the counters.
+	let before = crate::decommit_stats();
+	instance.call_export("test_empty_return", &[0]).unwrap();
+	let after = crate::decommit_stats();
+	if cfg!(any(target_os = "linux", target_os = "macos")) {
+		assert!(after.fast > before.fast, "{:?} {:?}", before, after);
+	} else {
+		assert!(after.fallback > before.fallback, "{:?} {:?}", before, after);
+	}
+
+	FORCE_DECOMMIT_FALLBACK.with(|force| force.set(true));
+	let before = crate::decommit_stats();
+	let result = instance.call_export("test_empty_return", &[0]);
+	let after = crate::decommit_stats();
+	FORCE_DECOMMIT_FALLBACK.with(|force| force.set(false));
+
+	result.unwrap();
+	assert!(after.fallback > before.fallback, "{:?} {:?}", before, after);
+}
+
+#[test]
+fn test_double_free() {
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(import "env" "ext_allocator_free_version_1" (func $free (param i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(local $ptr i32)
+				(local.set $ptr (call $malloc (i32.const 16)))
+				(call $free (local.get $ptr))
+				(call $free (local.get $ptr))
+				;; The allocator is still usable after an ignored double free.
+				(drop (call $malloc (i32.const 16)))
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let mut instance = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.build()
+		.new_instance()
+		.unwrap();
+	match instance.call_export("main", &[]).unwrap_err() {
+		Error::AbortedDueToTrap(error) => {
+			assert_eq!(error.message, crate::HostFuncErrorWasmEdge::DoubleFree.to_string())
+		},
+		error => panic!("unexpected error: {:?}", error),
+	}
+
+	let mut instance = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.ignore_double_free(true)
+		.build()
+		.new_instance()
+		.unwrap();
+	instance.call_export("main", &[]).unwrap();
+}
+
+#[test]
+fn test_poison_freed_memory() {
+	// Fills an allocation, frees it and returns its contents.
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(import "env" "ext_allocator_free_version_1" (func $free (param i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(local $ptr i32)
+				(local.set $ptr (call $malloc (i32.const 16)))
+				(i64.store offset=0 (local.get $ptr) (i64.const 0x1111111111111111))
+				(i64.store offset=8 (local.get $ptr) (i64.const 0x1111111111111111))
+				(call $free (local.get $ptr))
+				(i64.or
+					(i64.extend_i32_u (local.get $ptr))
+					(i64.shl (i64.const 16) (i64.const 32))
+				)
+			)
+		)
+		"#;
+
+	let run = |poison_freed_memory| {
+		RuntimeBuilder::new_on_demand()
+			.use_wat(WAT.to_string())
+			.allocator_config(crate::AllocatorConfig { poison_freed_memory })
+			.build()
+			.new_instance()
+			.unwrap()
+			.call_export("main", &[])
+			.unwrap()
+	};
+
+	assert_eq!(run(false), vec![0x11; 16]);
+	assert_eq!(run(true), vec![crate::AllocatorConfig::POISON; 16]);
+}
+
+#[test]
+fn test_allocation_failure() {
+	const WAT: &str = r#"
+		(module
+			(import "env" "ext_allocator_malloc_version_1"
+				(func $malloc (param i32) (result i32)))
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main")
+				(param i32 i32) (result i64)
+				(drop (call $malloc (i32.const 0x7fffffff)))
+				(i64.const 0)
+			)
+		)
+		"#;
+
+	let mut instance = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.build()
+		.new_instance()
+		.unwrap();
+	match instance.call_export("main", &[]).unwrap_err() {
+		Error::AbortedDueToTrap(error) =>
+			assert_eq!(error.message, crate::HostFuncErrorWasmEdge::AllocateMemoryErr.to_string()),
+		error => panic!("unexpected error: {:?}", error),
+	}
+}
+
+#[test]
+fn test_allocator_failures_are_returned() {
+	use crate::HostFuncErrorWasmEdge;
+	use sp_wasm_interface::FunctionContext;
+
+	let semantics = crate::Semantics::default();
+	let wasm = wat::parse_str(
+		r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+		)
+		"#,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&wasm,
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let heap_base = instance_wrapper.extract_heap_base().unwrap();
+
+	let mut host_state = HostState::new(FreeingBumpHeapAllocator::new(heap_base));
+	let mut context = HostContext::new(
+		instance_wrapper.instance().memory("memory").unwrap(),
+		None,
+		&mut host_state,
+	);
+
+	// The host functions get the failures as errors, the call fails with their codes later.
+	assert!(context.allocate_memory(0x7fffffff).is_err());
+	let ptr = context.allocate_memory(16).unwrap();
+	context.deallocate_memory(ptr).unwrap();
+	assert!(context.deallocate_memory(ptr).is_err());
+	drop(context);
+
+	let failure = host_state.take_allocator_failure().unwrap();
+	let (code, _) = crate::imports::classify_panic(&*failure);
+	assert!(matches!(code, HostFuncErrorWasmEdge::DoubleFree));
+	assert!(host_state.take_allocator_failure().is_none());
+}
+
+#[test]
+fn test_classify_host_function_panic() {
+	use crate::{
+		imports::{classify_panic, typed_panic_payload},
+		AllocationPanic, DoubleFreePanic, HostFuncErrorWasmEdge, SpawnedTaskPanic,
+	};
+
+	let classify = |payload: Box<dyn std::any::Any + Send>| classify_panic(&*payload);
+
+	let (code, message) = classify(Box::new(AllocationPanic("too large".into())));
+	assert!(matches!(code, HostFuncErrorWasmEdge::AllocateMemoryErr));
+	assert_eq!(message.as_deref(), Some("Failed to allocate memory: too large"));
+
+	let (code, _) = classify(Box::new(DoubleFreePanic("Double free".into())));
+	assert!(matches!(code, HostFuncErrorWasmEdge::DoubleFree));
+
+	let (code, _) = classify(Box::new(SpawnedTaskPanic("panicked for the handle".into())));
+	assert!(matches!(code, HostFuncErrorWasmEdge::SpawnedTaskErr));
+
+	// Only the typed payloads are classified, not messages resembling them.
+	let (code, message) = classify(Box::new(String::from("Failed to allocate memory")));
+	assert!(matches!(code, HostFuncErrorWasmEdge::Others));
+	assert_eq!(message.as_deref(), Some("Failed to allocate memory"));
+	let (code, _) = classify(Box::new(String::from("Spawned task panicked for the handle")));
+	assert!(matches!(code, HostFuncErrorWasmEdge::Others));
+
+	// Any panic of joining a spawned task is raised as the typed payload.
+	let (code, message) = classify(typed_panic_payload(
+		"ext_runtime_tasks_join_version_1",
+		Box::new(String::from("Spawned task panicked for the handle")),
+	));
+	assert!(matches!(code, HostFuncErrorWasmEdge::SpawnedTaskErr));
+	assert_eq!(
+		message.as_deref(),
+		Some("Failed to join a spawned task: Spawned task panicked for the handle")
+	);
+	let (code, _) = classify(typed_panic_payload(
+		"ext_allocator_malloc_version_1",
+		Box::new(String::from("Spawned task panicked for the handle")),
+	));
+	assert!(matches!(code, HostFuncErrorWasmEdge::Others));
+
+	let (code, message) = classify(Box::new(42u32));
+	assert!(matches!(code, HostFuncErrorWasmEdge::Others));
+	assert!(message.is_none());
+}
+
+#[test]
+fn test_with_memory() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			;; Returns the 4 bytes at offset 100.
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.or (i64.const 100) (i64.shl (i64.const 4) (i64.const 32)))
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.fast_instance_reuse(true)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	assert_eq!(instance.call_export("main", &[]).unwrap(), [0, 0, 0, 0]);
+	instance
+		.with_memory(|memory| memory[100..104].copy_from_slice(b"mark"))
+		.unwrap();
+	assert_eq!(instance.call_export("main", &[]).unwrap(), b"mark");
+	assert_eq!(instance.with_memory(|memory| memory.len()).unwrap(), 64 * 1024 * (1 + 1024));
+
+	// The memory doesn't persist without the fast instance reuse.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+	assert!(instance.with_memory(|_| ()).is_err());
+}
+
+#[test]
+fn test_call_ref() {
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 4096))
+			(data (i32.const 2048) "borrowed right from the memory")
+			;; Returns the 30 bytes at offset 2048.
+			(func (export "main") (param i32 i32) (result i64)
+				(i64.or (i64.const 2048) (i64.shl (i64.const 30) (i64.const 32)))
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.fast_instance_reuse(true)
+		.decommit_after_call(true)
+		.build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+
+	let copied = instance.call_export("main", &[]).unwrap();
+	assert_eq!(copied, b"borrowed right from the memory");
+
+	// The memory is only decommitted once the output is dropped.
+	let output = instance.call_ref(InvokeMethod::Export("main"), &[]).unwrap();
+	assert_eq!(&*output, &copied[..]);
+	drop(output);
+
+	assert!(instance.call_ref(InvokeMethod::Export("missing"), &[]).is_err());
+	assert_eq!(instance.call_export("main", &[]).unwrap(), copied);
+
+	// The memory doesn't persist without the fast instance reuse.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build_runtime();
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+	assert!(instance.call_ref(InvokeMethod::Export("main"), &[]).is_err());
+}
+
+#[test]
+fn test_export_and_import_state() {
+	use codec::{Decode, Encode};
+
+	const WAT: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(global $counter (export "counter") (mut i32) (i32.const 0))
+			;; Bumps the counter and stores it at offset 2000, returns the 4 bytes there.
+			(func (export "bump") (param i32 i32) (result i64)
+				(global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+				(i32.store (i32.const 2000) (global.get $counter))
+				(i64.or (i64.const 2000) (i64.shl (i64.const 4) (i64.const 32)))
+			)
+			;; Returns the 4 bytes at offset 2000.
+			(func (export "load") (param i32 i32) (result i64)
+				(i64.or (i64.const 2000) (i64.shl (i64.const 4) (i64.const 32)))
+			)
+		)
+		"#;
+
+	let runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.fast_instance_reuse(true)
+		.decommit_after_call(false)
+		.build_runtime();
+
+	let mut instance = runtime.new_wasmedge_instance().unwrap();
+	assert_eq!(instance.call_export("bump", &[]).unwrap(), [1, 0, 0, 0]);
+	let state = instance.export_state().unwrap();
+	assert_eq!(state.memory[2000..2004], [1, 0, 0, 0]);
+	assert!(state.globals.contains(&("counter".to_string(), Value::I32(1))));
+
+	let mut forked = runtime.import_state(&state).unwrap();
+	assert_eq!(forked.get_global_const("counter").unwrap(), Some(Value::I32(1)));
+	assert_eq!(forked.call_export("load", &[]).unwrap(), [1, 0, 0, 0]);
+
+	// The imported state is the snapshot the calls start from, the same as the state right
+	// after the instantiation is for the other instances.
+	assert_eq!(forked.call_export("bump", &[]).unwrap(), [2, 0, 0, 0]);
+	assert_eq!(forked.call_export("bump", &[]).unwrap(), [2, 0, 0, 0]);
+	assert_eq!(forked.call_export("load", &[]).unwrap(), [1, 0, 0, 0]);
+
+	// Also if the memory is decommitted after every call.
+	let decommitting_runtime = RuntimeBuilder::new_on_demand()
+		.use_wat(WAT.to_string())
+		.fast_instance_reuse(true)
+		.decommit_after_call(true)
+		.build_runtime();
+	let mut forked = decommitting_runtime.import_state(&state).unwrap();
+	assert_eq!(forked.call_export("bump", &[]).unwrap(), [2, 0, 0, 0]);
+	assert_eq!(forked.call_export("load", &[]).unwrap(), [1, 0, 0, 0]);
+
+	// The state survives encoding, e.g. for storing it on disk.
+	let decoded = crate::InstanceState::decode(&mut &state.encode()[..]).unwrap();
+	assert_eq!(decoded, state);
+	let mut forked = runtime.import_state(&decoded).unwrap();
+	assert_eq!(forked.call_export("load", &[]).unwrap(), [1, 0, 0, 0]);
+
+	// A fresh instance doesn't see the state.
+	let mut fresh = runtime.new_wasmedge_instance().unwrap();
+	assert_eq!(fresh.call_export("load", &[]).unwrap(), [0, 0, 0, 0]);
+
+	// No state is kept without the fast instance reuse.
+	let runtime = RuntimeBuilder::new_on_demand().use_wat(WAT.to_string()).build_runtime();
+	let instance = runtime.new_wasmedge_instance().unwrap();
+	assert!(instance.export_state().is_err());
+	assert!(runtime.import_state(&state).is_err());
+}
+
+#[test]
+fn test_max_artifact_size() {
+	let semantics = |max_artifact_size| crate::Semantics {
+		extra_heap_pages: 1024,
+		max_artifact_size,
+		..Default::default()
+	};
+	let blob = || RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap();
+
+	let dir = tempfile::tempdir().unwrap();
+	let path = dir.path().join("runtime.wasm");
+	let report =
+		crate::prepare_runtime_artifact_with_report(blob(), &semantics(None), &path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	// The artifact fits exactly.
+	let max_artifact_size = Some(report.output_size as usize);
+	crate::prepare_runtime_artifact(blob(), &semantics(max_artifact_size), &path).unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	let error = crate::prepare_runtime_artifact(blob(), &semantics(Some(1024)), &path)
+		.unwrap_err()
+		.to_string();
+	assert!(error.starts_with("artifact exceeds size limit"), "{}", error);
+	assert!(!path.exists());
+}
+
+// This test takes quite a while to execute in a debug build (over 6 minutes on a TR 3970x)
+// so it's ignored by default unless it was compiled with `--release`.
+#[cfg_attr(build_type = "debug", ignore)]
+#[test]
+fn test_instances_without_reuse_are_not_leaked() {
+	let runtime = crate::create_runtime::<HostFunctions>(
+		RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap(),
+		crate::Config {
+			allow_missing_func_imports: true,
+			semantics: crate::Semantics { extra_heap_pages: 2048, ..Default::default() },
+			..Default::default()
+		},
+	)
+	.unwrap();
+
+	// As long as the `wasmtime`'s `Store` lives the instances spawned through it
+	// will live indefinitely. Currently it has a maximum limit of 10k instances,
+	// so let's spawn 10k + 1 of them to make sure our code doesn't keep the `Store`
+	// alive longer than it is necessary. (And since we disabled instance reuse
+	// a new instance will be spawned on each call.)
+	let mut instance = runtime.new_instance().unwrap();
+	for _ in 0..10001 {
+		instance.call_export("test_empty_return", &[0]).unwrap();
+	}
+}
+
+// Compares the time it takes to repeatedly call the same export when the resolved function is
+// cached against looking it up by name on every call.
+//
+// Run it with `cargo test --release -- --ignored --nocapture bench_export_func_cache`.
+#[test]
+#[ignore]
+fn bench_export_func_cache() {
+	const CALLS: u32 = 100_000;
+
+	let semantics = crate::Semantics { extra_heap_pages: 1024, ..Default::default() };
+	let blob = crate::runtime::prepare_blob_for_compilation(
+		RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap(),
+		&semantics,
+		None,
+	)
+	.unwrap();
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&blob.serialize(),
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	crate::imports::prepare_imports(
+		&mut instance_wrapper,
+		&module,
+		&crate::imports::host_function_registry(&HostFunctions::host_functions()),
+		true,
+		false,
+		false,
+		false,
+		None,
+		&Default::default(),
+		None,
+	)
+	.unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let heap_base = instance_wrapper.extract_heap_base().unwrap();
+
+	let mut measure = |clear_cache: bool| {
+		let time = std::time::Instant::now();
+		for _ in 0..CALLS {
+			if clear_cache {
+				instance_wrapper.clear_export_cache();
+			}
+
+			instance_wrapper
+				.set_host_state(Some(HostState::new(FreeingBumpHeapAllocator::new(heap_base))));
+			instance_wrapper
+				.call(InvokeMethod::Export("test_empty_return"), Pointer::new(0), 0)
+				.unwrap();
+			instance_wrapper.take_host_state();
+		}
+		time.elapsed()
+	};
+
+	println!("{} calls with the export cache: {:?}", CALLS, measure(false));
+	println!("{} calls without the export cache: {:?}", CALLS, measure(true));
+}
+
+#[test]
+fn test_decommit_only_dirty() {
+	// Outputs the byte at 3 MiB, far above the allocator's heap, and overwrites it.
+	let wat = r#"
+		(module
+			(memory (export "memory") 1)
+			(global (export "__heap_base") i32 (i32.const 1024))
+			(func (export "main") (param i32 i32) (result i64)
+				(i32.store8 (i32.const 0) (i32.load8_u (i32.const 0x300000)))
+				(i32.store8 (i32.const 0x300000) (i32.const 0xff))
+
+				;; ptr = 0, len = 1
+				(i64.const 4294967296)
+			)
+		)
+	"#;
+
+	for decommit_only_dirty in [false, true] {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.use_wat(wat.to_string())
+			.fast_instance_reuse(true)
+			.decommit_only_dirty(decommit_only_dirty)
+			.build();
+		let mut instance = runtime.new_instance().unwrap();
+
+		// The write of the previous call doesn't survive the decommit.
+		for _ in 0..3 {
+			assert_eq!(instance.call_export("main", &[]).unwrap(), [0]);
+		}
+	}
+}
+
+// Compares the call throughput of a reused instance with and without decommitting the linear
+// memory after each call.
+//
+// Run it with `cargo test --release -- --ignored --nocapture bench_decommit_after_call`.
+#[test]
+#[ignore]
+fn bench_decommit_after_call() {
+	use codec::Encode;
+
+	const CALLS: u32 = 10_000;
+
+	let measure = |decommit_after_call: bool| {
+		let runtime = RuntimeBuilder::new_on_demand()
+			.fast_instance_reuse(true)
+			.decommit_after_call(decommit_after_call)
+			.build();
+		let mut instance = runtime.new_instance().unwrap();
+
+		let time = std::time::Instant::now();
+		for _ in 0..CALLS {
+			// Dirty 1 MiB of memory, so there is something to decommit.
+			instance.call_export("test_dirty_plenty_memory", &(0, 16).encode()).unwrap();
+		}
+		time.elapsed()
+	};
+
+	println!("{} calls with decommit: {:?}", CALLS, measure(true));
+	println!("{} calls without decommit: {:?}", CALLS, measure(false));
+}
+
+// Compares resetting an instance against instantiating the module again.
+//
+// Run it with `cargo test --release -- --ignored --nocapture bench_instance_reset`.
+#[test]
+#[ignore]
+fn bench_instance_reset() {
+	const RESETS: u32 = 1_000;
+
+	// Expose the mutable globals of the test runtime so that they can be restored on reset.
+	let semantics = crate::Semantics {
+		fast_instance_reuse: true,
+		extra_heap_pages: 1024,
+		..Default::default()
+	};
+	let blob = crate::runtime::prepare_blob_for_compilation(
+		RuntimeBlob::uncompress_if_needed(wasm_binary_unwrap()).unwrap(),
+		&semantics,
+		None,
+	)
+	.unwrap();
+	let data_segments_snapshot =
+		Arc::new(sc_executor_common::runtime_blob::DataSegmentsSnapshot::take(&blob).unwrap());
+	let mutable_globals =
+		sc_executor_common::runtime_blob::ExposedMutableGlobalsSet::collect(&blob);
+	let module = wasmedge_sdk::Module::from_bytes(
+		Some(&crate::runtime::common_config(&semantics).unwrap()),
+		&blob.serialize(),
+	)
+	.unwrap();
+
+	let mut instance_wrapper = InstanceWrapper::new(&semantics).unwrap();
+	crate::imports::prepare_imports(
+		&mut instance_wrapper,
+		&module,
+		&crate::imports::host_function_registry(&HostFunctions::host_functions()),
+		true,
+		false,
+		false,
+		false,
+		None,
+		&Default::default(),
+		None,
+	)
+	.unwrap();
+	instance_wrapper.instantiate(&module).unwrap();
+	let initial_state =
+		instance_wrapper.take_initial_state(&mutable_globals, data_segments_snapshot);
+
+	let time = std::time::Instant::now();
+	for _ in 0..RESETS {
+		assert!(instance_wrapper.reset(&initial_state).unwrap());
+	}
+	println!("{} resets: {:?}", RESETS, time.elapsed());
+
+	let time = std::time::Instant::now();
+	for _ in 0..RESETS {
+		instance_wrapper.instantiate(&module).unwrap();
+	}
+	println!("{} instantiations: {:?}", RESETS, time.elapsed());
+}
+
+// Compares resolving every host function by name through a linear search against a lookup in
+// the registry.
+//
+// Run it with `cargo test --release -- --ignored --nocapture bench_host_function_registry`.
+#[test]
+#[ignore]
+fn bench_host_function_registry() {
+	const ROUNDS: u32 = 1_000;
+
+	let host_functions = HostFunctions::host_functions();
+	let names = host_functions.iter().map(|host_func| host_func.name()).collect::<Vec<_>>();
+
+	let time = std::time::Instant::now();
+	for _ in 0..ROUNDS {
+		for name in &names {
+			let host_func = host_functions.iter().find(|host_func| host_func.name() == *name);
+			std::hint::black_box(host_func);
+		}
+	}
+	println!("{} rounds of linear searches: {:?}", ROUNDS, time.elapsed());
+
+	let time = std::time::Instant::now();
+	for _ in 0..ROUNDS {
+		let registry = crate::imports::host_function_registry(&host_functions);
+		for name in &names {
+			std::hint::black_box(registry.get(name));
+		}
+	}
+	println!("{} rounds of registry lookups, including building it: {:?}", ROUNDS, time.elapsed());
+}
+
+// Compares the call throughput of the same runtime with the fast instance reuse, with an instance
+// created for every call and with instances taken from a pool created up-front.
+//
+// Run it with `cargo test --release -- --ignored --nocapture bench_instance_pooling`.
+#[test]
+#[ignore]
+fn bench_instance_pooling() {
+	const CALLS: u32 = 10_000;
+	const POOL_SIZE: usize = 4;
+
+	let report = |strategy: &str, elapsed: std::time::Duration| {
+		println!(
+			"{}: {} calls in {:?}, {:.0} calls per second",
+			strategy,
+			CALLS,
+			elapsed,
+			CALLS as f64 / elapsed.as_secs_f64(),
+		);
+	};
+
+	let runtime = RuntimeBuilder::new_on_demand().fast_instance_reuse(true).build();
+	let mut instance = runtime.new_instance().unwrap();
+	let time = std::time::Instant::now();
+	for _ in 0..CALLS {
+		instance.call_export("test_empty_return", &[0]).unwrap();
+	}
+	report("fast_instance_reuse", time.elapsed());
+
+	let runtime = RuntimeBuilder::new_on_demand().build();
+	let time = std::time::Instant::now();
+	for _ in 0..CALLS {
+		let mut instance = runtime.new_instance().unwrap();
+		instance.call_export("test_empty_return", &[0]).unwrap();
+	}
+	report("RecreateInstance unpooled", time.elapsed());
+
+	let mut pool = RuntimeBuilder::new_on_demand().build_pool(POOL_SIZE);
+	let time = std::time::Instant::now();
+	for call in 0..CALLS as usize {
+		pool[call % POOL_SIZE].call_export("test_empty_return", &[0]).unwrap();
+	}
+	report("RecreateInstance pooled", time.elapsed());
+}
+
+/// Spawns `thread_count` threads, each of which `iterations` times creates a runtime, makes a call
+/// into a new instance and drops both.
+///
+/// Panics if any of the threads panics or doesn't finish in time, which most likely means a
+/// deadlock, or if the memory of the instances isn't released afterwards. Half of the threads use
+/// the fast instance reuse, so that both strategies run concurrently.
+fn stress_concurrent_runtimes(thread_count: usize, iterations: usize) {
+	use std::sync::mpsc::{channel, RecvTimeoutError};
+
+	const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
+	let instance_memory = crate::total_instance_memory();
+
+	let (finished_tx, finished_rx) = channel();
+	let threads = (0..thread_count)
+		.map(|thread| {
+			let finished_tx = finished_tx.clone();
+			std::thread::spawn(move || {
+				for _ in 0..iterations {
+					let runtime = RuntimeBuilder::new_on_demand()
+						.fast_instance_reuse(thread % 2 == 0)
+						.build();
+					let mut instance = runtime.new_instance().unwrap();
+					instance.call_export("test_empty_return", &[0]).unwrap();
+				}
+				finished_tx.send(()).unwrap();
+			})
+		})
+		.collect::<Vec<_>>();
+	drop(finished_tx);
+
+	for _ in 0..thread_count {
+		match finished_rx.recv_timeout(TIMEOUT) {
+			Ok(()) => (),
+			Err(RecvTimeoutError::Timeout) =>
+				panic!("the threads didn't finish in {:?}, likely deadlocked", TIMEOUT),
+			// A thread panicked, which is reported when it is joined.
+			Err(RecvTimeoutError::Disconnected) => break,
+		}
+	}
+	for thread in threads {
+		thread.join().expect("the thread doesn't panic");
+	}
+
+	assert_eq!(crate::total_instance_memory(), instance_memory);
+}
+
+// Creates, calls and drops runtimes from many threads at once, to catch the races and deadlocks
+// around the process-global state, e.g. the data of the host functions. The memory check
+// assumes no other test runs at the same time.
+//
+// Run it with `cargo test --release -- --ignored --test-threads=1 stress_concurrent_runtimes`.
+#[test]
+#[ignore]
+fn test_stress_concurrent_runtimes() {
+	stress_concurrent_runtimes(8, 32);
+}
@@ -1,3 +1,4 @@
+use crate::LOG_TARGET;
 use sc_executor_common::{
 	error::{Error, Result},
 	util::checked_range,
@@ -5,19 +6,31 @@ use sc_executor_common::{
 use sp_wasm_interface::{Pointer, Value, ValueType};
 use wasmedge_sdk::{types::Val, Memory, ValType, WasmValue};
 
+/// The size of a wasm page in bytes, the unit the size of the linear memory is measured in.
+pub(crate) const WASM_PAGE_SIZE: usize = 64 * 1024;
+
 /// Converts a [`wasmedge_sdk::types::Val`] into a substrate runtime interface [`Value`].
 ///
-/// Panics if the given value doesn't have a corresponding variant in `Value`.
-pub fn from_wasmedge_val(val: Val) -> Value {
+/// Returns an error if the given value doesn't have a corresponding variant in `Value`.
+pub fn try_from_wasmedge_val(val: Val) -> Result<Value> {
 	match val {
-		Val::I32(v) => Value::I32(v),
-		Val::I64(v) => Value::I64(v),
-		Val::F32(v) => Value::F32(v as u32),
-		Val::F64(v) => Value::F64(v as u64),
-		v => panic!("Given value type is unsupported by Substrate: {:?}", v),
+		Val::I32(v) => Ok(Value::I32(v)),
+		Val::I64(v) => Ok(Value::I64(v)),
+		Val::F32(v) => Ok(Value::F32(v as u32)),
+		Val::F64(v) => Ok(Value::F64(v as u64)),
+		v => Err(Error::Other(format!("Given value type is unsupported by Substrate: {:?}", v))),
 	}
 }
 
+/// Converts a [`wasmedge_sdk::types::Val`] into a substrate runtime interface [`Value`].
+///
+/// Panics if the given value doesn't have a corresponding variant in `Value`.
+#[allow(dead_code)]
+#[deprecated(note = "panics on unsupported value types, use `try_from_wasmedge_val` instead")]
+pub fn from_wasmedge_val(val: Val) -> Value {
+	try_from_wasmedge_val(val).unwrap_or_else(|e| panic!("{}", e))
+}
+
 /// Converts a sp_wasm_interface's [`Value`] into the corresponding variant in wasmedge's
 /// [`wasmedge_sdk::types::Val`].
 pub fn into_wasmedge_val(value: Value) -> Val {
@@ -31,17 +44,26 @@ pub fn into_wasmedge_val(value: Value) -> Val {
 
 /// Converts a [`wasmedge_sys::WasmValue`] into a substrate runtime interface [`Value`].
 ///
-/// Panics if the given value doesn't have a corresponding variant in `Value`.
-pub fn from_wasmedge_value(val: WasmValue) -> Value {
+/// Returns an error if the given value doesn't have a corresponding variant in `Value`.
+pub fn try_from_wasmedge_value(val: WasmValue) -> Result<Value> {
 	match val.ty() {
-		ValType::I32 => Value::I32(val.to_i32()),
-		ValType::I64 => Value::I64(val.to_i64()),
-		ValType::F32 => Value::F32(val.to_f32() as u32),
-		ValType::F64 => Value::F64(val.to_f64() as u64),
-		v => panic!("Given value type is unsupported by Substrate: {:?}", v),
+		ValType::I32 => Ok(Value::I32(val.to_i32())),
+		ValType::I64 => Ok(Value::I64(val.to_i64())),
+		ValType::F32 => Ok(Value::F32(val.to_f32() as u32)),
+		ValType::F64 => Ok(Value::F64(val.to_f64() as u64)),
+		v => Err(Error::Other(format!("Given value type is unsupported by Substrate: {:?}", v))),
 	}
 }
 
+/// Converts a [`wasmedge_sys::WasmValue`] into a substrate runtime interface [`Value`].
+///
+/// Panics if the given value doesn't have a corresponding variant in `Value`.
+#[allow(dead_code)]
+#[deprecated(note = "panics on unsupported value types, use `try_from_wasmedge_value` instead")]
+pub fn from_wasmedge_value(val: WasmValue) -> Value {
+	try_from_wasmedge_value(val).unwrap_or_else(|e| panic!("{}", e))
+}
+
 /// Converts a sp_wasm_interface's [`Value`] into the corresponding variant in wasmedge's
 /// [`wasmedge_sys::WasmValue`].
 pub fn into_wasmedge_value(value: Value) -> WasmValue {
@@ -92,18 +114,95 @@ pub(crate) fn read_memory(memory: &[u8], source_addr: Pointer<u8>, size: usize)
 	Ok(buffer)
 }
 
+/// Reads a little endian `u32` from the memory at the given address.
+///
+/// Wasm allows unaligned accesses and so does this function, but see [`check_alignment`].
+pub(crate) fn read_u32(memory: &[u8], address: Pointer<u8>) -> Result<u32> {
+	let mut buffer = [0; 4];
+	check_alignment(address, buffer.len());
+	read_memory_into(memory, address, &mut buffer)?;
+	Ok(u32::from_le_bytes(buffer))
+}
+
+/// Reads a little endian `u64` from the memory at the given address.
+///
+/// Wasm allows unaligned accesses and so does this function, but see [`check_alignment`].
+pub(crate) fn read_u64(memory: &[u8], address: Pointer<u8>) -> Result<u64> {
+	let mut buffer = [0; 8];
+	check_alignment(address, buffer.len());
+	read_memory_into(memory, address, &mut buffer)?;
+	Ok(u64::from_le_bytes(buffer))
+}
+
+/// Returns whether the address is naturally aligned for a value of the given size, logging a
+/// warning if it isn't.
+///
+/// The runtimes normally pass aligned pointers to values wider than a byte, so a misaligned one
+/// often points at a bug in the runtime. The check is only done in debug builds, in release
+/// builds this always returns `true`.
+pub(crate) fn check_alignment(address: Pointer<u8>, size: usize) -> bool {
+	if !cfg!(debug_assertions) || usize::from(address) % size == 0 {
+		return true
+	}
+
+	log::warn!(
+		target: LOG_TARGET,
+		"Misaligned access of {} bytes at {:#x}",
+		size,
+		usize::from(address),
+	);
+	false
+}
+
+/// Returns the size of the linear memory in bytes.
+pub(crate) fn memory_len(memory: &Memory) -> usize {
+	let len = memory.size() as usize * WASM_PAGE_SIZE;
+
+	// WasmEdge only reports the size in pages, so check that it holds exactly the bytes which
+	// we assume these pages to be. Only possible while the size fits in a `u32`.
+	if let Some(len) = u32::try_from(len).ok().filter(|len| *len > 0 && *len < u32::MAX) {
+		debug_assert!(
+			memory.data_pointer(0, len).is_ok() && memory.data_pointer(0, len + 1).is_err(),
+			"the linear memory of {} pages isn't {} bytes long",
+			memory.size(),
+			len,
+		);
+	}
+
+	len
+}
+
+/// Returns a view of the whole linear memory.
+///
+/// The view is only valid until the memory is grown: WasmEdge may move the backing store of a
+/// memory when growing it, which leaves any previously obtained view dangling. Hence the view
+/// must not be kept across anything which might grow the memory, e.g. a call into the runtime or
+/// into a host function. Obtain a fresh one instead.
 pub(crate) fn memory_slice(memory: &Memory) -> &[u8] {
+	let len = memory_len(memory);
+	// WasmEdge refuses to give out a pointer into a memory without any pages.
+	if len == 0 {
+		return &[]
+	}
+
 	let base_ptr: *const u8 = memory
 		.data_pointer(0, 1)
 		.expect("failed to returns the const data pointer to the Memory.");
 
-	unsafe { std::slice::from_raw_parts(base_ptr, (memory.size() * 64 * 1024) as usize) }
+	unsafe { std::slice::from_raw_parts(base_ptr, len) }
 }
 
+/// Returns a mutable view of the whole linear memory.
+///
+/// The same restrictions as for [`memory_slice`] apply.
 pub(crate) fn memory_slice_mut(memory: &mut Memory) -> &mut [u8] {
+	let len = memory_len(memory);
+	if len == 0 {
+		return &mut []
+	}
 	let base_ptr_mut: *mut u8 = memory
 		.data_pointer_mut(0, 1)
 		.expect("failed to returns the mut data pointer to the Memory.");
 
-	unsafe { std::slice::from_raw_parts_mut(base_ptr_mut, (memory.size() * 64 * 1024) as usize) }
+	unsafe { std::slice::from_raw_parts_mut(base_ptr_mut, len) }
 }
@@ -18,7 +18,7 @@
 
 //! This crate provides an implementation of `WasmModule` that is baked by wasmi.
 
-use std::{cell::RefCell, rc::Rc, str, sync::Arc};
+use std::{cell::RefCell, rc::Rc, str, sync::Arc, time::Duration};
 
 use log::{debug, error, trace};
 use wasmi::{
@@ -811,10 +811,10 @@ impl WasmInstance for WasmiInstance {
 		&mut self,
 		method: InvokeMethod,
 		data: &[u8],
-	) -> (Result<Vec<u8>, Error>, Option<AllocationStats>) {
+	) -> (Result<Vec<u8>, Error>, Option<AllocationStats>, Option<Duration>) {
 		let mut allocation_stats = None;
 		let result = self.call_impl(method, data, &mut allocation_stats);
-		(result, allocation_stats)
+		(result, allocation_stats, None)
 	}
 
 	fn get_global_const(&mut self, name: &str) -> Result<Option<sp_wasm_interface::Value>, Error> {
@@ -40,6 +40,7 @@ use std::{
 		atomic::{AtomicBool, Ordering},
 		Arc,
 	},
+	time::Duration,
 };
 use wasmtime::{Engine, Memory, StoreLimits, Table};
 
@@ -236,10 +237,10 @@ impl WasmInstance for WasmtimeInstance {
 		&mut self,
 		method: InvokeMethod,
 		data: &[u8],
-	) -> (Result<Vec<u8>>, Option<AllocationStats>) {
+	) -> (Result<Vec<u8>>, Option<AllocationStats>, Option<Duration>) {
 		let mut allocation_stats = None;
 		let result = self.call_impl(method, data, &mut allocation_stats);
-		(result, allocation_stats)
+		(result, allocation_stats, None)
 	}
 
 	fn get_global_const(&mut self, name: &str) -> Result<Option<Value>> {